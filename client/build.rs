@@ -12,15 +12,17 @@ fn main() -> Result<(), Error> {
     let outdir = completion_dir()?;
     let mut app = Swww::command();
 
-    // we must change the value parser for the img subcommand argument to a PathBuf so that the
-    // generator creates the correct autocompletion that suggests filepaths to our users
+    // we must change the value parser for the img and slideshow subcommand arguments to a PathBuf
+    // so that the generator creates the correct autocompletion that suggests filepaths to our users
     for cmd in app.get_subcommands_mut() {
-        if cmd.get_name() == "img" {
-            *cmd = cmd
-                .clone()
-                .mut_arg("image", |arg| arg.value_parser(value_parser!(PathBuf)));
-            break;
-        }
+        let arg = match cmd.get_name() {
+            "img" => "image",
+            "slideshow" => "images",
+            _ => continue,
+        };
+        *cmd = cmd
+            .clone()
+            .mut_arg(arg, |arg| arg.value_parser(value_parser!(PathBuf)));
     }
 
     let shells = [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Elvish];
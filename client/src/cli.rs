@@ -1,8 +1,9 @@
 /// Note: this file only has basic declarations and some definitions in order to be possible to
 /// import it in the build script, to automate shell completion
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fmt::Display;
 use std::path::PathBuf;
+use std::time::Duration;
 
 fn from_hex(hex: &str) -> Result<[u8; 3], String> {
     let chars = hex
@@ -37,6 +38,71 @@ fn from_hex(hex: &str) -> Result<[u8; 3], String> {
     Ok(color)
 }
 
+/// The colors `parse_color` knows by name, with the values CSS gives them
+const NAMED_COLORS: [(&str, [u8; 3]); 23] = [
+    ("black", [0x00, 0x00, 0x00]),
+    ("white", [0xff, 0xff, 0xff]),
+    ("gray", [0x80, 0x80, 0x80]),
+    ("grey", [0x80, 0x80, 0x80]),
+    ("silver", [0xc0, 0xc0, 0xc0]),
+    ("red", [0xff, 0x00, 0x00]),
+    ("maroon", [0x80, 0x00, 0x00]),
+    ("orange", [0xff, 0xa5, 0x00]),
+    ("yellow", [0xff, 0xff, 0x00]),
+    ("olive", [0x80, 0x80, 0x00]),
+    ("lime", [0x00, 0xff, 0x00]),
+    ("green", [0x00, 0x80, 0x00]),
+    ("teal", [0x00, 0x80, 0x80]),
+    ("cyan", [0x00, 0xff, 0xff]),
+    ("aqua", [0x00, 0xff, 0xff]),
+    ("blue", [0x00, 0x00, 0xff]),
+    ("navy", [0x00, 0x00, 0x80]),
+    ("purple", [0x80, 0x00, 0x80]),
+    ("magenta", [0xff, 0x00, 0xff]),
+    ("fuchsia", [0xff, 0x00, 0xff]),
+    ("pink", [0xff, 0xc0, 0xcb]),
+    ("brown", [0xa5, 0x2a, 0x2a]),
+    ("rebeccapurple", [0x66, 0x33, 0x99]),
+];
+
+/// Parses a color, given either by name or as `rgb`, `rgba`, `rrggbb` or `rrggbbaa` hex digits,
+/// optionally preceded by a '#'
+///
+/// Wallpapers can't be see-through, so the alpha is blended over black. Anything else goes
+/// through `from_hex`, which is what we always accepted
+fn parse_color(raw: &str) -> Result<[u8; 3], String> {
+    let raw = raw.trim();
+    if let Some((_, color)) = NAMED_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(raw))
+    {
+        return Ok(*color);
+    }
+
+    let hex = raw.strip_prefix('#').unwrap_or(raw);
+    let digits: Option<Vec<u8>> = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect();
+    let over_black = |rgb: [u8; 3], alpha: u8| {
+        rgb.map(|channel| ((channel as u32 * alpha as u32 + 127) / 255) as u8)
+    };
+    match digits.as_deref() {
+        Some(&[r, g, b]) => Ok([r * 17, g * 17, b * 17]),
+        Some(&[r, g, b, a]) => Ok(over_black([r * 17, g * 17, b * 17], a * 17)),
+        Some(&[r1, r2, g1, g2, b1, b2, a1, a2]) => Ok(over_black(
+            [r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2],
+            a1 * 16 + a2,
+        )),
+        _ => from_hex(hex).map_err(|_| {
+            format!(
+                "invalid color '{raw}': expected a name (like 'rebeccapurple') or rgb, rgba, \
+                 rrggbb or rrggbbaa hex digits"
+            )
+        }),
+    }
+}
+
 #[derive(Clone, ValueEnum)]
 pub enum PixelFormat {
     /// No swap, can copy directly onto WlBuffer
@@ -104,6 +170,11 @@ pub enum TransitionType {
     Wipe,
     Wave,
     Grow,
+    Reveal,
+    Dissolve,
+    Melt,
+    Crossfade,
+    Slide,
 }
 
 impl std::str::FromStr for TransitionType {
@@ -125,13 +196,64 @@ impl std::str::FromStr for TransitionType {
             "wave" => Ok(Self::Wave),
             "random" => Ok(Self::Random),
             "fade" => Ok(Self::Fade),
+            "reveal" => Ok(Self::Reveal),
+            "dissolve" => Ok(Self::Dissolve),
+            "melt" => Ok(Self::Melt),
+            "crossfade" => Ok(Self::Crossfade),
+            "slide" => Ok(Self::Slide),
             _ => Err("unrecognized transition type.\nValid transitions are:\n\
-                     \tsimple | fade | left | right | top | bottom | wipe | grow | center | outer | random | wave\n\
+                     \tsimple | fade | left | right | top | bottom | wipe | grow | center | outer | random | wave | reveal | dissolve | melt | crossfade | slide\n\
                      see swww img --help for more details"),
         }
     }
 }
 
+/// A `--transition-type` value: the transition every output gets, optionally with overrides for
+/// specific outputs, as in `fade,DP-1:left,HDMI-A-1:right`
+#[derive(Clone)]
+pub struct TransitionTypes {
+    pub default: TransitionType,
+    pub outputs: Vec<(String, TransitionType)>,
+}
+
+impl TransitionTypes {
+    /// The transition type `output` overrides the default with, if any
+    pub fn for_output(&self, output: &str) -> Option<&TransitionType> {
+        self.outputs
+            .iter()
+            .rev()
+            .find(|(name, _)| name == output)
+            .map(|(_, transition_type)| transition_type)
+    }
+}
+
+impl std::str::FromStr for TransitionTypes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut default = None;
+        let mut outputs = Vec::new();
+        for item in s.split(',') {
+            match item.split_once(':') {
+                Some(("", _)) => return Err(format!("missing output name in '{item}'")),
+                Some((output, transition_type)) => {
+                    outputs.push((output.to_string(), transition_type.parse()?))
+                }
+                None if default.is_none() => default = Some(item.parse()?),
+                None => {
+                    return Err(
+                        "only one transition type can be given without an output name".to_string(),
+                    )
+                }
+            }
+        }
+        Ok(Self {
+            default: default.unwrap_or(TransitionType::Simple),
+            outputs,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub enum CliCoord {
     Percent(f32),
@@ -168,7 +290,31 @@ pub enum CliImage {
 ///
 ///Note `swww` will only work in a compositor that implements the layer-shell protocol. Typically,
 ///wlr-roots based compositors.
-pub enum Swww {
+pub struct Swww {
+    /// Which daemon to talk to, as given to `swww-daemon --namespace`.
+    ///
+    /// If it isn't set, we talk to the daemon running in the default namespace.
+    #[arg(long, global = true, default_value = "", value_parser = parse_namespace)]
+    pub namespace: String,
+
+    /// Talk to the daemon listening on this socket, instead of the one in the default location.
+    ///
+    /// Takes precedence over `SWWW_SOCKET`, which in turn takes precedence over the namespace.
+    #[arg(long, global = true, env = "SWWW_SOCKET")]
+    pub socket: Option<PathBuf>,
+
+    /// Print what swww is doing to stderr.
+    ///
+    /// Pass it twice (`-vv`) to also print how long each step took.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
     ///Fills the specified outputs with the given color.
     ///
     ///Defaults to filling all outputs with black.
@@ -177,40 +323,190 @@ pub enum Swww {
     ///Restores the last displayed image on the specified outputs.
     Restore(Restore),
 
+    ///Goes back to the image displayed before the current one on the specified outputs.
+    ///
+    ///Undoing twice in a row goes back to the image you started with. Only images set with
+    ///`swww img` are remembered, not colors from `swww clear`.
+    Undo(Undo),
+
     ///Clears the swww cache.
     ///
     ///We currently store the address of the last file set as wallpaper for each monitor, as well
     ///as the animation frames of every gif ever set for a given version of `swww`.
-    ClearCache,
+    ///
+    ///By default, everything is deleted. Use `--older-than` or `--max-size` to only prune the
+    ///cached animation frames instead.
+    ClearCache(ClearCache),
 
     /// Sends an image (or animated gif) for the daemon to display.
     ///
     /// Use `-` to read from stdin
     Img(Img),
 
+    ///Caches the animation frames of an image for the current outputs, without displaying it.
+    ///
+    ///A later `swww img` with the same image and `--resize`, `--fill-color` and `--filter` values
+    ///then skips decoding and compressing the frames, and displays it right away. Still images
+    ///have nothing to cache, but are still decoded and resized, which makes this a way to check
+    ///an image can be displayed.
+    Preload(Preload),
+
+    ///Makes the daemon cycle through a list of images, changing them at a fixed interval.
+    ///
+    ///Sending a new image to an output with `swww img` (or clearing it with `swww clear`) stops
+    ///the slideshow running on that output.
+    Slideshow(Slideshow),
+
+    ///Pauses animated images and slideshows.
+    ///
+    ///Transitions that already started will still finish. Does nothing to outputs that are
+    ///already paused.
+    Pause(Pause),
+
+    ///Resumes animated images and slideshows paused with `swww pause`.
+    ///
+    ///Does nothing to outputs that are not paused.
+    Resume(Pause),
+
+    ///Pauses the outputs if any of them is running, and resumes them if they are all paused.
+    TogglePause(Pause),
+
     ///Kills the daemon
-    Kill,
+    Kill(Kill),
 
     ///Asks the daemon to print output information (names and dimensions).
     ///
     ///You may use this to find out valid values for the <swww-img --outputs> option. If you want
     ///more detailed information about your outputs, I would recommend trying wlr-randr.
-    Query,
+    Query(Query),
+
+    ///Saves what the daemon is currently displaying on an output as a png image.
+    ///
+    ///If the output is in the middle of an animation or transition, this saves the frame that was
+    ///drawn last.
+    Screenshot(Screenshot),
+
+    ///Moves the wallpapers to another layer-shell layer.
+    ///
+    ///This lasts until the daemon exits. Moving every output (the default) also changes the layer
+    ///of outputs connected afterwards.
+    Layer(SetLayer),
+
+    ///Prints what happens in the daemon, one event per line, until it exits.
+    ///
+    ///Events are `output-added <name>`, `output-removed <name>`,
+    ///`wallpaper-changed <name> <image: path | color: RRGGBB>`, `transition-started <name>`,
+    ///`transition-finished <name>`, `paused <name>` and `resumed <name>`, so that scripts can react
+    ///to monitors being plugged in, for example.
+    #[command(visible_alias = "listen")]
+    Subscribe(Subscribe),
+}
+
+#[derive(Parser)]
+pub struct Query {
+    /// Print the information as a JSON array, with one object per output
+    #[arg(short, long)]
+    pub json: bool,
+
+    /// Comma separated list of outputs to print the information of.
+    ///
+    /// If it isn't set, every output is printed. It's an error to ask for an output that doesn't
+    /// exist.
+    #[arg(short, long, visible_alias = "output", default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Subscribe {
+    /// Print every event as a JSON object, one per line
+    #[arg(short, long)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct Screenshot {
+    /// Name of the output to take the screenshot of
+    #[arg(short, long)]
+    pub output: String,
+
+    /// Path to save the png image at
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layer {
+    /// Below every other surface. This is where swww-daemon starts by default
+    Background,
+    Bottom,
+    Top,
+    /// Above every other surface, including fullscreen windows
+    Overlay,
+}
+
+#[derive(Parser)]
+pub struct SetLayer {
+    /// The layer to move the wallpapers to
+    pub layer: Layer,
+
+    /// Comma separated list of outputs to move.
+    ///
+    /// If it isn't set, all outputs are moved.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
 }
 
 #[derive(Parser)]
 pub struct Clear {
     /// Color to fill the screen with.
     ///
-    /// Must be given in rrggbb format (note there is no prepended '#').
-    #[arg(value_parser = from_hex, default_value = "000000")]
+    /// Either a name (like 'rebeccapurple') or hex digits in rgb, rgba, rrggbb or rrggbbaa format,
+    /// optionally prepended with '#'. The alpha is blended over black.
+    #[arg(value_parser = parse_color, default_value = "000000")]
     pub color: [u8; 3],
 
+    /// Fill the screen with a linear gradient instead of a single color.
+    ///
+    /// Must be given as two or more colors in the same format as the color, separated by '-' (e.g.
+    /// '000000-222244'). The colors are spread evenly across the screen.
+    #[arg(long, value_parser = parse_gradient, conflicts_with = "color")]
+    pub gradient: Option<Box<[[u8; 3]]>>,
+
+    /// Angle of the gradient, in degrees.
+    ///
+    /// 0 goes from left to right, and 90 from top to bottom.
+    #[arg(
+        long,
+        default_value = "0.0",
+        requires = "gradient",
+        allow_hyphen_values = true
+    )]
+    pub gradient_angle: f64,
+
     /// Comma separated list of outputs to display the image at.
     ///
     /// If it isn't set, the image is displayed on all outputs.
     #[clap(short, long, default_value = "")]
     pub outputs: String,
+
+    /// Only fill the outputs whose description contains this text, ignoring case.
+    ///
+    /// Descriptions usually hold the monitor's make and model, which, unlike output names, don't
+    /// change between reboots. Identical monitors all match.
+    #[arg(long, conflicts_with = "outputs")]
+    pub output_desc: Option<String>,
+
+    /// Only exit once the outputs are done changing to the new color.
+    #[arg(long)]
+    pub sync_wait: bool,
+
+    /// Clearing is instant, unless `--transition-type` is given in the command line. The other
+    /// `--transition-*` flags work just like they do for `swww img`
+    #[command(flatten)]
+    pub transition: TransitionArgs,
+
+    /// Whether `--transition-type` was given in the command line. Set after parsing
+    #[arg(skip)]
+    pub with_transition: bool,
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
@@ -228,6 +524,91 @@ pub enum ResizeStrategy {
     Fit,
     /// Resize the image to fit inside the screen, without preserving the original aspect ratio
     Stretch,
+    /// Do not resize the image, repeating it across the screen instead
+    ///
+    /// The first tile starts at the top left corner of the screen. Images larger than the screen
+    /// are cropped to it.
+    Tile,
+}
+
+/// Rotations and flips, with the same names as wlr-randr's output transforms
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Transform {
+    #[default]
+    Normal,
+    /// Rotate the image 90 degrees counter-clockwise
+    #[value(name = "90")]
+    Rotate90,
+    /// Rotate the image 180 degrees
+    #[value(name = "180")]
+    Rotate180,
+    /// Rotate the image 270 degrees counter-clockwise
+    #[value(name = "270")]
+    Rotate270,
+    /// Flip the image around its vertical axis
+    Flipped,
+    /// Flip the image around its vertical axis, then rotate it 90 degrees counter-clockwise
+    #[value(name = "flipped-90")]
+    Flipped90,
+    /// Flip the image around its vertical axis, then rotate it 180 degrees
+    #[value(name = "flipped-180")]
+    Flipped180,
+    /// Flip the image around its vertical axis, then rotate it 270 degrees counter-clockwise
+    #[value(name = "flipped-270")]
+    Flipped270,
+}
+
+impl Transform {
+    /// Whether the image's width and height trade places
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Self::Rotate90 | Self::Rotate270 | Self::Flipped90 | Self::Flipped270
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Resample {
+    /// Resize the image's sRGB values directly
+    #[default]
+    Srgb,
+    /// Convert the image to linear light before resizing it, and back afterwards
+    ///
+    /// This keeps fine detail from getting darker when downscaling, at the cost of a slower
+    /// resize.
+    Linear,
+}
+
+/// What fills the space around the image with `--resize fit`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FitFill {
+    /// A solid color, given by `--fill-color`
+    #[default]
+    Color,
+    /// The image itself, stretched over the whole screen and blurred
+    Blur,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// Use LZ4's regular compressor, which is many times faster than any level, but makes bigger
+    /// frames
+    Fast,
+    /// Same as `--compression-level 9`
+    Default,
+    /// Same as `--compression-level 12`, the smallest frames LZ4 can make
+    Best,
+}
+
+#[derive(Parser)]
+pub struct Kill {
+    /// How long to wait for the daemon to exit, in seconds, or with an 's', 'm' or 'h' suffix.
+    ///
+    /// We know it did once it deletes its socket. If it takes longer than this, we exit with an
+    /// error.
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub timeout: Duration,
 }
 
 #[derive(Parser)]
@@ -237,6 +618,47 @@ pub struct Restore {
     /// If it isn't set, all outputs will be restored.
     #[arg(short, long, default_value = "")]
     pub outputs: String,
+
+    /// Restore the wallpapers of every running daemon, whatever its namespace.
+    ///
+    /// `--namespace` is ignored when this is set.
+    #[arg(long)]
+    pub all_namespaces: bool,
+}
+
+#[derive(Parser)]
+pub struct Pause {
+    /// Comma separated list of outputs to pause or resume.
+    ///
+    /// If it isn't set, all outputs are affected. Outputs sharing an animated image or slideshow
+    /// can only be paused together, so pausing one of them also pauses the others.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Undo {
+    /// Comma separated list of outputs to undo.
+    ///
+    /// If it isn't set, all outputs will go back to their previous image.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct ClearCache {
+    /// Only delete cached animations that have not been used for this long.
+    ///
+    /// Accepts a number followed by `s`, `m`, `h` or `d` (seconds, minutes, hours or days). A
+    /// plain number is taken as seconds.
+    #[arg(long, value_parser = parse_age)]
+    pub older_than: Option<Duration>,
+
+    /// Delete the least recently used cached animations until they take up at most this much.
+    ///
+    /// Accepts a number of bytes, optionally followed by `K`, `M` or `G`.
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
 }
 
 #[derive(Parser)]
@@ -247,9 +669,21 @@ pub struct Img {
 
     /// Comma separated list of outputs to display the image at.
     ///
-    /// If it isn't set, the image is displayed on all outputs.
+    /// If it isn't set, the image is displayed on all outputs. Names may also be globs, like
+    /// `DP-*`, or regexes matching the whole name when prefixed with `re:`, like `re:DP-\d+`.
+    ///
+    /// This can be given more than once to use different transitions on different groups of
+    /// outputs: the n-th value of each `--transition-*` flag applies to the n-th group of
+    /// outputs. Groups without a value of their own use the last value given for that flag.
     #[arg(short, long, default_value = "")]
-    pub outputs: String,
+    pub outputs: Vec<String>,
+
+    /// Only display the image on the outputs whose description contains this text, ignoring case.
+    ///
+    /// Descriptions usually hold the monitor's make and model, which, unlike output names, don't
+    /// change between reboots. Identical monitors all match.
+    #[arg(long, conflicts_with = "outputs")]
+    pub output_desc: Option<String>,
 
     /// Do not resize the image. Equivalent to `--resize=no`
     ///
@@ -268,10 +702,47 @@ pub struct Img {
     )]
     pub resize: ResizeStrategy,
 
+    /// Which part of the image to keep when `--resize crop` cuts some of it out.
+    ///
+    /// Either an anchor (top-left, top, top-right, left, center, right, bottom-left, bottom or
+    /// bottom-right), or an `x,y` point, where `0,0` is the top left corner of the image and `1,1`
+    /// the bottom right one. Coordinates outside of that range are clamped to it.
+    #[arg(long, value_parser = parse_crop_focus, default_value = "center")]
+    pub crop_focus: (f32, f32),
+
+    /// Spread the image across all the outputs it goes to, as if they were a single screen.
+    ///
+    /// Every output shows the part of the image that matches where it is in the compositor's
+    /// layout, so the image carries on from one monitor to the next, whatever their resolution and
+    /// scale. The image covers the whole layout, like `--resize crop` does, keeping `--crop-focus`
+    /// in view. Outputs that partly overlap can't share an image this way. Does not work with
+    /// animated images.
+    #[arg(long, conflicts_with_all = ["resize", "no_resize", "random_per_output"])]
+    pub span: bool,
+
+    /// The part of the image to show, as `[x, y, width, height]` fractions of its size. Set when
+    /// restoring an image that was spanned across several outputs
+    #[arg(skip)]
+    pub region: Option<[f64; 4]>,
+
     /// Which color to fill the padding with when output image does not fill screen
-    #[arg(value_parser = from_hex, long, default_value = "000000")]
+    ///
+    /// Either a name (like 'rebeccapurple') or hex digits in rgb, rgba, rrggbb or rrggbbaa format,
+    /// optionally prepended with '#'.
+    #[arg(value_parser = parse_color, long, default_value = "000000")]
     pub fill_color: [u8; 3],
 
+    /// What to fill the space around the image with when using `--resize fit`.
+    ///
+    /// `blur` stretches the image over the whole screen and blurs it, like many phone lock screens
+    /// do, and puts the sharp, fitted image on top.
+    #[arg(long, default_value = "color")]
+    pub fit_fill: FitFill,
+
+    /// How strong the blur is with `--fit-fill blur`, as a radius in pixels from 0 to 200
+    #[arg(long, value_parser = parse_blur_radius, default_value = "20")]
+    pub fit_blur: f32,
+
     ///Filter to use when scaling images (run swww img --help to see options).
     ///
     ///Available options are:
@@ -289,12 +760,180 @@ pub struct Img {
     #[arg(short, long, default_value = "Lanczos3")]
     pub filter: Filter,
 
+    /// Which color space to resize images in.
+    ///
+    /// `linear` keeps fine detail, like leaves or text, from getting darker and losing contrast
+    /// when downscaling large photos, but makes resizing slower. The default, `srgb`, is faster.
+    #[arg(long, default_value = "srgb")]
+    pub resample: Resample,
+
+    /// Brightness adjustment, from -1.0 (black) to 1.0 (white)
+    ///
+    /// This is added to every color channel. Values outside the range are clamped. The default,
+    /// 0.0, leaves the image untouched.
+    #[arg(long, value_parser = parse_brightness, default_value = "0.0", allow_hyphen_values = true)]
+    pub brightness: f32,
+
+    /// Contrast adjustment, from 0.0 (flat gray) to 4.0
+    ///
+    /// Colors are pushed away from (or pulled towards) middle gray by this factor. Values outside
+    /// the range are clamped. The default, 1.0, leaves the image untouched.
+    #[arg(long, value_parser = parse_contrast, default_value = "1.0")]
+    pub contrast: f32,
+
+    /// Gamma correction, from 0.1 to 10.0
+    ///
+    /// Values above 1.0 brighten the dark parts of the image, and values below 1.0 darken them.
+    /// Values outside the range are clamped. The default, 1.0, leaves the image untouched.
+    #[arg(long, value_parser = parse_gamma, default_value = "1.0")]
+    pub gamma: f32,
+
+    /// Saturation adjustment, from 0.0 (grayscale) to 4.0
+    ///
+    /// Colors are pushed away from (or pulled towards) the gray of the same luminance by this
+    /// factor. Values outside the range are clamped. The default, 1.0, leaves the image untouched.
+    #[arg(long, value_parser = parse_saturation, default_value = "1.0")]
+    pub saturation: f32,
+
+    /// Turns the image into its negative
+    ///
+    /// The inversion comes before every other adjustment, so `--invert --brightness 0.2` gives a
+    /// brighter negative.
+    #[arg(long)]
+    pub invert: bool,
+
+    /// Turns the image grayscale, overriding `--saturation`
+    #[arg(long)]
+    pub grayscale: bool,
+
+    /// Blends the image towards a color, as in COLOR[:strength]
+    ///
+    /// The strength goes from 0.0 (no tint) to 1.0 (a solid color), and defaults to 0.5. It's
+    /// applied after every other adjustment, so `--grayscale --tint 88c0d0` gives a monochrome
+    /// image in that color's hue.
+    #[arg(long, value_parser = parse_tint)]
+    pub tint: Option<([u8; 3], f32)>,
+
+    /// Dither the brightness, contrast and gamma adjustments, instead of rounding them.
+    ///
+    /// Stretching smooth gradients with those adjustments leaves visible bands, which this hides
+    /// behind a fine, fixed pattern. It does nothing without any of them.
+    #[arg(long)]
+    pub dither: bool,
+
+    /// Don't rotate or flip images according to their EXIF orientation.
+    ///
+    /// By default, photos are displayed the way the camera that took them says they should be,
+    /// which is usually what you want.
+    #[arg(long)]
+    pub ignore_exif: bool,
+
+    /// Rotate or flip the image before resizing it to the outputs.
+    ///
+    /// By default, the wallpaper is drawn in whatever orientation the compositor asks for, so this
+    /// is only needed on compositors that still show it sideways on rotated monitors. Giving this,
+    /// even as `normal`, draws the image exactly as it comes out, ignoring what the compositor
+    /// asks for. Rotating by 90 or 270 degrees makes the image's width and height trade places.
+    #[arg(long)]
+    pub transform: Option<Transform>,
+
+    /// Don't write the frames of animated images to the cache.
+    ///
+    /// Useful if your cache lives on slow storage. Note the cache is also used to speed up
+    /// loading the same animated image again.
+    #[arg(long)]
+    pub no_cache_write: bool,
+
+    /// How hard to compress the frames of animated images, from 1 to 12.
+    ///
+    /// Lower levels get large animations on the screen faster on weak CPUs, at the cost of using
+    /// more memory. Frames read from the cache keep whatever level they were compressed with.
+    #[arg(long, default_value = "9", value_parser = clap::value_parser!(u8).range(1..=12))]
+    pub compression_level: u8,
+
+    /// A preset for how hard to compress the frames of animated images.
+    ///
+    /// Cannot be combined with `--compression-level`.
+    #[arg(long, value_enum, conflicts_with = "compression_level")]
+    pub compression: Option<Compression>,
+
+    /// How many times to play animated images before stopping at their last frame.
+    ///
+    /// 0 plays them forever.
+    #[arg(long, default_value = "0")]
+    pub loop_count: u32,
+
+    /// How fast to play animated images, as a multiple of their normal speed.
+    ///
+    /// For example, 0.5 plays them at half speed. Must be at least 0.05. Sending the image that is
+    /// already playing again with a different value changes its speed without restarting it.
+    #[arg(long, value_parser = parse_playback_speed, default_value = "1.0")]
+    pub playback_speed: f32,
+
+    /// Only exit once the transition is over.
+    ///
+    /// Useful in scripts that need the new image to be fully on screen, for example to take a
+    /// screenshot right after. Animated images do not make this wait any longer: only the
+    /// transition into them counts.
+    #[arg(long)]
+    pub sync_wait: bool,
+
+    /// Read the image from standard input as raw RGBA pixels of the given size, as in 1920x1080.
+    ///
+    /// Only works when the image is '-'. Standard input must hold exactly width * height * 4
+    /// bytes, with no header. This skips decoding entirely, which makes it a cheap way for other
+    /// programs to draw wallpapers.
+    #[arg(long, value_parser = parse_dimensions)]
+    pub raw: Option<(u32, u32)>,
+
+    /// Print what would be sent to each output, without sending anything.
+    ///
+    /// This still asks the daemon for the outputs' sizes, so that the report is accurate.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Treat the image as a directory, and display a random image out of it.
+    ///
+    /// Files that are not images, or cannot be read, are skipped.
+    #[arg(long)]
+    pub random: bool,
+
+    /// With `--random` or `--interval`, also take images from the directory's subdirectories.
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// With `--random`, give every output an image of its own, instead of the same one for all.
+    ///
+    /// Outputs only repeat images when there are more of them than images in the directory.
+    #[arg(long, requires = "random")]
+    pub random_per_output: bool,
+
+    /// Treat the image as a directory, and keep running, showing its next image every time this
+    /// much time passes.
+    ///
+    /// Accepts the same units as `swww slideshow --interval`. The directory and the outputs are
+    /// read again before every image, so new images and outputs are picked up as they appear. If
+    /// the daemon can't be reached, we skip that image and try again after the next interval.
+    /// Exits on SIGINT or SIGTERM.
+    #[arg(long, value_parser = parse_duration, conflicts_with_all = ["random", "random_per_output", "dry_run"])]
+    pub interval: Option<Duration>,
+
+    /// With `--interval`, the order in which to go through the directory's images.
+    #[arg(long, default_value = "alphabetical", requires = "interval")]
+    pub order: ImgOrder,
+
+    #[command(flatten)]
+    pub transition: TransitionArgs,
+}
+
+#[derive(Clone, Parser)]
+pub struct TransitionArgs {
     ///Sets the type of transition. Default is 'simple', that fades into the new image
     ///
     ///Possible transitions are:
     ///
     ///none | simple | fade | left | right | top | bottom | wipe | wave | grow | center | any |
-    /// outer | random
+    /// outer | reveal | dissolve | melt | crossfade | slide | random
     ///
     ///The 'left', 'right', 'top' and 'bottom' options make the transition happen from that
     ///position to its opposite in the screen.
@@ -319,9 +958,29 @@ pub struct Img {
     ///
     ///'outer' is the same as grow but the circle shrinks instead of growing.
     ///
+    ///'reveal' makes the brightest parts of the new image appear first, and the darkest ones last.
+    ///
+    ///'dissolve' flips the screen to the new image in small blocks, in a random looking order. Use
+    /// `--transition-step` to set the blocks' size, in pixels.
+    ///
+    ///'melt' slides the old image down in columns that start falling at different times, like the
+    /// classic Doom screen wipe. Use `--transition-step` to set the columns' width, in pixels.
+    ///
+    ///'crossfade' blends the old image into the new one, raising the new image's opacity by
+    /// `--transition-step` (out of 255) every frame. Unlike 'simple' and 'fade', every pixel
+    /// reaches the new image at the same time.
+    ///
+    ///'slide' moves the new image in from the edge closest to `--transition-angle`, pushing the
+    /// old one out through the opposite edge. It moves `--transition-step` 256ths of the screen
+    /// every frame, eased by `--transition-bezier`.
+    ///
     ///Finally, 'random' will select a transition effect at random
+    ///
+    ///Specific outputs can get their own transition by prefixing it with the output's name, in a
+    ///comma separated list. The value without a prefix (or 'simple', if there is none) goes to
+    ///every other output. For example, 'fade,DP-1:left,HDMI-A-1:right'
     #[arg(short, long, env = "SWWW_TRANSITION", default_value = "simple")]
-    pub transition_type: TransitionType,
+    pub transition_type: Vec<TransitionTypes>,
 
     ///How fast the transition approaches the new image.
     ///
@@ -331,36 +990,40 @@ pub struct Img {
     ///Larger values will make the transition faster, but more abrupt. A value of 255 will always
     ///switch to the new image immediately.
     ///
-    /// This defaults to 2 when transition-type is 'simple', and 90 otherwise
-    #[arg(
-        long,
-        env = "SWWW_TRANSITION_STEP",
-        default_value = "90",
-        default_value_if("transition_type", "simple", "2")
-    )]
-    pub transition_step: std::num::NonZeroU8,
+    ///For 'dissolve' and 'melt', this is the size of the blocks or columns the screen is split
+    ///into instead. For 'crossfade', it is how much the new image's opacity goes up every frame,
+    ///out of 255, and for 'slide' how far the new image moves every frame, in 256ths of the screen.
+    ///
+    /// This defaults to 2 when transition-type is 'simple', 1 when it is 'dissolve', 8 when it is
+    /// 'melt', 'crossfade' or 'slide', and 90 otherwise
+    #[arg(long, env = "SWWW_TRANSITION_STEP")]
+    pub transition_step: Vec<std::num::NonZeroU8>,
 
     ///How long the transition takes to complete in seconds.
     ///
     ///Note that this doesn't work with the 'simple' transition
     #[arg(long, env = "SWWW_TRANSITION_DURATION", default_value = "3")]
-    pub transition_duration: f32,
+    pub transition_duration: Vec<f32>,
 
     ///Frame rate for the transition effect.
     ///
     ///Note there is no point in setting this to a value smaller than what your monitor supports.
     ///
+    ///'auto' (or 0) matches the refresh rate of the outputs instead. When the same transition
+    ///plays on outputs with different refresh rates, it goes at the slowest one.
+    ///
     ///Also note this is **different** from the transition-step. That one controls by how much we
     ///approach the new image every frame.
-    #[arg(long, env = "SWWW_TRANSITION_FPS", default_value = "30")]
-    pub transition_fps: u16,
+    #[arg(long, env = "SWWW_TRANSITION_FPS", default_value = "30", value_parser = parse_fps)]
+    pub transition_fps: Vec<u16>,
 
-    ///This is used for the 'wipe' and 'wave' transitions. It controls the angle of the wipe
+    ///This is used for the 'wipe' and 'wave' transitions. It controls the angle of the wipe.
+    /// 'slide' rounds it to the closest edge
     ///
     ///Note that the angle is in degrees, where '0' is right to left and '90' is top to bottom,
     /// and '270' bottom to top
     #[arg(long, env = "SWWW_TRANSITION_ANGLE", default_value = "45")]
-    pub transition_angle: f64,
+    pub transition_angle: Vec<f64>,
 
     ///This is only used for the 'grow','outer' transitions. It controls the center of circle
     /// (default is 'center').
@@ -374,7 +1037,7 @@ pub struct Img {
     /// 'center' | 'top' | 'left' | 'right' | 'bottom' | 'top-left' | 'top-right' | 'bottom-left' |
     /// 'bottom-right'
     #[arg(long, env = "SWWW_TRANSITION_POS", default_value = "center", value_parser=parse_coords)]
-    pub transition_pos: CliPosition,
+    pub transition_pos: Vec<CliPosition>,
 
     /// inverts the y position sent in 'transition_pos' flag
     #[arg(long, env = "INVERT_Y", default_value = "false")]
@@ -385,11 +1048,338 @@ pub struct Img {
     ///
     ///eg: 0.0,0.0,1.0,1.0 for linear animation
     #[arg(long, env = "SWWW_TRANSITION_BEZIER", default_value = ".54,0,.34,.99", value_parser = parse_bezier)]
-    pub transition_bezier: (f32, f32, f32, f32),
+    pub transition_bezier: Vec<(f32, f32, f32, f32)>,
+
+    ///named easing curve to use for the transition, instead of a bezier curve
+    ///
+    ///This is just a shorthand for the matching `--transition-bezier` values, and takes
+    ///precedence over it when both are given
+    #[arg(long, env = "SWWW_TRANSITION_EASING")]
+    pub transition_easing: Vec<TransitionEasing>,
 
     ///currently only used for 'wave' transition to control the width and height of each wave
     #[arg(long, env = "SWWW_TRANSITION_WAVE", default_value = "20,20", value_parser = parse_wave)]
-    pub transition_wave: (f32, f32),
+    pub transition_wave: Vec<(f32, f32)>,
+
+    ///grayscale image telling the order pixels switch to the new image in: darker pixels switch
+    ///first. It is stretched to each output's size.
+    ///
+    ///Only used by the 'fade' and 'dissolve' transitions, to make wipes shaped like anything the
+    ///image can draw
+    #[arg(long, env = "SWWW_TRANSITION_MASK", value_hint = clap::ValueHint::FilePath)]
+    pub transition_mask: Vec<PathBuf>,
+
+    ///color space the 'fade' and 'crossfade' transitions blend the two images in
+    ///
+    ///'srgb' (the default) blends the stored color values directly, which makes the middle of the
+    ///fade look darker than it should, most noticeably between a black and a white image. 'linear'
+    ///blends in linear light instead, like a real crossfade would
+    #[arg(long, env = "SWWW_TRANSITION_COLORSPACE", default_value = "srgb")]
+    pub transition_colorspace: TransitionColorspace,
+}
+
+impl TransitionArgs {
+    /// How many values the most repeated `--transition-*` flag was given
+    pub fn count(&self) -> usize {
+        [
+            self.transition_type.len(),
+            self.transition_step.len(),
+            self.transition_duration.len(),
+            self.transition_fps.len(),
+            self.transition_angle.len(),
+            self.transition_pos.len(),
+            self.transition_bezier.len(),
+            self.transition_easing.len(),
+            self.transition_wave.len(),
+            self.transition_mask.len(),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TransitionColorspace {
+    #[default]
+    /// Blend the color values as they are stored
+    Srgb,
+    /// Blend in linear light, so the fade's midpoints keep their brightness
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TransitionEasing {
+    /// Constant speed
+    Linear,
+    /// Starts quickly, and slows down towards the end
+    Ease,
+    /// Starts slowly
+    EaseIn,
+    /// Ends slowly
+    EaseOut,
+    /// Starts and ends slowly
+    EaseInOut,
+}
+
+impl TransitionEasing {
+    /// The control points of the curve, the same ones CSS uses for these names
+    pub fn bezier(self) -> (f32, f32, f32, f32) {
+        match self {
+            Self::Linear => (0.0, 0.0, 1.0, 1.0),
+            Self::Ease => (0.25, 0.1, 0.25, 1.0),
+            Self::EaseIn => (0.42, 0.0, 1.0, 1.0),
+            Self::EaseOut => (0.0, 0.0, 0.58, 1.0),
+            Self::EaseInOut => (0.42, 0.0, 0.58, 1.0),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct Preload {
+    /// Path of the image to preload
+    pub image: PathBuf,
+
+    /// Comma separated list of outputs to preload the image for.
+    ///
+    /// If it isn't set, the image is preloaded for all outputs.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+
+    /// Whether to resize the image and the method by which to resize it
+    #[arg(long, default_value = "crop")]
+    pub resize: ResizeStrategy,
+
+    /// Which color to fill the padding with when output image does not fill screen
+    ///
+    /// Either a name (like 'rebeccapurple') or hex digits in rgb, rgba, rrggbb or rrggbbaa format,
+    /// optionally prepended with '#'.
+    #[arg(value_parser = parse_color, long, default_value = "000000")]
+    pub fill_color: [u8; 3],
+
+    ///Filter to use when scaling images (run swww img --help to see options).
+    #[arg(short, long, default_value = "Lanczos3")]
+    pub filter: Filter,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SlideshowOrder {
+    #[default]
+    /// Display the images in the order they were given
+    Sequential,
+    /// Pick the next image at random (never repeating the current one)
+    Random,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImgOrder {
+    #[default]
+    /// Go through the images sorted by path, starting over after the last one
+    Alphabetical,
+    /// Pick the next image at random (never repeating the current one)
+    Random,
+}
+
+#[derive(Parser)]
+pub struct Slideshow {
+    /// Paths of images or hexcodes (starting with 0x) to cycle through
+    #[arg(value_parser = parse_image, required = true, num_args = 1..)]
+    pub images: Vec<CliImage>,
+
+    /// Comma separated list of outputs to run the slideshow at.
+    ///
+    /// If it isn't set, the slideshow runs on all outputs.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+
+    /// How long each image stays on screen.
+    ///
+    /// Accepts a number followed by an optional unit: 's' for seconds (the default), 'm' for
+    /// minutes or 'h' for hours. Example: 30s, 5m, 1.5h
+    #[arg(short, long, default_value = "5m", value_parser = parse_duration)]
+    pub interval: Duration,
+
+    /// In which order to display the images
+    #[arg(long, default_value = "sequential")]
+    pub order: SlideshowOrder,
+
+    /// Whether to resize the images and the method by which to resize them
+    #[arg(long, default_value = "crop")]
+    pub resize: ResizeStrategy,
+
+    /// Which color to fill the padding with when output image does not fill screen
+    ///
+    /// Either a name (like 'rebeccapurple') or hex digits in rgb, rgba, rrggbb or rrggbbaa format,
+    /// optionally prepended with '#'.
+    #[arg(value_parser = parse_color, long, default_value = "000000")]
+    pub fill_color: [u8; 3],
+
+    ///Filter to use when scaling images (run swww img --help to see options).
+    #[arg(short, long, default_value = "Lanczos3")]
+    pub filter: Filter,
+
+    /// Don't write the frames of animated images to the cache.
+    ///
+    /// Useful if your cache lives on slow storage. Note the cache is also used to speed up
+    /// loading the same animated image again.
+    #[arg(long)]
+    pub no_cache_write: bool,
+
+    #[command(flatten)]
+    pub transition: TransitionArgs,
+}
+
+fn parse_namespace(raw: &str) -> Result<String, String> {
+    if raw.contains('/') {
+        return Err("namespace cannot contain '/'".to_string());
+    }
+    Ok(raw.to_string())
+}
+
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let (number, multiplier) = match raw.char_indices().last() {
+        Some((i, 's')) => (&raw[..i], 1.0),
+        Some((i, 'm')) => (&raw[..i], 60.0),
+        Some((i, 'h')) => (&raw[..i], 3600.0),
+        _ => (raw, 1.0),
+    };
+
+    let secs = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|e| format!("invalid duration '{raw}': {e}"))?
+        * multiplier;
+
+    if !secs.is_finite() || secs <= 0.0 {
+        return Err(format!("duration must be a positive value, got '{raw}'"));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+fn parse_age(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let (number, multiplier) = match raw.char_indices().last() {
+        Some((i, 'd')) => (&raw[..i], 86400.0),
+        _ => (raw, 1.0),
+    };
+    Ok(parse_duration(number)?.mul_f64(multiplier))
+}
+
+fn parse_size(raw: &str) -> Result<u64, String> {
+    let raw = raw.trim();
+    let (number, multiplier) = match raw.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&raw[..i], 1 << 10),
+        Some((i, 'M')) => (&raw[..i], 1 << 20),
+        Some((i, 'G')) => (&raw[..i], 1 << 30),
+        _ => (raw, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{raw}'"))
+}
+
+/// Parses a float, clamping it to `min..=max`
+fn parse_clamped(raw: &str, min: f32, max: f32) -> Result<f32, String> {
+    let value = raw
+        .trim()
+        .parse::<f32>()
+        .map_err(|e| format!("invalid number '{raw}': {e}"))?;
+    if value.is_nan() {
+        return Err(format!("invalid number '{raw}'"));
+    }
+    Ok(value.clamp(min, max))
+}
+
+fn parse_brightness(raw: &str) -> Result<f32, String> {
+    parse_clamped(raw, -1.0, 1.0)
+}
+
+fn parse_contrast(raw: &str) -> Result<f32, String> {
+    parse_clamped(raw, 0.0, 4.0)
+}
+
+fn parse_gamma(raw: &str) -> Result<f32, String> {
+    parse_clamped(raw, 0.1, 10.0)
+}
+
+fn parse_saturation(raw: &str) -> Result<f32, String> {
+    parse_clamped(raw, 0.0, 4.0)
+}
+
+fn parse_blur_radius(raw: &str) -> Result<f32, String> {
+    parse_clamped(raw, 0.0, 200.0)
+}
+
+/// Parses `COLOR[:strength]`, for `--tint`
+pub fn parse_tint(raw: &str) -> Result<([u8; 3], f32), String> {
+    let (color, strength) = match raw.split_once(':') {
+        Some((color, strength)) => (color, parse_clamped(strength, 0.0, 1.0)?),
+        None => (raw, 0.5),
+    };
+    Ok((parse_color(color)?, strength))
+}
+
+/// Parses either an anchor name or an `x,y` point, for `--crop-focus`
+fn parse_crop_focus(raw: &str) -> Result<(f32, f32), String> {
+    let focus = match raw.trim() {
+        "top-left" => (0.0, 0.0),
+        "top" => (0.5, 0.0),
+        "top-right" => (1.0, 0.0),
+        "left" => (0.0, 0.5),
+        "center" => (0.5, 0.5),
+        "right" => (1.0, 0.5),
+        "bottom-left" => (0.0, 1.0),
+        "bottom" => (0.5, 1.0),
+        "bottom-right" => (1.0, 1.0),
+        point => match point.split_once(',') {
+            Some((x, y)) => (parse_clamped(x, 0.0, 1.0)?, parse_clamped(y, 0.0, 1.0)?),
+            None => {
+                return Err(format!(
+                    "invalid crop focus '{raw}', expected an anchor like 'top-left' or an 'x,y' \
+                     point"
+                ))
+            }
+        },
+    };
+    Ok(focus)
+}
+
+/// `auto` is sent as 0, which tells the daemon to match each output's refresh rate
+fn parse_fps(raw: &str) -> Result<u16, String> {
+    match raw.trim() {
+        "auto" => Ok(0),
+        fps => fps
+            .parse()
+            .map_err(|_| format!("invalid frame rate '{raw}', expected a number or 'auto'")),
+    }
+}
+
+fn parse_playback_speed(raw: &str) -> Result<f32, String> {
+    let speed = raw
+        .trim()
+        .parse::<f32>()
+        .map_err(|e| format!("invalid number '{raw}': {e}"))?;
+    if speed.is_finite() && speed >= 0.05 {
+        Ok(speed)
+    } else {
+        Err(format!("playback speed must be at least 0.05, got '{raw}'"))
+    }
+}
+
+fn parse_dimensions(raw: &str) -> Result<(u32, u32), String> {
+    let parse = |n: &str| match n.trim().parse::<u32>() {
+        Ok(0) => Err("dimensions must be greater than 0".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("invalid dimension '{n}': {e}")),
+    };
+    let (width, height) = raw
+        .split_once('x')
+        .ok_or_else(|| format!("expected dimensions like 1920x1080, got '{raw}'"))?;
+    Ok((parse(width)?, parse(height)?))
 }
 
 fn parse_wave(raw: &str) -> Result<(f32, f32), String> {
@@ -419,9 +1409,20 @@ fn parse_bezier(raw: &str) -> Result<(f32, f32, f32, f32), String> {
     Ok(parsed)
 }
 
+fn parse_gradient(raw: &str) -> Result<Box<[[u8; 3]]>, String> {
+    let colors = raw
+        .split('-')
+        .map(parse_color)
+        .collect::<Result<Box<[_]>, _>>()?;
+    if colors.len() < 2 {
+        return Err("a gradient needs at least two colors, separated by '-'".to_string());
+    }
+    Ok(colors)
+}
+
 pub fn parse_image(raw: &str) -> Result<CliImage, String> {
     let path = PathBuf::from(raw);
-    if raw == "-" || path.exists() {
+    if raw == "-" || path.exists() || is_url(raw) {
         return Ok(CliImage::Path(path));
     }
     if let Some(color) = raw.strip_prefix("0x") {
@@ -432,6 +1433,11 @@ pub fn parse_image(raw: &str) -> Result<CliImage, String> {
     Err(format!("Path '{}' does not exist", raw))
 }
 
+/// Whether the image should be downloaded instead of read from a file
+pub fn is_url(raw: &str) -> bool {
+    raw.starts_with("http://") || raw.starts_with("https://")
+}
+
 // parses Percents and numbers in format of "<coord1>,<coord2>"
 fn parse_coords(raw: &str) -> Result<CliPosition, String> {
     let coords = raw.split(',').map(|s| s.trim()).collect::<Vec<&str>>();
@@ -537,6 +1543,117 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_parse_named_and_short_colors() {
+        assert_eq!(parse_color("1e1e2e"), Ok([0x1e, 0x1e, 0x2e]));
+        assert_eq!(parse_color("#1e1e2e"), Ok([0x1e, 0x1e, 0x2e]));
+        assert_eq!(parse_color("#f80"), Ok([0xff, 0x88, 0x00]));
+        assert_eq!(parse_color("RebeccaPurple"), Ok([0x66, 0x33, 0x99]));
+        assert_eq!(parse_color(" white "), Ok([0xff; 3]));
+
+        // the alpha is blended over black
+        assert_eq!(parse_color("#1e1e2eff"), Ok([0x1e, 0x1e, 0x2e]));
+        assert_eq!(parse_color("#ff804000"), Ok([0, 0, 0]));
+        assert_eq!(parse_color("#ff804080"), Ok([128, 64, 32]));
+        assert_eq!(parse_color("f008"), Ok([136, 0, 0]));
+
+        // whatever `from_hex` accepted before still works
+        assert_eq!(parse_color("1e,1e,2e"), Ok([0x1e, 0x1e, 0x2e]));
+
+        for wrong in [
+            "",
+            "#12",
+            "12345",
+            "1234567",
+            "123456789",
+            "#ggg",
+            "notacolor",
+        ] {
+            assert!(parse_color(wrong).is_err(), "accepted '{wrong}'");
+        }
+
+        assert_eq!(
+            parse_gradient("navy-#fff"),
+            Ok(vec![[0, 0, 0x80], [0xff; 3]].into())
+        );
+        assert_eq!(parse_tint("teal:0.25"), Ok(([0, 0x80, 0x80], 0.25)));
+    }
+
+    #[test]
+    fn should_accept_urls_as_images() {
+        let url = "https://example.com/wallpaper.png";
+        assert!(matches!(parse_image(url), Ok(CliImage::Path(path)) if path.to_str() == Some(url)));
+        assert!(parse_image("ftp://example.com/wallpaper.png").is_err());
+        assert!(matches!(
+            parse_image("0x112233"),
+            Ok(CliImage::Color([0x11, 0x22, 0x33]))
+        ));
+    }
+
+    #[test]
+    fn should_parse_crop_focus() {
+        assert_eq!(parse_crop_focus("center"), Ok((0.5, 0.5)));
+        assert_eq!(parse_crop_focus("bottom-right"), Ok((1.0, 1.0)));
+        assert_eq!(parse_crop_focus("0.25, 0.75"), Ok((0.25, 0.75)));
+        assert_eq!(parse_crop_focus("-2,7"), Ok((0.0, 1.0)));
+        assert!(parse_crop_focus("middle").is_err());
+        assert!(parse_crop_focus("0.5").is_err());
+        assert!(parse_crop_focus("x,0.5").is_err());
+    }
+
+    #[test]
+    fn should_parse_cache_limits() {
+        assert_eq!(parse_age("30d"), Ok(Duration::from_secs(30 * 86400)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_age("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("-1d").is_err());
+
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn should_clamp_color_adjustments() {
+        assert_eq!(parse_brightness("-3"), Ok(-1.0));
+        assert_eq!(parse_brightness("0.25"), Ok(0.25));
+        assert_eq!(parse_contrast("-1"), Ok(0.0));
+        assert_eq!(parse_contrast("100"), Ok(4.0));
+        assert_eq!(parse_gamma("0"), Ok(0.1));
+        assert!(parse_gamma("NaN").is_err());
+        assert!(parse_gamma("bright").is_err());
+    }
+
+    #[test]
+    fn should_parse_tint_with_optional_strength() {
+        assert_eq!(parse_tint("ff8000"), Ok(([255, 128, 0], 0.5)));
+        assert_eq!(parse_tint("#ff8000:0.25"), Ok(([255, 128, 0], 0.25)));
+        assert_eq!(parse_tint("ff8000:3"), Ok(([255, 128, 0], 1.0)));
+        assert!(parse_tint("ff800:0.5").is_err());
+        assert!(parse_tint("ff8000:much").is_err());
+    }
+
+    #[test]
+    fn should_parse_auto_fps_as_zero() {
+        assert_eq!(parse_fps("auto"), Ok(0));
+        assert_eq!(parse_fps("144"), Ok(144));
+        assert!(parse_fps("fast").is_err());
+    }
+
+    #[test]
+    fn should_reject_slow_playback_speeds() {
+        assert_eq!(parse_playback_speed("0.5"), Ok(0.5));
+        assert_eq!(parse_playback_speed("0.05"), Ok(0.05));
+        assert!(parse_playback_speed("0.01").is_err());
+        assert!(parse_playback_speed("-2").is_err());
+        assert!(parse_playback_speed("inf").is_err());
+        assert!(parse_playback_speed("NaN").is_err());
+    }
+
     #[test]
     fn should_convert_colors_from_hex() {
         let color = from_hex("101010").unwrap();
@@ -548,4 +1665,88 @@ mod tests {
         let color = from_hex("000000").unwrap();
         assert_eq!(color, [0, 0, 0]);
     }
+
+    #[test]
+    fn should_parse_gradients() {
+        assert_eq!(
+            parse_gradient("#000000-#222244").unwrap().as_ref(),
+            &[[0, 0, 0], [0x22, 0x22, 0x44]]
+        );
+        assert_eq!(parse_gradient("ff0000-00ff00-0000ff").unwrap().len(), 3);
+        assert!(parse_gradient("000000").is_err());
+        assert!(parse_gradient("000000-").is_err());
+    }
+
+    #[test]
+    fn should_parse_durations() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+    }
+
+    #[test]
+    fn should_parse_dimensions() {
+        assert_eq!(parse_dimensions("1920x1080"), Ok((1920, 1080)));
+        assert!(parse_dimensions("1920").is_err());
+        assert!(parse_dimensions("0x1080").is_err());
+        assert!(parse_dimensions("1920x-1").is_err());
+    }
+
+    #[test]
+    fn should_reject_wrong_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("-3s").is_err());
+        assert!(parse_duration("5d").is_err());
+    }
+
+    #[test]
+    fn should_parse_transitions_per_output_group() {
+        let swww = Swww::try_parse_from([
+            "swww", "img", "0x000000", "-o", "DP-1", "-t", "grow", "-o", "HDMI-A-1", "-t", "wipe",
+        ])
+        .unwrap();
+        let Command::Img(img) = swww.command else {
+            panic!("failed to parse img subcommand");
+        };
+        assert_eq!(img.outputs, ["DP-1", "HDMI-A-1"]);
+        assert!(matches!(
+            img.transition.transition_type[..],
+            [
+                TransitionTypes {
+                    default: TransitionType::Grow,
+                    ..
+                },
+                TransitionTypes {
+                    default: TransitionType::Wipe,
+                    ..
+                }
+            ]
+        ));
+        assert_eq!(img.transition.count(), 2);
+    }
+
+    #[test]
+    fn should_parse_transition_types_per_output() {
+        let types: TransitionTypes = "DP-1:left,fade,HDMI-A-1:right".parse().unwrap();
+        assert!(matches!(types.default, TransitionType::Fade));
+        assert!(matches!(
+            types.for_output("DP-1"),
+            Some(TransitionType::Left)
+        ));
+        assert!(matches!(
+            types.for_output("HDMI-A-1"),
+            Some(TransitionType::Right)
+        ));
+        assert!(types.for_output("eDP-1").is_none());
+
+        let types: TransitionTypes = "DP-1:wipe".parse().unwrap();
+        assert!(matches!(types.default, TransitionType::Simple));
+
+        assert!("fade,grow".parse::<TransitionTypes>().is_err());
+        assert!(":fade".parse::<TransitionTypes>().is_err());
+        assert!("DP-1:sideways".parse::<TransitionTypes>().is_err());
+    }
 }
@@ -1,11 +1,16 @@
-use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+use fast_image_resize::{
+    create_srgb_mapper, FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer,
+};
 use image::{
     codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
-    AnimationDecoder, DynamicImage, Frames, GenericImageView, ImageFormat,
+    metadata::Orientation,
+    AnimationDecoder, DynamicImage, Frame, Frames, GenericImageView, ImageDecoder, ImageFormat,
 };
 use std::{
     io::{stdin, Cursor, Read},
-    path::Path,
+    num::{NonZeroU8, NonZeroUsize},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
     time::Duration,
 };
 
@@ -14,28 +19,70 @@ use common::{
     ipc::{self, Coord, PixelFormat, Position},
 };
 
-use crate::cli::ResizeStrategy;
+use crate::cli::{Resample, ResizeStrategy, Transform};
 
 use super::cli;
 
 pub struct ImgBuf {
     bytes: Box<[u8]>,
-    format: ImageFormat,
+    encoding: Encoding,
     is_animated: bool,
 }
 
+enum Encoding {
+    Format(ImageFormat),
+    /// Raw RGBA pixels, with the image's width and height
+    Rgba(u32, u32),
+}
+
 impl ImgBuf {
-    /// Create a new ImgBuf from a given path. Use - for Stdin
+    /// Create a new ImgBuf from a given path. Use - for Stdin, or an http(s) url to download it
     pub fn new(path: &Path) -> Result<Self, String> {
         let bytes = if let Some("-") = path.to_str() {
-            let mut bytes = Vec::new();
-            stdin()
-                .read_to_end(&mut bytes)
-                .map_err(|e| format!("failed to read standard input: {e}"))?;
-            bytes
+            read_stdin()?
+        } else if let Some(url) = path.to_str().filter(|path| cli::is_url(path)) {
+            download(url)?
         } else {
             std::fs::read(path).map_err(|e| format!("failed to read file: {e}"))?
         };
+        Self::from_bytes(bytes)
+    }
+
+    /// Create a new ImgBuf from the raw RGBA pixels in Stdin, which must be exactly as many as
+    /// `dim` needs
+    pub fn from_raw_stdin(dim: (u32, u32)) -> Result<Self, String> {
+        Self::from_raw_bytes(read_stdin()?, dim)
+    }
+
+    fn from_raw_bytes(bytes: Vec<u8>, dim: (u32, u32)) -> Result<Self, String> {
+        let expected = dim.0 as usize * dim.1 as usize * 4;
+        if bytes.len() != expected {
+            return Err(format!(
+                "expected {expected} bytes of raw RGBA pixels for a {}x{} image, but got {}",
+                dim.0,
+                dim.1,
+                bytes.len()
+            ));
+        }
+        Ok(Self {
+            bytes: bytes.into_boxed_slice(),
+            encoding: Encoding::Rgba(dim.0, dim.1),
+            is_animated: false,
+        })
+    }
+
+    /// Create a new ImgBuf from the encoded image's bytes
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self, String> {
+        // the image crate cannot decode JPEG XL, so we have it converted to png first
+        if is_jxl(&bytes) {
+            #[cfg(feature = "jxl")]
+            return Self::from_bytes(decode_jxl(&bytes)?);
+            #[cfg(not(feature = "jxl"))]
+            return Err(
+                "JPEG XL images are only supported when swww is built with the `jxl` feature"
+                    .to_string(),
+            );
+        }
 
         let reader = image::ImageReader::new(Cursor::new(&bytes))
             .with_guessed_format()
@@ -56,7 +103,8 @@ impl ImgBuf {
         };
 
         Ok(Self {
-            format: format.unwrap(), // this is ok because we return err earlier if it is None
+            // this is ok because we return err earlier if it is None
+            encoding: Encoding::Format(format.unwrap()),
             bytes: bytes.into_boxed_slice(),
             is_animated,
         })
@@ -67,12 +115,31 @@ impl ImgBuf {
     }
 
     /// Decode the ImgBuf into am RgbImage
-    pub fn decode(&self, format: PixelFormat) -> Result<Image, String> {
-        let mut reader = image::ImageReader::new(Cursor::new(&self.bytes));
-        reader.set_format(self.format);
-        let dynimage = reader
-            .decode()
-            .map_err(|e| format!("failed to decode image: {e}"))?;
+    ///
+    /// Unless `ignore_exif` is set, the image is rotated and flipped the way its EXIF orientation
+    /// tag says it should be displayed, so the returned dimensions may be swapped
+    pub fn decode(&self, format: PixelFormat, ignore_exif: bool) -> Result<Image, String> {
+        let dynimage = match self.encoding {
+            Encoding::Format(image_format) => {
+                let mut reader = image::ImageReader::new(Cursor::new(&self.bytes));
+                reader.set_format(image_format);
+                let mut decoder = reader
+                    .into_decoder()
+                    .map_err(|e| format!("failed to decode image: {e}"))?;
+                let orientation = match decoder.orientation() {
+                    Ok(orientation) if !ignore_exif => orientation,
+                    _ => Orientation::NoTransforms,
+                };
+                let mut dynimage = DynamicImage::from_decoder(decoder)
+                    .map_err(|e| format!("failed to decode image: {e}"))?;
+                dynimage.apply_orientation(orientation);
+                dynimage
+            }
+            // we checked the length when reading the pixels
+            Encoding::Rgba(width, height) => image::DynamicImage::ImageRgba8(
+                image::RgbaImage::from_raw(width, height, self.bytes.to_vec()).unwrap(),
+            ),
+        };
 
         let width = dynimage.width();
         let height = dynimage.height();
@@ -102,7 +169,10 @@ impl ImgBuf {
 
     /// Convert this ImgBuf into Frames
     pub fn as_frames(&self) -> Result<Frames, String> {
-        match self.format {
+        let Encoding::Format(format) = self.encoding else {
+            return Err("raw pixels cannot be animated".to_string());
+        };
+        match format {
             ImageFormat::Gif => Ok(GifDecoder::new(Cursor::new(&self.bytes))
                 .map_err(|e| format!("failed to decode gif during animation: {e}"))?
                 .into_frames()),
@@ -114,12 +184,81 @@ impl ImgBuf {
                 .apng()
                 .unwrap() // we detected this earlier
                 .into_frames()),
-            _ => Err(format!(
-                "requested format has no decoder: {:#?}",
-                self.format
-            )),
+            _ => Err(format!("requested format has no decoder: {format:#?}")),
+        }
+    }
+}
+
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    stdin()
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("failed to read standard input: {e}"))?;
+    Ok(bytes)
+}
+
+/// How long downloading an image may take, in seconds
+#[cfg(feature = "url")]
+const DOWNLOAD_TIMEOUT: u32 = 60;
+/// How big a downloaded image may be, in bytes
+#[cfg(feature = "url")]
+const DOWNLOAD_MAX_SIZE: usize = 256 * 1024 * 1024;
+
+/// Downloads an image with `curl`
+#[cfg(feature = "url")]
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    let output = std::process::Command::new("curl")
+        .args(["--silent", "--show-error", "--location", "--max-time"])
+        .arg(DOWNLOAD_TIMEOUT.to_string())
+        .arg("--max-filesize")
+        .arg(DOWNLOAD_MAX_SIZE.to_string())
+        // the body goes to stdout, so we get the status after any error on stderr
+        .args(["--write-out", "%{stderr}\n%{http_code} %{content_type}"])
+        .arg(url)
+        .output()
+        .map_err(|e| format!("failed to run curl, which downloads images: {e}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let (error, status) = stderr.rsplit_once('\n').unwrap_or(("", &stderr));
+    match output.status.code() {
+        Some(0) => (),
+        Some(28) => return Err(format!("downloading {url} took over {DOWNLOAD_TIMEOUT}s")),
+        Some(63) => {
+            return Err(format!(
+                "{url} is larger than the {}MiB swww is willing to download",
+                DOWNLOAD_MAX_SIZE / (1024 * 1024)
+            ))
         }
+        _ => return Err(format!("failed to download {url}: {}", error.trim())),
+    }
+
+    let (code, content_type) = status.split_once(' ').unwrap_or((status, ""));
+    if code != "200" {
+        return Err(format!(
+            "failed to download {url}: the server answered with HTTP {code}"
+        ));
+    }
+    // servers that don't know better send images as generic binary data
+    let content_type = content_type.trim();
+    if !content_type.is_empty()
+        && !content_type.starts_with("image/")
+        && !content_type.starts_with("application/octet-stream")
+    {
+        return Err(format!("{url} is not an image, but {content_type}"));
+    }
+    if output.stdout.len() > DOWNLOAD_MAX_SIZE {
+        return Err(format!(
+            "{url} is larger than the {}MiB swww is willing to download",
+            DOWNLOAD_MAX_SIZE / (1024 * 1024)
+        ));
     }
+    Ok(output.stdout)
+}
+
+#[cfg(not(feature = "url"))]
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "cannot download {url}: downloading images needs swww to be built with the `url` feature"
+    ))
 }
 
 /// Created by decoding an ImgBuf
@@ -130,6 +269,45 @@ pub struct Image {
     bytes: Box<[u8]>,
 }
 
+/// JPEG XL files either start with a bare codestream, or are wrapped in an ISOBMFF container
+fn is_jxl(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0xff, 0x0a])
+        || bytes.starts_with(&[
+            0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+        ])
+}
+
+/// Converts a JPEG XL image to png with `djxl`, which reads and writes `-` as stdin and stdout
+/// since libjxl 0.9
+#[cfg(feature = "jxl")]
+fn decode_jxl(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("djxl")
+        .args(["-", "-", "--output_format", "png", "--quiet"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run djxl, which decodes JPEG XL images: {e}"))?;
+    let mut stdin = child.stdin.take().unwrap();
+    // djxl only starts writing once it has read everything, but the png may not fit in the pipe,
+    // so we have to read it while still writing
+    let output = std::thread::scope(|s| {
+        s.spawn(move || stdin.write_all(bytes));
+        child.wait_with_output()
+    })
+    .map_err(|e| format!("failed to wait for djxl: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "djxl failed to decode the JPEG XL image: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
 impl Image {
     #[must_use]
     fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Self {
@@ -165,8 +343,8 @@ impl Image {
         // NOTE: when animating frames, we ALWAYS use 3 channels
 
         let format = match format {
-            PixelFormat::Bgr | PixelFormat::Xbgr => PixelFormat::Bgr,
-            PixelFormat::Rgb | PixelFormat::Xrgb => PixelFormat::Rgb,
+            PixelFormat::Bgr | PixelFormat::Xbgr | PixelFormat::Xbgr2101010 => PixelFormat::Bgr,
+            PixelFormat::Rgb | PixelFormat::Xrgb | PixelFormat::Xrgb2101010 => PixelFormat::Rgb,
         };
 
         let mut bytes = dynimage.into_rgb8().into_raw().into_boxed_slice();
@@ -185,73 +363,448 @@ impl Image {
     }
 }
 
+/// Inversion, brightness, contrast, gamma, saturation and tint adjustments, applied to images
+/// before resizing them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorAdjustment {
+    /// turns the image into its negative, before any other adjustment
+    pub invert: bool,
+    /// added to every channel, in `-1.0..=1.0`
+    pub brightness: f32,
+    /// how far colors are pushed away from middle gray
+    pub contrast: f32,
+    /// values above 1.0 brighten the dark parts of the image
+    pub gamma: f32,
+    /// how far colors are pushed away from the gray of the same luminance
+    pub saturation: f32,
+    /// turns the image gray, whatever `saturation` says
+    pub grayscale: bool,
+    /// a color to blend every pixel towards, and how much of it to blend in, from 0.0 to 1.0
+    pub tint: Option<([u8; 3], f32)>,
+    /// whether to dither brightness, contrast and gamma instead of rounding them, which hides the
+    /// banding they cause when stretching smooth gradients
+    pub dither: bool,
+}
+
+/// The thresholds of an 8x8 ordered dither. Unlike random dithering, they only depend on the
+/// pixel's position, so the parts animation frames have in common still come out the same
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// What to add to the pixel at `(x, y)` before rounding it, from -0.5 to 0.5
+#[inline]
+fn dither_offset(x: usize, y: usize) -> f32 {
+    (BAYER_8X8[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            invert: false,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+            grayscale: false,
+            tint: None,
+            dither: false,
+        }
+    }
+}
+
+impl ColorAdjustment {
+    /// Whether applying this adjustment would leave images untouched. Dithering alone does not
+    /// count, since there is nothing to dither without the other adjustments
+    pub fn is_neutral(&self) -> bool {
+        Self {
+            dither: false,
+            ..*self
+        } == Self::default()
+    }
+
+    /// Since the adjustment is the same for every channel, we compute it once for every possible
+    /// channel value. The values are left unrounded, from 0.0 to 255.0
+    fn curve(&self) -> [f32; 256] {
+        let mut curve = [0.0; 256];
+        for (i, value) in curve.iter_mut().enumerate() {
+            let v = i as f32 / 255.0;
+            let v = if self.invert { 1.0 - v } else { v };
+            let v = v.powf(1.0 / self.gamma);
+            let v = (v - 0.5) * self.contrast + 0.5 + self.brightness;
+            *value = v.clamp(0.0, 1.0) * 255.0;
+        }
+        curve
+    }
+
+    fn lookup_table(&self) -> [u8; 256] {
+        self.curve().map(|v| v.round() as u8)
+    }
+
+    /// Whether the adjustment has parts that mix the channels of each pixel together, which the
+    /// lookup table cannot do
+    fn mixes_channels(&self) -> bool {
+        self.grayscale || self.saturation != 1.0 || self.tint.is_some()
+    }
+
+    /// Applies the saturation and tint to `pixel`. The first three bytes must be in rgb order,
+    /// unless `swapped` is set, in which case they are in bgr order
+    #[inline]
+    fn mix_channels(&self, pixel: &mut [u8], swapped: bool) {
+        let (r, b) = if swapped { (2, 0) } else { (0, 2) };
+        let saturation = if self.grayscale { 0.0 } else { self.saturation };
+        if saturation != 1.0 {
+            let luma = 0.299 * pixel[r] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[b] as f32;
+            for channel in &mut pixel[0..3] {
+                let v = luma + (*channel as f32 - luma) * saturation;
+                *channel = v.clamp(0.0, 255.0).round() as u8;
+            }
+        }
+        if let Some((color, strength)) = self.tint {
+            let color = [color[r], color[1], color[b]];
+            for (channel, tint) in pixel[0..3].iter_mut().zip(color) {
+                let v = *channel as f32 + (tint as f32 - *channel as f32) * strength;
+                *channel = v.clamp(0.0, 255.0).round() as u8;
+            }
+        }
+    }
+
+    /// Applies the adjustment to a single rgb color
+    pub fn adjust_color(&self, color: [u8; 3]) -> [u8; 3] {
+        let table = self.lookup_table();
+        let mut color = color.map(|channel| table[channel as usize]);
+        if self.mixes_channels() {
+            self.mix_channels(&mut color, false);
+        }
+        color
+    }
+
+    /// The `swww img` arguments that reproduce this adjustment, for the cache to remember it.
+    /// Neutral adjustments need none, so they leave the cache entry the way it always was
+    pub fn cache_args(&self) -> String {
+        let default = Self::default();
+        [
+            ("brightness", self.brightness, default.brightness),
+            ("contrast", self.contrast, default.contrast),
+            ("gamma", self.gamma, default.gamma),
+            ("saturation", self.saturation, default.saturation),
+        ]
+        .into_iter()
+        .filter(|(_, value, default)| value != default)
+        .map(|(name, value, _)| format!(" --{name}={value}"))
+        .chain(self.invert.then(|| " --invert".to_string()))
+        .chain(self.grayscale.then(|| " --grayscale".to_string()))
+        .chain(self.dither.then(|| " --dither".to_string()))
+        .chain(self.tint.map(|(color, strength)| {
+            format!(
+                " --tint={:02x}{:02x}{:02x}:{strength}",
+                color[0], color[1], color[2]
+            )
+        }))
+        .collect()
+    }
+}
+
+impl From<&cli::Img> for ColorAdjustment {
+    fn from(img: &cli::Img) -> Self {
+        Self {
+            invert: img.invert,
+            brightness: img.brightness,
+            contrast: img.contrast,
+            gamma: img.gamma,
+            saturation: img.saturation,
+            grayscale: img.grayscale,
+            tint: img.tint,
+            dither: img.dither,
+        }
+    }
+}
+
+impl Image {
+    /// Applies `adjustment` to the color channels, leaving the alpha channel alone
+    pub fn adjust_colors(&mut self, adjustment: &ColorAdjustment) {
+        if adjustment.is_neutral() {
+            return;
+        }
+
+        let table = adjustment.lookup_table();
+        let curve = adjustment.dither.then(|| adjustment.curve());
+        let mix = adjustment.mixes_channels();
+        let swapped = self.format.must_swap_r_and_b_channels();
+        let width = self.width as usize;
+        let pixels = self.bytes.chunks_exact_mut(self.format.channels() as usize);
+        for (i, pixel) in pixels.enumerate() {
+            match &curve {
+                Some(curve) => {
+                    let offset = dither_offset(i % width, i / width);
+                    for channel in &mut pixel[0..3] {
+                        *channel = (curve[*channel as usize] + offset)
+                            .round()
+                            .clamp(0.0, 255.0) as u8;
+                    }
+                }
+                None => {
+                    for channel in &mut pixel[0..3] {
+                        *channel = table[*channel as usize];
+                    }
+                }
+            }
+            if mix {
+                adjustment.mix_channels(pixel, swapped);
+            }
+        }
+    }
+
+    /// Flips the image around its vertical axis and then rotates it counter-clockwise, as
+    /// `transform` says, the same way the compositor would for an output with that transform
+    pub fn transform(&mut self, transform: Transform) {
+        if transform == Transform::Normal {
+            return;
+        }
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (new_width, new_height) = if transform.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let flipped = matches!(
+            transform,
+            Transform::Flipped
+                | Transform::Flipped90
+                | Transform::Flipped180
+                | Transform::Flipped270
+        );
+
+        let channels = self.format.channels() as usize;
+        let mut bytes = Vec::with_capacity(self.bytes.len());
+        for y in 0..new_height {
+            for x in 0..new_width {
+                // where the pixel was in the flipped image, before rotating it
+                let (src_x, src_y) = match transform {
+                    Transform::Rotate90 | Transform::Flipped90 => (width - 1 - y, x),
+                    Transform::Rotate180 | Transform::Flipped180 => (width - 1 - x, height - 1 - y),
+                    Transform::Rotate270 | Transform::Flipped270 => (y, height - 1 - x),
+                    Transform::Normal | Transform::Flipped => (x, y),
+                };
+                let src_x = if flipped { width - 1 - src_x } else { src_x };
+                let begin = (src_y * width + src_x) * channels;
+                bytes.extend_from_slice(&self.bytes[begin..begin + channels]);
+            }
+        }
+
+        self.width = new_width as u32;
+        self.height = new_height as u32;
+        self.bytes = bytes.into_boxed_slice();
+    }
+}
+
+/// How many frames each worker gets at a time in `compress_frames`. Bigger batches keep the workers
+/// busier, at the cost of holding more frames in memory at once.
+///
+/// A single 4K frame already takes longer to compress than spawning the workers, so there is
+/// little to gain from more than this, while at 4K every extra frame costs about 25MB
+const FRAMES_PER_WORKER: usize = 2;
+
+/// How many animation frames `compress_frames` processed so far, over every call, so that another
+/// thread can show how far along it is
+static FRAMES_PROCESSED: AtomicUsize = AtomicUsize::new(0);
+
+/// How many animation frames `compress_frames` processed so far. Only differences between two calls
+/// are meaningful
+pub fn frames_processed() -> usize {
+    FRAMES_PROCESSED.load(Ordering::Relaxed)
+}
+
+/// How many threads `compress_frames` should use by default
+pub fn default_compression_workers() -> NonZeroUsize {
+    std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+}
+
+/// Compresses the animation frames, stopping at the first frame that fails to decode
+///
+/// Frames are decoded in batches, which are then resized and compressed using up to `workers`
+/// threads. The result is the same whatever the number of workers.
+///
+/// Returns an empty `Vec` if less than two frames could be decoded, in which case the image should
+/// be displayed as a static image instead
+#[allow(clippy::too_many_arguments)]
 pub fn compress_frames(
     mut frames: Frames,
     dim: (u32, u32),
     format: PixelFormat,
     filter: FilterType,
+    resample: Resample,
     resize: ResizeStrategy,
+    crop_focus: (f32, f32),
     color: &[u8; 3],
+    fit_blur: Option<f32>,
+    adjustment: &ColorAdjustment,
+    transform: Transform,
+    compression_level: u8,
+    workers: NonZeroUsize,
 ) -> Result<Vec<(BitPack, Duration)>, String> {
-    let mut compressor = Compressor::new();
-    let mut compressed_frames = Vec::new();
-
-    // The first frame should always exist
-    let first = frames.next().unwrap().unwrap();
-    let first_duration = first.delay().numer_denom_ms();
-    let mut first_duration = Duration::from_millis((first_duration.0 / first_duration.1).into());
-    let first_img = Image::from_frame(first, format);
-    let first_img = match resize {
-        ResizeStrategy::No => img_pad(&first_img, dim, color)?,
-        ResizeStrategy::Crop => img_resize_crop(&first_img, dim, filter)?,
-        ResizeStrategy::Fit => img_resize_fit(&first_img, dim, filter, color)?,
-        ResizeStrategy::Stretch => img_resize_stretch(&first_img, dim, filter)?,
-    };
-
-    let mut canvas: Option<Box<[u8]>> = None;
-    while let Some(Ok(frame)) = frames.next() {
+    let workers = workers.get();
+    let prepare = |frame: Frame| -> Result<(Box<[u8]>, Duration), String> {
         let (dur_num, dur_div) = frame.delay().numer_denom_ms();
         let duration = Duration::from_millis((dur_num / dur_div).into());
 
-        let img = Image::from_frame(frame, format);
+        let mut img = Image::from_frame(frame, format);
+        img.adjust_colors(adjustment);
+        img.transform(transform);
         let img = match resize {
             ResizeStrategy::No => img_pad(&img, dim, color)?,
-            ResizeStrategy::Crop => img_resize_crop(&img, dim, filter)?,
-            ResizeStrategy::Fit => img_resize_fit(&img, dim, filter, color)?,
-            ResizeStrategy::Stretch => img_resize_stretch(&img, dim, filter)?,
+            ResizeStrategy::Crop => img_resize_crop(&img, dim, filter, resample, crop_focus)?,
+            ResizeStrategy::Fit => img_resize_fit(&img, dim, filter, resample, color, fit_blur)?,
+            ResizeStrategy::Stretch => img_resize_stretch(&img, dim, filter, resample)?,
+            ResizeStrategy::Tile => img_tile(&img, dim)?,
         };
+        Ok((img, duration))
+    };
 
-        if let Some(canvas) = canvas.as_ref() {
-            match compressor.compress(canvas, &img, format) {
-                Some(bytes) => compressed_frames.push((bytes, duration)),
+    let mut compressed_frames = Vec::new();
+    let mut first: Option<(Box<[u8]>, Duration)> = None;
+    // the last frame of the previous batch, which the next batch's first frame diffs against
+    let mut prev: Option<Box<[u8]>> = None;
+    let mut decoded = 0;
+    let mut finished = false;
+    while !finished {
+        let mut batch = Vec::with_capacity(workers * FRAMES_PER_WORKER);
+        while batch.len() < workers * FRAMES_PER_WORKER {
+            // a corrupt frame shouldn't ruin the whole animation, so we just stop at it
+            match frames.next() {
+                Some(Ok(frame)) => batch.push(frame),
+                Some(Err(e)) => {
+                    eprintln!("{}", frame_decode_warning(decoded, &e));
+                    finished = true;
+                    break;
+                }
+                None => {
+                    finished = true;
+                    break;
+                }
+            }
+            decoded += 1;
+        }
+
+        let mut imgs = in_parallel(batch, workers, |frames| {
+            frames.into_iter().map(&prepare).collect()
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>, String>>()?;
+        FRAMES_PROCESSED.fetch_add(imgs.len(), Ordering::Relaxed);
+
+        // If we can't even get the first frame, there is no animation to speak of
+        if first.is_none() {
+            if imgs.is_empty() {
+                return Ok(compressed_frames);
+            }
+            let (img, duration) = imgs.remove(0);
+            prev = Some(img.clone());
+            first = Some((img, duration));
+        }
+        let (Some(prev_img), Some((_, first_duration))) = (&mut prev, &mut first) else {
+            unreachable!("the first frame was set above");
+        };
+        if imgs.is_empty() {
+            continue;
+        }
+
+        // frame `i` diffs against frame `i - 1`, so each worker needs its own compressor, but
+        // otherwise they can go in any order
+        let diffs = in_parallel((0..imgs.len()).collect(), workers, |indices| {
+            let mut compressor = Compressor::with_level(compression_level);
+            indices
+                .into_iter()
+                .map(|i| {
+                    let prev = if i == 0 {
+                        &prev_img[..]
+                    } else {
+                        &imgs[i - 1].0
+                    };
+                    compressor.compress(prev, &imgs[i].0, format)
+                })
+                .collect()
+        });
+
+        for (bitpack, (_, duration)) in diffs.into_iter().zip(&imgs) {
+            match bitpack {
+                Some(bytes) => compressed_frames.push((bytes, *duration)),
                 None => match compressed_frames.last_mut() {
-                    Some(last) => last.1 += duration,
-                    None => first_duration += duration,
+                    Some(last) => last.1 += *duration,
+                    None => *first_duration += *duration,
                 },
             }
-        } else {
-            match compressor.compress(&first_img, &img, format) {
-                Some(bytes) => compressed_frames.push((bytes, duration)),
-                None => first_duration += duration,
-            }
         }
-        canvas = Some(img);
+        *prev_img = imgs.pop().unwrap().0;
     }
 
     //Add the first frame we got earlier:
-    if let Some(canvas) = canvas.as_ref() {
-        match compressor.compress(canvas, &first_img, format) {
-            Some(bytes) => compressed_frames.push((bytes, first_duration)),
-            None => match compressed_frames.last_mut() {
-                Some(last) => last.1 += first_duration,
-                None => first_duration += first_duration,
-            },
+    if let (Some(canvas), Some((first_img, first_duration))) = (prev, first) {
+        if decoded > 1 {
+            match Compressor::with_level(compression_level).compress(&canvas, &first_img, format) {
+                Some(bytes) => compressed_frames.push((bytes, first_duration)),
+                None => {
+                    if let Some(last) = compressed_frames.last_mut() {
+                        last.1 += first_duration;
+                    }
+                }
+            }
         }
     }
 
     Ok(compressed_frames)
 }
 
+/// Splits `items` into up to `workers` contiguous chunks, and runs `f` on each of them in its own
+/// thread. The results come back in the same order as the items
+fn in_parallel<T: Send, U: Send>(
+    items: Vec<T>,
+    workers: usize,
+    f: impl Fn(Vec<T>) -> Vec<U> + Sync,
+) -> Vec<U> {
+    let chunk_len = items.len().div_ceil(workers).max(1);
+    if items.len() <= chunk_len {
+        return f(items);
+    }
+
+    let mut chunks = Vec::with_capacity(workers);
+    let mut items = items.into_iter();
+    loop {
+        let chunk: Vec<T> = items.by_ref().take(chunk_len).collect();
+        if chunk.is_empty() {
+            break;
+        }
+        chunks.push(chunk);
+    }
+
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || f(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("frame worker panicked"))
+            .collect()
+    })
+}
+
+fn frame_decode_warning(frame: usize, error: &image::ImageError) -> String {
+    format!(
+        "WARNING: failed to decode frame {frame} of the animation: {error}. \
+         Keeping only the {frame} frame(s) before it"
+    )
+}
+
 pub fn make_filter(filter: &cli::Filter) -> fast_image_resize::FilterType {
     match filter {
         cli::Filter::Nearest => fast_image_resize::FilterType::Box,
@@ -323,21 +876,77 @@ pub fn img_pad(img: &Image, dimensions: (u32, u32), color: &[u8; 3]) -> Result<B
     Ok(padded.into_boxed_slice())
 }
 
+/// Resizes the whole of `img` to `dimensions`. With `Resample::Linear`, the image is converted to
+/// 16 bit linear light first, so that the filter averages light instead of sRGB values
+fn resize(
+    img: &Image,
+    dimensions: (u32, u32),
+    options: &ResizeOptions,
+    resample: Resample,
+) -> Result<Box<[u8]>, String> {
+    let (pixel_type, linear_type) = if img.format.channels() == 3 {
+        (PixelType::U8x3, PixelType::U16x3)
+    } else {
+        (PixelType::U8x4, PixelType::U16x4)
+    };
+    let src = fast_image_resize::images::ImageRef::new(
+        img.width,
+        img.height,
+        img.bytes.as_ref(),
+        pixel_type,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let (width, height) = dimensions;
+    let mut dst = fast_image_resize::images::Image::new(width, height, pixel_type);
+    let mut resizer = Resizer::new();
+    match resample {
+        Resample::Srgb => resizer
+            .resize(&src, &mut dst, Some(options))
+            .map_err(|e| e.to_string())?,
+        Resample::Linear => {
+            let mapper = create_srgb_mapper();
+            let mut linear_src =
+                fast_image_resize::images::Image::new(img.width, img.height, linear_type);
+            mapper
+                .forward_map(&src, &mut linear_src)
+                .map_err(|e| e.to_string())?;
+            let mut linear_dst = fast_image_resize::images::Image::new(width, height, linear_type);
+            resizer
+                .resize(&linear_src, &mut linear_dst, Some(options))
+                .map_err(|e| e.to_string())?;
+            mapper
+                .backward_map(&linear_dst, &mut dst)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(dst.into_vec().into_boxed_slice())
+}
+
 /// Resize an image to fit within the given dimensions, covering as much space as possible without
 /// cropping.
+///
+/// The space left around it is filled with `padding_color`, or, if `blur` is set, with the image
+/// itself stretched over all of `dimensions` and blurred by that radius
 pub fn img_resize_fit(
     img: &Image,
     dimensions: (u32, u32),
     filter: FilterType,
+    resample: Resample,
     padding_color: &[u8; 3],
+    blur: Option<f32>,
 ) -> Result<Box<[u8]>, String> {
     let (width, height) = dimensions;
-    if (img.width, img.height) != (width, height) {
-        // if our image is already scaled to fit, skip resizing it and just pad it directly
-        if img.width == width || img.height == height {
-            return img_pad(img, dimensions, padding_color);
-        }
+    if (img.width, img.height) == (width, height) {
+        return Ok(img.bytes.clone());
+    }
 
+    let resized;
+    // if our image is already scaled to fit, skip resizing it and just pad it directly
+    let fitted = if img.width == width || img.height == height {
+        img
+    } else {
         let ratio = width as f32 / height as f32;
         let img_r = img.width as f32 / img.height as f32;
 
@@ -349,38 +958,66 @@ pub fn img_resize_fit(
             (width, (img.height as f32 * scale) as u32)
         };
 
-        let pixel_type = if img.format.channels() == 3 {
-            PixelType::U8x3
-        } else {
-            PixelType::U8x4
-        };
-        let src = match fast_image_resize::images::ImageRef::new(
-            img.width,
-            img.height,
-            img.bytes.as_ref(),
-            pixel_type,
-        ) {
-            Ok(i) => i,
-            Err(e) => return Err(e.to_string()),
-        };
-
-        let mut dst = fast_image_resize::images::Image::new(trg_w, trg_h, pixel_type);
-        let mut resizer = Resizer::new();
         let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(filter));
-
-        if let Err(e) = resizer.resize(&src, &mut dst, Some(&options)) {
-            return Err(e.to_string());
-        }
-
-        let img = Image {
+        resized = Image {
             width: trg_w,
             height: trg_h,
             format: img.format,
-            bytes: dst.into_vec().into_boxed_slice(),
+            bytes: resize(img, (trg_w, trg_h), &options, resample)?,
         };
-        img_pad(&img, dimensions, padding_color)
+        &resized
+    };
+
+    match blur {
+        None => img_pad(fitted, dimensions, padding_color),
+        Some(radius) => {
+            let mut background = blurred_background(img, dimensions, radius)?;
+            img_overlay(&mut background, dimensions, fitted);
+            Ok(background)
+        }
+    }
+}
+
+/// Stretches `img` over the whole of `dimensions` and blurs it, for `--fit-fill blur`
+///
+/// The details all get blurred away, so this always uses the fastest filter
+fn blurred_background(
+    img: &Image,
+    dimensions: (u32, u32),
+    radius: f32,
+) -> Result<Box<[u8]>, String> {
+    let stretched = img_resize_stretch(img, dimensions, FilterType::Bilinear, Resample::Srgb)?;
+    if radius < 1.0 {
+        return Ok(stretched);
+    }
+
+    let (width, height) = dimensions;
+    let malformed = || "stretched image has the wrong size".to_string();
+    let blurred = if img.format.channels() == 3 {
+        let buf =
+            image::RgbImage::from_raw(width, height, stretched.into_vec()).ok_or_else(malformed)?;
+        image::imageops::fast_blur(&buf, radius).into_raw()
     } else {
-        Ok(img.bytes.clone())
+        let buf = image::RgbaImage::from_raw(width, height, stretched.into_vec())
+            .ok_or_else(malformed)?;
+        image::imageops::fast_blur(&buf, radius).into_raw()
+    };
+    Ok(blurred.into_boxed_slice())
+}
+
+/// Copies `img` onto the middle of `canvas`, cropping it evenly from both sides if it is larger
+fn img_overlay(canvas: &mut [u8], dimensions: (u32, u32), img: &Image) {
+    let channels = img.format.channels() as usize;
+    let (canvas_w, canvas_h) = (dimensions.0 as usize, dimensions.1 as usize);
+    let (img_w, img_h) = (img.width as usize, img.height as usize);
+    let (w, h) = (img_w.min(canvas_w), img_h.min(canvas_h));
+    let (src_x, src_y) = ((img_w - w) / 2, (img_h - h) / 2);
+    let (dst_x, dst_y) = ((canvas_w - w) / 2, (canvas_h - h) / 2);
+
+    for row in 0..h {
+        let src = ((src_y + row) * img_w + src_x) * channels;
+        let dst = ((dst_y + row) * canvas_w + dst_x) * channels;
+        canvas[dst..dst + w * channels].copy_from_slice(&img.bytes[src..src + w * channels]);
     }
 }
 
@@ -388,86 +1025,381 @@ pub fn img_resize_stretch(
     img: &Image,
     dimensions: (u32, u32),
     filter: FilterType,
+    resample: Resample,
 ) -> Result<Box<[u8]>, String> {
-    let (width, height) = dimensions;
-    let resized_img = if (img.width, img.height) != (width, height) {
-        let pixel_type = if img.format.channels() == 3 {
-            PixelType::U8x3
-        } else {
-            PixelType::U8x4
-        };
+    if (img.width, img.height) == dimensions {
+        return Ok(img.bytes.clone());
+    }
+    let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(filter));
+    resize(img, dimensions, &options, resample)
+}
 
-        let src = match fast_image_resize::images::ImageRef::new(
-            img.width,
-            img.height,
-            img.bytes.as_ref(),
-            pixel_type,
-        ) {
-            Ok(i) => i,
-            Err(e) => return Err(e.to_string()),
-        };
+/// Resizes the image to cover `dimensions`, cropping out whatever doesn't fit
+///
+/// `focus` is the point of the image, from `(0, 0)` at its top left corner to `(1, 1)` at its
+/// bottom right one, that the crop keeps in place: `(0.5, 0.5)` crops evenly from both sides.
+pub fn img_resize_crop(
+    img: &Image,
+    dimensions: (u32, u32),
+    filter: FilterType,
+    resample: Resample,
+    focus: (f32, f32),
+) -> Result<Box<[u8]>, String> {
+    if (img.width, img.height) == dimensions {
+        return Ok(img.bytes.clone());
+    }
+    let options = ResizeOptions::new()
+        .resize_alg(ResizeAlg::Convolution(filter))
+        .fit_into_destination(Some((
+            focus.0.clamp(0.0, 1.0).into(),
+            focus.1.clamp(0.0, 1.0).into(),
+        )));
+    resize(img, dimensions, &options, resample)
+}
 
-        let mut dst = fast_image_resize::images::Image::new(width, height, pixel_type);
-        let mut resizer = Resizer::new();
-        let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(filter));
+/// Where an output is in the compositor's layout, in logical pixels: x, y, width and height
+pub type OutputRect = (i32, i32, u32, u32);
 
-        if let Err(e) = resizer.resize(&src, &mut dst, Some(&options)) {
-            return Err(e.to_string());
+/// How to spread an image across several outputs with `swww img --span`
+pub enum Span {
+    /// The name and place in the layout of the output of every group
+    Layout(Vec<(String, OutputRect)>),
+    /// The region of the image to show on every group, as restored from the cache
+    Region([f64; 4]),
+}
+
+impl Span {
+    /// The region of `img` each of the `groups` output groups shows, as `[x, y, width, height]`
+    /// fractions of the image's size
+    pub fn regions(
+        &self,
+        img: &Image,
+        groups: usize,
+        focus: (f32, f32),
+    ) -> Result<Vec<[f64; 4]>, String> {
+        match self {
+            Self::Layout(layout) => span_regions((img.width, img.height), layout, focus),
+            Self::Region(region) => Ok(vec![*region; groups]),
         }
+    }
+}
 
-        dst.into_vec().into_boxed_slice()
-    } else {
-        img.bytes.clone()
-    };
+/// Which part of an image each output shows when spanning it across all of them, as
+/// `[x, y, width, height]` fractions of the image's size
+///
+/// The image covers the bounding box of the outputs, cropping whatever doesn't fit like
+/// `img_resize_crop` does. Outputs in the same place show the same region, but outputs that only
+/// partly overlap make it unclear which part of the image goes where, so they are an error.
+pub fn span_regions(
+    img_dim: (u32, u32),
+    layout: &[(String, OutputRect)],
+    focus: (f32, f32),
+) -> Result<Vec<[f64; 4]>, String> {
+    for (i, (name, a)) in layout.iter().enumerate() {
+        for (other, b) in &layout[i + 1..] {
+            let overlap_x = (a.0 + a.2 as i32).min(b.0 + b.2 as i32) > a.0.max(b.0);
+            let overlap_y = (a.1 + a.3 as i32).min(b.1 + b.3 as i32) > a.1.max(b.1);
+            if overlap_x && overlap_y && a != b {
+                return Err(format!(
+                    "outputs {name} and {other} overlap, so they can't share an image with `--span`"
+                ));
+            }
+        }
+    }
 
-    Ok(resized_img)
+    let left = layout.iter().map(|(_, r)| r.0).min().unwrap_or(0);
+    let top = layout.iter().map(|(_, r)| r.1).min().unwrap_or(0);
+    let right = layout
+        .iter()
+        .map(|(_, r)| r.0 + r.2 as i32)
+        .max()
+        .unwrap_or(0);
+    let bottom = layout
+        .iter()
+        .map(|(_, r)| r.1 + r.3 as i32)
+        .max()
+        .unwrap_or(0);
+    let (width, height) = ((right - left) as f64, (bottom - top) as f64);
+    let (img_w, img_h) = (img_dim.0 as f64, img_dim.1 as f64);
+
+    // how many layout pixels each pixel of the image takes, once it covers the whole layout
+    let scale = (width / img_w).max(height / img_h);
+    let crop_x = (img_w - width / scale) * focus.0.clamp(0.0, 1.0) as f64;
+    let crop_y = (img_h - height / scale) * focus.1.clamp(0.0, 1.0) as f64;
+    Ok(layout
+        .iter()
+        .map(|(_, (x, y, w, h))| {
+            [
+                (crop_x + (x - left) as f64 / scale) / img_w,
+                (crop_y + (y - top) as f64 / scale) / img_h,
+                *w as f64 / scale / img_w,
+                *h as f64 / scale / img_h,
+            ]
+        })
+        .collect())
 }
 
-pub fn img_resize_crop(
+/// Resizes `region` of the image, given as `[x, y, width, height]` fractions of its size, to
+/// `dimensions`
+pub fn img_resize_region(
     img: &Image,
     dimensions: (u32, u32),
     filter: FilterType,
+    resample: Resample,
+    region: [f64; 4],
 ) -> Result<Box<[u8]>, String> {
-    let (width, height) = dimensions;
-    let resized_img = if (img.width, img.height) != (width, height) {
-        let pixel_type = if img.format.channels() == 3 {
-            PixelType::U8x3
+    let (width, height) = (img.width as f64, img.height as f64);
+    let options = ResizeOptions::new()
+        .resize_alg(ResizeAlg::Convolution(filter))
+        .crop(
+            region[0] * width,
+            region[1] * height,
+            region[2] * width,
+            region[3] * height,
+        );
+    resize(img, dimensions, &options, resample)
+}
+
+/// What the cache stores to remember the region of the image an output shows
+pub fn region_cache_arg(region: [f64; 4]) -> String {
+    let [x, y, width, height] = region;
+    format!(" --region={x},{y},{width},{height}")
+}
+
+/// Parses what `region_cache_arg` made, if it is among `args`
+pub fn region_from_cache_args<'a>(mut args: impl Iterator<Item = &'a str>) -> Option<[f64; 4]> {
+    let region = args.find_map(|arg| arg.strip_prefix("--region="))?;
+    let values: Vec<f64> = region
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    values.try_into().ok()
+}
+
+/// Repeats an image across the given dimensions, starting at the top left corner, without
+/// resizing it
+///
+/// Tiles that do not fit in the bottom and right edges are cut short, so an image larger than
+/// `dimensions` simply ends up cropped to it.
+pub fn img_tile(img: &Image, dimensions: (u32, u32)) -> Result<Box<[u8]>, String> {
+    if img.width == 0 || img.height == 0 {
+        return Err("cannot tile an empty image".to_string());
+    }
+
+    let channels = img.format.channels() as usize;
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    let (img_w, img_h) = (img.width as usize, img.height as usize);
+    let row_len = width * channels;
+    let mut tiled = Vec::with_capacity(height * row_len);
+
+    for row in 0..height {
+        let start = (row % img_h) * img_w * channels;
+        let img_row = &img.bytes[start..start + img_w * channels];
+        let mut remaining = row_len;
+        while remaining > 0 {
+            let len = remaining.min(img_row.len());
+            tiled.extend_from_slice(&img_row[..len]);
+            remaining -= len;
+        }
+    }
+
+    Ok(tiled.into_boxed_slice())
+}
+
+/// Makes a linear gradient through `colors`, which are spread evenly along the direction given by
+/// `angle`, in degrees: 0 goes from left to right, and 90 from top to bottom
+pub fn make_gradient(
+    colors: &[[u8; 3]],
+    angle: f64,
+    dimensions: (u32, u32),
+    format: PixelFormat,
+) -> Box<[u8]> {
+    let (width, height) = (dimensions.0 as f64, dimensions.1 as f64);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // project every pixel onto the gradient's direction, so that the two corners furthest apart
+    // along it get the first and last colors
+    let half_len = (cos.abs() * width + sin.abs() * height) / 2.0;
+    let segments = (colors.len() - 1) as f64;
+
+    let channels = format.channels() as usize;
+    let mut img = vec![0; dimensions.0 as usize * dimensions.1 as usize * channels];
+    for (i, pixel) in img.chunks_exact_mut(channels).enumerate() {
+        let x = (i % dimensions.0 as usize) as f64 + 0.5 - width / 2.0;
+        let y = (i / dimensions.0 as usize) as f64 + 0.5 - height / 2.0;
+        let t = if half_len > 0.0 {
+            ((x * cos + y * sin) / half_len + 1.0) / 2.0
         } else {
-            PixelType::U8x4
-        };
-        let src = match fast_image_resize::images::ImageRef::new(
-            img.width,
-            img.height,
-            img.bytes.as_ref(),
-            pixel_type,
-        ) {
-            Ok(i) => i,
-            Err(e) => return Err(e.to_string()),
+            0.0
         };
 
-        let mut dst = fast_image_resize::images::Image::new(width, height, pixel_type);
-        let mut resizer = Resizer::new();
-        let options = ResizeOptions::new()
-            .resize_alg(ResizeAlg::Convolution(filter))
-            .fit_into_destination(Some((0.5, 0.5)));
+        let pos = t.clamp(0.0, 1.0) * segments;
+        let segment = (pos as usize).min(colors.len() - 2);
+        let (start, end) = (colors[segment], colors[segment + 1]);
+        let frac = pos - segment as f64;
+        for c in 0..3 {
+            let value = start[c] as f64 + (end[c] as f64 - start[c] as f64) * frac;
+            pixel[c] = value.round() as u8;
+        }
+        if format.must_swap_r_and_b_channels() {
+            pixel.swap(0, 2);
+        }
+    }
+    img.into_boxed_slice()
+}
 
-        if let Err(e) = resizer.resize(&src, &mut dst, Some(&options)) {
-            return Err(e.to_string());
+/// Converts the canvas the daemon sent us back into a regular rgb image, undoing whatever
+/// channel swapping and padding its pixel format required
+pub fn screenshot_to_rgb(screenshot: &ipc::Screenshot) -> image::RgbImage {
+    let ipc::Screenshot { dim, format, img } = screenshot;
+    let mut rgb = Vec::with_capacity(dim.0 as usize * dim.1 as usize * 3);
+    for pixel in img.chunks_exact(format.channels() as usize) {
+        if format.must_swap_r_and_b_channels() {
+            rgb.extend([pixel[2], pixel[1], pixel[0]]);
+        } else {
+            rgb.extend(&pixel[0..3]);
         }
+    }
+    image::RgbImage::from_raw(dim.0, dim.1, rgb).expect("screenshot has the wrong length")
+}
 
-        dst.into_vec().into_boxed_slice()
-    } else {
-        img.bytes.clone()
-    };
+/// Lists the files in `dir` with the extension of an image format we support, also looking into
+/// its subdirectories if `recursive`
+pub fn images_in_dir(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>, String> {
+    let mut images = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .map_err(|e| format!("failed to read directory {}: {e}", dir.display()))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if recursive {
+                    dirs.push(path);
+                }
+            } else if ImageFormat::from_path(&path).is_ok() {
+                images.push(path);
+            }
+        }
+    }
+    if images.is_empty() {
+        return Err(format!("there are no images in {}", dir.display()));
+    }
+    Ok(images)
+}
 
-    Ok(resized_img)
+/// Picks `count` images out of `candidates` at random, all different for as long as there are
+/// enough of them. The ones we can't read are skipped with a warning
+pub fn pick_random_images(
+    mut candidates: Vec<PathBuf>,
+    count: usize,
+) -> Result<Vec<PathBuf>, String> {
+    let mut picked: Vec<PathBuf> = Vec::with_capacity(count);
+    while picked.len() < count {
+        if candidates.is_empty() {
+            if picked.is_empty() {
+                return Err("none of the images could be read".to_string());
+            }
+            // more outputs than images, so some must repeat
+            picked.push(picked[fastrand::usize(..picked.len())].clone());
+            continue;
+        }
+        let path = candidates.swap_remove(fastrand::usize(..candidates.len()));
+        match ImgBuf::new(&path) {
+            Ok(_) => picked.push(path),
+            Err(e) => eprintln!("WARNING: skipping {}: {e}", path.display()),
+        }
+    }
+    Ok(picked)
 }
 
-pub fn make_transition(img: &cli::Img) -> ipc::Transition {
-    let mut angle = img.transition_angle;
-    let step = img.transition_step;
+/// Picks the image of `dir` that comes after `current` in `order`. The directory is read again
+/// every time, so that images added or removed since the last call are taken into account. The
+/// ones we can't read are skipped with a warning
+pub fn next_image_in_dir(
+    dir: &Path,
+    recursive: bool,
+    order: cli::ImgOrder,
+    current: Option<&Path>,
+) -> Result<PathBuf, String> {
+    let mut candidates = images_in_dir(dir, recursive)?;
+    match order {
+        cli::ImgOrder::Alphabetical => {
+            candidates.sort();
+            // start right after the current image, wrapping around to the first one
+            let start = current.map_or(0, |current| {
+                candidates.partition_point(|path| path.as_path() <= current)
+            });
+            candidates.rotate_left(start);
+            candidates
+                .into_iter()
+                .find(|path| match ImgBuf::new(path) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        eprintln!("WARNING: skipping {}: {e}", path.display());
+                        false
+                    }
+                })
+                .ok_or_else(|| "none of the images could be read".to_string())
+        }
+        cli::ImgOrder::Random => {
+            if candidates.len() > 1 {
+                candidates.retain(|path| Some(path.as_path()) != current);
+            }
+            pick_random_images(candidates, 1).map(|mut picked| picked.swap_remove(0))
+        }
+    }
+}
+
+/// Gets the value a `--transition-*` flag has for the `group`-th group of outputs, which is the
+/// last value given if that group has none of its own
+fn group_value<T: Clone>(values: &[T], group: usize) -> Option<T> {
+    values.get(group).or(values.last()).cloned()
+}
 
-    let x = match img.transition_pos.x {
+/// Makes the transition for the `group`-th group of outputs given in the command line
+pub fn make_transition(transition: &cli::TransitionArgs, group: usize) -> ipc::Transition {
+    // all of these have default values, so clap guarantees they hold at least one element
+    let cli_transition_type = group_value(&transition.transition_type, group)
+        .unwrap()
+        .default;
+    make_transition_of_type(transition, group, cli_transition_type)
+}
+
+/// Makes the transition for `output`, in the `group`-th group of outputs, if it was given a
+/// transition type of its own. Otherwise, it just gets the group's transition
+pub fn make_output_transition(
+    transition: &cli::TransitionArgs,
+    group: usize,
+    output: &str,
+) -> Option<ipc::Transition> {
+    let types = group_value(&transition.transition_type, group).unwrap();
+    let cli_transition_type = types.for_output(output)?.clone();
+    Some(make_transition_of_type(
+        transition,
+        group,
+        cli_transition_type,
+    ))
+}
+
+fn make_transition_of_type(
+    transition: &cli::TransitionArgs,
+    group: usize,
+    cli_transition_type: cli::TransitionType,
+) -> ipc::Transition {
+    let mut angle = group_value(&transition.transition_angle, group).unwrap();
+    let transition_pos = group_value(&transition.transition_pos, group).unwrap();
+    let step =
+        group_value(&transition.transition_step, group).unwrap_or(match cli_transition_type {
+            cli::TransitionType::Simple => NonZeroU8::new(2).unwrap(),
+            cli::TransitionType::Dissolve => NonZeroU8::new(1).unwrap(),
+            cli::TransitionType::Melt => NonZeroU8::new(8).unwrap(),
+            cli::TransitionType::Crossfade => NonZeroU8::new(8).unwrap(),
+            cli::TransitionType::Slide => NonZeroU8::new(8).unwrap(),
+            _ => NonZeroU8::new(90).unwrap(),
+        });
+
+    let x = match transition_pos.x {
         cli::CliCoord::Percent(x) => {
             if !(0.0..=1.0).contains(&x) {
                 println!(
@@ -479,7 +1411,7 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
         cli::CliCoord::Pixel(x) => Coord::Pixel(x),
     };
 
-    let y = match img.transition_pos.y {
+    let y = match transition_pos.y {
         cli::CliCoord::Percent(y) => {
             if !(0.0..=1.0).contains(&y) {
                 println!(
@@ -493,7 +1425,7 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
 
     let mut pos = Position::new(x, y);
 
-    let transition_type = match img.transition_type {
+    let transition_type = match cli_transition_type {
         cli::TransitionType::None => ipc::TransitionType::None,
         cli::TransitionType::Simple => ipc::TransitionType::Simple,
         cli::TransitionType::Fade => ipc::TransitionType::Fade,
@@ -501,6 +1433,11 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
         cli::TransitionType::Outer => ipc::TransitionType::Outer,
         cli::TransitionType::Grow => ipc::TransitionType::Grow,
         cli::TransitionType::Wave => ipc::TransitionType::Wave,
+        cli::TransitionType::Reveal => ipc::TransitionType::Reveal,
+        cli::TransitionType::Dissolve => ipc::TransitionType::Dissolve,
+        cli::TransitionType::Melt => ipc::TransitionType::Melt,
+        cli::TransitionType::Crossfade => ipc::TransitionType::Crossfade,
+        cli::TransitionType::Slide => ipc::TransitionType::Slide,
         cli::TransitionType::Right => {
             angle = 0.0;
             ipc::TransitionType::Wipe
@@ -549,14 +1486,1159 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
     };
 
     ipc::Transition {
-        duration: img.transition_duration,
+        duration: group_value(&transition.transition_duration, group).unwrap(),
         step,
-        fps: img.transition_fps,
-        bezier: img.transition_bezier,
+        fps: group_value(&transition.transition_fps, group).unwrap(),
+        bezier: group_value(&transition.transition_easing, group).map_or_else(
+            || group_value(&transition.transition_bezier, group).unwrap(),
+            cli::TransitionEasing::bezier,
+        ),
         angle,
         pos,
         transition_type,
-        wave: img.transition_wave,
-        invert_y: img.invert_y,
+        wave: group_value(&transition.transition_wave, group).unwrap(),
+        invert_y: transition.invert_y,
+        linear: transition.transition_colorspace == cli::TransitionColorspace::Linear,
+        mask: None,
+    }
+}
+
+/// Loads the `--transition-mask` of the `group`-th group of outputs, if it has one
+pub fn load_transition_mask(
+    transition: &cli::TransitionArgs,
+    group: usize,
+) -> Result<Option<image::GrayImage>, String> {
+    let Some(path) = group_value(&transition.transition_mask, group) else {
+        return Ok(None);
+    };
+    let mask = image::ImageReader::open(&path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("failed to open transition mask {}: {e}", path.display()))?
+        .decode()
+        .map_err(|e| format!("failed to decode transition mask {}: {e}", path.display()))?;
+    Ok(Some(mask.into_luma8()))
+}
+
+/// Copies `transition`, with `mask` resized to `dim`
+pub fn with_transition_mask(
+    transition: &ipc::Transition,
+    mask: &image::GrayImage,
+    dim: (u32, u32),
+) -> ipc::Transition {
+    let mask = image::imageops::resize(mask, dim.0, dim.1, image::imageops::Triangle);
+    ipc::Transition {
+        mask: Some(mask.into_raw().into_boxed_slice()),
+        ..transition.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compression::DEFAULT_COMPRESSION_LEVEL;
+    use image::{
+        codecs::{gif::GifEncoder, webp::WebPEncoder},
+        Delay, ExtendedColorType, Frame, RgbaImage,
+    };
+
+    /// Encodes a gif with one frame for every color in `colors`
+    fn make_gif(colors: &[[u8; 4]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for color in colors {
+                let img = RgbaImage::from_pixel(4, 4, image::Rgba(*color));
+                let frame = Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1));
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    /// Encodes an animated webp with one frame for every `(color, delay in ms)` in `frames`.
+    ///
+    /// The encoder can only make still images, so we take the bitstreams out of those and put them
+    /// in the animation chunks ourselves
+    fn make_webp(frames: &[([u8; 4], u32)]) -> Vec<u8> {
+        fn chunk(name: &[u8], data: &[u8]) -> Vec<u8> {
+            let mut chunk = name.to_vec();
+            chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        }
+        let u24 = |n: u32| n.to_le_bytes()[..3].to_vec();
+
+        // VP8X: the animation flag, then the canvas' width and height minus one
+        let mut vp8x = vec![0x02, 0, 0, 0];
+        vp8x.extend(u24(3));
+        vp8x.extend(u24(3));
+        let mut body = b"WEBP".to_vec();
+        body.extend(chunk(b"VP8X", &vp8x));
+        // ANIM: background color, then loop forever
+        body.extend(chunk(b"ANIM", &[0; 6]));
+
+        for (color, delay) in frames {
+            let mut still = Vec::new();
+            WebPEncoder::new_lossless(&mut still)
+                .encode(&color.repeat(16), 4, 4, ExtendedColorType::Rgba8)
+                .unwrap();
+            let vp8l = still.windows(4).position(|w| w == b"VP8L").unwrap();
+            let len = u32::from_le_bytes(still[vp8l + 4..vp8l + 8].try_into().unwrap());
+            let bitstream = &still[vp8l + 8..vp8l + 8 + len as usize];
+
+            // ANMF: x and y offsets, width and height minus one, duration, then no blending
+            let mut anmf = [u24(0), u24(0), u24(3), u24(3), u24(*delay)].concat();
+            anmf.push(0x02);
+            anmf.extend(chunk(b"VP8L", bitstream));
+            body.extend(chunk(b"ANMF", &anmf));
+        }
+        chunk(b"RIFF", &body)
+    }
+
+    fn compress_gif(bytes: &[u8]) -> Vec<(BitPack, Duration)> {
+        let frames = GifDecoder::new(Cursor::new(bytes)).unwrap().into_frames();
+        compress_frames(
+            frames,
+            (4, 4),
+            PixelFormat::Xrgb,
+            FilterType::Box,
+            Resample::Srgb,
+            ResizeStrategy::Crop,
+            (0.5, 0.5),
+            &[0, 0, 0],
+            None,
+            &ColorAdjustment::default(),
+            Transform::Normal,
+            DEFAULT_COMPRESSION_LEVEL,
+            default_compression_workers(),
+        )
+        .unwrap()
+    }
+
+    const COLORS: [[u8; 4]; 4] = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [255, 255, 255, 255],
+    ];
+
+    #[test]
+    fn should_count_the_frames_it_processed() {
+        // other tests compress frames at the same time, so the count can only grow by more
+        let before = frames_processed();
+        compress_gif(&make_gif(&COLORS));
+        assert!(frames_processed() - before >= COLORS.len());
+    }
+
+    #[test]
+    fn should_compress_every_frame() {
+        assert_eq!(compress_gif(&make_gif(&COLORS)).len(), 4);
+    }
+
+    #[test]
+    fn should_merge_repeated_frames_keeping_their_timing() {
+        // a long pause in the middle, and another one at the end that runs into the first frame
+        let [red, green, blue, _] = COLORS;
+        let mut colors = vec![red];
+        colors.extend([green; 30]);
+        colors.push(blue);
+        colors.extend([red; 5]);
+
+        let durations: Vec<_> = compress_gif(&make_gif(&colors))
+            .into_iter()
+            .map(|(_, duration)| duration)
+            .collect();
+        // every frame of the gif is shown for 100ms
+        let ms = Duration::from_millis;
+        assert_eq!(durations, [ms(3000), ms(100), ms(600)]);
+    }
+
+    #[test]
+    fn should_keep_the_total_duration() {
+        let [red, green, blue, white] = COLORS;
+        let animations: [&[[u8; 4]]; 4] = [
+            &[red, green, blue, white],
+            // an exact loop: the last frame diffs into the first one, which is the same
+            &[red, green, blue, red],
+            &[red, red, red, green, green, red, red],
+            &[green, red, red, blue, blue, blue, white, white, green],
+        ];
+        for colors in animations {
+            let compressed = compress_gif(&make_gif(colors));
+            let total: Duration = compressed.iter().map(|(_, duration)| *duration).sum();
+            assert_eq!(total, Duration::from_millis(100) * colors.len() as u32);
+            // one frame per change of color, counting the one back into the first frame
+            let changes = colors
+                .iter()
+                .zip(colors.iter().cycle().skip(1))
+                .filter(|(a, b)| a != b)
+                .count();
+            assert_eq!(compressed.len(), changes, "{colors:?}");
+        }
+    }
+
+    #[test]
+    fn should_span_images_across_the_layout() {
+        let output = |name: &str, rect: OutputRect| (name.to_string(), rect);
+        let center = (0.5, 0.5);
+
+        // two 1080p monitors side by side, showing one half each
+        let side_by_side = [
+            output("DP-1", (0, 0, 1920, 1080)),
+            output("DP-2", (1920, 0, 1920, 1080)),
+        ];
+        assert_eq!(
+            span_regions((3840, 1080), &side_by_side, center),
+            Ok(vec![[0.0, 0.0, 0.5, 1.0], [0.5, 0.0, 0.5, 1.0]])
+        );
+        // too tall an image loses its top and bottom, or just its bottom with the focus on top
+        assert_eq!(
+            span_regions((3840, 2160), &side_by_side, center),
+            Ok(vec![[0.0, 0.25, 0.5, 0.5], [0.5, 0.25, 0.5, 0.5]])
+        );
+        assert_eq!(
+            span_regions((3840, 2160), &side_by_side, (0.5, 0.0)),
+            Ok(vec![[0.0, 0.0, 0.5, 0.5], [0.5, 0.0, 0.5, 0.5]])
+        );
+
+        // a vertical stack, with the layout starting somewhere other than 0,0
+        let stacked = [
+            output("DP-1", (-100, 50, 1000, 500)),
+            output("DP-2", (-100, 550, 1000, 1500)),
+        ];
+        assert_eq!(
+            span_regions((100, 200), &stacked, center),
+            Ok(vec![[0.0, 0.0, 1.0, 0.25], [0.0, 0.25, 1.0, 0.75]])
+        );
+
+        // a smaller monitor, centered next to a bigger one, leaves parts of the image unseen
+        let mixed = [
+            output("DP-1", (0, 0, 2000, 1000)),
+            output("DP-2", (2000, 250, 1000, 500)),
+        ];
+        assert_eq!(
+            span_regions((300, 100), &mixed, center),
+            Ok(vec![
+                [0.0, 0.0, 2.0 / 3.0, 1.0],
+                [2.0 / 3.0, 0.25, 1.0 / 3.0, 0.5]
+            ])
+        );
+
+        // mirrored outputs show the same thing, but partly overlapping ones make no sense
+        let mirrored = [
+            output("DP-1", (0, 0, 10, 10)),
+            output("DP-2", (0, 0, 10, 10)),
+        ];
+        assert_eq!(
+            span_regions((10, 10), &mirrored, center),
+            Ok(vec![[0.0, 0.0, 1.0, 1.0]; 2])
+        );
+        let overlapping = [
+            output("DP-1", (0, 0, 10, 10)),
+            output("DP-2", (5, 0, 10, 10)),
+        ];
+        assert!(span_regions((10, 10), &overlapping, center)
+            .unwrap_err()
+            .contains("DP-1 and DP-2 overlap"));
+    }
+
+    #[test]
+    fn spanned_regions_join_up() {
+        // the left and right halves of a gradient, each on an output of their own
+        let img = Image {
+            width: 8,
+            height: 2,
+            format: PixelFormat::Bgr,
+            bytes: (0..16).flat_map(|i| [(i % 8) as u8 * 30; 3]).collect(),
+        };
+        for (region, expected) in [([0.0, 0.0, 0.5, 1.0], 0), ([0.5, 0.0, 0.5, 1.0], 120)] {
+            let half =
+                img_resize_region(&img, (4, 2), FilterType::Box, Resample::Srgb, region).unwrap();
+            let firsts: Vec<u8> = half.chunks_exact(3).take(4).map(|pixel| pixel[0]).collect();
+            let expected: Vec<u8> = (0..4).map(|x| expected + x * 30).collect();
+            assert_eq!(firsts, expected);
+        }
+
+        let region = [0.125, 0.5, 0.25, 0.5];
+        assert_eq!(
+            region_from_cache_args(region_cache_arg(region).split_whitespace()),
+            Some(region)
+        );
+        assert_eq!(
+            region_from_cache_args(["--grayscale", "--region=1,2"].into_iter()),
+            None
+        );
+    }
+
+    #[test]
+    fn should_truncate_animation_at_corrupt_frame() {
+        // the last frame of the full gif starts right where the shorter gif's trailer byte is
+        let last_frame_start = make_gif(&COLORS[..3]).len() - 1;
+        let mut corrupt = make_gif(&COLORS);
+        corrupt.truncate(last_frame_start + 8);
+
+        assert_eq!(compress_gif(&corrupt).len(), 3);
+    }
+
+    #[test]
+    fn should_not_animate_single_decodable_frame() {
+        let first_frame_end = make_gif(&COLORS[..1]).len() - 1;
+        let mut corrupt = make_gif(&COLORS);
+        corrupt.truncate(first_frame_end + 8);
+
+        assert!(compress_gif(&corrupt).is_empty());
+    }
+
+    #[test]
+    fn should_letterbox_animations_with_fit() {
+        const FILL: u8 = 9;
+        // gray, so that it doesn't matter whether the pixel format swaps channels
+        let grays = [50, 100, 150];
+        let colors: Vec<_> = grays.iter().map(|&g| [g, g, g, 255]).collect();
+        let frames = GifDecoder::new(Cursor::new(make_gif(&colors)))
+            .unwrap()
+            .into_frames();
+        let compressed = compress_frames(
+            frames,
+            (8, 4),
+            PixelFormat::Bgr,
+            FilterType::Box,
+            Resample::Srgb,
+            ResizeStrategy::Fit,
+            (0.5, 0.5),
+            &[FILL; 3],
+            None,
+            &ColorAdjustment::default(),
+            Transform::Normal,
+            DEFAULT_COMPRESSION_LEVEL,
+            default_compression_workers(),
+        )
+        .unwrap();
+        assert_eq!(compressed.len(), 3);
+
+        // the 4x4 frames go in the middle of the 8x4 output, with the fill color on the sides
+        let letterboxed = |gray: u8| -> Vec<u8> {
+            (0..8 * 4)
+                .flat_map(|i| {
+                    [if (2..6).contains(&(i % 8)) {
+                        gray
+                    } else {
+                        FILL
+                    }; 3]
+                })
+                .collect()
+        };
+        let mut decompressor = common::compression::Decompressor::new();
+        let mut buf = letterboxed(grays[0]);
+        for ((bitpack, _), &gray) in compressed.iter().zip(grays[1..].iter().chain(&grays[..1])) {
+            decompressor
+                .decompress(bitpack, &mut buf, PixelFormat::Bgr)
+                .unwrap();
+            assert_eq!(buf, letterboxed(gray));
+        }
+    }
+
+    #[test]
+    fn should_blur_the_image_behind_fit() {
+        // dark on the left, bright on the right
+        let img = Image {
+            width: 4,
+            height: 4,
+            format: PixelFormat::Xrgb,
+            bytes: (0..16)
+                .flat_map(|i| if i % 4 < 2 { [0, 0, 0, 255] } else { [255; 4] })
+                .collect(),
+        };
+        let fit = img_resize_fit(
+            &img,
+            (8, 4),
+            FilterType::Box,
+            Resample::Srgb,
+            &[9; 3],
+            Some(2.0),
+        )
+        .unwrap();
+        for (row, pixels) in fit.chunks_exact(8 * 4).enumerate() {
+            // the sharp image sits untouched in the middle
+            assert_eq!(pixels[2 * 4..6 * 4], img.bytes[row * 16..(row + 1) * 16]);
+            // and the sides come from its stretched edges instead of the fill color
+            let (left, right) = (&pixels[..2 * 4], &pixels[6 * 4..]);
+            assert!(left.chunks_exact(4).all(|p| p[0] < 128), "{left:?}");
+            assert!(right.chunks_exact(4).all(|p| p[0] > 128), "{right:?}");
+        }
+
+        // animations get the same background on every frame
+        let grays = [50, 100, 150];
+        let colors: Vec<_> = grays.iter().map(|&g| [g, g, g, 255]).collect();
+        let frames = GifDecoder::new(Cursor::new(make_gif(&colors)))
+            .unwrap()
+            .into_frames();
+        let compressed = compress_frames(
+            frames,
+            (8, 4),
+            PixelFormat::Bgr,
+            FilterType::Box,
+            Resample::Srgb,
+            ResizeStrategy::Fit,
+            (0.5, 0.5),
+            &[9; 3],
+            Some(2.0),
+            &ColorAdjustment::default(),
+            Transform::Normal,
+            DEFAULT_COMPRESSION_LEVEL,
+            default_compression_workers(),
+        )
+        .unwrap();
+        assert_eq!(compressed.len(), 3);
+        let mut decompressor = common::compression::Decompressor::new();
+        let mut buf = vec![grays[0]; 8 * 4 * 3];
+        for ((bitpack, _), &gray) in compressed.iter().zip(grays[1..].iter().chain(&grays[..1])) {
+            decompressor
+                .decompress(bitpack, &mut buf, PixelFormat::Bgr)
+                .unwrap();
+            assert_eq!(buf, vec![gray; 8 * 4 * 3]);
+        }
+    }
+
+    #[test]
+    fn should_animate_webp() {
+        let frames = [(COLORS[0], 100), (COLORS[1], 250), (COLORS[2], 40)];
+        let imgbuf = ImgBuf::from_bytes(make_webp(&frames)).unwrap();
+        assert!(imgbuf.is_animated());
+
+        let delays: Vec<_> = imgbuf
+            .as_frames()
+            .unwrap()
+            .map(|frame| Duration::from(frame.unwrap().delay()))
+            .collect();
+        let expected: Vec<_> = frames
+            .iter()
+            .map(|&(_, ms)| Duration::from_millis(ms.into()))
+            .collect();
+        assert_eq!(delays, expected);
+
+        let compressed = compress_frames(
+            imgbuf.as_frames().unwrap(),
+            (4, 4),
+            PixelFormat::Xrgb,
+            FilterType::Box,
+            Resample::Srgb,
+            ResizeStrategy::Crop,
+            (0.5, 0.5),
+            &[0, 0, 0],
+            None,
+            &ColorAdjustment::default(),
+            Transform::Normal,
+            DEFAULT_COMPRESSION_LEVEL,
+            default_compression_workers(),
+        )
+        .unwrap();
+        let durations: Vec<_> = compressed.iter().map(|(_, duration)| *duration).collect();
+        // the last frame diffs back into the first one, so the first frame's delay comes last
+        assert_eq!(durations, [expected[1], expected[2], expected[0]]);
+    }
+
+    #[test]
+    fn should_compress_the_same_whatever_the_workers() {
+        // repeated frames get merged into the previous one, which must also happen across batches
+        let colors: Vec<_> = (0..37)
+            .map(|i: usize| COLORS[(i / 2 + i / 5) % COLORS.len()])
+            .collect();
+        let gif = make_gif(&colors);
+
+        let serialize = |workers: usize| -> Vec<u8> {
+            let frames = GifDecoder::new(Cursor::new(&gif)).unwrap().into_frames();
+            let animation = compress_frames(
+                frames,
+                (4, 4),
+                PixelFormat::Xrgb,
+                FilterType::Box,
+                Resample::Srgb,
+                ResizeStrategy::Crop,
+                (0.5, 0.5),
+                &[0, 0, 0],
+                None,
+                &ColorAdjustment::default(),
+                Transform::Normal,
+                DEFAULT_COMPRESSION_LEVEL,
+                NonZeroUsize::new(workers).unwrap(),
+            )
+            .unwrap();
+            assert!(!animation.is_empty());
+
+            let transition = ipc::Transition {
+                transition_type: ipc::TransitionType::None,
+                duration: 0.0,
+                step: NonZeroU8::MAX,
+                fps: 30,
+                angle: 0.0,
+                pos: Position::new(Coord::Pixel(0.0), Coord::Pixel(0.0)),
+                bezier: (0.0, 0.0, 0.0, 0.0),
+                wave: (0.0, 0.0),
+                invert_y: false,
+                linear: false,
+                mask: None,
+            };
+            let mut builder = ipc::ImageRequestBuilder::new(transition, "");
+            builder.set_cache_writes(false);
+            builder.set_animation_cache_writes(false);
+            builder.push(
+                ipc::ImgSend {
+                    path: "animation".to_string(),
+                    img: vec![0; 4 * 4 * 4].into_boxed_slice(),
+                    dim: (4, 4),
+                    format: PixelFormat::Xrgb,
+                    transition: None,
+                },
+                "Lanczos3".to_string(),
+                &[],
+                Some(ipc::Animation {
+                    animation: animation.into_boxed_slice(),
+                    loop_count: 0,
+                    speed: 1.0,
+                }),
+            );
+            builder.build().slice().to_vec()
+        };
+
+        let serial = serialize(1);
+        for workers in [2, 3, 8, 64] {
+            assert!(
+                serialize(workers) == serial,
+                "{workers} workers compressed differently"
+            );
+        }
+    }
+
+    #[test]
+    fn should_read_raw_rgba_pixels() {
+        let pixels = vec![1, 2, 3, 255, 4, 5, 6, 255];
+        assert!(ImgBuf::from_raw_bytes(pixels.clone(), (2, 2)).is_err());
+        assert!(ImgBuf::from_raw_bytes(pixels[1..].to_vec(), (2, 1)).is_err());
+
+        let imgbuf = ImgBuf::from_raw_bytes(pixels, (2, 1)).unwrap();
+        assert!(!imgbuf.is_animated());
+        let img = imgbuf.decode(PixelFormat::Bgr, false).unwrap();
+        assert_eq!((img.width, img.height), (2, 1));
+        assert_eq!(&img.bytes[..], [1, 2, 3, 4, 5, 6]);
+        let img = imgbuf.decode(PixelFormat::Xrgb, false).unwrap();
+        assert_eq!(&img.bytes[..], [3, 2, 1, 255, 6, 5, 4, 255]);
+    }
+
+    #[cfg(not(feature = "url"))]
+    #[test]
+    fn urls_need_the_url_feature() {
+        let err = ImgBuf::new(Path::new("https://example.com/wallpaper.png"))
+            .err()
+            .unwrap();
+        assert!(err.contains("`url` feature"), "{err}");
+    }
+
+    #[test]
+    fn should_detect_jpeg_xl() {
+        // a bare codestream, and the start of a container
+        let codestream = vec![0xff, 0x0a, 0xfa, 0x1f];
+        let container = [
+            0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a, 0, 0,
+        ];
+        assert!(is_jxl(&codestream));
+        assert!(is_jxl(&container));
+        assert!(!is_jxl(&make_oriented_jpeg(1)));
+        assert!(!is_jxl(&container[..8]));
+
+        #[cfg(not(feature = "jxl"))]
+        assert!(ImgBuf::from_bytes(codestream)
+            .err()
+            .is_some_and(|e| e.contains("`jxl` feature")));
+    }
+
+    /// A jpeg whose left half is black and right half is white, tagged with the given EXIF
+    /// orientation
+    fn make_oriented_jpeg(orientation: u16) -> Vec<u8> {
+        let img =
+            image::GrayImage::from_fn(16, 8, |x, _| image::Luma([if x < 8 { 0 } else { 255 }]));
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 100)
+            .encode_image(&img)
+            .unwrap();
+
+        // a little endian TIFF header, followed by an IFD with only the orientation entry
+        let mut exif = b"Exif\0\0II*\0".to_vec();
+        exif.extend(8u32.to_le_bytes());
+        exif.extend(1u16.to_le_bytes());
+        exif.extend([0x12, 0x01, 3, 0]);
+        exif.extend(1u32.to_le_bytes());
+        exif.extend(orientation.to_le_bytes());
+        exif.extend([0; 2 + 4]);
+
+        // the APP1 segment goes right after the start of image marker
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend((exif.len() as u16 + 2).to_be_bytes());
+        app1.extend(exif);
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[test]
+    fn should_honor_exif_orientation() {
+        // `(width, height, top left pixel, bottom right pixel)`
+        let decode = |orientation, ignore_exif| {
+            let imgbuf = ImgBuf::from_bytes(make_oriented_jpeg(orientation)).unwrap();
+            let img = imgbuf.decode(PixelFormat::Bgr, ignore_exif).unwrap();
+            (
+                img.width,
+                img.height,
+                img.bytes[0],
+                img.bytes[img.bytes.len() - 1],
+            )
+        };
+        let is_dark = |value: u8| value < 64;
+
+        // 1 is the upright orientation
+        let (w, h, first, last) = decode(1, false);
+        assert_eq!((w, h), (16, 8));
+        assert!(is_dark(first) && !is_dark(last));
+
+        // 6 must be rotated 90 degrees clockwise, which moves the black half to the top
+        let (w, h, first, last) = decode(6, false);
+        assert_eq!((w, h), (8, 16));
+        assert!(is_dark(first) && !is_dark(last));
+
+        // 3 is upside down, and 2 mirrored, both of which move the black half to the right
+        for orientation in [2, 3] {
+            let (w, h, first, last) = decode(orientation, false);
+            assert_eq!((w, h), (16, 8));
+            assert!(!is_dark(first) && is_dark(last));
+        }
+
+        // 8 must be rotated 90 degrees counterclockwise, moving the black half to the bottom
+        let (w, h, first, last) = decode(8, false);
+        assert_eq!((w, h), (8, 16));
+        assert!(!is_dark(first) && is_dark(last));
+
+        let (w, h, first, last) = decode(6, true);
+        assert_eq!((w, h), (16, 8));
+        assert!(is_dark(first) && !is_dark(last));
+    }
+
+    #[test]
+    fn should_transform_images() {
+        // 0 1 2
+        // 3 4 5
+        let img = || Image {
+            width: 3,
+            height: 2,
+            format: PixelFormat::Bgr,
+            bytes: (0..6).flat_map(|i| [i; 3]).collect(),
+        };
+        for (transform, dim, expected) in [
+            (Transform::Normal, (3, 2), [0, 1, 2, 3, 4, 5]),
+            (Transform::Rotate90, (2, 3), [2, 5, 1, 4, 0, 3]),
+            (Transform::Rotate180, (3, 2), [5, 4, 3, 2, 1, 0]),
+            (Transform::Rotate270, (2, 3), [3, 0, 4, 1, 5, 2]),
+            (Transform::Flipped, (3, 2), [2, 1, 0, 5, 4, 3]),
+            (Transform::Flipped90, (2, 3), [0, 3, 1, 4, 2, 5]),
+            (Transform::Flipped180, (3, 2), [3, 4, 5, 0, 1, 2]),
+            (Transform::Flipped270, (2, 3), [5, 2, 4, 1, 3, 0]),
+        ] {
+            let mut img = img();
+            img.transform(transform);
+            assert_eq!((img.width, img.height), dim, "{transform:?}");
+            let pixels: Vec<u8> = img.bytes.chunks_exact(3).map(|pixel| pixel[0]).collect();
+            assert_eq!(pixels, expected, "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn should_resize_transition_masks() {
+        use clap::Parser;
+
+        let path = std::env::temp_dir().join(format!("swww-mask-{}.png", std::process::id()));
+        image::GrayImage::from_fn(2, 1, |x, _| image::Luma([x as u8 * 255]))
+            .save(&path)
+            .unwrap();
+        let args =
+            cli::TransitionArgs::parse_from(["swww", "--transition-mask", path.to_str().unwrap()]);
+        let mask = load_transition_mask(&args, 0);
+        std::fs::remove_file(&path).unwrap();
+
+        let transition = make_transition(&args, 0);
+        assert!(transition.mask.is_none());
+        let masked = with_transition_mask(&transition, &mask.unwrap().unwrap(), (8, 2));
+        let mask = masked.mask.unwrap();
+        assert_eq!(mask.len(), 8 * 2);
+        assert!(
+            mask[0] < mask[7],
+            "the mask was not stretched left to right"
+        );
+
+        let args = cli::TransitionArgs::parse_from(["swww", "--transition-mask", "/no/mask.png"]);
+        assert!(load_transition_mask(&args, 0).is_err());
+        let args = cli::TransitionArgs::parse_from(["swww"]);
+        assert!(load_transition_mask(&args, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn should_prefer_named_easing_over_bezier() {
+        use clap::Parser;
+
+        let args = cli::TransitionArgs::parse_from(["swww", "--transition-bezier", "1,2,3,4"]);
+        assert_eq!(make_transition(&args, 0).bezier, (1.0, 2.0, 3.0, 4.0));
+
+        let args = cli::TransitionArgs::parse_from([
+            "swww",
+            "--transition-bezier",
+            "1,2,3,4",
+            "--transition-easing",
+            "ease-out",
+            "--transition-easing",
+            "linear",
+        ]);
+        assert_eq!(make_transition(&args, 0).bezier, (0.0, 0.0, 0.58, 1.0));
+        assert_eq!(make_transition(&args, 1).bezier, (0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn should_skip_unreadable_random_images() {
+        let dir = std::env::temp_dir().join(format!("swww-random-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        assert!(images_in_dir(&dir, true).is_err());
+
+        let good = dir.join("good.png");
+        image::RgbImage::new(1, 1).save(&good).unwrap();
+        std::fs::write(dir.join("corrupt.png"), b"not a png").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not an image").unwrap();
+        image::RgbImage::new(1, 1)
+            .save(dir.join("nested/deep.png"))
+            .unwrap();
+
+        let images = images_in_dir(&dir, false).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images_in_dir(&dir, true).unwrap().len(), 3);
+
+        let picked = pick_random_images(images, 3).unwrap();
+        assert_eq!(picked, [good.clone(), good.clone(), good]);
+        let corrupt = vec![dir.join("corrupt.png")];
+        assert!(pick_random_images(corrupt, 1).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_cycle_through_directory_in_order() {
+        let dir = std::env::temp_dir().join(format!("swww-interval-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["c.png", "a.png", "b.png"] {
+            image::RgbImage::new(1, 1).save(dir.join(name)).unwrap();
+        }
+        std::fs::write(dir.join("b2.png"), b"not a png").unwrap();
+
+        let mut current = None;
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            let next =
+                next_image_in_dir(&dir, false, cli::ImgOrder::Alphabetical, current.as_deref())
+                    .unwrap();
+            seen.push(next.file_name().unwrap().to_str().unwrap().to_string());
+            current = Some(next);
+        }
+        assert_eq!(seen, ["a.png", "b.png", "c.png", "a.png"]);
+
+        // the current image may have been deleted in the meantime
+        let deleted = dir.join("aa.png");
+        let next =
+            next_image_in_dir(&dir, false, cli::ImgOrder::Alphabetical, Some(&deleted)).unwrap();
+        assert_eq!(next, dir.join("b.png"));
+
+        let current = dir.join("a.png");
+        for _ in 0..10 {
+            let next =
+                next_image_in_dir(&dir, false, cli::ImgOrder::Random, Some(&current)).unwrap();
+            assert_ne!(next, current);
+            assert_ne!(next, dir.join("b2.png"));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_report_corrupt_frame_index() {
+        let e = image::ImageError::IoError(std::io::ErrorKind::UnexpectedEof.into());
+        let warning = frame_decode_warning(3, &e);
+        assert!(warning.contains("frame 3"), "{warning}");
+        assert!(warning.contains("only the 3 frame(s)"), "{warning}");
+    }
+
+    fn xrgb_image(pixel: [u8; 4]) -> Image {
+        Image {
+            width: 2,
+            height: 2,
+            format: PixelFormat::Xrgb,
+            bytes: pixel.repeat(4).into_boxed_slice(),
+        }
+    }
+
+    #[test]
+    fn neutral_adjustment_is_a_no_op() {
+        let mut img = xrgb_image([10, 128, 250, 7]);
+        img.adjust_colors(&ColorAdjustment::default());
+        assert_eq!(&*img.bytes, &[10, 128, 250, 7].repeat(4));
+        assert_eq!(
+            ColorAdjustment::default().adjust_color([10, 128, 250]),
+            [10, 128, 250]
+        );
+    }
+
+    #[test]
+    fn adjustments_leave_the_fourth_channel_alone() {
+        let adjust = |adjustment: ColorAdjustment| {
+            let mut img = xrgb_image([0, 64, 255, 7]);
+            img.adjust_colors(&adjustment);
+            img.bytes[..4].to_vec()
+        };
+
+        let brighter = adjust(ColorAdjustment {
+            brightness: 0.5,
+            ..Default::default()
+        });
+        assert_eq!(brighter, [128, 192, 255, 7]);
+
+        let flat = adjust(ColorAdjustment {
+            contrast: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(flat, [128, 128, 128, 7]);
+
+        let gamma = adjust(ColorAdjustment {
+            gamma: 2.0,
+            ..Default::default()
+        });
+        assert_eq!(gamma, [0, 128, 255, 7]);
+
+        let gray = adjust(ColorAdjustment {
+            saturation: 0.0,
+            ..Default::default()
+        });
+        assert!(gray[..3].iter().all(|c| *c == gray[0]), "{gray:?}");
+        assert_eq!(gray[3], 7);
+    }
+
+    #[test]
+    fn saturation_weighs_channels_by_their_color() {
+        let gray = ColorAdjustment {
+            saturation: 0.0,
+            ..Default::default()
+        };
+        // pure red is much darker than pure green
+        assert_eq!(gray.adjust_color([255, 0, 0]), [76, 76, 76]);
+        assert_eq!(gray.adjust_color([0, 255, 0]), [150, 150, 150]);
+
+        // channels are swapped for this format, so the red is in the third byte
+        let mut img = xrgb_image([0, 0, 255, 0]);
+        img.adjust_colors(&gray);
+        assert_eq!(img.bytes[..3], [76, 76, 76]);
+    }
+
+    #[test]
+    fn grayscale_and_tint_golden_values() {
+        let tinted = ColorAdjustment {
+            grayscale: true,
+            tint: Some(([0, 0, 200], 0.25)),
+            ..Default::default()
+        };
+        // luma of (200, 100, 50) is 124.2, then a quarter of the way towards the tint
+        assert_eq!(tinted.adjust_color([200, 100, 50]), [93, 93, 143]);
+
+        // in an xrgb image, blue is the first byte
+        let mut img = xrgb_image([50, 100, 200, 9]);
+        img.adjust_colors(&tinted);
+        assert_eq!(img.bytes[..4], [143, 93, 93, 9]);
+
+        let full = ColorAdjustment {
+            tint: Some(([1, 2, 3], 1.0)),
+            ..Default::default()
+        };
+        assert_eq!(full.adjust_color([200, 100, 50]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn invert_comes_before_the_other_adjustments() {
+        let invert = ColorAdjustment {
+            invert: true,
+            ..Default::default()
+        };
+        assert!(!invert.is_neutral());
+        assert_eq!(invert.adjust_color([0, 100, 255]), [255, 155, 0]);
+
+        // inverting every channel the same way works whatever their order, and leaves alpha alone
+        let mut img = xrgb_image([0, 100, 255, 9]);
+        img.adjust_colors(&invert);
+        assert_eq!(img.bytes[..4], [255, 155, 0, 9]);
+
+        let brighter = ColorAdjustment {
+            brightness: 0.2,
+            ..invert
+        };
+        assert_eq!(brighter.adjust_color([255, 100, 0]), [51, 206, 255]);
+        let gray = ColorAdjustment {
+            grayscale: true,
+            ..invert
+        };
+        assert_eq!(gray.adjust_color([255, 0, 0]), [179, 179, 179]);
+
+        let args = gray.cache_args();
+        assert_eq!(args, " --invert --grayscale");
+        assert_eq!(from_cache_args(&args).unwrap(), gray);
+    }
+
+    #[test]
+    fn dithering_hides_banding_without_shifting_colors() {
+        // a smooth horizontal gray ramp, which doubling the contrast turns into steps of 2
+        let ramp = || Image {
+            width: 64,
+            height: 8,
+            format: PixelFormat::Bgr,
+            bytes: (0..8)
+                .flat_map(|_| (96..160u8).flat_map(|v| [v; 3]))
+                .collect(),
+        };
+        let contrast = ColorAdjustment {
+            contrast: 2.0,
+            ..Default::default()
+        };
+        let dithered = ColorAdjustment {
+            dither: true,
+            ..contrast
+        };
+        assert!(!dithered.is_neutral());
+        assert!(ColorAdjustment {
+            dither: true,
+            ..Default::default()
+        }
+        .is_neutral());
+
+        let mut banded = ramp();
+        banded.adjust_colors(&contrast);
+        let mut img = ramp();
+        img.adjust_colors(&dithered);
+        let distinct = |img: &Image| {
+            let mut values = img.bytes.to_vec();
+            values.sort();
+            values.dedup();
+            values.len()
+        };
+        assert_eq!(distinct(&banded), 64);
+        assert!(distinct(&img) > 120);
+
+        // every column averages out to the value it would have without the rounding
+        for x in 0..64 {
+            let column: u32 = (0..8).map(|y| img.bytes[(y * 64 + x) * 3] as u32).sum();
+            let exact = ((96 + x) as f32 / 255.0 - 0.5) * 2.0 * 255.0 + 127.5;
+            assert!((column as f32 / 8.0 - exact).abs() <= 0.5, "column {x}");
+        }
+
+        // the same image always dithers the same way, or animation frames would never diff well
+        let mut again = ramp();
+        again.adjust_colors(&dithered);
+        assert_eq!(again.bytes, img.bytes);
+    }
+
+    /// Restoring an image parses its cached settings as `swww img` flags
+    fn from_cache_args(args: &str) -> Result<ColorAdjustment, clap::Error> {
+        use clap::Parser;
+        let args = ["img"].into_iter().chain(args.split_whitespace());
+        let img = cli::Img::try_parse_from(args.chain(["0x102030"]))?;
+        Ok(ColorAdjustment::from(&img))
+    }
+
+    #[test]
+    fn adjustments_round_trip_through_the_cache() {
+        assert_eq!(ColorAdjustment::default().cache_args(), "");
+        let adjustment = ColorAdjustment {
+            brightness: -0.25,
+            saturation: 1.5,
+            ..Default::default()
+        };
+        let args = adjustment.cache_args();
+        assert_eq!(args, " --brightness=-0.25 --saturation=1.5");
+        assert_eq!(from_cache_args(&args).unwrap(), adjustment);
+
+        let adjustment = ColorAdjustment {
+            grayscale: true,
+            tint: Some(([0x88, 0xc0, 0xd0], 0.3)),
+            ..Default::default()
+        };
+        let args = adjustment.cache_args();
+        assert_eq!(args, " --grayscale --tint=88c0d0:0.3");
+        assert_eq!(from_cache_args(&args).unwrap(), adjustment);
+
+        let adjustment = ColorAdjustment {
+            contrast: 2.0,
+            dither: true,
+            ..Default::default()
+        };
+        let args = adjustment.cache_args();
+        assert_eq!(args, " --contrast=2 --dither");
+        assert_eq!(from_cache_args(&args).unwrap(), adjustment);
+        assert!(from_cache_args(" --gamma=x").is_err());
+    }
+
+    #[test]
+    fn gradient_follows_the_angle() {
+        let colors = [[0, 0, 0], [200, 100, 50]];
+        let pixel = |img: &[u8], x: usize, y: usize| img[(y * 4 + x) * 3..][..3].to_vec();
+
+        // left to right, with the format's channel order
+        let img = make_gradient(&colors, 0.0, (4, 4), PixelFormat::Rgb);
+        assert_eq!(pixel(&img, 0, 2), [6, 13, 25]);
+        assert_eq!(pixel(&img, 3, 0), [44, 88, 175]);
+        assert_eq!(pixel(&img, 0, 0), pixel(&img, 0, 3));
+        let img = make_gradient(&colors, 0.0, (4, 4), PixelFormat::Bgr);
+        assert_eq!(pixel(&img, 3, 0), [175, 88, 44]);
+
+        // top to bottom
+        let img = make_gradient(&colors, 90.0, (4, 4), PixelFormat::Bgr);
+        assert_eq!(pixel(&img, 0, 0), pixel(&img, 3, 0));
+        assert!(pixel(&img, 0, 0) < pixel(&img, 0, 3));
+
+        // more than two colors are spread evenly
+        let colors = [[0, 0, 0], [255, 255, 255], [0, 0, 0]];
+        let img = make_gradient(&colors, 180.0, (4, 1), PixelFormat::Bgr);
+        assert_eq!(pixel(&img, 0, 0), pixel(&img, 3, 0));
+        assert!(pixel(&img, 1, 0)[0] > 128);
+    }
+
+    #[test]
+    fn screenshot_undoes_pixel_format() {
+        let screenshot = |format: PixelFormat, pixel: &[u8]| ipc::Screenshot {
+            dim: (2, 1),
+            format,
+            img: pixel.repeat(2).into_boxed_slice(),
+        };
+
+        let expected = image::RgbImage::from_pixel(2, 1, image::Rgb([1, 2, 3]));
+        for (format, pixel) in [
+            (PixelFormat::Bgr, &[1, 2, 3][..]),
+            (PixelFormat::Rgb, &[3, 2, 1]),
+            (PixelFormat::Xbgr, &[1, 2, 3, 0]),
+            (PixelFormat::Xrgb, &[3, 2, 1, 0]),
+        ] {
+            assert_eq!(screenshot_to_rgb(&screenshot(format, pixel)), expected);
+        }
+    }
+
+    #[test]
+    fn tile_repeats_small_images() {
+        // a 2x2 image, with one distinct value for every pixel
+        let img = Image {
+            width: 2,
+            height: 2,
+            format: PixelFormat::Bgr,
+            bytes: [[1; 3], [2; 3], [3; 3], [4; 3]].concat().into_boxed_slice(),
+        };
+        let tiled = img_tile(&img, (5, 3)).unwrap();
+        let pixels: Vec<u8> = tiled.chunks_exact(3).map(|p| p[0]).collect();
+        assert_eq!(pixels, [1, 2, 1, 2, 1, 3, 4, 3, 4, 3, 1, 2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn tile_crops_large_images() {
+        let img = Image {
+            width: 3,
+            height: 3,
+            format: PixelFormat::Xrgb,
+            bytes: (0..9u8).flat_map(|p| [p; 4]).collect(),
+        };
+        let tiled = img_tile(&img, (2, 2)).unwrap();
+        let pixels: Vec<u8> = tiled.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(pixels, [0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn crop_keeps_the_focus_point_in_view() {
+        // a 4x1 strip, cropped down to a single pixel without scaling
+        let img = Image {
+            width: 4,
+            height: 1,
+            format: PixelFormat::Bgr,
+            bytes: [[10; 3], [20; 3], [30; 3], [40; 3]]
+                .concat()
+                .into_boxed_slice(),
+        };
+        let crop = |focus| {
+            img_resize_crop(&img, (1, 1), FilterType::Box, Resample::Srgb, focus).unwrap()[0]
+        };
+        assert_eq!(crop((0.0, 0.5)), 10);
+        assert_eq!(crop((1.0, 0.5)), 40);
+        // out of range values are clamped instead of cropping outside of the image
+        assert_eq!(crop((-3.0, 0.5)), 10);
+        assert_eq!(crop((8.0, 0.5)), 40);
+    }
+
+    #[test]
+    fn linear_resampling_averages_light() {
+        // a black and white checkerboard, averaged down to half its size
+        let img = Image {
+            width: 4,
+            height: 4,
+            format: PixelFormat::Xrgb,
+            bytes: (0..16)
+                .flat_map(|i| {
+                    let v = if (i + i / 4) % 2 == 0 { 0 } else { 255 };
+                    [v, v, v, 255]
+                })
+                .collect(),
+        };
+        for (resample, expected) in [(Resample::Srgb, 128), (Resample::Linear, 188)] {
+            let stretched = img_resize_stretch(&img, (2, 2), FilterType::Box, resample).unwrap();
+            for pixel in stretched.chunks_exact(4) {
+                assert!(
+                    pixel[..3].iter().all(|&v| v.abs_diff(expected) <= 1),
+                    "{resample:?}: {stretched:?}"
+                );
+            }
+            let fit =
+                img_resize_fit(&img, (2, 2), FilterType::Box, resample, &[0; 3], None).unwrap();
+            assert_eq!(fit, stretched);
+            let crop =
+                img_resize_crop(&img, (2, 2), FilterType::Box, resample, (0.5, 0.5)).unwrap();
+            assert_eq!(crop, stretched);
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_` to see how much linear
+    /// resampling costs on a typical 4k photo going to a 1080p output
+    #[test]
+    #[ignore]
+    fn bench_linear_resampling() {
+        let (width, height) = (3840, 2160);
+        let img = Image {
+            width,
+            height,
+            format: PixelFormat::Xrgb,
+            bytes: (0..width * height)
+                .flat_map(|i| [(i % 251) as u8, (i % 241) as u8, (i % 239) as u8, 255])
+                .collect(),
+        };
+        for resample in [Resample::Srgb, Resample::Linear] {
+            const RUNS: u32 = 10;
+            let start = std::time::Instant::now();
+            for _ in 0..RUNS {
+                img_resize_crop(
+                    &img,
+                    (1920, 1080),
+                    FilterType::Lanczos3,
+                    resample,
+                    (0.5, 0.5),
+                )
+                .unwrap();
+            }
+            eprintln!("{resample:?}: {:?} per resize", start.elapsed() / RUNS);
+        }
     }
 }
@@ -1,7 +1,16 @@
-use std::{path::Path, str::FromStr, time::Duration};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use common::cache;
+use common::compression::{
+    DEFAULT_COMPRESSION_LEVEL, FAST_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL,
+};
 use common::ipc::{self, Answer, Client, IpcSocket, RequestSend};
 use common::mmap::Mmap;
 use image::Pixel;
@@ -10,114 +19,959 @@ mod imgproc;
 use imgproc::*;
 
 mod cli;
-use cli::{CliImage, Filter, ResizeStrategy, Swww};
+use cli::{CliImage, Command, Filter, Resample, ResizeStrategy, Swww};
+
+/// How long we wait for the animation frames to be written to the cache before exiting
+const CACHE_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by the signal handlers `swww img --interval` installs, to tell it to stop
+static EXIT: AtomicBool = AtomicBool::new(false);
+
+/// How many times `-v` was given: 1 prints what we are doing, 2 also prints how long it took
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Prints a diagnostic to stderr if `-v` was given at least `$level` times
+macro_rules! verbose {
+    ($level:literal, $($arg:tt)*) => {
+        if VERBOSITY.load(Ordering::Relaxed) >= $level {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+extern "C" fn signal_handler(_s: libc::c_int) {
+    EXIT.store(true, Ordering::Relaxed);
+}
 
 fn main() -> Result<(), String> {
-    let swww = Swww::parse();
+    let matches = Swww::command().get_matches();
+    let mut swww = Swww::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let (Command::Clear(clear), Some((_, matches))) = (&mut swww.command, matches.subcommand()) {
+        clear.with_transition =
+            matches.value_source("transition_type") == Some(ValueSource::CommandLine);
+    }
+
+    if let Some(socket) = &swww.socket {
+        std::env::set_var(ipc::SOCKET_ENV, socket);
+    }
+    VERBOSITY.store(swww.verbose, Ordering::Relaxed);
 
-    if let Swww::ClearCache = &swww {
+    if let Command::ClearCache(clear_cache) = &swww.command {
+        return clear_cache_entries(clear_cache);
+    }
+
+    let result = match &swww.command {
+        Command::Restore(restore) if restore.all_namespaces => restore_all_namespaces(restore),
+        // we want to hear about the outputs being configured too, so we don't wait for them
+        Command::Subscribe(args) => subscribe(args, &swww.namespace),
+        // the daemon may come and go while we run, so we don't wait for it here either
+        Command::Img(img) if img.interval.is_some() => run_img_interval(&swww, img),
+        _ => wait_for_daemon(&swww.namespace).and_then(|()| process_swww_args(&swww)),
+    };
+    if !cache::wait_for_pending_writes(CACHE_WRITE_TIMEOUT) {
+        eprintln!("WARNING: timed out writing animation frames to the cache; they were not cached");
+    }
+    result
+}
+
+fn clear_cache_entries(clear_cache: &cli::ClearCache) -> Result<(), String> {
+    if clear_cache.older_than.is_none() && clear_cache.max_size.is_none() {
         return cache::clean().map_err(|e| format!("failed to clean the cache: {e}"));
     }
 
-    let socket = IpcSocket::connect().map_err(|err| err.to_string())?;
+    if let Some(age) = clear_cache.older_than {
+        cache::clean_older_than(age).map_err(|e| format!("failed to prune the cache: {e}"))?;
+    }
+    if let Some(max_size) = clear_cache.max_size {
+        cache::shrink_to(max_size).map_err(|e| format!("failed to prune the cache: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Waits until the daemon in `namespace` has configured all of its outputs
+fn wait_for_daemon(namespace: &str) -> Result<(), String> {
+    let socket = IpcSocket::connect(namespace).map_err(|err| err.to_string())?;
     loop {
         RequestSend::Ping.send(&socket)?;
         let bytes = socket.recv().map_err(|err| err.to_string())?;
-        let answer = Answer::receive(bytes);
-        if let Answer::Ping(configured) = answer {
-            if configured {
-                break;
-            }
-        } else {
-            return Err("Daemon did not return Answer::Ping, as expected".to_string());
+        match Answer::receive(bytes) {
+            Answer::Ping(true) => return Ok(()),
+            Answer::Ping(false) => (),
+            Answer::Err(e) => return Err(e),
+            _ => return Err("Daemon did not return Answer::Ping, as expected".to_string()),
         }
         std::thread::sleep(Duration::from_millis(1));
     }
+}
 
-    process_swww_args(&swww)
+/// Restores the cached wallpapers of every daemon we can find, skipping the ones that fail
+fn restore_all_namespaces(restore: &cli::Restore) -> Result<(), String> {
+    let namespaces = IpcSocket::<Client>::all_namespaces();
+    if namespaces.is_empty() {
+        return Err("could not find any running swww-daemon".to_string());
+    }
+    let requested_outputs = split_cmdline_outputs(&restore.outputs);
+    for namespace in namespaces {
+        let result = wait_for_daemon(&namespace)
+            .and_then(|()| restore_from_cache(&namespace, &requested_outputs));
+        if let Err(e) = result {
+            let namespace = if namespace.is_empty() {
+                "the default namespace"
+            } else {
+                &namespace
+            };
+            eprintln!("WARNING: failed to restore {namespace}: {e}");
+        }
+    }
+    Ok(())
 }
 
 fn process_swww_args(args: &Swww) -> Result<(), String> {
-    let request = match make_request(args)? {
-        Some(request) => request,
-        None => return Ok(()),
-    };
-    let socket = IpcSocket::connect().map_err(|err| err.to_string())?;
+    match make_request(args)? {
+        Some(request) => send_request(args, request),
+        None => Ok(()),
+    }
+}
+
+/// Sends `request` to the daemon, and handles its answer
+fn send_request(args: &Swww, request: RequestSend) -> Result<(), String> {
+    let socket = IpcSocket::connect(&args.namespace).map_err(|err| err.to_string())?;
+    let start = Instant::now();
     request.send(&socket)?;
     let bytes = socket.recv().map_err(|err| err.to_string())?;
+    let daemon_pid = socket.peer_pid();
     drop(socket);
+    verbose!(2, "the daemon answered after {:?}", start.elapsed());
     match Answer::receive(bytes) {
-        Answer::Info(info) => info.iter().for_each(|i| println!("{}", i)),
+        Answer::Info(info) => {
+            let (json, info) = match &args.command {
+                Command::Query(query) => (
+                    query.json,
+                    filter_infos(info, &split_cmdline_outputs(&query.outputs))?,
+                ),
+                _ => (false, info),
+            };
+            if json {
+                println!("{}", bg_infos_json(&info));
+            } else {
+                info.iter().for_each(|i| println!("{}", i));
+            }
+        }
         Answer::Ok => {
-            if let Swww::Kill = args {
-                #[cfg(debug_assertions)]
-                let tries = 20;
-                #[cfg(not(debug_assertions))]
-                let tries = 10;
-                let path = IpcSocket::<Client>::path();
-                let path = Path::new(path);
-                for _ in 0..tries {
-                    if !path.exists() {
-                        return Ok(());
-                    }
-                    std::thread::sleep(Duration::from_millis(100));
-                }
-                return Err(format!("Could not confirm socket deletion at: {path:?}"));
+            if let Command::Kill(cli::Kill { timeout }) = args.command {
+                return wait_for_daemon_exit(&args.namespace, timeout, daemon_pid);
+            }
+            if let Some(outputs) = sync_wait_outputs(&args.command) {
+                wait_idle(&args.namespace, outputs)?;
             }
         }
         Answer::Ping(_) => {
             return Ok(());
         }
+        Answer::Screenshot(screenshot) => {
+            if let Command::Screenshot(cli::Screenshot { output, file }) = &args.command {
+                let screenshot =
+                    screenshot.ok_or_else(|| format!("there is no output named {output}"))?;
+                screenshot_to_rgb(&screenshot)
+                    .save_with_format(file, image::ImageFormat::Png)
+                    .map_err(|e| format!("failed to save screenshot to {file:?}: {e}"))?;
+            }
+        }
+        Answer::Paused(paused) => {
+            if paused {
+                println!("paused");
+            } else {
+                println!("running");
+            }
+        }
+        Answer::Event(_) => {
+            return Err("Daemon sent an event without us subscribing".to_string());
+        }
+        Answer::Warnings(warnings) => {
+            // the rest of the request went through, so this isn't an error
+            for warning in warnings.iter() {
+                eprintln!("WARNING: {warning}");
+            }
+            if let Some(outputs) = sync_wait_outputs(&args.command) {
+                wait_idle(&args.namespace, outputs)?;
+            }
+        }
+        Answer::Err(e) => return Err(e),
     }
     Ok(())
 }
 
+/// Waits for the daemon to exit. Deleting its socket is the last thing it does, but under systemd
+/// socket activation the socket belongs to systemd and outlives it. So if we know the daemon's
+/// `pid`, we also stop waiting once its process is gone.
+///
+/// We never connect to the socket to check: with socket activation, that would just make systemd
+/// start a new daemon
+fn wait_for_daemon_exit(
+    namespace: &str,
+    timeout: Duration,
+    pid: Option<libc::pid_t>,
+) -> Result<(), String> {
+    let path = IpcSocket::<Client>::path(namespace);
+    let path = Path::new(&path);
+    let start = std::time::Instant::now();
+    while path.exists() && pid.is_none_or(is_running) {
+        if start.elapsed() >= timeout {
+            return Err(match pid {
+                Some(pid) => format!(
+                    "the daemon was told to exit, but it did not within {timeout:?}: it is \
+                     still running, with pid {pid}"
+                ),
+                None => format!(
+                    "the daemon was told to exit, but it did not within {timeout:?}: its socket \
+                     is still at {path:?}"
+                ),
+            });
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// Whether the process `pid` is still running. Zombies don't count: they already exited, and are
+/// only waiting for their parent to notice
+fn is_running(pid: libc::pid_t) -> bool {
+    if let Ok(stat) = std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+        // the state comes right after the executable's name, which is in parentheses
+        let state = stat
+            .rsplit_once(") ")
+            .and_then(|(_, rest)| rest.chars().next());
+        return !matches!(state, Some('Z' | 'X'));
+    }
+    // without procfs, all we can ask is whether the process exists
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Shows the next image of the directory given to `swww img --interval` every interval, until we
+/// get a SIGINT or SIGTERM. Failing to show an image is not fatal: we just try again with the next
+/// one
+fn run_img_interval(args: &Swww, img: &cli::Img) -> Result<(), String> {
+    let interval = img.interval.expect("we only get here with an interval");
+    let dir = match &img.image {
+        CliImage::Path(path) if path.is_dir() => path,
+        _ => return Err("`--interval` needs a directory to take images from".to_string()),
+    };
+    setup_signals();
+
+    let mut current: Option<PathBuf> = None;
+    while !EXIT.load(Ordering::Relaxed) {
+        let result = wait_for_daemon(&args.namespace).and_then(|()| {
+            let next = next_image_in_dir(dir, img.recursive, img.order, current.as_deref())?;
+            // move on even if this one fails, so that a single bad image can't get us stuck
+            current = Some(next.clone());
+            match make_img_command(img, &args.namespace, |_| Ok(vec![CliImage::Path(next)]))? {
+                Some(request) => send_request(args, request),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("WARNING: failed to change the wallpaper: {e}");
+        }
+
+        // sleep in small steps, so that we exit right away when told to
+        let start = std::time::Instant::now();
+        while !EXIT.load(Ordering::Relaxed) {
+            let Some(left) = interval.checked_sub(start.elapsed()) else {
+                break;
+            };
+            std::thread::sleep(left.min(Duration::from_millis(100)));
+        }
+    }
+    Ok(())
+}
+
+fn setup_signals() {
+    let handler = signal_handler as extern "C" fn(libc::c_int);
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let ret = unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+        if ret == libc::SIG_ERR {
+            eprintln!("WARNING: failed to install signal handler");
+        }
+    }
+}
+
+/// Prints every event the daemon sends us, until it exits
+fn subscribe(args: &cli::Subscribe, namespace: &str) -> Result<(), String> {
+    let socket = IpcSocket::connect(namespace).map_err(|err| err.to_string())?;
+    RequestSend::Subscribe.send(&socket)?;
+    let bytes = socket.recv().map_err(|err| err.to_string())?;
+    match Answer::receive(bytes) {
+        Answer::Ok => (),
+        Answer::Err(e) => return Err(e),
+        _ => return Err("Daemon did not return Answer::Ok, as expected".to_string()),
+    }
+    // events may be hours apart
+    socket
+        .set_recv_timeout(None)
+        .map_err(|err| err.to_string())?;
+    let mut stdout = std::io::stdout().lock();
+    loop {
+        let bytes = match socket.recv() {
+            Ok(bytes) => bytes,
+            Err(e) if e.is_closed() => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let Answer::Event(event) = Answer::receive(bytes) else {
+            return Err("Daemon sent something other than an event".to_string());
+        };
+        // stdout is only line buffered if it is a terminal, and scripts need every line right away
+        let line = if args.json {
+            event_json(&event)
+        } else {
+            event.to_string()
+        };
+        writeln!(stdout, "{line}")
+            .and_then(|()| stdout.flush())
+            .map_err(|e| format!("failed to print event: {e}"))?;
+    }
+}
+
+/// The outputs we should wait on before exiting, if the user asked us to with `--sync-wait`. An
+/// empty list means every output
+fn sync_wait_outputs(command: &Command) -> Option<Box<[String]>> {
+    match command {
+        Command::Clear(clear) if clear.sync_wait => Some(split_cmdline_outputs(&clear.outputs)),
+        Command::Img(img) if img.sync_wait => {
+            let groups: Vec<_> = img
+                .outputs
+                .iter()
+                .map(|o| split_cmdline_outputs(o))
+                .collect();
+            if groups.iter().any(|group| group.is_empty()) {
+                Some(Box::new([]))
+            } else {
+                Some(
+                    groups
+                        .into_iter()
+                        .flat_map(|group| group.into_vec())
+                        .collect(),
+                )
+            }
+        }
+        _ => None,
+    }
+}
+
+fn pause_request(pause: &cli::Pause, namespace: &str) -> Result<Mmap, String> {
+    Ok(ipc::PauseSend {
+        outputs: resolve_outputs(namespace, split_cmdline_outputs(&pause.outputs))?,
+    }
+    .create_request())
+}
+
+/// Blocks until the daemon tells us none of `outputs` is in the middle of a transition
+fn wait_idle(namespace: &str, outputs: Box<[String]>) -> Result<(), String> {
+    let outputs = resolve_outputs(namespace, outputs)?;
+    let socket = IpcSocket::connect(namespace).map_err(|err| err.to_string())?;
+    // transitions can take far longer than the usual timeout
+    socket
+        .set_recv_timeout(None)
+        .map_err(|err| err.to_string())?;
+    RequestSend::WaitIdle(ipc::WaitIdleSend { outputs }.create_request()).send(&socket)?;
+    let bytes = socket.recv().map_err(|err| err.to_string())?;
+    match Answer::receive(bytes) {
+        Answer::Ok => Ok(()),
+        Answer::Err(e) => Err(e),
+        _ => Err("Daemon did not return Answer::Ok, as expected".to_string()),
+    }
+}
+
 fn make_request(args: &Swww) -> Result<Option<RequestSend>, String> {
-    match args {
-        Swww::Clear(c) => {
-            let (format, _, _) = get_format_dims_and_outputs(&[])?;
+    let namespace = &args.namespace;
+    match &args.command {
+        Command::Clear(c) => {
+            if let Some(gradient) = &c.gradient {
+                return make_gradient_request(c, gradient, namespace).map(Some);
+            }
+            let (format, _, _) = get_format_dims_and_outputs(namespace, &[])?;
             let mut color = c.color;
             if format.must_swap_r_and_b_channels() {
                 color.swap(0, 2);
             }
-            let clear = ipc::ClearSend {
-                color,
-                outputs: split_cmdline_outputs(&c.outputs),
+            let clear = match &c.output_desc {
+                // the daemon matches descriptions itself
+                Some(desc) => ipc::ClearSend {
+                    color,
+                    outputs: Box::new([desc.clone()]),
+                    match_by: ipc::OutputMatch::Description,
+                    transition: clear_transition(c)?,
+                },
+                None => ipc::ClearSend {
+                    color,
+                    outputs: resolve_outputs(namespace, split_cmdline_outputs(&c.outputs))?,
+                    match_by: ipc::OutputMatch::Name,
+                    transition: clear_transition(c)?,
+                },
             };
             Ok(Some(RequestSend::Clear(clear.create_request())))
         }
-        Swww::Restore(restore) => {
+        Command::Restore(restore) => {
             let requested_outputs = split_cmdline_outputs(&restore.outputs);
-            restore_from_cache(&requested_outputs)?;
+            restore_from_cache(namespace, &requested_outputs)?;
             Ok(None)
         }
-        Swww::ClearCache => unreachable!("there is no request for clear-cache"),
-        Swww::Img(img) => {
-            let requested_outputs = split_cmdline_outputs(&img.outputs);
-            let (format, dims, outputs) = get_format_dims_and_outputs(&requested_outputs)?;
-            // let imgbuf = ImgBuf::new(&img.path)?;
+        Command::Undo(undo) => {
+            let requested_outputs = split_cmdline_outputs(&undo.outputs);
+            undo_from_cache(namespace, &requested_outputs)?;
+            Ok(None)
+        }
+        Command::ClearCache(_) => unreachable!("there is no request for clear-cache"),
+        Command::Subscribe(_) => unreachable!("subscribe keeps its own connection open"),
+        Command::Preload(preload) => {
+            preload_image(preload, namespace)?;
+            Ok(None)
+        }
+        Command::Img(img) => make_img_command(img, namespace, |groups| img_images(img, groups)),
+        Command::Slideshow(slideshow) => {
+            if slideshow.transition.count() > 1 {
+                return Err(
+                    "slideshows only take one value for each `--transition-*` flag".to_string(),
+                );
+            }
+            if slideshow
+                .transition
+                .transition_type
+                .iter()
+                .any(|types| !types.outputs.is_empty())
+            {
+                return Err("slideshows can't give outputs their own transition type".to_string());
+            }
+            let mask = load_transition_mask(&slideshow.transition, 0)?;
+            let requested_outputs = split_cmdline_outputs(&slideshow.outputs);
+            let (format, dims, outputs) =
+                get_format_dims_and_outputs(namespace, &requested_outputs)?;
+            let transition = make_transition(&slideshow.transition, 0);
+            let transitions: Vec<_> = dims
+                .iter()
+                .map(|&dim| {
+                    mask.as_ref()
+                        .map(|mask| with_transition_mask(&transition, mask, dim))
+                })
+                .collect();
+            let mut img_req_builder = ipc::ImageRequestBuilder::new(transition, namespace);
+            img_req_builder.set_animation_cache_writes(!slideshow.no_cache_write);
+            let options =
+                ImgOptions::resized(slideshow.resize, slideshow.fill_color, &slideshow.filter);
+            for image in &slideshow.images {
+                push_img(
+                    &mut img_req_builder,
+                    image,
+                    &options,
+                    &dims,
+                    format,
+                    &outputs,
+                    &transitions,
+                )?;
+            }
+            let order = match slideshow.order {
+                cli::SlideshowOrder::Sequential => ipc::SlideshowOrder::Sequential,
+                cli::SlideshowOrder::Random => ipc::SlideshowOrder::Random,
+            };
+            Ok(Some(RequestSend::Slideshow(
+                img_req_builder.build_slideshow(slideshow.interval, order),
+            )))
+        }
+        Command::Pause(pause) => Ok(Some(RequestSend::Pause(pause_request(pause, namespace)?))),
+        Command::Resume(resume) => Ok(Some(RequestSend::Resume(pause_request(resume, namespace)?))),
+        Command::TogglePause(toggle) => Ok(Some(RequestSend::TogglePause(pause_request(
+            toggle, namespace,
+        )?))),
+        Command::Kill(_) => Ok(Some(RequestSend::Kill)),
+        Command::Query(_) => Ok(Some(RequestSend::Query)),
+        Command::Screenshot(screenshot) => {
+            let screenshot = ipc::ScreenshotSend {
+                output: screenshot.output.clone(),
+            };
+            Ok(Some(RequestSend::Screenshot(screenshot.create_request())))
+        }
+        Command::Layer(set_layer) => {
+            let layer = ipc::LayerSend {
+                layer: match set_layer.layer {
+                    cli::Layer::Background => ipc::Layer::Background,
+                    cli::Layer::Bottom => ipc::Layer::Bottom,
+                    cli::Layer::Top => ipc::Layer::Top,
+                    cli::Layer::Overlay => ipc::Layer::Overlay,
+                },
+                outputs: resolve_outputs(namespace, split_cmdline_outputs(&set_layer.outputs))?,
+            };
+            Ok(Some(RequestSend::SetLayer(layer.create_request())))
+        }
+    }
+}
 
-            let img_request = make_img_request(img, &dims, format, &outputs)?;
+/// The transition `swww clear` changes into the new color with, if it was asked for one
+fn clear_transition(clear: &cli::Clear) -> Result<Option<ipc::Transition>, String> {
+    if !clear.with_transition {
+        return Ok(None);
+    }
+    let transition = &clear.transition;
+    if transition.count() > 1 {
+        return Err("`swww clear` only takes one value for each `--transition-*` flag".to_string());
+    }
+    if !transition.transition_mask.is_empty() {
+        return Err("`swww clear` does not take a `--transition-mask`".to_string());
+    }
+    if transition
+        .transition_type
+        .iter()
+        .any(|types| !types.outputs.is_empty())
+    {
+        return Err("`swww clear` can't give outputs their own transition type".to_string());
+    }
+    Ok(Some(make_transition(transition, 0)))
+}
 
-            Ok(Some(RequestSend::Img(img_request)))
-        }
-        Swww::Kill => Ok(Some(RequestSend::Kill)),
-        Swww::Query => Ok(Some(RequestSend::Query)),
+/// A transition that switches to the new image instantly
+fn no_transition() -> ipc::Transition {
+    ipc::Transition {
+        transition_type: ipc::TransitionType::None,
+        duration: 0.0,
+        step: std::num::NonZeroU8::MAX,
+        fps: 30,
+        angle: 0.0,
+        pos: ipc::Position::new(ipc::Coord::Pixel(0.0), ipc::Coord::Pixel(0.0)),
+        bezier: (0.0, 0.0, 0.0, 0.0),
+        wave: (0.0, 0.0),
+        invert_y: false,
+        linear: false,
+        mask: None,
+    }
+}
+
+/// Gradients have to be drawn for each output's dimensions, so unlike a plain `swww clear`, they
+/// go through the same path as images
+fn make_gradient_request(
+    clear: &cli::Clear,
+    colors: &[[u8; 3]],
+    namespace: &str,
+) -> Result<RequestSend, String> {
+    let requested_outputs = match &clear.output_desc {
+        Some(desc) => outputs_with_description(namespace, desc)?,
+        None => split_cmdline_outputs(&clear.outputs),
+    };
+    let (format, dims, outputs) = get_format_dims_and_outputs(namespace, &requested_outputs)?;
+
+    let transition = clear_transition(clear)?.unwrap_or_else(no_transition);
+    let mut img_req_builder = ipc::ImageRequestBuilder::new(transition, namespace);
+    // like a plain `swww clear`, this isn't remembered for `swww restore`
+    img_req_builder.set_cache_writes(false);
+
+    let hex: Vec<_> = colors
+        .iter()
+        .map(|c| format!("{:02x}{:02x}{:02x}", c[0], c[1], c[2]))
+        .collect();
+    let path = format!("gradient {} at {}°", hex.join("-"), clear.gradient_angle);
+    for (&dim, outputs) in dims.iter().zip(&outputs) {
+        img_req_builder.push(
+            ipc::ImgSend {
+                img: make_gradient(colors, clear.gradient_angle, dim, format),
+                path: path.clone(),
+                dim,
+                format,
+                transition: None,
+            },
+            Filter::Lanczos3.to_string(),
+            outputs,
+            None,
+        );
     }
+    Ok(RequestSend::Img(img_req_builder.build()))
 }
 
-fn make_img_request(
+/// Makes the request for `swww img`, with the images `pick_images` gives us for the number of
+/// groups of outputs we end up with
+fn make_img_command(
     img: &cli::Img,
-    dims: &[(u32, u32)],
-    pixel_format: ipc::PixelFormat,
+    namespace: &str,
+    pick_images: impl FnOnce(usize) -> Result<Vec<CliImage>, String>,
+) -> Result<Option<RequestSend>, String> {
+    if img.raw.is_some() && !matches!(&img.image, CliImage::Path(p) if p.to_str() == Some("-")) {
+        return Err(
+            "`--raw` only works when reading the image from standard input, with '-'".to_string(),
+        );
+    }
+    if img.transition.count() > img.outputs.len() {
+        return Err(
+            "there are more values for the `--transition-*` flags than groups of `--outputs`"
+                .to_string(),
+        );
+    }
+
+    let mut format = ipc::PixelFormat::Xrgb;
+    let mut dims = Vec::new();
+    let mut outputs = Vec::new();
+    let mut transitions = Vec::new();
+    for (i, group) in img.outputs.iter().enumerate() {
+        // load the mask first, so that a bad path fails before we talk to the daemon
+        let mask = load_transition_mask(&img.transition, i)?;
+        let requested_outputs = match &img.output_desc {
+            Some(desc) => outputs_with_description(namespace, desc)?,
+            None => split_cmdline_outputs(group),
+        };
+        if requested_outputs.is_empty() && img.outputs.len() > 1 {
+            return Err(
+                "when `--outputs` is given more than once, none of them may be empty".to_string(),
+            );
+        }
+        let (group_format, group_dims, group_outputs) =
+            get_format_dims_and_outputs(namespace, &requested_outputs)?;
+        let transition = make_transition(&img.transition, i);
+        for (dim, group_outputs) in group_dims.into_iter().zip(group_outputs) {
+            // outputs with a transition type of their own can't share it with the others
+            let mut shared = Vec::new();
+            for output in group_outputs {
+                let Some(own) = make_output_transition(&img.transition, i, &output) else {
+                    shared.push(output);
+                    continue;
+                };
+                transitions.push(Some(match &mask {
+                    Some(mask) => with_transition_mask(&own, mask, dim),
+                    None => own,
+                }));
+                dims.push(dim);
+                outputs.push(vec![output]);
+            }
+            if !shared.is_empty() {
+                // the first group uses the request's transition, unless it needs a mask
+                // made for each output size
+                transitions.push(match &mask {
+                    Some(mask) => Some(with_transition_mask(&transition, mask, dim)),
+                    None => (i > 0).then(|| transition.clone()),
+                });
+                dims.push(dim);
+                outputs.push(shared);
+            }
+        }
+        format = group_format;
+    }
+
+    if img.random_per_output {
+        split_output_groups(&mut dims, &mut outputs, &mut transitions);
+    }
+    let span = if img.span {
+        if img.outputs.len() > 1 {
+            return Err(
+                "`--span` spreads the image across a single group of `--outputs`".to_string(),
+            );
+        }
+        // every output shows a different part of the image
+        split_output_groups(&mut dims, &mut outputs, &mut transitions);
+        Some(Span::Layout(output_layout(namespace, &outputs)?))
+    } else {
+        img.region.map(Span::Region)
+    };
+    let images = pick_images(outputs.len())?;
+
+    if img.dry_run {
+        for (image, groups) in image_groups(&images, outputs.len()) {
+            let report =
+                dry_run_report(img, image, &dims[groups.clone()], format, &outputs[groups])?;
+            print!("{report}");
+        }
+        return Ok(None);
+    }
+
+    let options = ImgOptions {
+        span: span.as_ref(),
+        ..ImgOptions::from(img)
+    };
+    let img_request = make_img_request_in_background(namespace, || {
+        let mut img_req_builder =
+            ipc::ImageRequestBuilder::new(make_transition(&img.transition, 0), namespace);
+        // the cache is keyed by path only, so it cannot tell adjusted animations apart
+        img_req_builder
+            .set_animation_cache_writes(!img.no_cache_write && options.adjustment.is_neutral());
+        img_req_builder.set_transformed(img.transform.is_some());
+        for (image, groups) in image_groups(&images, outputs.len()) {
+            push_img(
+                &mut img_req_builder,
+                image,
+                &options,
+                &dims[groups.clone()],
+                format,
+                &outputs[groups.clone()],
+                &transitions[groups],
+            )?;
+        }
+        Ok(img_req_builder.build())
+    })?;
+
+    Ok(Some(RequestSend::Img(img_request)))
+}
+
+/// Where the first output of every group is in the compositor's layout
+fn output_layout(
+    namespace: &str,
     outputs: &[Vec<String>],
+) -> Result<Vec<(String, OutputRect)>, String> {
+    let infos = query_outputs(namespace)?;
+    outputs
+        .iter()
+        .map(|group| {
+            let name = &group[0];
+            let info = infos
+                .iter()
+                .find(|info| &info.name == name)
+                .ok_or_else(|| format!("output {name} disappeared"))?;
+            let (x, y) = info.position;
+            Ok((name.clone(), (x, y, info.dim.0, info.dim.1)))
+        })
+        .collect()
+}
+
+/// The images to display: just the one in the command line, or random picks out of its directory
+/// with `--random`. With `--random-per-output`, there is one for each of the `groups`
+fn img_images(img: &cli::Img, groups: usize) -> Result<Vec<CliImage>, String> {
+    let dir = match &img.image {
+        CliImage::Path(path) if path.is_dir() => path,
+        _ if img.random => {
+            return Err("`--random` needs a directory to pick images from".to_string())
+        }
+        _ => return Ok(vec![img.image.clone()]),
+    };
+    if !img.random {
+        return Err(format!(
+            "{} is a directory. Use `--random` to display a random image out of it",
+            dir.display()
+        ));
+    }
+    let count = if img.random_per_output { groups } else { 1 };
+    let candidates = images_in_dir(dir, img.recursive)?;
+    Ok(pick_random_images(candidates, count)?
+        .into_iter()
+        .map(CliImage::Path)
+        .collect())
+}
+
+/// Which groups of outputs each of `images` goes to: all of them if there is only one image, and
+/// the group at the same index otherwise
+fn image_groups(
+    images: &[CliImage],
+    groups: usize,
+) -> impl Iterator<Item = (&CliImage, std::ops::Range<usize>)> {
+    let single = images.len() == 1;
+    images
+        .iter()
+        .enumerate()
+        .map(move |(i, image)| (image, if single { 0..groups } else { i..i + 1 }))
+}
+
+/// Gives every output a group of its own, so that it can get an image of its own
+fn split_output_groups(
+    dims: &mut Vec<(u32, u32)>,
+    outputs: &mut Vec<Vec<String>>,
+    transitions: &mut Vec<Option<ipc::Transition>>,
+) {
+    let groups = std::mem::take(outputs);
+    let old_dims = std::mem::take(dims);
+    let old_transitions = std::mem::take(transitions);
+    for ((dim, group), transition) in old_dims.into_iter().zip(groups).zip(old_transitions) {
+        for output in group {
+            dims.push(dim);
+            outputs.push(vec![output]);
+            transitions.push(transition.clone());
+        }
+    }
+}
+
+/// Runs the image through the same pipeline as `swww img` for every output, without sending it, so
+/// that its animation frames end up in the cache
+fn preload_image(preload: &cli::Preload, namespace: &str) -> Result<(), String> {
+    if preload.image.to_str() == Some("-") {
+        return Err("can't preload standard input, since it can't be read again later".to_string());
+    }
+    let requested_outputs = split_cmdline_outputs(&preload.outputs);
+    let (format, dims, outputs) = get_format_dims_and_outputs(namespace, &requested_outputs)?;
+    let mut img_req_builder = ipc::ImageRequestBuilder::new(no_transition(), namespace);
+    // nothing gets displayed, so there is nothing for `swww restore` to remember
+    img_req_builder.set_cache_writes(false);
+    push_img(
+        &mut img_req_builder,
+        &CliImage::Path(preload.image.clone()),
+        &ImgOptions::resized(preload.resize, preload.fill_color, &preload.filter),
+        &dims,
+        format,
+        &outputs,
+        &vec![None; dims.len()],
+    )?;
+    // the frames are written to the cache in the background, and `main` waits for them before
+    // exiting
+    drop(img_req_builder);
+    Ok(())
+}
+
+/// How often we make sure the daemon is still running, while an image is processed
+const DAEMON_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// After how long processing an image we start showing how many animation frames are done
+const PROGRESS_DELAY: Duration = Duration::from_millis(500);
+
+/// Runs `make_request` on a worker thread, since decoding, resizing and compressing big images and
+/// animations can take several seconds. Meanwhile, we show how many animation frames are done, and
+/// keep checking on the daemon, so that we can tell if it went away before it got the image
+fn make_img_request_in_background(
+    namespace: &str,
+    make_request: impl FnOnce() -> Result<Mmap, String> + Send,
 ) -> Result<Mmap, String> {
-    let transition = make_transition(img);
-    let mut img_req_builder = ipc::ImageRequestBuilder::new(transition);
+    use std::io::IsTerminal;
+
+    let start = std::time::Instant::now();
+    let frames_before = frames_processed();
+    // progress lines only make sense to someone watching
+    let show_progress = std::io::stderr().is_terminal();
+    let mut shown_progress = None;
+    let mut last_check = start;
+    let mut daemon_error = None;
+
+    let request = std::thread::scope(|scope| {
+        let worker = scope.spawn(make_request);
+        while !worker.is_finished() {
+            std::thread::sleep(Duration::from_millis(50));
+            let frames = frames_processed() - frames_before;
+            if show_progress
+                && frames > 0
+                && start.elapsed() >= PROGRESS_DELAY
+                && shown_progress != Some(frames)
+            {
+                eprint!("\rprocessed {frames} animation frames");
+                shown_progress = Some(frames);
+            }
+            if daemon_error.is_none() && last_check.elapsed() >= DAEMON_CHECK_INTERVAL {
+                last_check = std::time::Instant::now();
+                daemon_error = ping_daemon(namespace).err();
+            }
+        }
+        worker.join().expect("image worker panicked")
+    });
+    if shown_progress.is_some() {
+        eprintln!();
+    }
+
+    let request = request?;
+    match daemon_error {
+        Some(e) => Err(format!("lost the daemon while processing the image: {e}")),
+        None => Ok(request),
+    }
+}
+
+/// Makes sure the daemon in `namespace` is still running and answering requests
+fn ping_daemon(namespace: &str) -> Result<(), String> {
+    let socket = IpcSocket::connect(namespace).map_err(|err| err.to_string())?;
+    RequestSend::Ping.send(&socket)?;
+    let bytes = socket.recv().map_err(|err| err.to_string())?;
+    match Answer::receive(bytes) {
+        Answer::Ping(_) => Ok(()),
+        Answer::Err(e) => Err(e),
+        _ => Err("Daemon did not return Answer::Ping, as expected".to_string()),
+    }
+}
+
+/// How `push_img` turns an image into what the daemon displays
+struct ImgOptions<'a> {
+    /// the dimensions of the raw pixels read from standard input, with `--raw`
+    raw: Option<(u32, u32)>,
+    resize: ResizeStrategy,
+    crop_focus: (f32, f32),
+    span: Option<&'a Span>,
+    fill_color: [u8; 3],
+    /// the blur radius behind images resized with `--resize fit`, if they aren't padded with a
+    /// color
+    fit_blur: Option<f32>,
+    filter: Filter,
+    resample: Resample,
+    ignore_exif: bool,
+    adjustment: ColorAdjustment,
+    transform: Option<cli::Transform>,
+    compression_level: u8,
+    loop_count: u32,
+    playback_speed: f32,
+}
+
+impl ImgOptions<'_> {
+    /// `swww img`'s defaults for everything but how the image is resized, for the commands that
+    /// only take flags for that
+    fn resized(resize: ResizeStrategy, fill_color: [u8; 3], filter: &Filter) -> Self {
+        Self {
+            raw: None,
+            resize,
+            crop_focus: (0.5, 0.5),
+            span: None,
+            fill_color,
+            fit_blur: None,
+            filter: filter.clone(),
+            resample: Resample::Srgb,
+            ignore_exif: false,
+            adjustment: ColorAdjustment::default(),
+            transform: None,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            loop_count: 0,
+            playback_speed: 1.0,
+        }
+    }
+}
+
+/// Everything but `--span`, which needs the outputs' layout
+impl From<&cli::Img> for ImgOptions<'_> {
+    fn from(img: &cli::Img) -> Self {
+        Self {
+            raw: img.raw,
+            resize: img.resize,
+            crop_focus: img.crop_focus,
+            span: None,
+            fill_color: img.fill_color,
+            fit_blur: (img.fit_fill == cli::FitFill::Blur).then_some(img.fit_blur),
+            filter: img.filter.clone(),
+            resample: img.resample,
+            ignore_exif: img.ignore_exif,
+            adjustment: ColorAdjustment::from(img),
+            transform: img.transform,
+            compression_level: match img.compression {
+                Some(cli::Compression::Fast) => FAST_COMPRESSION_LEVEL,
+                Some(cli::Compression::Default) => DEFAULT_COMPRESSION_LEVEL,
+                Some(cli::Compression::Best) => MAX_COMPRESSION_LEVEL,
+                None => img.compression_level,
+            },
+            loop_count: img.loop_count,
+            playback_speed: img.playback_speed,
+        }
+    }
+}
 
-    match &img.image {
+fn push_img(
+    img_req_builder: &mut ipc::ImageRequestBuilder,
+    image: &CliImage,
+    options: &ImgOptions,
+    dims: &[(u32, u32)],
+    pixel_format: ipc::PixelFormat,
+    outputs: &[Vec<String>],
+    transitions: &[Option<ipc::Transition>],
+) -> Result<(), String> {
+    let ImgOptions {
+        raw,
+        resize,
+        crop_focus,
+        span,
+        ref fill_color,
+        fit_blur,
+        ref filter,
+        resample,
+        ignore_exif,
+        ref adjustment,
+        transform,
+        compression_level,
+        loop_count,
+        playback_speed,
+    } = *options;
+    match image {
         CliImage::Color(color) => {
-            for (&dim, outputs) in dims.iter().zip(outputs) {
+            let color = &adjustment.adjust_color(*color);
+            for ((&dim, outputs), transition) in dims.iter().zip(outputs).zip(transitions) {
                 img_req_builder.push(
                     ipc::ImgSend {
                         img: image::RgbaImage::from_pixel(
@@ -130,6 +984,7 @@ fn make_img_request(
                         path: format!("0x{:02x}{:02x}{:02x}", color[0], color[1], color[2]),
                         dim,
                         format: pixel_format,
+                        transition: transition.clone(),
                     },
                     Filter::Lanczos3.to_string(),
                     outputs,
@@ -138,72 +993,167 @@ fn make_img_request(
             }
         }
         CliImage::Path(img_path) => {
-            let imgbuf = ImgBuf::new(img_path)?;
-            let img_raw = imgbuf.decode(pixel_format)?;
+            let cache_variant = animation_cache_variant(options);
+            img_req_builder.set_animation_cache_variant(cache_variant.clone());
+            verbose!(1, "loading {}", img_path.display());
+            let start = Instant::now();
+            let imgbuf = match raw {
+                Some(dim) => ImgBuf::from_raw_stdin(dim)?,
+                None => ImgBuf::new(img_path)?,
+            };
+            let mut img_raw = imgbuf.decode(pixel_format, ignore_exif)?;
+            img_raw.adjust_colors(adjustment);
+            img_raw.transform(transform.unwrap_or_default());
+            verbose!(2, "decoded {} in {:?}", img_path.display(), start.elapsed());
+            let regions = match span {
+                Some(_) if imgbuf.is_animated() => {
+                    return Err("`--span` does not work with animated images".to_string())
+                }
+                Some(span) => Some(span.regions(&img_raw, dims.len(), crop_focus)?),
+                None => None,
+            };
 
-            for (&dim, outputs) in dims.iter().zip(outputs) {
+            for (i, ((&dim, outputs), transition)) in
+                dims.iter().zip(outputs).zip(transitions).enumerate()
+            {
                 let path = match img_path.canonicalize() {
                     Ok(p) => p.to_string_lossy().to_string(),
-                    Err(e) => {
-                        if let Some("-") = img_path.to_str() {
-                            "STDIN".to_string()
-                        } else {
-                            return Err(format!("failed no canonicalize image path: {e}"));
-                        }
-                    }
+                    Err(e) => match img_path.to_str() {
+                        Some("-") => "STDIN".to_string(),
+                        // `swww restore` downloads the image again from the same url
+                        Some(url) if cli::is_url(url) => url.to_string(),
+                        _ => return Err(format!("failed no canonicalize image path: {e}")),
+                    },
                 };
 
                 let animation = if !imgbuf.is_animated() {
                     None
-                } else if img.resize == ResizeStrategy::Crop {
-                    match cache::load_animation_frames(path.as_ref(), dim, pixel_format) {
-                        Ok(Some(animation)) => Some(animation),
+                } else {
+                    let cached = if adjustment.is_neutral() {
+                        cache::load_animation_frames(
+                            path.as_ref(),
+                            &cache_variant,
+                            dim,
+                            pixel_format,
+                        )
+                    } else {
+                        Ok(None)
+                    };
+                    let animation = match cached {
+                        Ok(Some(animation)) => {
+                            verbose!(
+                                1,
+                                "using the cached animation frames for {}x{}",
+                                dim.0,
+                                dim.1
+                            );
+                            Some(animation)
+                        }
                         otherwise => {
                             if let Err(e) = otherwise {
                                 eprintln!("Error loading cache for {:?}: {e}", img_path);
                             }
 
-                            Some({
-                                ipc::Animation {
-                                    animation: compress_frames(
-                                        imgbuf.as_frames()?,
-                                        dim,
-                                        pixel_format,
-                                        make_filter(&img.filter),
-                                        img.resize,
-                                        &img.fill_color,
-                                    )?
-                                    .into_boxed_slice(),
-                                }
-                            })
+                            verbose!(
+                                1,
+                                "compressing the animation frames for {}x{}",
+                                dim.0,
+                                dim.1
+                            );
+                            let start = Instant::now();
+                            let animation = compress_frames(
+                                imgbuf.as_frames()?,
+                                dim,
+                                pixel_format,
+                                make_filter(filter),
+                                resample,
+                                resize,
+                                crop_focus,
+                                fill_color,
+                                fit_blur,
+                                adjustment,
+                                transform.unwrap_or_default(),
+                                compression_level,
+                                default_compression_workers(),
+                            )?;
+                            verbose!(
+                                2,
+                                "compressed {} frames in {:?}",
+                                animation.len(),
+                                start.elapsed()
+                            );
+                            // if too few frames could be decoded, we just send a static image
+                            if animation.is_empty() {
+                                None
+                            } else {
+                                Some(ipc::Animation {
+                                    animation: animation.into_boxed_slice(),
+                                    loop_count: 0,
+                                    speed: 1.0,
+                                })
+                            }
                         }
-                    }
-                } else {
-                    None
+                    };
+                    animation.map(|animation| ipc::Animation {
+                        loop_count,
+                        speed: playback_speed,
+                        ..animation
+                    })
                 };
 
-                let filter = img.filter.to_string();
-                let img = match img.resize {
-                    ResizeStrategy::No => img_pad(&img_raw, dim, &img.fill_color)?,
-                    ResizeStrategy::Crop => {
-                        img_resize_crop(&img_raw, dim, make_filter(&img.filter))?
-                    }
-                    ResizeStrategy::Fit => {
-                        img_resize_fit(&img_raw, dim, make_filter(&img.filter), &img.fill_color)?
-                    }
-                    ResizeStrategy::Stretch => {
-                        img_resize_stretch(&img_raw, dim, make_filter(&img.filter))?
+                let region = regions.as_ref().map(|regions| regions[i]);
+                let start = Instant::now();
+                let img = if let Some(region) = region {
+                    img_resize_region(&img_raw, dim, make_filter(filter), resample, region)?
+                } else {
+                    match resize {
+                        ResizeStrategy::No => img_pad(&img_raw, dim, fill_color)?,
+                        ResizeStrategy::Crop => img_resize_crop(
+                            &img_raw,
+                            dim,
+                            make_filter(filter),
+                            resample,
+                            crop_focus,
+                        )?,
+                        ResizeStrategy::Fit => img_resize_fit(
+                            &img_raw,
+                            dim,
+                            make_filter(filter),
+                            resample,
+                            fill_color,
+                            fit_blur,
+                        )?,
+                        ResizeStrategy::Stretch => {
+                            img_resize_stretch(&img_raw, dim, make_filter(filter), resample)?
+                        }
+                        ResizeStrategy::Tile => img_tile(&img_raw, dim)?,
                     }
                 };
+                verbose!(2, "resized to {}x{} in {:?}", dim.0, dim.1, start.elapsed());
 
+                // everything the image was displayed with is remembered along with the filter, so
+                // restoring it brings it back looking the same
+                let settings = format!(
+                    "{filter}{}{}{}{}{}",
+                    adjustment.cache_args(),
+                    transform_cache_arg(transform),
+                    img_cache_args(options),
+                    transition_cache_args(
+                        transition
+                            .as_ref()
+                            .unwrap_or_else(|| img_req_builder.transition())
+                    ),
+                    region.map(region_cache_arg).unwrap_or_default()
+                );
                 img_req_builder.push(
                     ipc::ImgSend {
                         img,
                         path,
                         dim,
                         format: pixel_format,
+                        transition: transition.clone(),
                     },
-                    filter,
+                    settings,
                     outputs,
                     animation,
                 );
@@ -211,56 +1161,518 @@ fn make_img_request(
         }
     }
 
-    Ok(img_req_builder.build())
+    Ok(())
+}
+
+/// Describes what `swww img` would send to each group of outputs, for `--dry-run`
+fn dry_run_report(
+    img: &cli::Img,
+    image: &CliImage,
+    dims: &[(u32, u32)],
+    pixel_format: ipc::PixelFormat,
+    outputs: &[Vec<String>],
+) -> Result<String, String> {
+    let resize = img.resize.to_possible_value().unwrap();
+    let mut report = match image {
+        CliImage::Color(color) => format!(
+            "color: 0x{:02x}{:02x}{:02x}\n",
+            color[0], color[1], color[2]
+        ),
+        CliImage::Path(path) => format!(
+            "image: {}\nfilter: {}\nresize: {}\n",
+            path.display(),
+            img.filter,
+            resize.get_name()
+        ),
+    };
+
+    // the cache is keyed by the canonical path, and is not used for adjusted animations
+    let options = ImgOptions::from(img);
+    let animation = match image {
+        CliImage::Path(path) if img.raw.is_none() && ImgBuf::new(path)?.is_animated() => {
+            let key = path.canonicalize().ok().or_else(|| {
+                let url = path.to_str().filter(|path| cli::is_url(path))?;
+                Some(PathBuf::from(url))
+            });
+            Some(key.filter(|_| options.adjustment.is_neutral()))
+        }
+        _ => None,
+    };
+    let cache_variant = animation_cache_variant(&options);
+    for (&dim, outputs) in dims.iter().zip(outputs) {
+        report.push_str(&format!("{}: {}x{}", outputs.join(", "), dim.0, dim.1));
+        match &animation {
+            Some(Some(path))
+                if cache::has_animation_frames(path, &cache_variant, dim, pixel_format) =>
+            {
+                report.push_str(", animated (cached frames will be reused)")
+            }
+            Some(_) => report.push_str(", animated (frames will be compressed)"),
+            None => (),
+        }
+        report.push('\n');
+    }
+    Ok(report)
+}
+
+/// Animations are resized frame by frame, so the same image has different cached frames for each
+/// resize strategy. Frames cropped around the center keep the plain cache entries, like before
+/// other strategies could be animated
+fn animation_cache_variant(options: &ImgOptions) -> String {
+    let ImgOptions {
+        resize,
+        crop_focus,
+        resample,
+        fill_color,
+        fit_blur,
+        ..
+    } = *options;
+    let fill_color = format!(
+        "{:02x}{:02x}{:02x}",
+        fill_color[0], fill_color[1], fill_color[2]
+    );
+    let variant = match resize {
+        ResizeStrategy::Crop if crop_focus == (0.5, 0.5) => String::new(),
+        ResizeStrategy::Crop => format!("crop_{}_{}", crop_focus.0, crop_focus.1),
+        // the padding is part of the frames
+        ResizeStrategy::No => format!("no_{fill_color}"),
+        ResizeStrategy::Fit => match fit_blur {
+            Some(radius) => format!("fit_blur{radius}"),
+            None => format!("fit_{fill_color}"),
+        },
+        ResizeStrategy::Stretch => "stretch".to_string(),
+        ResizeStrategy::Tile => "tile".to_string(),
+    };
+    let variant = match (resize, resample) {
+        // these never resize, so there is nothing to resample
+        (ResizeStrategy::No | ResizeStrategy::Tile, _) | (_, Resample::Srgb) => variant,
+        _ if variant.is_empty() => "linear".to_string(),
+        _ => format!("{variant}_linear"),
+    };
+    match options.transform.unwrap_or_default() {
+        cli::Transform::Normal => variant,
+        transform => {
+            let transform = transform.to_possible_value().unwrap();
+            let transform = transform.get_name();
+            if variant.is_empty() {
+                format!("transform{transform}")
+            } else {
+                format!("{variant}_transform{transform}")
+            }
+        }
+    }
+}
+
+/// What the cache stores to remember how the image was rotated or flipped, if it was. Even
+/// `normal` is remembered, since it also means the image is drawn as is, whatever the compositor
+/// asks for
+fn transform_cache_arg(transform: Option<cli::Transform>) -> String {
+    match transform {
+        None => String::new(),
+        Some(transform) => format!(
+            " --transform={}",
+            transform.to_possible_value().unwrap().get_name()
+        ),
+    }
+}
+
+/// What the cache stores to remember how the image was resized and played. Only what differs from
+/// `swww img`'s defaults is stored
+fn img_cache_args(options: &ImgOptions) -> String {
+    let ImgOptions {
+        resize,
+        crop_focus,
+        fill_color,
+        fit_blur,
+        resample,
+        ignore_exif,
+        loop_count,
+        playback_speed,
+        ..
+    } = *options;
+    let mut args = String::new();
+    if resize != ResizeStrategy::Crop {
+        let resize = resize.to_possible_value().unwrap();
+        args.push_str(&format!(" --resize={}", resize.get_name()));
+    }
+    if crop_focus != (0.5, 0.5) {
+        args.push_str(&format!(" --crop-focus={},{}", crop_focus.0, crop_focus.1));
+    }
+    if fill_color != [0, 0, 0] {
+        args.push_str(&format!(
+            " --fill-color={:02x}{:02x}{:02x}",
+            fill_color[0], fill_color[1], fill_color[2]
+        ));
+    }
+    if let Some(radius) = fit_blur {
+        args.push_str(&format!(" --fit-fill=blur --fit-blur={radius}"));
+    }
+    if resample != Resample::Srgb {
+        let resample = resample.to_possible_value().unwrap();
+        args.push_str(&format!(" --resample={}", resample.get_name()));
+    }
+    if ignore_exif {
+        args.push_str(" --ignore-exif");
+    }
+    if loop_count != 0 {
+        args.push_str(&format!(" --loop-count={loop_count}"));
+    }
+    if playback_speed != 1.0 {
+        args.push_str(&format!(" --playback-speed={playback_speed}"));
+    }
+    args
+}
+
+/// What the cache stores to remember the transition the image came in with. All of it is stored,
+/// so that the `SWWW_TRANSITION_*` variables don't change it when restoring. Masks are left out
+fn transition_cache_args(transition: &ipc::Transition) -> String {
+    let transition_type = match transition.transition_type {
+        ipc::TransitionType::Simple => "simple",
+        ipc::TransitionType::Fade => "fade",
+        ipc::TransitionType::Outer => "outer",
+        ipc::TransitionType::Wipe => "wipe",
+        ipc::TransitionType::Grow => "grow",
+        ipc::TransitionType::Wave => "wave",
+        ipc::TransitionType::None => "none",
+        ipc::TransitionType::Reveal => "reveal",
+        ipc::TransitionType::Dissolve => "dissolve",
+        ipc::TransitionType::Melt => "melt",
+        ipc::TransitionType::Crossfade => "crossfade",
+        ipc::TransitionType::Slide => "slide",
+    };
+    // whole numbers are taken as pixels, so percents must keep their decimal point
+    let coord = |coord: &ipc::Coord| match *coord {
+        ipc::Coord::Pixel(pixel) => format!("{pixel}"),
+        ipc::Coord::Percent(percent) => format!("{percent:?}"),
+    };
+    let (a, b, c, d) = transition.bezier;
+    format!(
+        " --transition-type={transition_type} --transition-duration={} --transition-step={} \
+         --transition-fps={} --transition-angle={} --transition-pos={},{} \
+         --transition-bezier={a},{b},{c},{d} --transition-wave={},{} --transition-colorspace={}{}",
+        transition.duration,
+        transition.step,
+        transition.fps,
+        transition.angle,
+        coord(&transition.pos.x),
+        coord(&transition.pos.y),
+        transition.wave.0,
+        transition.wave.1,
+        if transition.linear { "linear" } else { "srgb" },
+        if transition.invert_y {
+            " --invert-y"
+        } else {
+            ""
+        },
+    )
 }
 
 #[allow(clippy::type_complexity)]
 fn get_format_dims_and_outputs(
+    namespace: &str,
     requested_outputs: &[String],
 ) -> Result<(ipc::PixelFormat, Vec<(u32, u32)>, Vec<Vec<String>>), String> {
     let mut outputs: Vec<Vec<String>> = Vec::new();
     let mut dims: Vec<(u32, u32)> = Vec::new();
     let mut imgs: Vec<ipc::BgImg> = Vec::new();
 
-    let socket = IpcSocket::connect().map_err(|err| err.to_string())?;
+    let patterns = parse_output_patterns(requested_outputs)?;
+    let infos = query_outputs(namespace)?;
+
+    let mut format = ipc::PixelFormat::Xrgb;
+    for info in infos.iter() {
+        format = info.pixel_format;
+        let info_img = &info.img;
+        let name = info.name.to_string();
+        if !patterns.is_empty()
+            && !patterns
+                .iter()
+                .any(|pattern| pattern.matches(&name, &info.desc))
+        {
+            continue;
+        }
+        let real_dim = info.real_dim();
+        if let Some((_, output)) = dims
+            .iter_mut()
+            .zip(&imgs)
+            .zip(&mut outputs)
+            .find(|((dim, img), _)| real_dim == **dim && info_img == *img)
+        {
+            output.push(name);
+        } else {
+            outputs.push(vec![name]);
+            dims.push(real_dim);
+            imgs.push(info_img.clone());
+        }
+    }
+    if outputs.is_empty() {
+        Err(no_valid_outputs(&infos))
+    } else {
+        for (dim, outputs) in dims.iter().zip(&outputs) {
+            verbose!(1, "{}: {}x{}, {format:?}", outputs.join(", "), dim.0, dim.1);
+        }
+        Ok((format, dims, outputs))
+    }
+}
+
+fn query_outputs(namespace: &str) -> Result<Box<[ipc::BgInfo]>, String> {
+    let socket = IpcSocket::connect(namespace).map_err(|err| err.to_string())?;
     RequestSend::Query.send(&socket)?;
     let bytes = socket.recv().map_err(|err| err.to_string())?;
     drop(socket);
-    let answer = Answer::receive(bytes);
-    match answer {
-        Answer::Info(infos) => {
-            let mut format = ipc::PixelFormat::Xrgb;
-            for info in infos.iter() {
-                format = info.pixel_format;
-                let info_img = &info.img;
-                let name = info.name.to_string();
-                if !requested_outputs.is_empty() && !requested_outputs.contains(&name) {
-                    continue;
-                }
-                let real_dim = info.real_dim();
-                if let Some((_, output)) = dims
-                    .iter_mut()
-                    .zip(&imgs)
-                    .zip(&mut outputs)
-                    .find(|((dim, img), _)| real_dim == **dim && info_img == *img)
-                {
-                    output.push(name);
-                } else {
-                    outputs.push(vec![name]);
-                    dims.push(real_dim);
-                    imgs.push(info_img.clone());
-                }
-            }
-            if outputs.is_empty() {
-                Err("none of the requested outputs are valid".to_owned())
-            } else {
-                Ok((format, dims, outputs))
-            }
+    match Answer::receive(bytes) {
+        Answer::Info(infos) => Ok(infos),
+        Answer::Err(e) => Err(e),
+        _ => Err("Daemon did not return Answer::Info, as expected".to_string()),
+    }
+}
+
+/// The names of the outputs whose description contains `desc`, ignoring case, the same way the
+/// daemon matches them
+fn outputs_with_description(namespace: &str, desc: &str) -> Result<Box<[String]>, String> {
+    let infos = query_outputs(namespace)?;
+    let pattern = OutputPattern::Description(desc.to_lowercase());
+    let names: Box<[String]> = infos
+        .iter()
+        .filter(|info| pattern.matches(&info.name, &info.desc))
+        .map(|info| info.name.clone())
+        .collect();
+    if names.is_empty() {
+        return Err(format!("no output has a description containing '{desc}'"));
+    }
+    Ok(names)
+}
+
+fn no_valid_outputs(infos: &[ipc::BgInfo]) -> String {
+    let names: Vec<_> = infos.iter().map(|info| info.name.to_string()).collect();
+    format!(
+        "none of the requested outputs are valid. Available outputs are: {}",
+        names.join(", ")
+    )
+}
+
+/// Keeps only the outputs `swww query --outputs` asked for, failing if any of them matches nothing
+fn filter_infos(
+    infos: Box<[ipc::BgInfo]>,
+    requested_outputs: &[String],
+) -> Result<Box<[ipc::BgInfo]>, String> {
+    if requested_outputs.is_empty() {
+        return Ok(infos);
+    }
+    let patterns = parse_output_patterns(requested_outputs)?;
+    for (requested, pattern) in requested_outputs.iter().zip(&patterns) {
+        if !infos
+            .iter()
+            .any(|info| pattern.matches(&info.name, &info.desc))
+        {
+            let names: Vec<_> = infos.iter().map(|info| info.name.to_string()).collect();
+            return Err(format!(
+                "there is no output named {requested}. Available outputs are: {}",
+                names.join(", ")
+            ));
+        }
+    }
+    Ok(infos
+        .into_vec()
+        .into_iter()
+        .filter(|info| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches(&info.name, &info.desc))
+        })
+        .collect())
+}
+
+/// An output name given in the command line, which may match several outputs
+enum OutputPattern {
+    Name(String),
+    /// `*` matches any number of characters, and `?` exactly one
+    Glob(Vec<char>),
+    /// Given with a `re:` prefix. It must match the whole name
+    Regex(regex::Regex),
+    /// Given with a `desc:` prefix, in lowercase. The output's description only has to contain
+    /// it, ignoring case
+    Description(String),
+}
+
+impl OutputPattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        if let Some(regex) = pattern.strip_prefix("re:") {
+            regex::Regex::new(&format!("^(?:{regex})$"))
+                .map(Self::Regex)
+                .map_err(|e| format!("invalid output regex '{regex}': {e}"))
+        } else if let Some(desc) = pattern.strip_prefix("desc:") {
+            Ok(Self::Description(desc.to_lowercase()))
+        } else if pattern.contains(['*', '?']) {
+            Ok(Self::Glob(pattern.chars().collect()))
+        } else {
+            Ok(Self::Name(pattern.to_string()))
+        }
+    }
+
+    fn matches(&self, name: &str, desc: &str) -> bool {
+        match self {
+            Self::Name(pattern) => pattern == name,
+            Self::Glob(pattern) => glob_matches(pattern, &name.chars().collect::<Vec<_>>()),
+            Self::Regex(regex) => regex.is_match(name),
+            Self::Description(pattern) => desc.to_lowercase().contains(pattern),
         }
-        _ => unreachable!(),
     }
 }
 
+fn glob_matches(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some(('*', rest)), _) => (0..=name.len()).any(|skip| glob_matches(rest, &name[skip..])),
+        (Some((p, rest)), Some((c, name_rest))) => {
+            (*p == '?' || p == c) && glob_matches(rest, name_rest)
+        }
+        (Some(_), None) => false,
+    }
+}
+
+fn parse_output_patterns(requested_outputs: &[String]) -> Result<Vec<OutputPattern>, String> {
+    requested_outputs
+        .iter()
+        .map(|output| OutputPattern::parse(output))
+        .collect()
+}
+
+/// Turns the globs and regexes in `requested_outputs` into the names of the outputs they match,
+/// for the requests the daemon matches against exact names. Plain names and descriptions, which
+/// the daemon understands, are passed as they are, without asking the daemon anything
+fn resolve_outputs(
+    namespace: &str,
+    requested_outputs: Box<[String]>,
+) -> Result<Box<[String]>, String> {
+    let patterns = parse_output_patterns(&requested_outputs)?;
+    if patterns.iter().all(|pattern| {
+        matches!(
+            pattern,
+            OutputPattern::Name(_) | OutputPattern::Description(_)
+        )
+    }) {
+        return Ok(requested_outputs);
+    }
+    let infos = query_outputs(namespace)?;
+    let resolved: Box<[String]> = infos
+        .iter()
+        .filter(|info| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches(&info.name, &info.desc))
+        })
+        .map(|info| info.name.to_string())
+        .collect();
+    // an empty list would mean every output to the daemon
+    if resolved.is_empty() {
+        return Err(no_valid_outputs(&infos));
+    }
+    Ok(resolved)
+}
+
+/// Formats the outputs' information as a JSON array, for `swww query --json`
+fn bg_infos_json(infos: &[ipc::BgInfo]) -> String {
+    let objects: Vec<String> = infos
+        .iter()
+        .map(|info| {
+            let real_dim = info.real_dim();
+            let pixel_format = match info.pixel_format {
+                ipc::PixelFormat::Bgr => "bgr",
+                ipc::PixelFormat::Rgb => "rgb",
+                ipc::PixelFormat::Xbgr => "xbgr",
+                ipc::PixelFormat::Xrgb => "xrgb",
+                ipc::PixelFormat::Xbgr2101010 => "xbgr2101010",
+                ipc::PixelFormat::Xrgb2101010 => "xrgb2101010",
+            };
+            let displaying = bg_img_json(&info.img);
+            // only outputs that are transitioning get this field
+            let transition = match info.transition {
+                Some(progress) => format!(
+                    ",\"transition\":{{\"duration_ms\":{},\"elapsed_ms\":{},\"remaining_ms\":{}}}",
+                    progress.duration.as_millis(),
+                    progress.elapsed.as_millis(),
+                    progress.remaining.as_millis()
+                ),
+                None => String::new(),
+            };
+            format!(
+                "{{\"name\":{},\"description\":{},\"dim\":[{},{}],\"real_dim\":[{},{}],\"scale\":{},\"transform\":\"{}\",\
+                \"position\":[{},{}],\"refresh_mhz\":{},\"pixel_format\":\"{pixel_format}\",\"layer\":\"{}\",\"namespace\":{},\
+                \"paused\":{},\"animated\":{},\"displaying\":{displaying}{transition}}}",
+                json_string(&info.name),
+                json_string(&info.desc),
+                info.dim.0,
+                info.dim.1,
+                real_dim.0,
+                real_dim.1,
+                info.scale_factor,
+                info.transform,
+                info.position.0,
+                info.position.1,
+                info.refresh,
+                info.layer,
+                json_string(&info.namespace),
+                info.paused,
+                info.animated,
+            )
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+/// Formats what an output displays as a JSON object, with either an `image` or a `color` field
+fn bg_img_json(img: &ipc::BgImg) -> String {
+    match img {
+        ipc::BgImg::Color(color) => format!(
+            "{{\"color\":\"{:02x}{:02x}{:02x}\"}}",
+            color[0], color[1], color[2]
+        ),
+        ipc::BgImg::Img(path) => format!("{{\"image\":{}}}", json_string(path)),
+    }
+}
+
+/// Formats an event as a JSON object, for `swww subscribe --json`. `event` is named as in the
+/// plain output
+fn event_json(event: &ipc::Event) -> String {
+    let (kind, output) = match event {
+        ipc::Event::OutputAdded(name) => ("output-added", name),
+        ipc::Event::OutputRemoved(name) => ("output-removed", name),
+        ipc::Event::WallpaperChanged { output, .. } => ("wallpaper-changed", output),
+        ipc::Event::TransitionStarted(name) => ("transition-started", name),
+        ipc::Event::TransitionFinished(name) => ("transition-finished", name),
+        ipc::Event::Paused(name) => ("paused", name),
+        ipc::Event::Resumed(name) => ("resumed", name),
+    };
+    let displaying = match event {
+        ipc::Event::WallpaperChanged { img, .. } => format!(",\"displaying\":{}", bg_img_json(img)),
+        _ => String::new(),
+    };
+    format!(
+        "{{\"event\":\"{kind}\",\"output\":{}{displaying}}}",
+        json_string(output)
+    )
+}
+
+/// Quotes and escapes `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
 fn split_cmdline_outputs(outputs: &str) -> Box<[String]> {
     outputs
         .split(',')
@@ -269,11 +1681,11 @@ fn split_cmdline_outputs(outputs: &str) -> Box<[String]> {
         .collect()
 }
 
-fn restore_from_cache(requested_outputs: &[String]) -> Result<(), String> {
-    let (_, _, outputs) = get_format_dims_and_outputs(requested_outputs)?;
+fn restore_from_cache(namespace: &str, requested_outputs: &[String]) -> Result<(), String> {
+    let (_, _, outputs) = get_format_dims_and_outputs(namespace, requested_outputs)?;
 
     for output in outputs.iter().flatten() {
-        if let Err(e) = restore_output(output) {
+        if let Err(e) = restore_output(output, namespace) {
             eprintln!("WARNING: failed to load cache for output {output}: {e}");
         }
     }
@@ -281,32 +1693,346 @@ fn restore_from_cache(requested_outputs: &[String]) -> Result<(), String> {
     Ok(())
 }
 
-fn restore_output(output: &str) -> Result<(), String> {
-    let (filter, img_path) = common::cache::get_previous_image_path(output)
+fn restore_output(output: &str, namespace: &str) -> Result<(), String> {
+    let (settings, img_path) = common::cache::get_previous_image_path(output, namespace)
         .map_err(|e| format!("failed to get previous image path: {e}"))?;
     if img_path.is_empty() {
         return Err("cache file does not exist".to_string());
     }
+    set_cached_image(output, namespace, &settings, &img_path)
+}
 
-    #[allow(deprecated)]
-    process_swww_args(&Swww::Img(cli::Img {
-        image: cli::parse_image(&img_path)?,
-        outputs: output.to_string(),
-        no_resize: false,
-        resize: ResizeStrategy::Crop,
-        fill_color: [0, 0, 0],
-        filter: Filter::from_str(&filter).unwrap_or(Filter::Lanczos3),
-        transition_type: cli::TransitionType::None,
-        transition_step: std::num::NonZeroU8::MAX,
-        transition_duration: 0.0,
-        transition_fps: 30,
-        transition_angle: 0.0,
-        transition_pos: cli::CliPosition {
-            x: cli::CliCoord::Pixel(0.0),
-            y: cli::CliCoord::Pixel(0.0),
-        },
-        invert_y: false,
-        transition_bezier: (0.0, 0.0, 0.0, 0.0),
-        transition_wave: (0.0, 0.0),
-    }))
+fn undo_from_cache(namespace: &str, requested_outputs: &[String]) -> Result<(), String> {
+    let (_, _, outputs) = get_format_dims_and_outputs(namespace, requested_outputs)?;
+
+    for output in outputs.iter().flatten() {
+        if let Err(e) = undo_output(output, namespace) {
+            eprintln!("WARNING: failed to undo output {output}: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+fn undo_output(output: &str, namespace: &str) -> Result<(), String> {
+    let (settings, img_path) = common::cache::get_undo_image_path(output, namespace)
+        .map_err(|e| format!("failed to get undo image path: {e}"))?;
+    if img_path.is_empty() {
+        return Err("there is no previous image to go back to".to_string());
+    }
+    if img_path == "-" {
+        return Err("the previous image was read from stdin".to_string());
+    }
+    set_cached_image(output, namespace, &settings, &img_path)
+}
+
+/// Displays `img_path` on `output` right away, just like the cache entry it came from says
+fn set_cached_image(
+    output: &str,
+    namespace: &str,
+    settings: &str,
+    img_path: &str,
+) -> Result<(), String> {
+    verbose!(1, "restoring {img_path} on {output} with: {settings}");
+    process_swww_args(&Swww {
+        namespace: namespace.to_string(),
+        socket: None,
+        verbose: VERBOSITY.load(Ordering::Relaxed),
+        command: Command::Img(cached_img(output, settings, img_path)?),
+    })
+}
+
+/// Rebuilds the `swww img` that displayed `img_path` on `output`. `settings` is the cache entry's
+/// filter, followed by the `swww img` flags the image was displayed with, and the region of it
+/// the output showed, if it was spanned across several outputs
+///
+/// Entries written before transitions were remembered are restored without one, like they always
+/// were. Whatever else they don't mention gets its default value.
+fn cached_img(output: &str, settings: &str, img_path: &str) -> Result<cli::Img, String> {
+    let mut settings = settings.split_whitespace();
+    let filter = settings.next().unwrap_or_default();
+    let region = region_from_cache_args(settings.clone());
+
+    let mut args = vec!["img".to_string(), format!("--outputs={output}")];
+    if Filter::from_str(filter).is_ok() {
+        args.push(format!("--filter={filter}"));
+    }
+    if !settings
+        .clone()
+        .any(|arg| arg.starts_with("--transition-type="))
+    {
+        args.push("--transition-type=none".to_string());
+    }
+    args.extend(
+        settings
+            .clone()
+            .filter(|arg| !arg.starts_with("--region="))
+            .map(str::to_string),
+    );
+    args.extend(["--".to_string(), img_path.to_string()]);
+
+    let mut img = cli::Img::try_parse_from(args)
+        .map_err(|e| format!("invalid settings in the cache entry: {e}"))?;
+    img.region = region;
+    // these come from the environment if the entry doesn't mention them, but the entry says
+    // everything about the transition the image was shown with
+    img.transition.invert_y = settings.any(|arg| arg == "--invert-y");
+    img.transition.transition_easing = Vec::new();
+    img.transition.transition_mask = Vec::new();
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_tell_whether_a_process_is_running() {
+        assert!(is_running(std::process::id() as libc::pid_t));
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id() as libc::pid_t;
+        // until we wait for it, the child is a zombie
+        let start = Instant::now();
+        while is_running(pid) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "`true` did not exit"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        child.wait().unwrap();
+        assert!(!is_running(pid));
+    }
+
+    #[test]
+    fn should_match_output_patterns() {
+        let matches = |pattern: &str, name: &str| {
+            OutputPattern::parse(pattern)
+                .unwrap()
+                .matches(name, "Dell Inc. DELL U2720Q")
+        };
+        assert!(matches("DP-3", "DP-3"));
+        assert!(!matches("DP-3", "DP-35"));
+        assert!(matches("DP-*", "DP-3"));
+        assert!(matches("DP-*", "DP-"));
+        assert!(!matches("DP-*", "HDMI-A-1"));
+        assert!(matches("*-A-?", "HDMI-A-1"));
+        assert!(!matches("*-A-?", "HDMI-A-10"));
+        assert!(matches("画面-?", "画面-é"));
+        assert!(matches(r"re:DP-\d+", "DP-12"));
+        // regexes must match the whole name
+        assert!(!matches(r"re:DP-\d", "DP-12"));
+        assert!(!matches(r"re:DP-\d+", "eDP-1"));
+        assert!(OutputPattern::parse("re:DP-(").is_err());
+        assert!(matches("desc:dell u2720", "DP-3"));
+        assert!(matches("desc:", "DP-3"));
+        assert!(!matches("desc:DP-3", "DP-3"));
+        assert!(!matches("desc:BenQ", "DP-3"));
+    }
+
+    #[test]
+    fn should_report_dry_run_per_output_group() {
+        let swww = Swww::parse_from(["swww", "img", "0x102030", "--dry-run"]);
+        let Command::Img(img) = &swww.command else {
+            panic!("parsed the wrong command");
+        };
+        let report = dry_run_report(
+            img,
+            &img.image,
+            &[(1920, 1080), (3840, 2160)],
+            ipc::PixelFormat::Xrgb,
+            &[
+                vec!["DP-1".to_string(), "DP-2".to_string()],
+                vec!["HDMI-A-1".to_string()],
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            "color: 0x102030\nDP-1, DP-2: 1920x1080\nHDMI-A-1: 3840x2160\n"
+        );
+    }
+
+    #[test]
+    fn should_restore_images_the_way_they_were_displayed() {
+        let img = cli::Img::try_parse_from([
+            "img",
+            "--resize=fit",
+            "--fit-fill=blur",
+            "--fit-blur=8",
+            "--crop-focus=top-left",
+            "--fill-color=1e1e2e",
+            "--resample=linear",
+            "--ignore-exif",
+            "--loop-count=2",
+            "--playback-speed=0.5",
+            "--transform=flipped-90",
+            "--grayscale",
+            "--transition-type=grow",
+            "--transition-duration=1.5",
+            "--transition-pos=0.25,200",
+            "--transition-colorspace=linear",
+            "--invert-y",
+            "0x102030",
+        ])
+        .unwrap();
+        let transition = make_transition(&img.transition, 0);
+        let settings = format!(
+            "Nearest --grayscale{}{}{}",
+            transform_cache_arg(img.transform),
+            img_cache_args(&ImgOptions::from(&img)),
+            transition_cache_args(&transition),
+        );
+
+        let restored = cached_img("DP-1", &settings, "0x102030").unwrap();
+        assert_eq!(restored.outputs, ["DP-1"]);
+        assert_eq!(restored.filter.to_string(), "Nearest");
+        assert_eq!(restored.resize, ResizeStrategy::Fit);
+        assert_eq!(restored.fit_fill, cli::FitFill::Blur);
+        assert_eq!(restored.fit_blur, 8.0);
+        assert_eq!(restored.crop_focus, (0.0, 0.0));
+        assert_eq!(restored.fill_color, [0x1e, 0x1e, 0x2e]);
+        assert_eq!(restored.resample, Resample::Linear);
+        assert!(restored.ignore_exif);
+        assert_eq!((restored.loop_count, restored.playback_speed), (2, 0.5));
+        assert_eq!(restored.transform, Some(cli::Transform::Flipped90));
+        assert!(restored.grayscale);
+        assert!(make_transition(&restored.transition, 0) == transition);
+
+        // nothing but the defaults stores nothing
+        let img = cli::Img::try_parse_from(["img", "0x102030"]).unwrap();
+        assert_eq!(transform_cache_arg(img.transform), "");
+        assert_eq!(
+            transform_cache_arg(Some(cli::Transform::Normal)),
+            " --transform=normal"
+        );
+        assert_eq!(img_cache_args(&ImgOptions::from(&img)), "");
+    }
+
+    #[test]
+    fn should_restore_old_cache_entries_without_a_transition() {
+        let restored =
+            cached_img("DP-1", "Lanczos3 --invert --region=0,0,0.5,1", "0x102030").unwrap();
+        assert_eq!(restored.resize, ResizeStrategy::Crop);
+        assert!(restored.invert);
+        assert_eq!(restored.region, Some([0.0, 0.0, 0.5, 1.0]));
+        assert!(matches!(
+            restored.transition.transition_type[0].default,
+            cli::TransitionType::None
+        ));
+
+        // entries of images shown before the filter was remembered
+        let restored = cached_img("DP-1", "", "0x102030").unwrap();
+        assert_eq!(restored.filter.to_string(), "Lanczos3");
+    }
+
+    #[test]
+    fn should_escape_json_strings() {
+        assert_eq!(json_string("DP-1"), r#""DP-1""#);
+        assert_eq!(json_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(json_string("a\nb\u{1}"), r#""a\nb\u0001""#);
+    }
+
+    #[test]
+    fn should_format_events_as_json() {
+        assert_eq!(
+            event_json(&ipc::Event::OutputAdded("DP-1".to_string())),
+            r#"{"event":"output-added","output":"DP-1"}"#
+        );
+        assert_eq!(
+            event_json(&ipc::Event::TransitionFinished("DP-1".to_string())),
+            r#"{"event":"transition-finished","output":"DP-1"}"#
+        );
+        assert_eq!(
+            event_json(&ipc::Event::WallpaperChanged {
+                output: "DP-1".to_string(),
+                img: ipc::BgImg::Img("/tmp/\"a\".png".to_string()),
+            }),
+            r#"{"event":"wallpaper-changed","output":"DP-1","displaying":{"image":"/tmp/\"a\".png"}}"#
+        );
+        assert_eq!(
+            event_json(&ipc::Event::WallpaperChanged {
+                output: "HDMI-A-1".to_string(),
+                img: ipc::BgImg::Color([0, 0x80, 0xff]),
+            }),
+            r#"{"event":"wallpaper-changed","output":"HDMI-A-1","displaying":{"color":"0080ff"}}"#
+        );
+    }
+
+    #[test]
+    fn should_format_bg_infos_as_json() {
+        let info = ipc::BgInfo {
+            name: "DP-1".to_string(),
+            desc: "Dell Inc. DELL U2720Q".to_string(),
+            dim: (960, 540),
+            scale_factor: ipc::Scale::Whole(std::num::NonZeroI32::new(2).unwrap()),
+            transform: ipc::Transform::Normal,
+            position: (1920, 0),
+            refresh: 59951,
+            img: ipc::BgImg::Img("/tmp/a.png".to_string()),
+            pixel_format: ipc::PixelFormat::Xrgb,
+            paused: false,
+            animated: false,
+            layer: ipc::Layer::Background,
+            namespace: "swww-daemon".to_string(),
+            transition: None,
+        };
+        assert_eq!(
+            bg_infos_json(std::slice::from_ref(&info)),
+            concat!(
+                r#"[{"name":"DP-1","description":"Dell Inc. DELL U2720Q","#,
+                r#""dim":[960,540],"real_dim":[1920,1080],"scale":2,"transform":"normal","#,
+                r#""position":[1920,0],"refresh_mhz":59951,"#,
+                r#""pixel_format":"xrgb","layer":"background","namespace":"swww-daemon","#,
+                r#""paused":false,"animated":false,"displaying":{"image":"/tmp/a.png"}}]"#
+            )
+        );
+        assert_eq!(bg_infos_json(&[]), "[]");
+
+        let info = ipc::BgInfo {
+            transition: Some(ipc::TransitionProgress::new(
+                Duration::from_millis(3000),
+                Duration::from_millis(1250),
+            )),
+            ..info
+        };
+        assert!(bg_infos_json(&[info]).ends_with(concat!(
+            r#""displaying":{"image":"/tmp/a.png"},"#,
+            r#""transition":{"duration_ms":3000,"elapsed_ms":1250,"remaining_ms":1750}}]"#
+        )));
+    }
+
+    #[test]
+    fn should_filter_query_by_output() {
+        let info = |name: &str| ipc::BgInfo {
+            name: name.to_string(),
+            desc: String::new(),
+            dim: (1920, 1080),
+            scale_factor: ipc::Scale::Whole(std::num::NonZeroI32::new(1).unwrap()),
+            transform: ipc::Transform::Normal,
+            position: (0, 0),
+            refresh: 0,
+            img: ipc::BgImg::Color([0, 0, 0]),
+            pixel_format: ipc::PixelFormat::Xrgb,
+            paused: false,
+            animated: false,
+            layer: ipc::Layer::Background,
+            namespace: "swww-daemon".to_string(),
+            transition: None,
+        };
+        let infos = || -> Box<[ipc::BgInfo]> { ["DP-1", "HDMI-A-1", "eDP-1"].map(info).into() };
+        let names = |outputs: &str| -> Result<Vec<String>, String> {
+            let filtered = filter_infos(infos(), &split_cmdline_outputs(outputs))?;
+            Ok(filtered.iter().map(|info| info.name.clone()).collect())
+        };
+
+        assert_eq!(names("").unwrap(), ["DP-1", "HDMI-A-1", "eDP-1"]);
+        // the daemon's order is kept
+        assert_eq!(names("eDP-1,DP-1").unwrap(), ["DP-1", "eDP-1"]);
+        assert_eq!(names("*DP-*").unwrap(), ["DP-1", "eDP-1"]);
+        let err = names("DP-1,DP-2").unwrap_err();
+        assert!(err.contains("DP-2"), "{err}");
+        assert!(err.contains("HDMI-A-1"), "{err}");
+    }
 }
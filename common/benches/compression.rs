@@ -1,4 +1,4 @@
-use common::compression::{Compressor, Decompressor};
+use common::compression::{Compressor, Decompressor, FAST_COMPRESSION_LEVEL};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 fn generate_data() -> (Box<[u8]>, Box<[u8]>) {
@@ -50,6 +50,16 @@ pub fn compression_and_decompression(c: &mut Criterion) {
             )
         })
     });
+    let mut fast_compressor = Compressor::with_level(FAST_COMPRESSION_LEVEL);
+    comp.bench_function("Fast", |b| {
+        b.iter(|| {
+            black_box(
+                fast_compressor
+                    .compress(&prev, &cur, common::ipc::PixelFormat::Xrgb)
+                    .is_some(),
+            )
+        })
+    });
     comp.finish();
 
     let mut decomp = c.benchmark_group("decompression 4 channels");
@@ -5,44 +5,276 @@
 //!   2. the daemon spawns a client that reloads that image when an output is created
 
 use std::{
-    fs::File,
-    io::{self, Read, Write},
+    fs::{File, FileTimes},
+    io::{self, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::Mutex,
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use crate::ipc::Animation;
+use crate::ipc::IpcSocket;
 use crate::ipc::PixelFormat;
+use crate::ipc::Server;
 use crate::mmap::Mmap;
 
-pub(crate) fn store(output_name: &str, img_path: &str, filter: &str) -> io::Result<()> {
-    let mut filepath = cache_dir()?;
-    filepath.push(output_name);
-    File::create(filepath)?.write_all(format!("{filter}\n{img_path}").as_bytes())
+pub(crate) fn store(
+    output_name: &str,
+    namespace: &str,
+    img_path: &str,
+    filter: &str,
+) -> io::Result<()> {
+    store_in(&cache_dir()?, output_name, namespace, img_path, filter)
+}
+
+/// The entry being replaced is kept in the output's undo slot, for `swww undo`. Storing the same
+/// entry again (like restoring it does) leaves the undo slot alone
+fn store_in(
+    cache_dir: &Path,
+    output_name: &str,
+    namespace: &str,
+    img_path: &str,
+    filter: &str,
+) -> io::Result<()> {
+    let filepath = cache_dir.join(output_filename(output_name, namespace));
+    let entry = format!("{filter}\n{img_path}");
+    if let Ok(current) = std::fs::read_to_string(&filepath) {
+        if current != entry {
+            std::fs::rename(
+                &filepath,
+                cache_dir.join(undo_filename(output_name, namespace)),
+            )?;
+        }
+    }
+    File::create(filepath)?.write_all(entry.as_bytes())
 }
 
-pub(crate) fn store_animation_frames(
+/// Cached animations start with this, followed by their format and the fingerprint of the image
+/// they were made from. Files without it were written before we checked the source, and are made
+/// again
+const ANIMATION_MAGIC: &[u8; 4] = b"SWWA";
+
+/// Bump this whenever the layout of cached animations changes, so that old files are made again
+/// instead of being misread
+const ANIMATION_FORMAT: u8 = 1;
+
+const ANIMATION_HEADER_LEN: usize = ANIMATION_MAGIC.len() + 1 + SourceFingerprint::LEN;
+
+/// How many bytes from the start and from the end of the source image go into its fingerprint
+const FINGERPRINT_SAMPLE: u64 = 64 * 1024;
+
+/// Tells whether the image some cached frames were made from changed since, without decoding it
+///
+/// Overwriting an image updates its modification time, and usually its size. Hashing its first
+/// and last bytes also catches copies that keep both, like `cp --preserve` makes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SourceFingerprint {
+    modified: Duration,
+    size: u64,
+    hash: u64,
+}
+
+impl SourceFingerprint {
+    /// a byte telling whether there is a fingerprint at all, then every field
+    const LEN: usize = 1 + 8 + 4 + 8 + 8;
+
+    /// `None` for sources that are not files, like urls, which we have no cheap way to check
+    fn of(path: &Path) -> Option<Self> {
+        let mut file = File::open(path).ok()?;
+        let metadata = file.metadata().ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        let size = metadata.len();
+
+        let mut sample = Vec::with_capacity(2 * FINGERPRINT_SAMPLE as usize);
+        (&file)
+            .take(FINGERPRINT_SAMPLE)
+            .read_to_end(&mut sample)
+            .ok()?;
+        if size > 2 * FINGERPRINT_SAMPLE {
+            file.seek(SeekFrom::End(-(FINGERPRINT_SAMPLE as i64)))
+                .ok()?;
+        }
+        file.read_to_end(&mut sample).ok()?;
+
+        Some(Self {
+            modified,
+            size,
+            hash: fnv1a(&sample),
+        })
+    }
+
+    fn serialize(fingerprint: Option<Self>) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        if let Some(Self {
+            modified,
+            size,
+            hash,
+        }) = fingerprint
+        {
+            bytes[0] = 1;
+            bytes[1..9].copy_from_slice(&modified.as_secs().to_ne_bytes());
+            bytes[9..13].copy_from_slice(&modified.subsec_nanos().to_ne_bytes());
+            bytes[13..21].copy_from_slice(&size.to_ne_bytes());
+            bytes[21..29].copy_from_slice(&hash.to_ne_bytes());
+        }
+        bytes
+    }
+}
+
+/// The 64 bit FNV-1a hash. It is nowhere near as fast as xxhash, but only ever sees 128KiB, and
+/// unlike `std`'s hashers it is guaranteed to stay the same across Rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn animation_header(source: Option<SourceFingerprint>) -> [u8; ANIMATION_HEADER_LEN] {
+    let mut header = [0; ANIMATION_HEADER_LEN];
+    header[..4].copy_from_slice(ANIMATION_MAGIC);
+    header[4] = ANIMATION_FORMAT;
+    header[5..].copy_from_slice(&SourceFingerprint::serialize(source));
+    header
+}
+
+/// The frames in a cached animation file, if they were made from `source` in the current format
+fn current_frames(bytes: &[u8], source: Option<SourceFingerprint>) -> Option<&[u8]> {
+    let header = bytes.get(..ANIMATION_HEADER_LEN)?;
+    (header == animation_header(source)).then(|| &bytes[ANIMATION_HEADER_LEN..])
+}
+
+/// Whether the cached animation at `filepath` was made from `source` in the current format, which
+/// only needs to read its header
+fn is_current(filepath: &Path, source: Option<SourceFingerprint>) -> bool {
+    let mut header = [0; ANIMATION_HEADER_LEN];
+    File::open(filepath)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok()
+        && header == animation_header(source)
+}
+
+fn store_animation_frames(
     animation: &[u8],
+    source: Option<SourceFingerprint>,
     path: &Path,
+    variant: &str,
     dimensions: (u32, u32),
     pixel_format: PixelFormat,
 ) -> io::Result<()> {
-    let filename = animation_filename(path, dimensions, pixel_format);
+    let filename = animation_filename(path, variant, dimensions, pixel_format);
     let mut filepath = cache_dir()?;
     filepath.push(&filename);
 
-    if !filepath.is_file() {
-        File::create(filepath)?.write_all(animation)
-    } else {
-        Ok(())
+    if !is_current(&filepath, source) {
+        // write to a temporary file first, so that we never leave a half written cache entry
+        // behind if we get interrupted
+        let mut tmp_filepath = filepath.clone();
+        tmp_filepath.set_file_name(format!(
+            "tmp-{}-{}",
+            std::process::id(),
+            filename.to_string_lossy()
+        ));
+        let mut file = File::create(&tmp_filepath)?;
+        file.write_all(&animation_header(source))?;
+        file.write_all(animation)?;
+        std::fs::rename(tmp_filepath, &filepath)?;
+
+        if let Some(limit) = cache_limit() {
+            shrink_keeping(
+                filepath.parent().unwrap_or(Path::new(".")),
+                limit,
+                &filepath,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Cache writes still running in the background
+static PENDING_WRITES: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Like `store_animation_frames`, but the frames are written in a background thread, so that we
+/// do not have to wait on slow storage before sending the request to the daemon
+///
+/// Call `wait_for_pending_writes` before exiting, to give the write a chance to complete
+pub(crate) fn store_animation_frames_in_background(
+    animation: Box<[u8]>,
+    path: PathBuf,
+    variant: String,
+    dimensions: (u32, u32),
+    pixel_format: PixelFormat,
+) {
+    // the frames were made from the image as it is now, not as it will be once the thread runs
+    let source = SourceFingerprint::of(&path);
+    let spawned = std::thread::Builder::new()
+        .name("cache writer".to_string())
+        .spawn(move || {
+            if let Err(e) = store_animation_frames(
+                &animation,
+                source,
+                &path,
+                &variant,
+                dimensions,
+                pixel_format,
+            ) {
+                eprintln!("Error storing cache for {}: {e}", path.display());
+            }
+        });
+    match spawned {
+        Ok(handle) => PENDING_WRITES.lock().unwrap().push(handle),
+        Err(e) => eprintln!("failed to spawn cache writer thread: {e}"),
     }
 }
 
+/// Waits for at most `timeout` for all the background cache writes to finish
+///
+/// Returns whether they all finished. Writes that did not finish leave no cache entry behind.
+pub fn wait_for_pending_writes(timeout: Duration) -> bool {
+    let start = Instant::now();
+    let mut pending = PENDING_WRITES.lock().unwrap();
+    loop {
+        pending.retain(|handle| !handle.is_finished());
+        if pending.is_empty() {
+            return true;
+        }
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Whether `load_animation_frames` would find frames for these arguments, without loading them
+pub fn has_animation_frames(
+    path: &Path,
+    variant: &str,
+    dimensions: (u32, u32),
+    pixel_format: PixelFormat,
+) -> bool {
+    cache_dir().is_ok_and(|cache_dir| {
+        is_current(
+            &cache_dir.join(animation_filename(path, variant, dimensions, pixel_format)),
+            SourceFingerprint::of(path),
+        )
+    })
+}
+
+/// `variant` tells apart frames made from the same image in different ways (see
+/// `ImageRequestBuilder::set_animation_cache_variant`)
+///
+/// Frames made from an older version of the image at `path` are deleted instead, so that the ones
+/// made to replace them can be stored.
 pub fn load_animation_frames(
     path: &Path,
+    variant: &str,
     dimensions: (u32, u32),
     pixel_format: PixelFormat,
 ) -> io::Result<Option<Animation>> {
-    let filename = animation_filename(path, dimensions, pixel_format);
+    let filename = animation_filename(path, variant, dimensions, pixel_format);
     let cache_dir = cache_dir()?;
     let mut filepath = cache_dir.clone();
     filepath.push(filename);
@@ -51,24 +283,59 @@ pub fn load_animation_frames(
 
     for entry in read_dir.into_iter().flatten() {
         if entry.path() == filepath {
-            let fd = File::open(&filepath)?.into();
+            let file = File::open(&filepath)?;
+            // some filesystems are mounted with `noatime`, but the cache limit still needs to know
+            // which animations were used last
+            let _ = file.set_times(FileTimes::new().set_accessed(SystemTime::now()));
+            let fd = file.into();
             let len = rustix::fs::seek(&fd, rustix::fs::SeekFrom::End(0))?;
             let mmap = Mmap::from_fd(fd, len as usize);
 
-            match std::panic::catch_unwind(|| Animation::deserialize(&mmap, mmap.slice())) {
+            let Some(frames) = current_frames(mmap.slice(), SourceFingerprint::of(path)) else {
+                if let Err(e) = std::fs::remove_file(&filepath) {
+                    eprintln!(
+                        "WARNING: failed to remove outdated cache file {}: {e}",
+                        filepath.display()
+                    );
+                }
+                return Ok(None);
+            };
+            match Animation::deserialize(&mmap, frames) {
                 Ok((frames, _)) => return Ok(Some(frames)),
-                Err(e) => eprintln!("Error loading animation frames: {e:?}"),
+                Err(e) => {
+                    eprintln!(
+                        "WARNING: discarding corrupt cache file {}: {e}",
+                        filepath.display()
+                    );
+                    if let Err(e) = std::fs::remove_file(&filepath) {
+                        eprintln!(
+                            "WARNING: failed to remove corrupt cache file {}: {e}",
+                            filepath.display()
+                        );
+                    }
+                    return Ok(None);
+                }
             }
         }
     }
     Ok(None)
 }
 
-pub fn get_previous_image_path(output_name: &str) -> io::Result<(String, String)> {
+pub fn get_previous_image_path(output_name: &str, namespace: &str) -> io::Result<(String, String)> {
     let mut filepath = cache_dir()?;
     clean_previous_verions(&filepath);
 
-    filepath.push(output_name);
+    filepath.push(output_filename(output_name, namespace));
+    read_entry(&filepath)
+}
+
+/// The filter and image path the output displayed before its current one, for `swww undo`
+pub fn get_undo_image_path(output_name: &str, namespace: &str) -> io::Result<(String, String)> {
+    read_entry(&cache_dir()?.join(undo_filename(output_name, namespace)))
+}
+
+/// Reads the filter and image path stored in `filepath`, which are both empty if it doesn't exist
+fn read_entry(filepath: &Path) -> io::Result<(String, String)> {
     if !filepath.is_file() {
         return Ok(("".to_string(), "".to_string()));
     }
@@ -91,12 +358,7 @@ pub fn get_previous_image_path(output_name: &str) -> io::Result<(String, String)
     }
 }
 
-pub fn load(output_name: &str) -> io::Result<()> {
-    let (filter, img_path) = get_previous_image_path(output_name)?;
-    if img_path.is_empty() {
-        return Ok(());
-    }
-
+pub fn load(output_name: &str, namespace: &str) -> io::Result<()> {
     if let Ok(mut child) = std::process::Command::new("pidof").arg("swww").spawn() {
         if let Ok(status) = child.wait() {
             if status.success() {
@@ -107,24 +369,224 @@ pub fn load(output_name: &str) -> io::Result<()> {
             }
         }
     }
+    reload(output_name, namespace)
+}
 
-    std::process::Command::new("swww")
+/// Like `load`, but without backing off when another swww process is running.
+///
+/// The daemon uses this when an output changes size: whatever else is running (`swww img
+/// --interval`, for example) isn't going to redraw the image at the new size for us
+pub fn reload(output_name: &str, namespace: &str) -> io::Result<()> {
+    let (settings, img_path) = get_previous_image_path(output_name, namespace)?;
+    if img_path.is_empty() {
+        return Ok(());
+    }
+    // the filter may be followed by more arguments the image was displayed with
+    let mut settings = settings.split_whitespace();
+    let filter = settings.next().unwrap_or_default();
+
+    let mut command = std::process::Command::new("swww");
+    if !namespace.is_empty() {
+        command.arg(format!("--namespace={namespace}"));
+    }
+    command
         .arg("img")
         .args([
             &format!("--outputs={output_name}"),
             &format!("--filter={filter}"),
             "--transition-type=none",
-            &img_path,
         ])
+        // the image is only drawn again at the output's new size, so it doesn't come in with the
+        // transition it was first shown with
+        .args(settings.filter(|arg| !arg.starts_with("--transition-") && *arg != "--invert-y"))
+        .arg(&img_path)
         .spawn()?
         .wait()?;
     Ok(())
 }
 
+/// Stores the pixel format the daemon is using, so that it may prefer it the next time it starts.
+///
+/// This is tied to the daemon's socket, so that daemons running on different wayland displays or
+/// namespaces do not interfere with each other.
+pub fn store_pixel_format(namespace: &str, pixel_format: PixelFormat) -> io::Result<()> {
+    let mut filepath = cache_dir()?;
+    filepath.push(pixel_format_filename(namespace));
+    File::create(filepath)?.write_all(format!("{pixel_format:?}").as_bytes())
+}
+
+/// Loads the pixel format stored with `store_pixel_format`, if there is one.
+///
+/// Note this is only ever used as a hint: the daemon still has to make sure the compositor supports
+/// it.
+pub fn load_pixel_format(namespace: &str) -> io::Result<Option<PixelFormat>> {
+    let mut filepath = cache_dir()?;
+    filepath.push(pixel_format_filename(namespace));
+    if !filepath.is_file() {
+        return Ok(None);
+    }
+
+    let mut buf = String::with_capacity(8);
+    File::open(filepath)?.read_to_string(&mut buf)?;
+    match buf.trim() {
+        "Bgr" => Ok(Some(PixelFormat::Bgr)),
+        "Rgb" => Ok(Some(PixelFormat::Rgb)),
+        "Xbgr" => Ok(Some(PixelFormat::Xbgr)),
+        "Xrgb" => Ok(Some(PixelFormat::Xrgb)),
+        "Xbgr2101010" => Ok(Some(PixelFormat::Xbgr2101010)),
+        "Xrgb2101010" => Ok(Some(PixelFormat::Xrgb2101010)),
+        other => Err(std::io::Error::other(format!(
+            "unrecognized pixel format: {other}"
+        ))),
+    }
+}
+
+/// Deletes the animation frames cached for the image at `path` at `dimensions`, in every variant
+/// and pixel format
+///
+/// The daemon calls this when the output showing that image changes modes: the frames made for
+/// the old resolution would otherwise just linger forever.
+pub fn prune_stale(path: &Path, dimensions: (u32, u32)) -> io::Result<()> {
+    prune_stale_in(&cache_dir()?, path, dimensions)
+}
+
+fn prune_stale_in(cache_dir: &Path, path: &Path, dimensions: (u32, u32)) -> io::Result<()> {
+    let prefix = path.to_string_lossy().replace('/', "_");
+    for entry in cache_dir.read_dir()?.flatten() {
+        let filename = entry.file_name();
+        let filename = match filename.to_str() {
+            Some(filename) => filename,
+            None => continue,
+        };
+
+        let same_image = filename
+            .strip_prefix(&prefix)
+            .is_some_and(|rest| rest.starts_with("__"));
+        if same_image && animation_dimensions(filename) == Some(dimensions) {
+            if let Err(e) = std::fs::remove_file(entry.path()) {
+                eprintln!("WARNING: failed to remove stale cache file {filename}: {e}");
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn clean() -> io::Result<()> {
     std::fs::remove_dir_all(cache_dir()?)
 }
 
+/// A cached animation, as found by `animation_entries`
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    /// in bytes
+    pub size: u64,
+    /// The latest of the file's modification and access times
+    pub last_used: SystemTime,
+}
+
+/// Lists the cached animations, with their sizes and when they were last used
+///
+/// The files recording each output's current image are left out: they are tiny, and deleting them
+/// would make the daemon forget what to restore.
+pub fn animation_entries() -> io::Result<Vec<CacheEntry>> {
+    animation_entries_in(&cache_dir()?)
+}
+
+fn animation_entries_in(cache_dir: &Path) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    for entry in cache_dir.read_dir()?.flatten() {
+        let filename = entry.file_name();
+        let filename = match filename.to_str() {
+            Some(filename) => filename,
+            None => continue,
+        };
+
+        // these are still being written by some other `swww` process
+        if filename.starts_with("tmp-") || animation_dimensions(filename).is_none() {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!("WARNING: failed to read metadata of cache file {filename}: {e}");
+                continue;
+            }
+        };
+        let modified = metadata.modified()?;
+        let last_used = metadata.accessed().map_or(modified, |a| a.max(modified));
+        entries.push(CacheEntry {
+            path: entry.path(),
+            size: metadata.len(),
+            last_used,
+        });
+    }
+    Ok(entries)
+}
+
+/// Deletes the cached animations that have not been used in at least `age`
+///
+/// Returns how many bytes were freed
+pub fn clean_older_than(age: Duration) -> io::Result<u64> {
+    let entries = animation_entries()?;
+    let now = SystemTime::now();
+    Ok(remove_entries(entries.iter().filter(|entry| {
+        now.duration_since(entry.last_used)
+            .is_ok_and(|elapsed| elapsed >= age)
+    })))
+}
+
+/// Deletes the least recently used cached animations until they take up at most `max_size` bytes
+///
+/// Returns how many bytes were freed
+pub fn shrink_to(max_size: u64) -> io::Result<u64> {
+    let entries = animation_entries()?;
+    Ok(remove_entries(
+        least_recently_used(entries, max_size).iter(),
+    ))
+}
+
+/// Like `shrink_to`, but never deletes `keep`, the animation that was just stored, even if it
+/// alone goes over the limit
+fn shrink_keeping(cache_dir: &Path, max_size: u64, keep: &Path) -> io::Result<u64> {
+    let entries = animation_entries_in(cache_dir)?;
+    Ok(remove_entries(
+        least_recently_used(entries, max_size)
+            .iter()
+            .filter(|entry| entry.path != keep),
+    ))
+}
+
+/// Picks the entries to delete so that the remaining ones add up to at most `max_size` bytes,
+/// starting from the ones used the longest time ago
+fn least_recently_used(mut entries: Vec<CacheEntry>, max_size: u64) -> Vec<CacheEntry> {
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    entries.sort_by_key(|entry| entry.last_used);
+    entries
+        .into_iter()
+        .take_while(|entry| {
+            let over_budget = total > max_size;
+            total -= entry.size;
+            over_budget
+        })
+        .collect()
+}
+
+fn remove_entries<'a>(entries: impl Iterator<Item = &'a CacheEntry>) -> u64 {
+    let mut freed = 0;
+    for entry in entries {
+        match std::fs::remove_file(&entry.path) {
+            Ok(()) => freed += entry.size,
+            Err(e) => eprintln!(
+                "WARNING: failed to remove cache file {}: {e}",
+                entry.path.display()
+            ),
+        }
+    }
+    freed
+}
+
 fn clean_previous_verions(cache_dir: &Path) {
     let mut read_dir = match std::fs::read_dir(cache_dir) {
         Ok(read_dir) => read_dir,
@@ -162,36 +624,130 @@ fn clean_previous_verions(cache_dir: &Path) {
 
 fn create_dir(p: &Path) -> io::Result<()> {
     if !p.is_dir() {
-        std::fs::create_dir(p)
+        std::fs::create_dir_all(p)
     } else {
         Ok(())
     }
 }
 
+/// The environment variable that overrides where the cache lives. `swww-daemon --cache-dir` sets
+/// it, so that the clients it spawns to load the cache look in the same place
+pub const CACHE_DIR_ENV: &str = "SWWW_CACHE_DIR";
+
+/// The environment variable that sets how much space cached animations may take up before the
+/// least recently used ones are deleted. `swww-daemon --cache-limit` sets it, for the clients it
+/// spawns
+pub const CACHE_LIMIT_ENV: &str = "SWWW_CACHE_LIMIT";
+
+/// 1GiB, which fits a few minutes of 4K animation
+pub const DEFAULT_CACHE_LIMIT: u64 = 1 << 30;
+
+/// Parses a number of bytes, optionally followed by `K`, `M` or `G`
+pub fn parse_size(raw: &str) -> Option<u64> {
+    let raw = raw.trim();
+    let (number, multiplier) = match raw.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&raw[..i], 1 << 10),
+        Some((i, 'M')) => (&raw[..i], 1 << 20),
+        Some((i, 'G')) => (&raw[..i], 1 << 30),
+        _ => (raw, 1),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// How much space cached animations may take up, from `$SWWW_CACHE_LIMIT`. A limit of 0 means
+/// there is none
+fn cache_limit() -> Option<u64> {
+    let limit = match std::env::var(CACHE_LIMIT_ENV) {
+        Ok(raw) if !raw.is_empty() => parse_size(&raw).unwrap_or_else(|| {
+            eprintln!("WARNING: invalid ${CACHE_LIMIT_ENV} '{raw}', using the default limit");
+            DEFAULT_CACHE_LIMIT
+        }),
+        _ => DEFAULT_CACHE_LIMIT,
+    };
+    (limit != 0).then_some(limit)
+}
+
+/// Empty variables count as unset, like the XDG base directory spec asks
+fn env_path(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Every cache file lives in here: `$SWWW_CACHE_DIR/swww`, `$XDG_CACHE_HOME/swww` or
+/// `$HOME/.cache/swww`, in that order. It is created if it does not exist yet
 fn cache_dir() -> io::Result<PathBuf> {
-    if let Ok(path) = std::env::var("XDG_CACHE_HOME") {
-        let mut path: PathBuf = path.into();
-        path.push("swww");
-        create_dir(&path)?;
-        Ok(path)
-    } else if let Ok(path) = std::env::var("HOME") {
-        let mut path: PathBuf = path.into();
-        path.push(".cache");
-        path.push("swww");
-        create_dir(&path)?;
-        Ok(path)
+    let path = cache_dir_from(
+        env_path(CACHE_DIR_ENV),
+        env_path("XDG_CACHE_HOME"),
+        env_path("HOME"),
+    )
+    .ok_or_else(|| {
+        std::io::Error::other("failed to read both $XDG_CACHE_HOME and $HOME environment variables")
+    })?;
+    create_dir(&path)?;
+    Ok(path)
+}
+
+/// Where the cache lives, given the values of `$SWWW_CACHE_DIR`, `$XDG_CACHE_HOME` and `$HOME`
+///
+/// The directory given in `$SWWW_CACHE_DIR` may well hold other files, so we keep to a `swww`
+/// subdirectory of it too: `swww clear-cache` deletes the whole cache directory
+fn cache_dir_from(
+    swww_cache_dir: Option<PathBuf>,
+    xdg_cache_home: Option<PathBuf>,
+    home: Option<PathBuf>,
+) -> Option<PathBuf> {
+    if let Some(path) = swww_cache_dir {
+        Some(path.join("swww"))
+    } else if let Some(path) = xdg_cache_home {
+        Some(path.join("swww"))
     } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "failed to read both $XDG_CACHE_HOME and $HOME environment variables".to_string(),
-        ))
+        home.map(|path| path.join(".cache").join("swww"))
+    }
+}
+
+/// Daemons in the default namespace use just the output name, like before we had namespaces
+#[must_use]
+fn output_filename(output_name: &str, namespace: &str) -> String {
+    if namespace.is_empty() {
+        output_name.to_string()
+    } else {
+        format!("{output_name}.{namespace}")
     }
 }
 
 #[must_use]
-fn animation_filename(path: &Path, dimensions: (u32, u32), pixel_format: PixelFormat) -> PathBuf {
+fn undo_filename(output_name: &str, namespace: &str) -> String {
+    format!("undo-{}", output_filename(output_name, namespace))
+}
+
+#[must_use]
+fn pixel_format_filename(namespace: &str) -> String {
+    let socket = IpcSocket::<Server>::path(namespace);
+    let socket = Path::new(&socket);
+    let socket_name = socket
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    format!("pixel_format_{socket_name}")
+}
+
+/// An empty `variant` gives the same names older versions used, so their entries stay valid
+#[must_use]
+fn animation_filename(
+    path: &Path,
+    variant: &str,
+    dimensions: (u32, u32),
+    pixel_format: PixelFormat,
+) -> PathBuf {
+    let variant = if variant.is_empty() {
+        String::new()
+    } else {
+        format!("__{variant}")
+    };
     format!(
-        "{}__{}x{}_{:?}_v{}",
+        "{}{variant}__{}x{}_{:?}_v{}",
         path.to_string_lossy().replace('/', "_"),
         dimensions.0,
         dimensions.1,
@@ -200,3 +756,277 @@ fn animation_filename(path: &Path, dimensions: (u32, u32), pixel_format: PixelFo
     )
     .into()
 }
+
+/// Parses the dimensions back out of a filename made by `animation_filename`
+///
+/// Returns `None` for every other file in the cache
+#[must_use]
+fn animation_dimensions(filename: &str) -> Option<(u32, u32)> {
+    let (rest, _version) = filename.rsplit_once("_v")?;
+    let (rest, _pixel_format) = rest.rsplit_once('_')?;
+    let (_path, dims) = rest.rsplit_once("__")?;
+    let (width, height) = dims.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes an empty directory only this test will use
+    fn temp_cache_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("swww-cache-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn animation_dimensions_round_trip() {
+        let path = Path::new("/some__weird/1x1_path__2x2.gif");
+        for variant in ["", "fit_000000", "stretch"] {
+            let filename = animation_filename(path, variant, (2560, 1440), PixelFormat::Xrgb);
+            assert_eq!(
+                animation_dimensions(filename.to_str().unwrap()),
+                Some((2560, 1440))
+            );
+        }
+        assert_ne!(
+            animation_filename(path, "", (2560, 1440), PixelFormat::Xrgb),
+            animation_filename(path, "fit_000000", (2560, 1440), PixelFormat::Xrgb)
+        );
+
+        assert_eq!(animation_dimensions("HDMI-A-1"), None);
+        assert_eq!(animation_dimensions("HDMI-A-1.namespace"), None);
+        assert_eq!(animation_dimensions(&pixel_format_filename("")), None);
+    }
+
+    #[test]
+    fn overwriting_the_source_invalidates_its_frames() {
+        let dir = temp_cache_dir("fingerprint");
+        let gif = dir.join("wall.gif");
+        let frames = [7; 42];
+        std::fs::write(&gif, [1; 200_000]).unwrap();
+        let source = SourceFingerprint::of(&gif);
+        assert!(source.is_some());
+        assert_eq!(SourceFingerprint::of(&gif), source);
+
+        let cached = dir.join("cached");
+        std::fs::write(&cached, [&animation_header(source)[..], &frames].concat()).unwrap();
+        assert!(is_current(&cached, source));
+        let bytes = std::fs::read(&cached).unwrap();
+        assert_eq!(current_frames(&bytes, source), Some(&frames[..]));
+
+        // a different animation with the same name and size, even with the old mtime put back
+        let modified = std::fs::metadata(&gif).unwrap().modified().unwrap();
+        let mut contents = vec![1; 200_000];
+        contents[199_999] = 2;
+        std::fs::write(&gif, &contents).unwrap();
+        File::options()
+            .write(true)
+            .open(&gif)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let changed = SourceFingerprint::of(&gif);
+        assert_ne!(changed, source);
+        assert!(!is_current(&cached, changed));
+        assert_eq!(current_frames(&bytes, changed), None);
+
+        // urls have no fingerprint, which still has to match
+        assert_eq!(
+            SourceFingerprint::of(Path::new("https://example.com/a.gif")),
+            None
+        );
+        assert!(!is_current(&cached, None));
+
+        // files from before the header existed, or from another format, are never read as frames
+        assert_eq!(current_frames(&frames, source), None);
+        let mut other_format = bytes.clone();
+        other_format[4] = ANIMATION_FORMAT + 1;
+        assert_eq!(current_frames(&other_format, source), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prune_stale_only_removes_that_image_at_that_size() {
+        let dir = temp_cache_dir("prune");
+        let gif = Path::new("/home/user/wallpaper.gif");
+        let stale = [
+            animation_filename(gif, "", (2560, 1440), PixelFormat::Xrgb),
+            animation_filename(gif, "fit_000000", (2560, 1440), PixelFormat::Bgr),
+        ];
+        let other_gif = Path::new("/home/user/wallpaper.gif.old");
+        let in_progress = format!("tmp-1-{}", stale[0].to_string_lossy());
+        let kept = [
+            PathBuf::from("DP-1"),
+            animation_filename(gif, "", (1920, 1080), PixelFormat::Xrgb),
+            animation_filename(other_gif, "", (2560, 1440), PixelFormat::Xrgb),
+            PathBuf::from(in_progress),
+        ];
+
+        for filename in kept.iter().chain(&stale) {
+            File::create(dir.join(filename)).unwrap();
+        }
+
+        prune_stale_in(&dir, gif, (2560, 1440)).unwrap();
+        for filename in &stale {
+            assert!(!dir.join(filename).exists(), "{filename:?} was kept");
+        }
+        for filename in &kept {
+            assert!(dir.join(filename).exists(), "{filename:?} was removed");
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_stops_under_budget() {
+        let entry = |name: &str, size, secs| CacheEntry {
+            path: PathBuf::from(name),
+            size,
+            last_used: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        };
+        let entries = vec![
+            entry("new", 300, 30),
+            entry("oldest", 100, 10),
+            entry("old", 200, 20),
+        ];
+        let names = |max_size| -> Vec<PathBuf> {
+            least_recently_used(entries.clone(), max_size)
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect()
+        };
+
+        assert!(names(600).is_empty());
+        assert_eq!(names(599), [PathBuf::from("oldest")]);
+        assert_eq!(names(500), [PathBuf::from("oldest")]);
+        assert_eq!(names(300), ["oldest", "old"].map(PathBuf::from));
+        assert_eq!(names(0), ["oldest", "old", "new"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn shrinking_after_a_store_keeps_the_new_animation() {
+        let dir = temp_cache_dir("limit");
+        let name = |gif: &str| animation_filename(Path::new(gif), "", (10, 10), PixelFormat::Xrgb);
+        let entry = |gif: &str, size: usize, secs: u64| {
+            let path = dir.join(name(gif));
+            std::fs::write(&path, vec![0; size]).unwrap();
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_times(FileTimes::new().set_accessed(time).set_modified(time))
+                .unwrap();
+            path
+        };
+        let oldest = entry("/oldest.gif", 100, 10);
+        let old = entry("/old.gif", 100, 20);
+        let new = entry("/new.gif", 300, 30);
+        std::fs::write(dir.join("DP-1"), "Lanczos3\n/oldest.gif").unwrap();
+
+        assert_eq!(shrink_keeping(&dir, 400, &new).unwrap(), 100);
+        assert!(!oldest.exists());
+        assert!(old.exists());
+
+        // the animation that was just stored stays, even when it alone is over the limit
+        assert_eq!(shrink_keeping(&dir, 200, &new).unwrap(), 100);
+        assert!(!old.exists());
+        assert!(new.exists());
+        assert!(dir.join("DP-1").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sizes_take_binary_suffixes() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size(" 2k "), Some(2048));
+        assert_eq!(parse_size("512M"), Some(512 << 20));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("1T"), None);
+        assert_eq!(parse_size("-1G"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn animation_entries_skip_output_files() {
+        let dir = temp_cache_dir("entries");
+        let gif = Path::new("/home/user/wallpaper.gif");
+        let animation = animation_filename(gif, "", (1920, 1080), PixelFormat::Xrgb);
+        let in_progress = format!("tmp-1-{}", animation.to_string_lossy());
+        std::fs::write(dir.join(&animation), [0; 42]).unwrap();
+        std::fs::write(dir.join(in_progress), [0; 42]).unwrap();
+        std::fs::write(dir.join("DP-1"), "Lanczos3\n/home/user/wallpaper.gif").unwrap();
+
+        let entries = animation_entries_in(&dir).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, dir.join(animation));
+        assert_eq!(entries[0].size, 42);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cache_dir_env_var_overrides_the_default_location() {
+        let cache_dir = |swww: Option<&str>, xdg: Option<&str>, home: Option<&str>| {
+            cache_dir_from(
+                swww.map(PathBuf::from),
+                xdg.map(PathBuf::from),
+                home.map(PathBuf::from),
+            )
+        };
+        let home = Some("/home/user");
+        assert_eq!(
+            cache_dir(Some("/mnt/pics"), Some("/xdg"), home),
+            Some(PathBuf::from("/mnt/pics/swww"))
+        );
+        assert_eq!(
+            cache_dir(None, Some("/xdg"), home),
+            Some(PathBuf::from("/xdg/swww"))
+        );
+        assert_eq!(
+            cache_dir(None, None, home),
+            Some(PathBuf::from("/home/user/.cache/swww"))
+        );
+        assert_eq!(cache_dir(None, None, None), None);
+    }
+
+    #[test]
+    fn storing_a_new_image_keeps_the_old_one_for_undo() {
+        let dir = temp_cache_dir("undo");
+        let undo = dir.join(undo_filename("DP-1", ""));
+        store_in(&dir, "DP-1", "", "/a.png", "Lanczos3").unwrap();
+        assert!(!undo.exists());
+
+        store_in(&dir, "DP-1", "", "/b.png", "Nearest").unwrap();
+        assert_eq!(
+            read_entry(&undo).unwrap(),
+            ("Lanczos3".into(), "/a.png".into())
+        );
+
+        // restoring stores the current image again, which must not lose the undo slot
+        store_in(&dir, "DP-1", "", "/b.png", "Nearest").unwrap();
+        assert_eq!(
+            read_entry(&undo).unwrap(),
+            ("Lanczos3".into(), "/a.png".into())
+        );
+
+        // undoing stores the old image, so undoing twice goes back to where we started
+        store_in(&dir, "DP-1", "", "/a.png", "Lanczos3").unwrap();
+        assert_eq!(
+            read_entry(&undo).unwrap(),
+            ("Nearest".into(), "/b.png".into())
+        );
+        assert_eq!(
+            read_entry(&dir.join("DP-1")).unwrap(),
+            ("Lanczos3".into(), "/a.png".into())
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
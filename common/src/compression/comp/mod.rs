@@ -16,6 +16,8 @@
 //! The default implementation lies in this file. Architecture-specific implementations
 //! that make use of specialized instructions lie in other submodules.
 
+#[cfg(target_arch = "aarch64")]
+pub(super) mod neon;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub(super) mod sse2;
 
@@ -84,6 +86,10 @@ pub(super) unsafe fn pack_bytes(cur: &[u8], goal: &[u8], v: &mut Vec<u8>) {
         if super::cpu::features::sse2() {
             return unsafe { sse2::pack_bytes(cur, goal, v) };
         }
+        #[cfg(target_arch = "aarch64")]
+        if super::cpu::features::neon() {
+            return unsafe { neon::pack_bytes(cur, goal, v) };
+        }
     }
 
     let mut i = 0;
@@ -49,7 +49,19 @@ pub mod features {
     }
 }
 
-#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+#[cfg(target_arch = "aarch64")]
+pub mod features {
+    decl_feature!(NEON, neon);
+
+    /// # Safety
+    ///
+    /// Same as the x86 version: it must not be called while someone else is reading the features
+    pub(super) unsafe fn init() {
+        NEON = std::arch::is_aarch64_feature_detected!("neon");
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
 pub mod features {
 
     /// UNIMPLEMENTED!!! This function must exist so that the init function in super compiles on
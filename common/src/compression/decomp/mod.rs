@@ -1,9 +1,57 @@
 //! This modules contains all the decompression functions, including the specialized ones using
 //! architecture-dependent instructions
 
+#[cfg(target_arch = "aarch64")]
+pub(super) mod neon;
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub(super) mod ssse3;
 
+/// Walks over the diff without writing anything, checking that every run stays within both the
+/// diff and a buffer of `pixels` pixels. The unpack functions below can only assert on the diff's
+/// bounds, so this must be called first for any diff that did not come straight from a Compressor
+pub(super) fn validate_diff(diff: &[u8], pixels: usize) -> Result<(), String> {
+    if diff.len() < 3 || diff[diff.len() - 1] | diff[diff.len() - 2] != 0 {
+        return Err("Poorly formed BitPack".to_string());
+    }
+    let len = diff.len() - 3;
+    let read_count = |diff_idx: &mut usize| -> Result<usize, String> {
+        let mut count = 0;
+        loop {
+            let Some(&byte) = diff.get(*diff_idx) else {
+                return Err("BitPack ends in the middle of a run".to_string());
+            };
+            *diff_idx += 1;
+            count += byte as usize;
+            if byte != u8::MAX {
+                return Ok(count);
+            }
+        }
+    };
+
+    let mut diff_idx = 0;
+    let mut pix_idx = 0;
+    while diff_idx < len {
+        pix_idx += read_count(&mut diff_idx)?;
+        let to_cpy = read_count(&mut diff_idx)?;
+        // the 4 channel unpackers copy a fourth byte along with every pixel
+        if diff_idx + to_cpy * 3 + 1 >= diff.len() {
+            return Err(format!(
+                "BitPack run of {to_cpy} pixels overflows its {} bytes",
+                diff.len()
+            ));
+        }
+        if to_cpy > 0 && pix_idx + to_cpy > pixels {
+            return Err(format!(
+                "BitPack writes up to pixel {}, but the buffer only has {pixels}",
+                pix_idx + to_cpy
+            ));
+        }
+        diff_idx += to_cpy * 3;
+        pix_idx += to_cpy + 1;
+    }
+    Ok(())
+}
+
 /// diff must be a slice produced by a BitPack
 /// buf must have the EXACT expected size by the BitPack
 #[inline(always)]
@@ -19,6 +67,10 @@ pub(super) fn unpack_bytes_4channels(buf: &mut [u8], diff: &[u8]) {
         if super::cpu::features::ssse3() {
             return unsafe { ssse3::unpack_bytes_4channels(buf, diff) };
         }
+        #[cfg(target_arch = "aarch64")]
+        if super::cpu::features::neon() {
+            return unsafe { neon::unpack_bytes_4channels(buf, diff) };
+        }
     }
 
     // The final bytes are just padding to prevent us from going out of bounds
@@ -107,6 +159,64 @@ pub(super) fn unpack_bytes_3channels(buf: &mut [u8], diff: &[u8]) {
     }
 }
 
+/// Unpacks the diff onto a buffer whose rows are `stride` bytes long, of which only the first
+/// `width` pixels belong to the image. The remaining bytes in each row are never touched.
+///
+/// This is slower than the functions above, so we only use it when rows actually carry padding
+pub(super) fn unpack_bytes_strided(
+    buf: &mut [u8],
+    diff: &[u8],
+    channels: usize,
+    width: usize,
+    stride: usize,
+) {
+    assert!(
+        diff[diff.len() - 1] | diff[diff.len() - 2] == 0,
+        "Poorly formed BitPack"
+    );
+    // The final bytes are just padding to prevent us from going out of bounds
+    let len = diff.len() - 3;
+
+    let mut diff_idx = 0;
+    let mut pix_idx = 0;
+    while diff_idx < len {
+        while diff[diff_idx] == u8::MAX {
+            pix_idx += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        pix_idx += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        let mut to_cpy = 0;
+        while diff[diff_idx] == u8::MAX {
+            to_cpy += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        to_cpy += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        let mut run = &diff[diff_idx..diff_idx + to_cpy * 3];
+        diff_idx += to_cpy * 3;
+        // a single run may span several rows, so we must split it at every row's end
+        while !run.is_empty() {
+            let (line, col) = (pix_idx / width, pix_idx % width);
+            let n = (width - col).min(run.len() / 3);
+            let start = line * stride + col * channels;
+            let dst = &mut buf[start..start + n * channels];
+            if channels == 3 {
+                dst.copy_from_slice(&run[..n * 3]);
+            } else {
+                for (dst, src) in dst.chunks_exact_mut(channels).zip(run.chunks_exact(3)) {
+                    dst[..3].copy_from_slice(src);
+                }
+            }
+            run = &run[n * 3..];
+            pix_idx += n;
+        }
+        pix_idx += 1;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
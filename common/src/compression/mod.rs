@@ -3,7 +3,7 @@
 //! Our compression strategy is documented in `comp/mod.rs`
 
 use comp::pack_bytes;
-use decomp::{unpack_bytes_3channels, unpack_bytes_4channels};
+use decomp::{unpack_bytes_3channels, unpack_bytes_4channels, unpack_bytes_strided, validate_diff};
 use std::ffi::{c_char, c_int};
 
 use crate::ipc::ImageRequestBuilder;
@@ -18,6 +18,15 @@ mod decomp;
 /// extracted from lz4.h
 const LZ4_MAX_INPUT_SIZE: usize = 0x7E000000;
 
+/// Compresses frames with LZ4's regular compressor instead of LZ4 HC. It is many times faster
+/// than any HC level, but makes bigger frames. Both make the same format, so it takes nothing
+/// special to decompress them
+pub const FAST_COMPRESSION_LEVEL: u8 = 0;
+/// The LZ4 HC level frames are compressed with, unless told otherwise
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 9;
+/// Levels above this one are all treated the same by LZ4 HC
+pub const MAX_COMPRESSION_LEVEL: u8 = 12;
+
 extern "C" {
     /// # Safety
     ///
@@ -30,6 +39,16 @@ extern "C" {
         comp_level: c_int,
     ) -> c_int;
 
+    /// # Safety
+    ///
+    /// This is guaranteed to succeed if `dst_cap >= LZ4_compressBound`.
+    fn LZ4_compress_default(
+        src: *const c_char,
+        dst: *mut c_char,
+        src_len: c_int,
+        dst_cap: c_int,
+    ) -> c_int;
+
     /// # Safety
     ///
     /// Fails when src is malformed, or dst_cap is insufficient.
@@ -74,21 +93,49 @@ impl BitPack {
         buf.extend(self.bytes());
     }
 
-    #[must_use]
-    pub(crate) fn deserialize(map: &Mmap, bytes: &[u8]) -> (Self, usize) {
-        assert!(bytes.len() > 12);
-        let len = u32::from_ne_bytes(bytes[0..4].try_into().unwrap()) as usize;
-        let expected_buf_size = u32::from_ne_bytes(bytes[4..8].try_into().unwrap());
-        let compressed_size = i32::from_ne_bytes(bytes[8..12].try_into().unwrap());
-        let inner = Inner::Mmapped(MmappedBytes::new_with_len(map, &bytes[12..12 + len], len));
-        (
+    /// Reads a BitPack back from `bytes`, returning it and how many bytes it took.
+    ///
+    /// The data may come from a cache file on disk, so we check everything we will later rely on
+    /// during decompression
+    pub(crate) fn deserialize(map: &Mmap, bytes: &[u8]) -> Result<(Self, usize), String> {
+        let field = |i: usize| -> Result<[u8; 4], String> {
+            bytes
+                .get(i..i + 4)
+                .map(|b| b.try_into().unwrap())
+                .ok_or_else(|| "BitPack header is truncated".to_string())
+        };
+        let len = u32::from_ne_bytes(field(0)?) as usize;
+        let expected_buf_size = u32::from_ne_bytes(field(4)?);
+        let compressed_size = i32::from_ne_bytes(field(8)?);
+
+        if len == 0 || len > i32::MAX as usize {
+            return Err(format!("BitPack has invalid len {len}"));
+        }
+        if expected_buf_size == 0 {
+            return Err("BitPack expects an empty buffer".to_string());
+        }
+        // the smallest possible diff is a single run plus the two bytes of padding
+        if !(3..=LZ4_MAX_INPUT_SIZE as i32).contains(&compressed_size) {
+            return Err(format!(
+                "BitPack has invalid decompressed size {compressed_size}"
+            ));
+        }
+        let Some(data) = bytes.get(12..12 + len) else {
+            return Err(format!(
+                "BitPack claims {len} bytes, but only {} are left",
+                bytes.len().saturating_sub(12)
+            ));
+        };
+
+        let inner = Inner::Mmapped(MmappedBytes::new_with_len(map, data, len));
+        Ok((
             Self {
                 inner,
                 expected_buf_size,
                 compressed_size,
             },
             12 + len,
-        )
+        ))
     }
 
     #[inline]
@@ -103,16 +150,32 @@ impl BitPack {
 
 /// Struct responsible for compressing our data. We use it to cache vector extensions that might
 /// speed up compression
-#[derive(Default)]
 pub struct Compressor {
     buf: Vec<u8>,
+    level: c_int,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compressor {
     #[inline]
     pub fn new() -> Self {
+        Self::with_level(DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    /// Lower levels compress faster, but make bigger frames. `level` is clamped to
+    /// `FAST_COMPRESSION_LEVEL..=MAX_COMPRESSION_LEVEL`
+    #[inline]
+    pub fn with_level(level: u8) -> Self {
         cpu::init();
-        Self { buf: Vec::new() }
+        Self {
+            buf: Vec::new(),
+            level: level.min(MAX_COMPRESSION_LEVEL).into(),
+        }
     }
 
     /// Compresses a frame of animation by getting the difference between the previous and the
@@ -159,14 +222,23 @@ impl Compressor {
         let mut v = vec![0; size];
         // SAFETY: we've ensured above that size >= LZ4_compressBound, so this should always work
         let n = unsafe {
-            LZ4_compress_HC(
-                self.buf.as_ptr().cast(),
-                v.as_mut_ptr() as _,
-                self.buf.len() as c_int,
-                size as c_int,
-                9,
-            ) as usize
-        };
+            if self.level == FAST_COMPRESSION_LEVEL.into() {
+                LZ4_compress_default(
+                    self.buf.as_ptr().cast(),
+                    v.as_mut_ptr() as _,
+                    self.buf.len() as c_int,
+                    size as c_int,
+                )
+            } else {
+                LZ4_compress_HC(
+                    self.buf.as_ptr().cast(),
+                    v.as_mut_ptr() as _,
+                    self.buf.len() as c_int,
+                    size as c_int,
+                    self.level,
+                )
+            }
+        } as usize;
         v.truncate(n);
 
         let expected_buf_size = if pixel_format.channels() == 3 {
@@ -239,7 +311,7 @@ impl Decompressor {
     }
 
     ///returns whether unpacking was successful. Note it can only fail if `buf.len() !=
-    ///expected_buf_size`
+    ///expected_buf_size`, or if the BitPack was corrupted (e.g. in the cache)
     #[inline]
     pub fn decompress(
         &mut self,
@@ -255,6 +327,60 @@ impl Decompressor {
             ));
         }
 
+        let v = self.decompress_diff(bitpack)?;
+        validate_diff(v, buf.len() / pixel_format.channels() as usize)?;
+        if pixel_format.can_copy_directly_onto_wl_buffer() {
+            unpack_bytes_3channels(buf, v);
+        } else {
+            unpack_bytes_4channels(buf, v);
+        }
+
+        Ok(())
+    }
+
+    /// Like `decompress`, but for buffers whose rows are `stride` bytes long, of which only the
+    /// first `width` pixels are part of the image.
+    ///
+    /// BitPacks are always made from images without any padding, so this unpacks them row by row
+    #[inline]
+    pub fn decompress_strided(
+        &mut self,
+        bitpack: &BitPack,
+        buf: &mut [u8],
+        pixel_format: PixelFormat,
+        width: usize,
+        stride: usize,
+    ) -> Result<(), String> {
+        let channels = pixel_format.channels() as usize;
+        if stride == width * channels {
+            return self.decompress(bitpack, buf, pixel_format);
+        }
+
+        let row_len = width * channels;
+        let expected_buf_size = bitpack.expected_buf_size as usize;
+        if row_len == 0 || stride < row_len || expected_buf_size % row_len != 0 {
+            return Err(format!(
+                "cannot unpack a BitPack of len {expected_buf_size} onto rows of {width} pixels \
+                 with stride {stride}"
+            ));
+        }
+        let expected_len = expected_buf_size / row_len * stride;
+        if buf.len() != expected_len {
+            return Err(format!(
+                "buf has len {}, but expected len is {expected_len}",
+                buf.len(),
+            ));
+        }
+
+        let v = self.decompress_diff(bitpack)?;
+        validate_diff(v, expected_buf_size / channels)?;
+        unpack_bytes_strided(buf, v, channels, width, stride);
+
+        Ok(())
+    }
+
+    /// Runs lz4 on the BitPack, returning the diff between the two frames
+    fn decompress_diff(&mut self, bitpack: &BitPack) -> Result<&[u8], String> {
         self.ensure_capacity(bitpack.compressed_size as usize);
 
         // SAFETY: errors will never happen because BitPacked is *always* only produced
@@ -275,23 +401,16 @@ impl Decompressor {
 
         // SAFETY: the call to self.ensure_capacity guarantees the pointer has the necessary size
         // to hold all the data
-        let v = unsafe {
-            std::slice::from_raw_parts_mut(self.ptr.as_ptr(), bitpack.compressed_size as usize)
-        };
-
-        if pixel_format.can_copy_directly_onto_wl_buffer() {
-            unpack_bytes_3channels(buf, v);
-        } else {
-            unpack_bytes_4channels(buf, v);
-        }
-
-        Ok(())
+        Ok(unsafe {
+            std::slice::from_raw_parts(self.ptr.as_ptr(), bitpack.compressed_size as usize)
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ipc::Animation;
 
     const FORMATS: [PixelFormat; 2] = [PixelFormat::Xrgb, PixelFormat::Rgb];
 
@@ -333,6 +452,165 @@ mod tests {
         }
     }
 
+    #[test]
+    fn every_level_decompresses_the_same() {
+        let frame1 = vec![0; 3000];
+        let frame2: Vec<u8> = (0..frame1.len()).map(|i| (i / 7) as u8).collect();
+        for format in FORMATS {
+            for level in 0..=MAX_COMPRESSION_LEVEL + 1 {
+                let compressed = Compressor::with_level(level)
+                    .compress(&frame1, &frame2, format)
+                    .unwrap();
+
+                let mut buf = buf_from(&frame1, format.channels().into());
+                Decompressor::new()
+                    .decompress(&compressed, &mut buf, format)
+                    .unwrap();
+                for (pix, expected) in buf
+                    .chunks_exact(format.channels().into())
+                    .zip(frame2.chunks_exact(3))
+                {
+                    assert_eq!(pix[..3], *expected, "level {level}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strided() {
+        // 1366 * 3 isn't a multiple of 4, so compositors want padding at the end of each row
+        let (width, height) = (1366, 4);
+        for format in FORMATS {
+            let channels = format.channels() as usize;
+            let stride = (width * channels).next_multiple_of(4);
+
+            let frame1 = vec![0; width * height * 3];
+            let frame2: Vec<u8> = (0..frame1.len()).map(|_| fastrand::u8(1..)).collect();
+            let compressed = Compressor::new()
+                .compress(&frame1, &frame2, format)
+                .unwrap();
+
+            let mut buf = vec![0; stride * height];
+            Decompressor::new()
+                .decompress_strided(&compressed, &mut buf, format, width, stride)
+                .unwrap();
+            for (line, row) in buf.chunks_exact(stride).enumerate() {
+                for (col, pix) in row[..width * channels].chunks_exact(channels).enumerate() {
+                    let i = (line * width + col) * 3;
+                    assert_eq!(pix[..3], frame2[i..i + 3], "line: {line}, col: {col}");
+                }
+                assert!(row[width * channels..].iter().all(|&b| b == 0));
+            }
+
+            let mut wrong_len = vec![0; stride * (height - 1)];
+            assert!(Decompressor::new()
+                .decompress_strided(&compressed, &mut wrong_len, format, width, stride)
+                .is_err());
+        }
+    }
+
+    /// Lays out frames the same way `Animation::serialize` does
+    fn serialize_animation(frames: &[(&BitPack, f64)]) -> Vec<u8> {
+        let mut v = (frames.len() as u32).to_ne_bytes().to_vec();
+        for (bitpack, duration) in frames {
+            v.extend_from_slice(&(bitpack.bytes().len() as u32).to_ne_bytes());
+            v.extend_from_slice(&bitpack.expected_buf_size.to_ne_bytes());
+            v.extend_from_slice(&bitpack.compressed_size.to_ne_bytes());
+            v.extend_from_slice(bitpack.bytes());
+            v.extend_from_slice(&duration.to_ne_bytes());
+        }
+        v
+    }
+
+    fn mmap_from(bytes: &[u8]) -> Mmap {
+        let mut mmap = Mmap::create(bytes.len().max(1));
+        mmap.slice_mut()[..bytes.len()].copy_from_slice(bytes);
+        mmap
+    }
+
+    #[test]
+    fn truncated_animations_are_rejected() {
+        let frame1 = [0; 300];
+        let frame2 = [1; 300];
+        let mut compressor = Compressor::new();
+        let forward = compressor
+            .compress(&frame1, &frame2, PixelFormat::Xrgb)
+            .unwrap();
+        let back = compressor
+            .compress(&frame2, &frame1, PixelFormat::Xrgb)
+            .unwrap();
+        let bytes = serialize_animation(&[(&forward, 0.1), (&back, 0.2)]);
+
+        let mmap = mmap_from(&bytes);
+        let (animation, len) = Animation::deserialize(&mmap, &mmap.slice()[..bytes.len()]).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(animation.animation.len(), 2);
+
+        for cut in 0..bytes.len() {
+            let mmap = mmap_from(&bytes[..cut]);
+            assert!(
+                Animation::deserialize(&mmap, &mmap.slice()[..cut]).is_err(),
+                "accepted an animation cut at {cut} of {} bytes",
+                bytes.len()
+            );
+        }
+
+        // a huge frame count must not make us allocate for all of it
+        let mut lying = bytes.clone();
+        lying[..4].copy_from_slice(&u32::MAX.to_ne_bytes());
+        let mmap = mmap_from(&lying);
+        assert!(Animation::deserialize(&mmap, mmap.slice()).is_err());
+
+        for duration in [f64::NAN, -1.0, f64::INFINITY] {
+            let bytes = serialize_animation(&[(&forward, duration)]);
+            let mmap = mmap_from(&bytes);
+            assert!(Animation::deserialize(&mmap, mmap.slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupt_bitpacks_are_rejected() {
+        let frame1 = [0; 12];
+        let frame2 = [0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1];
+        let bitpack = Compressor::new()
+            .compress(&frame1, &frame2, PixelFormat::Xrgb)
+            .unwrap();
+        let header = |len: u32, expected: u32, compressed: i32| {
+            let mut v = len.to_ne_bytes().to_vec();
+            v.extend_from_slice(&expected.to_ne_bytes());
+            v.extend_from_slice(&compressed.to_ne_bytes());
+            v.extend_from_slice(bitpack.bytes());
+            v
+        };
+        let len = bitpack.bytes().len() as u32;
+        for bytes in [
+            header(0, 16, bitpack.compressed_size),
+            header(len + 1, 16, bitpack.compressed_size),
+            header(u32::MAX, 16, bitpack.compressed_size),
+            header(len, 0, bitpack.compressed_size),
+            header(len, 16, 0),
+            header(len, 16, -1),
+            header(len, 16, i32::MAX),
+        ] {
+            let mmap = mmap_from(&bytes);
+            assert!(BitPack::deserialize(&mmap, mmap.slice()).is_err());
+        }
+
+        // a frame that claims to be smaller than it is would write past the end of the buffer
+        let mmap = mmap_from(&header(len, 8, bitpack.compressed_size));
+        let (shrunk, _) = BitPack::deserialize(&mmap, mmap.slice()).unwrap();
+        let mut buf = [0; 8];
+        for format in [PixelFormat::Xrgb, PixelFormat::Rgb] {
+            assert!(Decompressor::new()
+                .decompress(&shrunk, &mut buf, format)
+                .is_err());
+        }
+        let mut strided = [0; 16];
+        assert!(Decompressor::new()
+            .decompress_strided(&shrunk, &mut strided, PixelFormat::Xrgb, 1, 8)
+            .is_err());
+    }
+
     #[test]
     fn total_random() {
         for format in FORMATS.into_iter() {
@@ -14,6 +14,25 @@ impl IpcError {
     pub(crate) fn new(kind: IpcErrorKind, err: Errno) -> Self {
         Self { err, kind }
     }
+
+    /// Whether this happened because the other end closed the connection
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        matches!(self.kind, IpcErrorKind::Closed)
+    }
+
+    /// Whether the message itself was bad, as opposed to the socket. The connection is still
+    /// usable, so these can be answered with an error
+    #[must_use]
+    pub fn is_bad_message(&self) -> bool {
+        matches!(
+            self.kind,
+            IpcErrorKind::BadCode
+                | IpcErrorKind::BadMagic
+                | IpcErrorKind::WrongVersion
+                | IpcErrorKind::MalformedMsg
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -28,14 +47,24 @@ pub enum IpcErrorKind {
     Listen,
     /// Socket file wasn't found
     NoSocketFile,
+    /// The socket path can't be used, usually because it's too long
+    BadPath,
+    /// The socket systemd passed us through socket activation can't be used
+    SystemdSocket,
     /// Socket timeout couldn't be set
     SetTimeout,
     /// IPC contained invalid identification code
     BadCode,
+    /// The message didn't start with swww's magic number, so it wasn't sent by swww
+    BadMagic,
+    /// The message was sent by a version of swww that lays out its messages differently
+    WrongVersion,
     /// IPC payload was broken
     MalformedMsg,
     /// Reading socket failed
     Read,
+    /// The other end closed the connection before sending anything
+    Closed,
 }
 
 impl IpcErrorKind {
@@ -46,10 +75,21 @@ impl IpcErrorKind {
             Self::Bind => "failed to bind to socket",
             Self::Listen => "failed to listen on socket",
             Self::NoSocketFile => "Socket file not found. Are you sure swww-daemon is running?",
+            Self::BadPath => "invalid socket path. Unix socket paths must be under 108 bytes",
+            Self::SystemdSocket => {
+                "systemd passed an invalid socket. swww-daemon needs a single listening unix \
+                 stream socket, or one named `swww` with `FileDescriptorName=`"
+            }
             Self::SetTimeout => "failed to set read timeout for socket",
             Self::BadCode => "invalid message code",
+            Self::BadMagic => "message was not sent by swww, or by an older version of it",
+            Self::WrongVersion => {
+                "message was sent by a different version of swww. Restart swww-daemon after \
+                 upgrading swww"
+            }
             Self::MalformedMsg => "malformed ancillary message",
             Self::Read => "failed to receive message",
+            Self::Closed => "connection closed",
         }
     }
 }
@@ -85,3 +125,32 @@ impl<T> ErrnoExt for Result<T, Errno> {
         self.map_err(|error| error.context(kind))
     }
 }
+
+/// Why the contents of a message could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The message has no shared memory, but its code needs some
+    NoPayload,
+    /// The message ended in the middle of this field
+    Truncated(&'static str),
+    /// This field holds a value no version of swww would send
+    Invalid(&'static str),
+    /// The animation frames that came with an image are malformed
+    Animation(String),
+    /// The message's code is not one this end of the socket answers to
+    UnexpectedCode,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoPayload => f.write_str("message is missing its payload"),
+            Self::Truncated(field) => write!(f, "message ends in the middle of its {field}"),
+            Self::Invalid(field) => write!(f, "message has an invalid {field}"),
+            Self::Animation(e) => write!(f, "message has a malformed animation: {e}"),
+            Self::UnexpectedCode => f.write_str("unexpected message code"),
+        }
+    }
+}
+
+impl Error for ParseError {}
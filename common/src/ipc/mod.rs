@@ -1,19 +1,28 @@
 use std::path::PathBuf;
-
-use transmit::RawMsg;
+use std::time::Duration;
 
 mod error;
 mod socket;
 mod transmit;
 mod types;
 
+#[cfg(test)]
+mod tests;
+
 use crate::cache;
 use crate::mmap::Mmap;
 pub use error::*;
 pub use socket::*;
+pub use transmit::RawMsg;
 pub use types::*;
 
 pub struct ImageRequestBuilder {
+    namespace: String,
+    transition: Transition,
+    write_cache: bool,
+    write_animation_cache: bool,
+    animation_cache_variant: String,
+    transformed: bool,
     memory: Mmap,
     len: usize,
     img_count: u8,
@@ -21,23 +30,69 @@ pub struct ImageRequestBuilder {
 }
 
 impl ImageRequestBuilder {
+    /// `namespace` is the namespace of the daemon we are sending the request to. We need it to
+    /// store the cache correctly
     #[inline]
-    pub fn new(transition: Transition) -> Self {
+    pub fn new(transition: Transition, namespace: &str) -> Self {
         let memory = Mmap::create(1 << (20 + 3)); // start with 8 MB
         let len = 0;
         let mut builder = Self {
+            namespace: namespace.to_string(),
+            transition,
+            write_cache: true,
+            write_animation_cache: true,
+            animation_cache_variant: String::new(),
+            transformed: false,
             memory,
             len,
             img_count: 0,
             img_count_index: 0,
         };
-        transition.serialize(&mut builder);
+        let mut bytes = Vec::with_capacity(Transition::SERIALIZED_LEN);
+        builder.transition.serialize(&mut bytes);
+        builder.extend(&bytes);
         builder.img_count_index = builder.len;
         builder.len += 1;
-        assert_eq!(builder.len, 52);
         builder
     }
 
+    /// The transition of every image that wasn't pushed with one of its own
+    #[inline]
+    pub fn transition(&self) -> &Transition {
+        &self.transition
+    }
+
+    /// Whether to write the frames of animated images to the cache. This is on by default.
+    ///
+    /// The frames are written in the background: see `cache::wait_for_pending_writes`
+    #[inline]
+    pub fn set_animation_cache_writes(&mut self, write: bool) {
+        self.write_animation_cache = write;
+    }
+
+    /// Distinguishes the cached frames of animations made from the same image in different ways
+    /// (for example, with different resize strategies), so that they don't get mixed up. Empty by
+    /// default
+    #[inline]
+    pub fn set_animation_cache_variant(&mut self, variant: String) {
+        self.animation_cache_variant = variant;
+    }
+
+    /// Whether the images pushed from now on were already rotated or flipped by the user, in which
+    /// case the daemon draws them as they are, instead of in the orientation the compositor
+    /// prefers. This is off by default
+    #[inline]
+    pub fn set_transformed(&mut self, transformed: bool) {
+        self.transformed = transformed;
+    }
+
+    /// Whether to remember the images as the outputs' wallpapers, for `swww restore`. This is on
+    /// by default
+    #[inline]
+    pub fn set_cache_writes(&mut self, write: bool) {
+        self.write_cache = write;
+    }
+
     fn push_byte(&mut self, byte: u8) {
         if self.len >= self.memory.len() {
             self.grow();
@@ -73,12 +128,20 @@ impl ImageRequestBuilder {
             img,
             dim: dims,
             format,
+            transition,
         } = &img;
         self.serialize_bytes(path.as_bytes());
         self.serialize_bytes(img);
         self.extend(&dims.0.to_ne_bytes());
         self.extend(&dims.1.to_ne_bytes());
         self.push_byte(*format as u8);
+        self.push_byte(self.transformed as u8);
+        if let Some(transition) = transition {
+            self.push_byte(1);
+            self.push_transition(transition);
+        } else {
+            self.push_byte(0);
+        }
 
         self.push_byte(outputs.len() as u8);
         for output in outputs.iter() {
@@ -92,24 +155,27 @@ impl ImageRequestBuilder {
         } else {
             self.push_byte(0);
         }
+        let animation_end = self.len;
+        if let Some(animation) = animation.as_ref() {
+            self.extend(&animation.loop_count.to_ne_bytes());
+            self.extend(&animation.speed.to_ne_bytes());
+        }
 
         // cache the request
-        for output in outputs.iter() {
-            if let Err(e) = super::cache::store(output, path, &filter) {
+        for output in outputs.iter().filter(|_| self.write_cache) {
+            if let Err(e) = super::cache::store(output, &self.namespace, path, &filter) {
                 eprintln!("ERROR: failed to store cache: {e}");
             }
         }
 
-        if animation.is_some() && path != "-" {
-            let p = PathBuf::from(&path);
-            if let Err(e) = cache::store_animation_frames(
-                &self.memory.slice()[animation_start..],
-                &p,
+        if animation.is_some() && path != "-" && self.write_animation_cache {
+            cache::store_animation_frames_in_background(
+                self.memory.slice()[animation_start..animation_end].into(),
+                PathBuf::from(&path),
+                self.animation_cache_variant.clone(),
                 *dims,
                 *format,
-            ) {
-                eprintln!("Error storing cache for {}: {e}", path);
-            }
+            );
         }
     }
 
@@ -119,6 +185,21 @@ impl ImageRequestBuilder {
         self.memory
     }
 
+    /// Builds a slideshow request, where every image pushed into the builder is one of the images
+    /// the daemon will cycle through on its outputs
+    #[inline]
+    pub fn build_slideshow(mut self, interval: Duration, order: SlideshowOrder) -> Mmap {
+        self.extend(&interval.as_secs_f64().to_ne_bytes());
+        self.push_byte(order as u8);
+        self.build()
+    }
+
+    fn push_transition(&mut self, transition: &Transition) {
+        let mut bytes = Vec::with_capacity(Transition::SERIALIZED_LEN);
+        transition.serialize(&mut bytes);
+        self.extend(&bytes);
+    }
+
     fn serialize_bytes(&mut self, bytes: &[u8]) {
         self.extend(&(bytes.len() as u32).to_ne_bytes());
         self.extend(bytes);
@@ -130,6 +211,16 @@ pub enum RequestSend {
     Query,
     Clear(Mmap),
     Img(Mmap),
+    Slideshow(Mmap),
+    Screenshot(Mmap),
+    SetLayer(Mmap),
+    /// answered only once no transition is running on the given outputs
+    WaitIdle(Mmap),
+    /// keeps the connection open and streams an `Answer::Event` for everything that happens
+    Subscribe,
+    Pause(Mmap),
+    Resume(Mmap),
+    TogglePause(Mmap),
     Kill,
 }
 
@@ -138,6 +229,14 @@ pub enum RequestRecv {
     Query,
     Clear(ClearReq),
     Img(ImageReq),
+    Slideshow(SlideshowReq),
+    Screenshot(ScreenshotReq),
+    SetLayer(LayerReq),
+    WaitIdle(WaitIdleReq),
+    Subscribe,
+    Pause(PauseReq),
+    Resume(PauseReq),
+    TogglePause(PauseReq),
     Kill,
 }
 
@@ -152,10 +251,10 @@ impl RequestSend {
 }
 
 impl RequestRecv {
-    #[must_use]
+    /// Parses a request, which may have been written to the socket by anything
     #[inline]
-    pub fn receive(msg: RawMsg) -> Self {
-        msg.into()
+    pub fn receive(msg: RawMsg) -> Result<Self, ParseError> {
+        msg.try_into()
     }
 }
 
@@ -163,6 +262,16 @@ pub enum Answer {
     Ok,
     Ping(bool),
     Info(Box<[BgInfo]>),
+    /// whether the daemon is paused after handling a pause/resume request
+    Paused(bool),
+    /// the requested output's canvas, or `None` if there is no output with that name
+    Screenshot(Option<Screenshot>),
+    /// sent to subscribed clients, after the initial `Ok`
+    Event(Event),
+    /// the daemon could not make sense of the request, or could not do any of it
+    Err(String),
+    /// the daemon did only part of the request. One message for each part it could not do
+    Warnings(Box<[String]>),
 }
 
 impl Answer {
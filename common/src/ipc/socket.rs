@@ -3,7 +3,9 @@ use std::marker::PhantomData;
 use std::sync::OnceLock;
 use std::time::Duration;
 
+use rustix::fd::FromRawFd;
 use rustix::fd::OwnedFd;
+use rustix::fd::RawFd;
 use rustix::io::Errno;
 use rustix::net;
 
@@ -11,6 +13,15 @@ use super::ErrnoExt;
 use super::IpcError;
 use super::IpcErrorKind;
 
+/// When set, the path of the socket, whatever the namespace. `swww --socket` and
+/// `swww-daemon --socket` set it for themselves (and the processes they spawn), so the flag wins
+/// over a value inherited from the environment
+pub const SOCKET_ENV: &str = "SWWW_SOCKET";
+
+/// The `FileDescriptorName=` to give swww-daemon's socket in a systemd socket unit that passes it
+/// more than one socket. With a single socket, its name doesn't matter
+pub const SYSTEMD_SOCKET_NAME: &str = "swww";
+
 /// Represents client in IPC communication, via typestate pattern in [`IpcSocket`]
 pub struct Client;
 /// Represents server in IPC communication, via typestate pattern in [`IpcSocket`]
@@ -37,31 +48,36 @@ impl<T> IpcSocket<T> {
         self.fd
     }
 
-    fn socket_file() -> String {
-        let runtime = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
-            let uid = rustix::process::getuid();
-            format!("/run/user/{}", uid.as_raw())
-        });
+    /// The part of the socket path common to every namespace
+    fn socket_file_base() -> &'static str {
+        static BASE: OnceLock<String> = OnceLock::new();
+        BASE.get_or_init(|| {
+            let runtime = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
+                let uid = rustix::process::getuid();
+                format!("/run/user/{}", uid.as_raw())
+            });
 
-        let display = if let Ok(wayland_socket) = std::env::var("WAYLAND_DISPLAY") {
-            let mut i = 0;
-            // if WAYLAND_DISPLAY is a full path, use only its final component
-            for (j, ch) in wayland_socket.bytes().enumerate().rev() {
-                if ch == b'/' {
-                    i = j + 1;
-                    break;
+            let display = if let Ok(wayland_socket) = std::env::var("WAYLAND_DISPLAY") {
+                let mut i = 0;
+                // if WAYLAND_DISPLAY is a full path, use only its final component
+                for (j, ch) in wayland_socket.bytes().enumerate().rev() {
+                    if ch == b'/' {
+                        i = j + 1;
+                        break;
+                    }
                 }
-            }
-            (wayland_socket[i..]).to_string()
-        } else {
-            eprintln!("WARNING: WAYLAND_DISPLAY variable not set. Defaulting to wayland-0");
-            "wayland-0.sock".to_string()
-        };
+                (wayland_socket[i..]).to_string()
+            } else {
+                eprintln!("WARNING: WAYLAND_DISPLAY variable not set. Defaulting to wayland-0");
+                "wayland-0.sock".to_string()
+            };
 
-        format!("{runtime}/swww-{display}.sock")
+            format!("{runtime}/swww-{display}")
+        })
     }
 
-    /// Retreives path to socket file
+    /// Retreives path to socket file of the daemon running in `namespace`. The empty namespace is
+    /// the default one. If `SWWW_SOCKET` is set, it is the path for every namespace.
     ///
     /// To treat this as filesystem path, wrap it in [`Path`].
     /// If you get errors with missing generics, you can shove any type as `T`, but
@@ -69,9 +85,47 @@ impl<T> IpcSocket<T> {
     ///
     /// [`Path`]: std::path::Path
     #[must_use]
-    pub fn path() -> &'static str {
-        static PATH: OnceLock<String> = OnceLock::new();
-        PATH.get_or_init(Self::socket_file)
+    pub fn path(namespace: &str) -> String {
+        if let Some(path) = socket_override() {
+            return path;
+        }
+        let base = Self::socket_file_base();
+        if namespace.is_empty() {
+            format!("{base}.sock")
+        } else {
+            format!("{base}.{namespace}.sock")
+        }
+    }
+
+    /// The namespaces of every daemon with a socket file for the current wayland display. The
+    /// default namespace is the empty string
+    ///
+    /// With `SWWW_SOCKET` set there is a single socket, whose namespace we can't know, so it counts
+    /// as the default one
+    #[must_use]
+    pub fn all_namespaces() -> Vec<String> {
+        if let Some(path) = socket_override() {
+            return if std::path::Path::new(&path).exists() {
+                vec![String::new()]
+            } else {
+                Vec::new()
+            };
+        }
+        let base = std::path::Path::new(Self::socket_file_base());
+        let (Some(dir), Some(prefix)) = (base.parent(), base.file_name()) else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let prefix = prefix.to_string_lossy();
+
+        let mut namespaces: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| socket_namespace(entry.file_name().to_str()?, &prefix))
+            .collect();
+        namespaces.sort();
+        namespaces
     }
 
     #[must_use]
@@ -80,9 +134,41 @@ impl<T> IpcSocket<T> {
     }
 }
 
+/// The socket path set through `SWWW_SOCKET`, if any
+fn socket_override() -> Option<String> {
+    env::var(SOCKET_ENV).ok().filter(|path| !path.is_empty())
+}
+
+/// The namespace of the socket file named `filename`, if it's one of ours. `prefix` is the file name
+/// of the socket's path, without the namespace and the extension
+fn socket_namespace(filename: &str, prefix: &str) -> Option<String> {
+    let rest = filename.strip_prefix(prefix)?.strip_suffix(".sock")?;
+    if rest.is_empty() {
+        Some(String::new())
+    } else {
+        rest.strip_prefix('.')
+            .map(|namespace| namespace.to_string())
+    }
+}
+
 impl IpcSocket<Client> {
-    /// Connects to already running `Daemon`, if there is one.
-    pub fn connect() -> Result<Self, IpcError> {
+    /// The pid of the daemon at the other end, if the OS can tell us
+    #[must_use]
+    pub fn peer_pid(&self) -> Option<i32> {
+        #[cfg(target_os = "linux")]
+        {
+            net::sockopt::get_socket_peercred(&self.fd)
+                .ok()
+                .map(|cred| cred.pid.as_raw_nonzero().get())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Connects to already running `Daemon` in `namespace`, if there is one.
+    pub fn connect(namespace: &str) -> Result<Self, IpcError> {
         // these were hardcoded everywhere, no point in passing them around
         let tries = 5;
         let interval = 100;
@@ -95,7 +181,8 @@ impl IpcSocket<Client> {
         )
         .context(IpcErrorKind::Socket)?;
 
-        let addr = net::SocketAddrUnix::new(Self::path()).expect("addr is correct");
+        let addr =
+            net::SocketAddrUnix::new(Self::path(namespace)).context(IpcErrorKind::BadPath)?;
 
         // this will be overwriten, Rust just doesn't know it
         let mut error = Errno::INVAL;
@@ -127,12 +214,20 @@ impl IpcSocket<Client> {
 
         Err(error.context(kind))
     }
+
+    /// Changes how long [`recv`](Self::recv) waits for an answer. `None` waits forever, which is
+    /// what we want when the daemon only answers once it's done with something
+    pub fn set_recv_timeout(&self, timeout: Option<Duration>) -> Result<(), IpcError> {
+        net::sockopt::set_socket_timeout(&self.fd, net::sockopt::Timeout::Recv, timeout)
+            .context(IpcErrorKind::SetTimeout)
+    }
 }
 
 impl IpcSocket<Server> {
     /// Creates [`IpcSocket`] for use in server (i.e `Daemon`)
-    pub fn server() -> Result<Self, IpcError> {
-        let addr = net::SocketAddrUnix::new(Self::path()).expect("addr is correct");
+    pub fn server(namespace: &str) -> Result<Self, IpcError> {
+        let addr =
+            net::SocketAddrUnix::new(Self::path(namespace)).context(IpcErrorKind::BadPath)?;
         let socket = net::socket_with(
             net::AddressFamily::UNIX,
             net::SocketType::STREAM,
@@ -144,4 +239,127 @@ impl IpcSocket<Server> {
         net::listen(&socket, 0).context(IpcErrorKind::Listen)?;
         Ok(Self::new(socket))
     }
+
+    /// Takes the socket systemd bound for us, if we were started through socket activation. See
+    /// `sd_listen_fds(3)`
+    ///
+    /// This unsets `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`, so that nothing we spawn
+    /// mistakes the socket for its own
+    pub fn from_systemd() -> Result<Option<Self>, IpcError> {
+        let var = |name| env::var(name).ok();
+        let fd = systemd_listen_fd(
+            var("LISTEN_PID").as_deref(),
+            var("LISTEN_FDS").as_deref(),
+            var("LISTEN_FDNAMES").as_deref(),
+            rustix::process::getpid().as_raw_nonzero().get() as u32,
+        );
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+        let Some(fd) = fd? else {
+            return Ok(None);
+        };
+
+        // SAFETY: systemd passed this file descriptor to us, and nothing else in the process
+        // knows about it
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        let is_listening_unix_stream = net::sockopt::get_socket_type(&socket)
+            .is_ok_and(|kind| kind == net::SocketType::STREAM)
+            && net::sockopt::get_socket_domain(&socket)
+                .is_ok_and(|domain| domain == net::AddressFamily::UNIX)
+            && net::sockopt::get_socket_acceptconn(&socket).unwrap_or(false);
+        if !is_listening_unix_stream {
+            return Err(IpcError::new(IpcErrorKind::SystemdSocket, Errno::NOTSOCK));
+        }
+        // the same flags `server` gives the sockets it binds
+        rustix::io::fcntl_setfd(&socket, rustix::io::FdFlags::CLOEXEC)
+            .context(IpcErrorKind::SystemdSocket)?;
+        rustix::io::ioctl_fionbio(&socket, true).context(IpcErrorKind::SystemdSocket)?;
+        Ok(Some(Self::new(socket)))
+    }
+}
+
+/// The first file descriptor systemd passes, as in `SD_LISTEN_FDS_START`
+const SYSTEMD_FIRST_FD: RawFd = 3;
+
+/// Which of the file descriptors systemd passed is our socket, given the values of `LISTEN_PID`,
+/// `LISTEN_FDS` and `LISTEN_FDNAMES`. `None` if we were not socket activated
+fn systemd_listen_fd(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    listen_fdnames: Option<&str>,
+    pid: u32,
+) -> Result<Option<RawFd>, IpcError> {
+    let invalid = || IpcError::new(IpcErrorKind::SystemdSocket, Errno::INVAL);
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(None);
+    };
+    // the variables were meant for some other process, which we inherited them from
+    if listen_pid.parse::<u32>().map_err(|_| invalid())? != pid {
+        return Ok(None);
+    }
+    let count: RawFd = listen_fds.parse().map_err(|_| invalid())?;
+    match count {
+        0 => Ok(None),
+        1 => Ok(Some(SYSTEMD_FIRST_FD)),
+        _ => {
+            let names = listen_fdnames.ok_or_else(invalid)?;
+            names
+                .split(':')
+                .take(count as usize)
+                .position(|name| name == SYSTEMD_SOCKET_NAME)
+                .map(|i| Some(SYSTEMD_FIRST_FD + i as RawFd))
+                .ok_or_else(invalid)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_namespaces_in_socket_file_names() {
+        let prefix = "swww-wayland-1";
+        let namespace = |filename| socket_namespace(filename, prefix);
+        assert_eq!(namespace("swww-wayland-1.sock").as_deref(), Some(""));
+        assert_eq!(namespace("swww-wayland-1.top.sock").as_deref(), Some("top"));
+        // other displays and other files
+        assert_eq!(namespace("swww-wayland-10.sock"), None);
+        assert_eq!(namespace("swww-wayland-1.top"), None);
+        assert_eq!(namespace("wayland-1.sock"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_know_the_pid_at_the_other_end() {
+        let (client, _server) = net::socketpair(
+            net::AddressFamily::UNIX,
+            net::SocketType::STREAM,
+            net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let client = IpcSocket::<Client>::new(client);
+        assert_eq!(client.peer_pid(), Some(std::process::id() as i32));
+    }
+
+    #[test]
+    fn should_find_the_socket_systemd_passed() {
+        let fd = |pid, fds, names| systemd_listen_fd(pid, fds, names, 42).ok();
+        // not socket activated, or the variables were meant for another process
+        assert_eq!(fd(None, None, None), Some(None));
+        assert_eq!(fd(Some("7"), Some("1"), None), Some(None));
+        assert_eq!(fd(Some("42"), Some("0"), None), Some(None));
+
+        assert_eq!(fd(Some("42"), Some("1"), None), Some(Some(3)));
+        assert_eq!(fd(Some("42"), Some("1"), Some("whatever")), Some(Some(3)));
+        assert_eq!(fd(Some("42"), Some("2"), Some("other:swww")), Some(Some(4)));
+
+        // with several sockets, we can't guess which is ours
+        assert_eq!(fd(Some("42"), Some("2"), None), None);
+        assert_eq!(fd(Some("42"), Some("2"), Some("a:b")), None);
+        assert_eq!(fd(Some("42"), Some("nope"), None), None);
+        assert_eq!(fd(Some("nope"), Some("1"), None), None);
+    }
 }
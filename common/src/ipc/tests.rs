@@ -0,0 +1,491 @@
+//! Round-trip tests for the image request wire format: everything we push into an
+//! `ImageRequestBuilder` must come out of `RequestRecv` exactly as it went in
+
+use std::num::NonZeroU8;
+use std::time::Duration;
+
+use super::*;
+use crate::compression::{Compressor, Decompressor};
+
+const FORMATS: [PixelFormat; 6] = [
+    PixelFormat::Bgr,
+    PixelFormat::Rgb,
+    PixelFormat::Xbgr,
+    PixelFormat::Xrgb,
+    PixelFormat::Xbgr2101010,
+    PixelFormat::Xrgb2101010,
+];
+
+const TRANSITION_TYPES: [TransitionType; 12] = [
+    TransitionType::Simple,
+    TransitionType::Fade,
+    TransitionType::Outer,
+    TransitionType::Wipe,
+    TransitionType::Grow,
+    TransitionType::Wave,
+    TransitionType::None,
+    TransitionType::Reveal,
+    TransitionType::Dissolve,
+    TransitionType::Melt,
+    TransitionType::Crossfade,
+    TransitionType::Slide,
+];
+
+const DIMS: [(u32, u32); 5] = [
+    (1, 1),
+    (0, 0),
+    (u32::MAX, u32::MAX),
+    (1, u32::MAX),
+    (3841, 2161),
+];
+
+/// Strings with multi-byte characters, of odd byte lengths so that whatever comes after them is
+/// unaligned
+fn unicode_string(rng: &mut fastrand::Rng, max_chars: usize) -> String {
+    const CHARS: [char; 8] = ['a', '/', '-', 'é', 'ß', '壁', '紙', '🖼'];
+    let mut s: String = (0..rng.usize(..max_chars))
+        .map(|_| CHARS[rng.usize(..CHARS.len())])
+        .collect();
+    if s.len() % 2 == 0 {
+        s.push('.');
+    }
+    s
+}
+
+fn random_transition(rng: &mut fastrand::Rng) -> Transition {
+    let coord = |rng: &mut fastrand::Rng| {
+        if rng.bool() {
+            Coord::Pixel(rng.f32() * 10000.0)
+        } else {
+            Coord::Percent(rng.f32())
+        }
+    };
+    Transition {
+        transition_type: TRANSITION_TYPES[rng.usize(..TRANSITION_TYPES.len())],
+        duration: rng.f32() * 100.0,
+        step: NonZeroU8::new(rng.u8(1..)).unwrap(),
+        fps: rng.u16(..),
+        angle: rng.f64() * 360.0 - 180.0,
+        pos: Position::new(coord(rng), coord(rng)),
+        bezier: (f32::MIN, f32::MAX, rng.f32(), -rng.f32()),
+        wave: (f32::EPSILON, rng.f32() * 50.0),
+        invert_y: rng.bool(),
+        linear: rng.bool(),
+        mask: rng
+            .bool()
+            .then(|| (0..rng.usize(..64)).map(|_| rng.u8(..)).collect()),
+    }
+}
+
+/// The frames the animation goes through (starting from the image itself), and the animation
+/// made out of them
+fn random_animation(rng: &mut fastrand::Rng) -> (Vec<Vec<u8>>, Animation) {
+    let frame_len = rng.usize(1..50) * 3;
+    let mut compressor = Compressor::new();
+    let mut frames = vec![(0..frame_len).map(|_| rng.u8(..)).collect::<Vec<_>>()];
+    let mut animation = Vec::new();
+    for _ in 0..rng.usize(1..4) {
+        let prev = frames.last().unwrap();
+        let mut next = prev.clone();
+        next[rng.usize(..frame_len)] ^= 0xff;
+        let bitpack = compressor
+            .compress(prev, &next, PixelFormat::Rgb)
+            .expect("frames are different");
+        animation.push((bitpack, Duration::from_millis(rng.u64(1..100_000))));
+        frames.push(next);
+    }
+    (
+        frames,
+        Animation {
+            animation: animation.into(),
+            loop_count: rng.u32(..),
+            speed: rng.f32() * 10.0 + 0.05,
+        },
+    )
+}
+
+struct Group {
+    path: String,
+    img: Vec<u8>,
+    dim: (u32, u32),
+    format: PixelFormat,
+    transition: Option<Transition>,
+    transformed: bool,
+    outputs: Vec<String>,
+    /// the frames the animation should decompress to
+    frames: Option<Vec<Vec<u8>>>,
+    loop_count: u32,
+    speed: f32,
+}
+
+fn random_request(rng: &mut fastrand::Rng, groups: usize) -> (Transition, Vec<Group>, Mmap) {
+    let transition = random_transition(rng);
+    let mut builder = ImageRequestBuilder::new(transition.clone(), "");
+    builder.set_cache_writes(false);
+    builder.set_animation_cache_writes(false);
+
+    let mut expected = Vec::new();
+    for _ in 0..groups {
+        let img_len = rng.usize(..64) * 3;
+        let group = Group {
+            path: unicode_string(rng, 300),
+            img: (0..img_len).map(|_| rng.u8(..)).collect(),
+            dim: DIMS[rng.usize(..DIMS.len())],
+            format: FORMATS[rng.usize(..FORMATS.len())],
+            transition: rng.bool().then(|| random_transition(rng)),
+            transformed: rng.bool(),
+            outputs: (0..rng.usize(..4))
+                .map(|_| unicode_string(rng, 20))
+                .collect(),
+            frames: None,
+            loop_count: 0,
+            speed: 1.0,
+        };
+        let (frames, animation) = if rng.bool() {
+            let (frames, animation) = random_animation(rng);
+            (Some(frames), Some(animation))
+        } else {
+            (None, None)
+        };
+        let loop_count = animation.as_ref().map_or(0, |a| a.loop_count);
+        let speed = animation.as_ref().map_or(1.0, |a| a.speed);
+
+        builder.set_transformed(group.transformed);
+        builder.push(
+            ImgSend {
+                path: group.path.clone(),
+                img: group.img.clone().into_boxed_slice(),
+                dim: group.dim,
+                format: group.format,
+                transition: group.transition.clone(),
+            },
+            "Lanczos3".to_string(),
+            &group.outputs,
+            animation,
+        );
+        expected.push(Group {
+            frames,
+            loop_count,
+            speed,
+            ..group
+        });
+    }
+    (transition, expected, builder.build())
+}
+
+fn assert_image_req_eq(req: &ImageReq, transition: &Transition, groups: &[Group]) {
+    assert!(req.transition == *transition);
+    assert_eq!(req.imgs.len(), groups.len());
+    assert_eq!(req.outputs.len(), groups.len());
+    assert_eq!(req.animations.len(), groups.len());
+
+    let mut decompressor = Decompressor::new();
+    for (i, group) in groups.iter().enumerate() {
+        let img = &req.imgs[i];
+        assert_eq!(img.path.str(), group.path);
+        assert_eq!(img.img.bytes(), group.img);
+        assert_eq!(img.dim, group.dim);
+        assert_eq!(img.format, group.format);
+        assert!(img.transition == group.transition);
+        assert_eq!(img.transformed, group.transformed);
+
+        let outputs: Vec<_> = req.outputs[i].iter().map(|o| o.str()).collect();
+        assert_eq!(outputs, group.outputs);
+
+        match (&req.animations[i], &group.frames) {
+            (None, None) => (),
+            (Some(animation), Some(frames)) => {
+                assert_eq!(animation.animation.len(), frames.len() - 1);
+                assert_eq!(animation.loop_count, group.loop_count);
+                assert_eq!(animation.speed, group.speed);
+                let mut buf = frames[0].clone();
+                for ((bitpack, _), frame) in animation.animation.iter().zip(&frames[1..]) {
+                    decompressor
+                        .decompress(bitpack, &mut buf, PixelFormat::Rgb)
+                        .unwrap();
+                    assert_eq!(&buf, frame);
+                }
+            }
+            _ => panic!("group {i} lost or gained an animation"),
+        }
+    }
+}
+
+#[test]
+fn image_requests_round_trip() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    for groups in 1..=5 {
+        for _ in 0..20 {
+            let (transition, expected, mmap) = random_request(&mut rng, groups);
+            match RequestRecv::receive(RequestSend::Img(mmap).into()).unwrap() {
+                RequestRecv::Img(req) => assert_image_req_eq(&req, &transition, &expected),
+                _ => panic!("image request was parsed as something else"),
+            }
+        }
+    }
+}
+
+#[test]
+fn animation_durations_round_trip() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    let (frames, animation) = random_animation(&mut rng);
+    let durations: Vec<_> = animation.animation.iter().map(|(_, d)| *d).collect();
+
+    let mut builder = ImageRequestBuilder::new(random_transition(&mut rng), "");
+    builder.set_cache_writes(false);
+    builder.set_animation_cache_writes(false);
+    builder.push(
+        ImgSend {
+            path: "animation".to_string(),
+            img: frames[0].clone().into_boxed_slice(),
+            dim: (frames[0].len() as u32 / 3, 1),
+            format: PixelFormat::Rgb,
+            transition: None,
+        },
+        "Lanczos3".to_string(),
+        &[],
+        Some(animation),
+    );
+
+    let RequestRecv::Img(req) =
+        RequestRecv::receive(RequestSend::Img(builder.build()).into()).unwrap()
+    else {
+        panic!("image request was parsed as something else");
+    };
+    let animation = req.animations[0].as_ref().unwrap();
+    let parsed: Vec<_> = animation.animation.iter().map(|(_, d)| *d).collect();
+    assert_eq!(parsed, durations);
+}
+
+#[test]
+fn slideshow_requests_round_trip() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    for (groups, order) in [(1, SlideshowOrder::Random), (5, SlideshowOrder::Sequential)] {
+        let (transition, expected, _) = random_request(&mut rng, groups);
+        let mut builder = ImageRequestBuilder::new(transition.clone(), "");
+        builder.set_cache_writes(false);
+        for group in &expected {
+            builder.set_transformed(group.transformed);
+            builder.push(
+                ImgSend {
+                    path: group.path.clone(),
+                    img: group.img.clone().into_boxed_slice(),
+                    dim: group.dim,
+                    format: group.format,
+                    transition: group.transition.clone(),
+                },
+                "Lanczos3".to_string(),
+                &group.outputs,
+                None,
+            );
+        }
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|group| Group {
+                frames: None,
+                ..group
+            })
+            .collect();
+
+        let interval = Duration::from_secs_f64(1234.5);
+        let mmap = builder.build_slideshow(interval, order);
+        let RequestRecv::Slideshow(req) =
+            RequestRecv::receive(RequestSend::Slideshow(mmap).into()).unwrap()
+        else {
+            panic!("slideshow request was parsed as something else");
+        };
+        assert_image_req_eq(&req.image, &transition, &expected);
+        assert_eq!(req.interval, interval);
+        assert_eq!(req.order, order);
+    }
+
+    let mut builder = ImageRequestBuilder::new(random_transition(&mut rng), "");
+    builder.set_cache_writes(false);
+    let mmap = builder.build_slideshow(Duration::ZERO, SlideshowOrder::Sequential);
+    assert!(matches!(
+        RequestRecv::receive(RequestSend::Slideshow(mmap).into()),
+        Err(ParseError::Invalid("slideshow interval"))
+    ));
+}
+
+#[test]
+fn clear_requests_round_trip() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    for (transition, match_by) in [
+        (None, OutputMatch::Name),
+        (Some(random_transition(&mut rng)), OutputMatch::Description),
+    ] {
+        let outputs: Box<[String]> = (0..3).map(|_| unicode_string(&mut rng, 20)).collect();
+        let clear = ClearSend {
+            color: [1, 2, 3],
+            outputs: outputs.clone(),
+            match_by,
+            transition: transition.clone(),
+        };
+        let RequestRecv::Clear(req) =
+            RequestRecv::receive(RequestSend::Clear(clear.create_request()).into()).unwrap()
+        else {
+            panic!("clear request was parsed as something else");
+        };
+        assert_eq!(req.color, [1, 2, 3]);
+        let parsed: Vec<_> = req.outputs.iter().map(|o| o.str()).collect();
+        assert_eq!(parsed, outputs[..]);
+        assert_eq!(req.match_by, match_by);
+        assert!(req.transition == transition);
+    }
+}
+
+#[test]
+fn events_round_trip() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    let events = [
+        Event::OutputAdded(unicode_string(&mut rng, 20)),
+        Event::OutputRemoved(unicode_string(&mut rng, 20)),
+        Event::WallpaperChanged {
+            output: unicode_string(&mut rng, 20),
+            img: BgImg::Color([1, 2, 3]),
+        },
+        Event::WallpaperChanged {
+            output: unicode_string(&mut rng, 20),
+            img: BgImg::Img(unicode_string(&mut rng, 300)),
+        },
+        Event::TransitionStarted(unicode_string(&mut rng, 20)),
+        Event::TransitionFinished(unicode_string(&mut rng, 20)),
+        Event::Paused(unicode_string(&mut rng, 20)),
+        Event::Resumed(unicode_string(&mut rng, 20)),
+    ];
+    for event in events {
+        match Answer::receive(Answer::Event(event.clone()).into()) {
+            Answer::Event(parsed) => assert_eq!(parsed, event),
+            _ => panic!("event was parsed as something else"),
+        }
+    }
+}
+
+/// Every code a client may send to the daemon
+const REQUEST_CODES: [u64; 13] = [0, 1, 2, 3, 4, 9, 10, 11, 12, 15, 17, 18, 19];
+
+#[test]
+fn random_requests_never_panic() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    for _ in 0..5000 {
+        let code = REQUEST_CODES[rng.usize(..REQUEST_CODES.len())];
+        let bytes: Vec<u8> = (0..rng.usize(..512)).map(|_| rng.u8(..)).collect();
+        let _ = RequestRecv::receive(RawMsg::from_raw_parts(code, &bytes).unwrap());
+    }
+}
+
+#[test]
+fn damaged_image_requests_never_panic() {
+    let mut rng = fastrand::Rng::with_seed(0x5757);
+    let (frames, animation) = random_animation(&mut rng);
+    let mut transition = random_transition(&mut rng);
+    transition.mask = Some(vec![0xaa; 8].into());
+    let mut builder = ImageRequestBuilder::new(transition, "");
+    builder.set_cache_writes(false);
+    builder.set_animation_cache_writes(false);
+    builder.push(
+        ImgSend {
+            path: "animation".to_string(),
+            img: frames[0].clone().into_boxed_slice(),
+            dim: (frames[0].len() as u32 / 3, 1),
+            format: PixelFormat::Rgb,
+            transition: None,
+        },
+        "Lanczos3".to_string(),
+        &["output".to_string()],
+        Some(animation),
+    );
+    let len = builder.len;
+    let mmap = builder.build();
+    let bytes = &mmap.slice()[..len];
+    let receive = |bytes: &[u8]| RequestRecv::receive(RawMsg::from_raw_parts(3, bytes).unwrap());
+
+    assert!(receive(bytes).is_ok());
+    for cut in 1..len {
+        assert!(
+            receive(&bytes[..cut]).is_err(),
+            "accepted a request cut at {cut}"
+        );
+    }
+    for _ in 0..5000 {
+        let mut damaged = bytes.to_vec();
+        for _ in 0..rng.usize(1..4) {
+            damaged[rng.usize(..len)] = rng.u8(..);
+        }
+        let _ = receive(&damaged);
+    }
+}
+
+#[test]
+fn requests_without_their_payload_are_rejected() {
+    for code in [2, 3, 9, 10, 11, 12, 15, 17, 18] {
+        assert!(
+            RequestRecv::receive(RawMsg::from_raw_parts(code, &[]).unwrap()).is_err(),
+            "accepted code {code} without a payload"
+        );
+    }
+}
+
+#[test]
+fn foreign_messages_are_rejected() {
+    let socket = |header: [u8; 16]| {
+        let (client, server) = rustix::net::socketpair(
+            rustix::net::AddressFamily::UNIX,
+            rustix::net::SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        rustix::io::write(&client, &header).unwrap();
+        IpcSocket::<Server>::new(server).recv()
+    };
+
+    let mut header = [0u8; 16];
+    header[0..4].copy_from_slice(b"swww");
+    header[4..6].copy_from_slice(&1u16.to_ne_bytes());
+    assert!(matches!(
+        RequestRecv::receive(socket(header).unwrap()),
+        Ok(RequestRecv::Ping)
+    ));
+
+    let mut bad_magic = header;
+    bad_magic[0..4].copy_from_slice(b"GET ");
+    assert!(socket(bad_magic).is_err_and(|e| e.is_bad_message()));
+
+    let mut wrong_version = header;
+    wrong_version[4..6].copy_from_slice(&u16::MAX.to_ne_bytes());
+    assert!(socket(wrong_version).is_err_and(|e| e.is_bad_message()));
+
+    let mut bad_code = header;
+    bad_code[6..8].copy_from_slice(&1000u16.to_ne_bytes());
+    assert!(socket(bad_code).is_err_and(|e| e.is_bad_message()));
+
+    // says it comes with shared memory, but doesn't send any
+    let mut no_fd = header;
+    no_fd[6..8].copy_from_slice(&3u16.to_ne_bytes());
+    no_fd[8..16].copy_from_slice(&64u64.to_ne_bytes());
+    assert!(socket(no_fd).is_err_and(|e| e.is_bad_message()));
+}
+
+#[test]
+fn errors_round_trip() {
+    for err in ["", "malformed request: 壁紙"] {
+        match Answer::receive(Answer::Err(err.to_string()).into()) {
+            Answer::Err(parsed) => assert_eq!(parsed, err),
+            _ => panic!("error was parsed as something else"),
+        }
+    }
+}
+
+#[test]
+fn warnings_round_trip() {
+    let warnings: Box<[String]> = Box::new([
+        "there is no output named 壁紙".to_string(),
+        "failed to show\nimg on DP-1".to_string(),
+    ]);
+    match Answer::receive(Answer::Warnings(warnings.clone()).into()) {
+        Answer::Warnings(parsed) => assert_eq!(parsed, warnings),
+        _ => panic!("warnings were parsed as something else"),
+    }
+}
@@ -11,23 +11,60 @@ use super::Answer;
 use super::BgInfo;
 use super::ClearReq;
 use super::ErrnoExt;
+use super::Event;
 use super::ImageReq;
 use super::ImgReq;
 use super::IpcError;
 use super::IpcErrorKind;
 use super::IpcSocket;
+use super::Layer;
+use super::LayerReq;
+use super::OutputMatch;
+use super::ParseError;
+use super::PauseReq;
 use super::RequestRecv;
 use super::RequestSend;
+use super::Screenshot;
+use super::ScreenshotReq;
+use super::SlideshowOrder;
+use super::SlideshowReq;
 use super::Transition;
+use super::WaitIdleReq;
 use crate::mmap::Mmap;
 use crate::mmap::MmappedStr;
 
+/// Every message starts with these bytes, so that we can tell apart anything other programs write
+/// to the socket
+const MAGIC: [u8; 4] = *b"swww";
+
+/// Comes right after `MAGIC`. Bump it whenever the layout of any message changes, so that a daemon
+/// and a client from different versions don't misread each other
+const PROTOCOL_VERSION: u16 = 1;
+
 // could be enum
 pub struct RawMsg {
     code: Code,
     shm: Option<Mmap>,
 }
 
+impl RawMsg {
+    /// Makes the message we would get from receiving `code`, with `bytes` in its shared memory.
+    /// Returns `None` if `code` isn't a valid code
+    ///
+    /// This only exists so that we can fuzz the parsers, which would otherwise need a socket
+    #[doc(hidden)]
+    #[must_use]
+    pub fn from_raw_parts(code: u64, bytes: &[u8]) -> Option<Self> {
+        let code = Code::from(code)?;
+        let shm = (!bytes.is_empty()).then(|| {
+            let mut mmap = Mmap::create(bytes.len());
+            mmap.slice_mut().copy_from_slice(bytes);
+            mmap
+        });
+        Some(Self { code, shm })
+    }
+}
+
 impl From<RequestSend> for RawMsg {
     fn from(value: RequestSend) -> Self {
         let code = match value {
@@ -35,11 +72,27 @@ impl From<RequestSend> for RawMsg {
             RequestSend::Query => Code::ReqQuery,
             RequestSend::Clear(_) => Code::ReqClear,
             RequestSend::Img(_) => Code::ReqImg,
+            RequestSend::Slideshow(_) => Code::ReqSlideshow,
+            RequestSend::Screenshot(_) => Code::ReqScreenshot,
+            RequestSend::SetLayer(_) => Code::ReqSetLayer,
+            RequestSend::WaitIdle(_) => Code::ReqWaitIdle,
+            RequestSend::Subscribe => Code::ReqSubscribe,
+            RequestSend::Pause(_) => Code::ReqPause,
+            RequestSend::Resume(_) => Code::ReqResume,
+            RequestSend::TogglePause(_) => Code::ReqTogglePause,
             RequestSend::Kill => Code::ReqKill,
         };
 
         let shm = match value {
-            RequestSend::Clear(mem) | RequestSend::Img(mem) => Some(mem),
+            RequestSend::Clear(mem)
+            | RequestSend::Img(mem)
+            | RequestSend::Slideshow(mem)
+            | RequestSend::Screenshot(mem)
+            | RequestSend::SetLayer(mem)
+            | RequestSend::WaitIdle(mem)
+            | RequestSend::Pause(mem)
+            | RequestSend::Resume(mem)
+            | RequestSend::TogglePause(mem) => Some(mem),
             _ => None,
         };
 
@@ -54,9 +107,19 @@ impl From<Answer> for RawMsg {
             Answer::Ping(true) => Code::ResConfigured,
             Answer::Ping(false) => Code::ResAwait,
             Answer::Info(_) => Code::ResInfo,
+            Answer::Paused(true) => Code::ResPaused,
+            Answer::Paused(false) => Code::ResResumed,
+            Answer::Screenshot(_) => Code::ResScreenshot,
+            Answer::Event(_) => Code::ResEvent,
+            Answer::Err(_) => Code::ResErr,
+            Answer::Warnings(_) => Code::ResWarnings,
         };
 
-        let shm = if let Answer::Info(infos) = value {
+        let shm = if let Answer::Screenshot(Some(screenshot)) = value {
+            let mut mmap = Mmap::create(screenshot.serialized_size());
+            screenshot.serialize(mmap.slice_mut());
+            Some(mmap)
+        } else if let Answer::Info(infos) = value {
             let len = 1 + infos
                 .iter()
                 .map(|info| info.serialized_size())
@@ -72,6 +135,24 @@ impl From<Answer> for RawMsg {
             }
 
             Some(mmap)
+        } else if let Answer::Event(event) = value {
+            let mut mmap = Mmap::create(event.serialized_size());
+            event.serialize(mmap.slice_mut());
+            Some(mmap)
+        } else if let Answer::Err(err) = value {
+            (!err.is_empty()).then(|| {
+                let mut mmap = Mmap::create(err.len());
+                mmap.slice_mut().copy_from_slice(err.as_bytes());
+                mmap
+            })
+        } else if let Answer::Warnings(warnings) = value {
+            // they are made out of paths and wayland strings, neither of which may hold a NUL
+            let warnings = warnings.join("\0");
+            (!warnings.is_empty()).then(|| {
+                let mut mmap = Mmap::create(warnings.len());
+                mmap.slice_mut().copy_from_slice(warnings.as_bytes());
+                mmap
+            })
         } else {
             None
         };
@@ -80,78 +161,168 @@ impl From<Answer> for RawMsg {
     }
 }
 
+/// Reads the fields of a message one after the other, failing instead of reading out of bounds,
+/// since anything could have been written to the socket
+struct Reader<'a> {
+    mmap: &'a Mmap,
+    i: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(mmap: Option<&'a Mmap>) -> Result<Self, ParseError> {
+        let mmap = mmap.ok_or(ParseError::NoPayload)?;
+        Ok(Self { mmap, i: 0 })
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.mmap.slice()[self.i..]
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.i += len;
+    }
+
+    fn array<const N: usize>(&mut self, field: &'static str) -> Result<[u8; N], ParseError> {
+        let bytes = self.rest().get(..N).ok_or(ParseError::Truncated(field))?;
+        self.i += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self, field: &'static str) -> Result<u8, ParseError> {
+        self.array::<1>(field).map(|[byte]| byte)
+    }
+
+    fn str(&mut self, field: &'static str) -> Result<MmappedStr, ParseError> {
+        let s = MmappedStr::new(self.mmap, self.rest()).ok_or(ParseError::Invalid(field))?;
+        self.i += 4 + s.str().len();
+        Ok(s)
+    }
+
+    /// A list of output names: a one byte count, followed by that many length-prefixed names
+    fn outputs(&mut self) -> Result<Box<[MmappedStr]>, ParseError> {
+        let len = self.u8("output count")?;
+        (0..len).map(|_| self.str("output name")).collect()
+    }
+
+    fn transition(&mut self) -> Result<Transition, ParseError> {
+        let (transition, len) = Transition::deserialize(self.rest())?;
+        self.i += len;
+        Ok(transition)
+    }
+}
+
 // TODO: remove this ugly mess
-impl From<RawMsg> for RequestRecv {
-    fn from(value: RawMsg) -> Self {
-        match value.code {
+impl TryFrom<RawMsg> for RequestRecv {
+    type Error = ParseError;
+
+    fn try_from(value: RawMsg) -> Result<Self, ParseError> {
+        let reader = || Reader::new(value.shm.as_ref());
+        let request = match value.code {
             Code::ReqPing => Self::Ping,
             Code::ReqQuery => Self::Query,
             Code::ReqClear => {
-                let mmap = value.shm.unwrap();
-                let bytes = mmap.slice();
-                let len = bytes[0] as usize;
-                let mut outputs = Vec::with_capacity(len);
-                let mut i = 1;
-                for _ in 0..len {
-                    let output = MmappedStr::new(&mmap, &bytes[i..]);
-                    i += 4 + output.str().len();
-                    outputs.push(output);
-                }
-                let color = [bytes[i], bytes[i + 1], bytes[i + 2]];
+                let mut reader = reader()?;
+                let outputs = reader.outputs()?;
+                let [r, g, b, match_by, has_transition] = reader.array("clear color")?;
+                let transition = if has_transition == 1 {
+                    Some(reader.transition()?)
+                } else {
+                    None
+                };
                 Self::Clear(ClearReq {
-                    color,
-                    outputs: outputs.into(),
+                    color: [r, g, b],
+                    outputs,
+                    match_by: OutputMatch::from_u8(match_by),
+                    transition,
                 })
             }
-            Code::ReqImg => {
-                let mmap = value.shm.unwrap();
-                let bytes = mmap.slice();
-                let transition = Transition::deserialize(&bytes[0..]);
-                let len = bytes[51] as usize;
-
-                let mut imgs = Vec::with_capacity(len);
-                let mut outputs = Vec::with_capacity(len);
-                let mut animations = Vec::with_capacity(len);
-
-                let mut i = 52;
-                for _ in 0..len {
-                    let (img, offset) = ImgReq::deserialize(&mmap, &bytes[i..]);
-                    i += offset;
-                    imgs.push(img);
-
-                    let n_outputs = bytes[i] as usize;
-                    i += 1;
-                    let mut out = Vec::with_capacity(n_outputs);
-                    for _ in 0..n_outputs {
-                        let output = MmappedStr::new(&mmap, &bytes[i..]);
-                        i += 4 + output.str().len();
-                        out.push(output);
-                    }
-                    outputs.push(out.into());
-
-                    if bytes[i] == 1 {
-                        let (animation, offset) = Animation::deserialize(&mmap, &bytes[i + 1..]);
-                        i += offset;
-                        animations.push(animation);
-                    }
-                    i += 1;
-                }
-
-                Self::Img(ImageReq {
-                    transition,
-                    imgs,
-                    outputs,
-                    animations: if animations.is_empty() {
-                        None
-                    } else {
-                        Some(animations)
-                    },
+            Code::ReqImg => Self::Img(deserialize_image_req(&mut reader()?)?),
+            Code::ReqSlideshow => {
+                let mut reader = reader()?;
+                let image = deserialize_image_req(&mut reader)?;
+                let interval = f64::from_ne_bytes(reader.array("slideshow interval")?);
+                // the client doesn't allow it, and the daemon would be changing images nonstop
+                let interval = Duration::try_from_secs_f64(interval)
+                    .ok()
+                    .filter(|interval| !interval.is_zero())
+                    .ok_or(ParseError::Invalid("slideshow interval"))?;
+                let order = match reader.u8("slideshow order")? {
+                    0 => SlideshowOrder::Sequential,
+                    _ => SlideshowOrder::Random,
+                };
+                Self::Slideshow(SlideshowReq {
+                    image,
+                    interval,
+                    order,
                 })
             }
+            Code::ReqScreenshot => Self::Screenshot(ScreenshotReq {
+                output: reader()?.str("output name")?,
+            }),
+            Code::ReqSetLayer => {
+                let mut reader = reader()?;
+                let layer = Layer::from_u8(reader.u8("layer")?);
+                Self::SetLayer(LayerReq {
+                    layer,
+                    outputs: reader.outputs()?,
+                })
+            }
+            Code::ReqWaitIdle => Self::WaitIdle(WaitIdleReq {
+                outputs: reader()?.outputs()?,
+            }),
+            Code::ReqSubscribe => Self::Subscribe,
+            Code::ReqPause => Self::Pause(PauseReq {
+                outputs: reader()?.outputs()?,
+            }),
+            Code::ReqResume => Self::Resume(PauseReq {
+                outputs: reader()?.outputs()?,
+            }),
+            Code::ReqTogglePause => Self::TogglePause(PauseReq {
+                outputs: reader()?.outputs()?,
+            }),
             Code::ReqKill => Self::Kill,
-            _ => Self::Kill,
+            _ => return Err(ParseError::UnexpectedCode),
+        };
+        Ok(request)
+    }
+}
+
+fn deserialize_image_req(reader: &mut Reader) -> Result<ImageReq, ParseError> {
+    let transition = reader.transition()?;
+    let len = reader.u8("image count")? as usize;
+
+    let mut imgs = Vec::with_capacity(len);
+    let mut outputs = Vec::with_capacity(len);
+    let mut animations = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        let (img, offset) = ImgReq::deserialize(reader.mmap, reader.rest())?;
+        reader.skip(offset);
+        imgs.push(img);
+        outputs.push(reader.outputs()?);
+
+        if reader.u8("animation flag")? == 1 {
+            let (mut animation, offset) = Animation::deserialize(reader.mmap, reader.rest())
+                .map_err(ParseError::Animation)?;
+            reader.skip(offset);
+            animation.loop_count = u32::from_ne_bytes(reader.array("animation loop count")?);
+            animation.speed = f32::from_ne_bytes(reader.array("animation speed")?);
+            // the client doesn't allow anything slower, and the frame durations are divided by it
+            if !(animation.speed.is_finite() && animation.speed >= 0.05) {
+                return Err(ParseError::Invalid("animation speed"));
+            }
+            animations.push(Some(animation));
+        } else {
+            animations.push(None);
         }
     }
+
+    Ok(ImageReq {
+        transition,
+        imgs,
+        outputs,
+        animations,
+    })
 }
 
 impl From<RawMsg> for Answer {
@@ -160,6 +331,11 @@ impl From<RawMsg> for Answer {
             Code::ResOk => Self::Ok,
             Code::ResConfigured => Self::Ping(true),
             Code::ResAwait => Self::Ping(false),
+            Code::ResPaused => Self::Paused(true),
+            Code::ResResumed => Self::Paused(false),
+            Code::ResScreenshot => {
+                Self::Screenshot(value.shm.map(|mmap| Screenshot::deserialize(mmap.slice())))
+            }
             Code::ResInfo => {
                 let mmap = value.shm.unwrap();
                 let bytes = mmap.slice();
@@ -175,6 +351,24 @@ impl From<RawMsg> for Answer {
 
                 Self::Info(bg_infos.into())
             }
+            Code::ResEvent => Self::Event(Event::deserialize(value.shm.unwrap().slice())),
+            Code::ResErr => Self::Err(
+                value
+                    .shm
+                    .map(|mmap| String::from_utf8_lossy(mmap.slice()).into_owned())
+                    .unwrap_or_default(),
+            ),
+            Code::ResWarnings => Self::Warnings(
+                value
+                    .shm
+                    .map(|mmap| {
+                        String::from_utf8_lossy(mmap.slice())
+                            .split('\0')
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            ),
             _ => panic!("Received malformed answer from daemon"),
         }
     }
@@ -207,16 +401,30 @@ macro_rules! code {
 }
 
 code! {
-    ReqPing       0,
-    ReqQuery      1,
-    ReqClear      2,
-    ReqImg        3,
-    ReqKill       4,
-
-    ResOk         5,
-    ResConfigured 6,
-    ResAwait      7,
-    ResInfo       8,
+    ReqPing        0,
+    ReqQuery       1,
+    ReqClear       2,
+    ReqImg         3,
+    ReqKill        4,
+    ReqSlideshow   9,
+    ReqPause       10,
+    ReqResume      11,
+    ReqTogglePause 12,
+    ReqScreenshot  15,
+    ReqSetLayer    17,
+    ReqWaitIdle    18,
+    ReqSubscribe   19,
+
+    ResOk          5,
+    ResConfigured  6,
+    ResAwait       7,
+    ResInfo        8,
+    ResPaused      13,
+    ResResumed     14,
+    ResScreenshot  16,
+    ResEvent       20,
+    ResErr         21,
+    ResWarnings    22,
 }
 
 impl TryFrom<u64> for Code {
@@ -230,7 +438,9 @@ impl TryFrom<u64> for Code {
 impl<T> IpcSocket<T> {
     pub fn send(&self, msg: RawMsg) -> io::Result<bool> {
         let mut payload = [0u8; 16];
-        payload[0..8].copy_from_slice(&msg.code.into().to_ne_bytes());
+        payload[0..4].copy_from_slice(&MAGIC);
+        payload[4..6].copy_from_slice(&PROTOCOL_VERSION.to_ne_bytes());
+        payload[6..8].copy_from_slice(&(msg.code.into() as u16).to_ne_bytes());
 
         let mut ancillary_buf = [0u8; rustix::cmsg_space!(ScmRights(1))];
         let mut ancillary = net::SendAncillaryBuffer::new(&mut ancillary_buf);
@@ -248,7 +458,8 @@ impl<T> IpcSocket<T> {
             self.as_fd(),
             &[iov],
             &mut ancillary,
-            net::SendFlags::empty(),
+            // a subscriber that went away must not take the daemon down with a SIGPIPE
+            net::SendFlags::NOSIGNAL,
         )
         .map(|written| written == payload.len())
     }
@@ -262,21 +473,29 @@ impl<T> IpcSocket<T> {
         for _ in 0..5 {
             let iov = io::IoSliceMut::new(&mut buf);
             match net::recvmsg(self.as_fd(), &mut [iov], &mut control, RecvFlags::WAITALL) {
+                Ok(msg) if msg.bytes == 0 => {
+                    return Err(IpcError::new(IpcErrorKind::Closed, Errno::CONNRESET))
+                }
+                Ok(msg) if msg.bytes < buf.len() => {
+                    return Err(IpcError::new(IpcErrorKind::MalformedMsg, Errno::BADMSG))
+                }
                 Ok(_) => break,
                 Err(Errno::WOULDBLOCK | Errno::INTR) => thread::sleep(Duration::from_millis(1)),
                 Err(err) => return Err(err).context(IpcErrorKind::Read),
             }
         }
 
-        let code = u64::from_ne_bytes(buf[0..8].try_into().unwrap()).try_into()?;
+        if buf[0..4] != MAGIC {
+            return Err(IpcError::new(IpcErrorKind::BadMagic, Errno::PROTO));
+        }
+        if u16::from_ne_bytes(buf[4..6].try_into().unwrap()) != PROTOCOL_VERSION {
+            return Err(IpcError::new(IpcErrorKind::WrongVersion, Errno::PROTO));
+        }
+        let code = u64::from(u16::from_ne_bytes(buf[6..8].try_into().unwrap())).try_into()?;
         let len = u64::from_ne_bytes(buf[8..16].try_into().unwrap()) as usize;
 
+        // requests that need a payload and didn't get one are rejected when parsing them
         let shm = if len == 0 {
-            debug_assert!(
-                !matches!(code, Code::ReqImg | Code::ReqClear | Code::ResInfo),
-                "Received: Code {:?}, which should have sent a shm fd",
-                code
-            );
             None
         } else {
             let file = control
@@ -288,6 +507,13 @@ impl<T> IpcSocket<T> {
                 })
                 .ok_or(Errno::BADMSG)
                 .context(IpcErrorKind::MalformedMsg)?;
+            // mapping more than the file holds would crash us with a SIGBUS once we read it
+            let size = rustix::fs::fstat(&file)
+                .context(IpcErrorKind::MalformedMsg)?
+                .st_size;
+            if u64::try_from(size).map_or(true, |size| size < len as u64) {
+                return Err(IpcError::new(IpcErrorKind::MalformedMsg, Errno::BADMSG));
+            }
             Some(Mmap::from_fd(file, len))
         };
         Ok(RawMsg { code, shm })
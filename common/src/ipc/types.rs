@@ -10,6 +10,7 @@ use crate::mmap::MmappedBytes;
 use crate::mmap::MmappedStr;
 
 use super::ImageRequestBuilder;
+use super::ParseError;
 
 #[derive(Clone, PartialEq)]
 pub enum Coord {
@@ -87,6 +88,33 @@ impl BgImg {
         }
     }
 
+    fn serialize(&self, buf: &mut [u8]) -> usize {
+        match self {
+            Self::Color(color) => {
+                buf[0] = 0;
+                buf[1..4].copy_from_slice(color);
+                4
+            }
+            Self::Img(path) => {
+                buf[0] = 1;
+                let len = path.len();
+                buf[1..5].copy_from_slice(&(len as u32).to_ne_bytes());
+                buf[5..5 + len].copy_from_slice(path.as_bytes());
+                5 + len
+            }
+        }
+    }
+
+    fn deserialize(bytes: &[u8]) -> (Self, usize) {
+        if bytes[0] == 0 {
+            (Self::Color([bytes[1], bytes[2], bytes[3]]), 4)
+        } else {
+            let path = deserialize_string(&bytes[1..]);
+            let len = 5 + path.len();
+            (Self::Img(path), len)
+        }
+    }
+
     pub fn is_set(&self) -> bool {
         matches!(self, Self::Img(_))
     }
@@ -114,6 +142,10 @@ pub enum PixelFormat {
     Xbgr = 2,
     /// Swap R and B channels at client, must extend pixel with an extra byte when copying
     Xrgb = 3,
+    /// Laid out like `Xbgr` everywhere but in the daemon's buffers, which get 10 bits per channel
+    Xbgr2101010 = 4,
+    /// Laid out like `Xrgb` everywhere but in the daemon's buffers, which get 10 bits per channel
+    Xrgb2101010 = 5,
 }
 
 impl PixelFormat {
@@ -125,6 +157,8 @@ impl PixelFormat {
             Self::Bgr => 3,
             Self::Xbgr => 4,
             Self::Xrgb => 4,
+            Self::Xbgr2101010 => 4,
+            Self::Xrgb2101010 => 4,
         }
     }
 
@@ -136,6 +170,8 @@ impl PixelFormat {
             Self::Rgb => true,
             Self::Xbgr => false,
             Self::Xrgb => true,
+            Self::Xbgr2101010 => false,
+            Self::Xrgb2101010 => true,
         }
     }
 
@@ -147,8 +183,19 @@ impl PixelFormat {
             Self::Rgb => true,
             Self::Xbgr => false,
             Self::Xrgb => false,
+            Self::Xbgr2101010 => false,
+            Self::Xrgb2101010 => false,
         }
     }
+
+    /// Whether the daemon's buffers pack each channel in 10 bits. Everything else, from the client
+    /// to the daemon's transitions, still works with 8 bits per channel, and the daemon packs them
+    /// right before giving the buffers to the compositor
+    #[inline]
+    #[must_use]
+    pub const fn is_10_bit(&self) -> bool {
+        matches!(self, Self::Xbgr2101010 | Self::Xrgb2101010)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -212,13 +259,131 @@ impl fmt::Display for Scale {
     }
 }
 
+/// The layer-shell layer a wallpaper's surface is in
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layer {
+    Background = 0,
+    Bottom = 1,
+    Top = 2,
+    Overlay = 3,
+}
+
+impl Layer {
+    pub(super) fn from_u8(byte: u8) -> Self {
+        match byte {
+            0 => Self::Background,
+            1 => Self::Bottom,
+            2 => Self::Top,
+            _ => Self::Overlay,
+        }
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Background => "background",
+            Self::Bottom => "bottom",
+            Self::Top => "top",
+            Self::Overlay => "overlay",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// How the compositor rotates and flips an output, as in `wl_output::transform`. Rotations are
+/// counter-clockwise, and flips are around the vertical axis, before rotating
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transform {
+    #[default]
+    Normal = 0,
+    Rotate90 = 1,
+    Rotate180 = 2,
+    Rotate270 = 3,
+    Flipped = 4,
+    Flipped90 = 5,
+    Flipped180 = 6,
+    Flipped270 = 7,
+}
+
+impl Transform {
+    pub(super) fn from_u8(byte: u8) -> Self {
+        match byte {
+            1 => Self::Rotate90,
+            2 => Self::Rotate180,
+            3 => Self::Rotate270,
+            4 => Self::Flipped,
+            5 => Self::Flipped90,
+            6 => Self::Flipped180,
+            7 => Self::Flipped270,
+            _ => Self::Normal,
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // same names as wlr-randr
+        let s = match self {
+            Self::Normal => "normal",
+            Self::Rotate90 => "90",
+            Self::Rotate180 => "180",
+            Self::Rotate270 => "270",
+            Self::Flipped => "flipped",
+            Self::Flipped90 => "flipped-90",
+            Self::Flipped180 => "flipped-180",
+            Self::Flipped270 => "flipped-270",
+        };
+        write!(f, "{s}")
+    }
+}
+
 #[derive(Clone)]
 pub struct BgInfo {
     pub name: String,
+    /// the output's `wl_output::description`, or an empty string if the compositor sent none
+    pub desc: String,
+    /// the output's size once `transform` is applied, so rotated outputs are taller than wide
     pub dim: (u32, u32),
     pub scale_factor: Scale,
+    pub transform: Transform,
+    /// where the output is in the compositor's global space
+    pub position: (i32, i32),
+    /// the output's refresh rate in mHz, or 0 if the compositor doesn't know it
+    pub refresh: u32,
     pub img: BgImg,
     pub pixel_format: PixelFormat,
+    pub paused: bool,
+    /// whether an animated image is playing on the output
+    pub animated: bool,
+    pub layer: Layer,
+    /// the namespace of the wallpaper's layer surface
+    pub namespace: String,
+    /// only present while the output is transitioning to a new image
+    pub transition: Option<TransitionProgress>,
+}
+
+/// How far along an output's transition is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransitionProgress {
+    /// how long we expect the transition to take
+    pub duration: Duration,
+    pub elapsed: Duration,
+    /// estimate of how much longer the transition will take. Zero once it runs past `duration`
+    pub remaining: Duration,
+}
+
+impl TransitionProgress {
+    #[must_use]
+    pub fn new(duration: Duration, elapsed: Duration) -> Self {
+        Self {
+            duration,
+            elapsed,
+            remaining: duration.saturating_sub(elapsed),
+        }
+    }
 }
 
 impl BgInfo {
@@ -234,25 +399,50 @@ impl BgInfo {
     pub(super) fn serialized_size(&self) -> usize {
         4 // name len
             + self.name.len()
+            + 4 // desc len
+            + self.desc.len()
             + 8 //dim
             + 5 //scale_factor (discriminant + value)
+            + 1 //transform
+            + 8 //position
+            + 4 //refresh
             + self.img.serialized_size()
             + 1 //pixel_format
+            + 1 //paused
+            + 1 //animated
+            + 1 //layer
+            + 4 // namespace len
+            + self.namespace.len()
+            + 1 // whether we are transitioning
+            + if self.transition.is_some() { 12 } else { 0 } // durations in milliseconds
     }
 
     pub(super) fn serialize(&self, buf: &mut [u8]) -> usize {
         let Self {
             name,
+            desc,
             dim,
             scale_factor,
+            transform,
+            position,
+            refresh,
             img,
             pixel_format,
+            paused,
+            animated,
+            layer,
+            namespace,
+            transition,
         } = self;
 
         let len = name.as_bytes().len();
         buf[0..4].copy_from_slice(&(len as u32).to_ne_bytes());
         buf[4..4 + len].copy_from_slice(name.as_bytes());
         let mut i = 4 + len;
+        let len = desc.len();
+        buf[i..i + 4].copy_from_slice(&(len as u32).to_ne_bytes());
+        buf[i + 4..i + 4 + len].copy_from_slice(desc.as_bytes());
+        i += 4 + len;
         buf[i..i + 4].copy_from_slice(&dim.0.to_ne_bytes());
         buf[i + 4..i + 8].copy_from_slice(&dim.1.to_ne_bytes());
         i += 8;
@@ -267,33 +457,52 @@ impl BgInfo {
                 buf[i + 1..i + 5].copy_from_slice(&value.get().to_ne_bytes());
             }
         }
-        i += 5;
+        buf[i + 5] = *transform as u8;
+        i += 6;
 
-        match img {
-            BgImg::Color(color) => {
+        buf[i..i + 4].copy_from_slice(&position.0.to_ne_bytes());
+        buf[i + 4..i + 8].copy_from_slice(&position.1.to_ne_bytes());
+        buf[i + 8..i + 12].copy_from_slice(&refresh.to_ne_bytes());
+        i += 12;
+
+        i += img.serialize(&mut buf[i..]);
+
+        buf[i] = *pixel_format as u8;
+        buf[i + 1] = *paused as u8;
+        buf[i + 2] = *animated as u8;
+        buf[i + 3] = *layer as u8;
+        i += 4;
+
+        let len = namespace.len();
+        buf[i..i + 4].copy_from_slice(&(len as u32).to_ne_bytes());
+        buf[i + 4..i + 4 + len].copy_from_slice(namespace.as_bytes());
+        i += 4 + len;
+
+        match transition {
+            None => {
                 buf[i] = 0;
-                buf[i + 1..i + 4].copy_from_slice(color);
-                i += 4;
+                i + 1
             }
-            BgImg::Img(path) => {
+            Some(progress) => {
                 buf[i] = 1;
                 i += 1;
-                let len = path.as_bytes().len();
-                buf[i..i + 4].copy_from_slice(&(len as u32).to_ne_bytes());
-                buf[i + 4..i + 4 + len].copy_from_slice(path.as_bytes());
-                i += 4 + len;
+                for duration in [progress.duration, progress.elapsed, progress.remaining] {
+                    let millis = duration.as_millis().min(u32::MAX as u128) as u32;
+                    buf[i..i + 4].copy_from_slice(&millis.to_ne_bytes());
+                    i += 4;
+                }
+                i
             }
         }
-
-        buf[i] = *pixel_format as u8;
-        i + 1
     }
 
     pub(super) fn deserialize(bytes: &[u8]) -> (Self, usize) {
         let name = deserialize_string(bytes);
         let mut i = name.len() + 4;
+        let desc = deserialize_string(&bytes[i..]);
+        i += desc.len() + 4;
 
-        assert!(bytes.len() > i + 17);
+        assert!(bytes.len() > i + 18);
 
         let dim = (
             u32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap()),
@@ -314,33 +523,76 @@ impl BgInfo {
                     .unwrap(),
             )
         };
-        i += 5;
+        let transform = Transform::from_u8(bytes[i + 5]);
+        i += 6;
 
-        let img = if bytes[i] == 0 {
-            i += 4;
-            BgImg::Color([bytes[i - 3], bytes[i - 2], bytes[i - 1]])
-        } else {
-            i += 1;
-            let path = deserialize_string(&bytes[i..]);
-            i += 4 + path.len();
-            BgImg::Img(path)
-        };
+        let position = (
+            i32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap()),
+            i32::from_ne_bytes(bytes[i + 4..i + 8].try_into().unwrap()),
+        );
+        let refresh = u32::from_ne_bytes(bytes[i + 8..i + 12].try_into().unwrap());
+        i += 12;
+
+        let (img, len) = BgImg::deserialize(&bytes[i..]);
+        i += len;
 
         let pixel_format = match bytes[i] {
             0 => PixelFormat::Bgr,
             1 => PixelFormat::Rgb,
             2 => PixelFormat::Xbgr,
+            4 => PixelFormat::Xbgr2101010,
+            5 => PixelFormat::Xrgb2101010,
             _ => PixelFormat::Xrgb,
         };
         i += 1;
 
+        let paused = bytes[i] != 0;
+        i += 1;
+
+        let animated = bytes[i] != 0;
+        i += 1;
+
+        let layer = Layer::from_u8(bytes[i]);
+        i += 1;
+
+        let namespace = deserialize_string(&bytes[i..]);
+        i += 4 + namespace.len();
+
+        let transition = if bytes[i] == 0 {
+            i += 1;
+            None
+        } else {
+            let millis = |j: usize| {
+                let j = i + 1 + j * 4;
+                Duration::from_millis(
+                    u32::from_ne_bytes(bytes[j..j + 4].try_into().unwrap()).into(),
+                )
+            };
+            let progress = TransitionProgress {
+                duration: millis(0),
+                elapsed: millis(1),
+                remaining: millis(2),
+            };
+            i += 13;
+            Some(progress)
+        };
+
         (
             Self {
                 name,
+                desc,
                 dim,
                 scale_factor,
+                transform,
+                position,
+                refresh,
                 img,
                 pixel_format,
+                paused,
+                animated,
+                layer,
+                namespace,
+                transition,
             },
             i,
         )
@@ -351,14 +603,47 @@ impl fmt::Display for BgInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}: {}x{}, scale: {}, currently displaying: {}",
-            self.name, self.dim.0, self.dim.1, self.scale_factor, self.img
-        )
+            "{}: {}x{}, scale: {}, position: {},{}, refresh: {}.{:03}Hz, layer: {}, namespace: {}, \
+             currently displaying: {}",
+            self.name,
+            self.dim.0,
+            self.dim.1,
+            self.scale_factor,
+            self.position.0,
+            self.position.1,
+            self.refresh / 1000,
+            self.refresh % 1000,
+            self.layer,
+            self.namespace,
+            self.img
+        )?;
+        if !self.desc.is_empty() {
+            write!(f, ", description: {}", self.desc)?;
+        }
+        if self.transform != Transform::Normal {
+            write!(f, ", transform: {}", self.transform)?;
+        }
+        if self.animated {
+            write!(f, " (animated)")?;
+        }
+        if self.paused {
+            write!(f, " (paused)")?;
+        }
+        if let Some(progress) = self.transition {
+            write!(
+                f,
+                ", transitioning: {}ms of {}ms elapsed, {}ms remaining",
+                progress.elapsed.as_millis(),
+                progress.duration.as_millis(),
+                progress.remaining.as_millis()
+            )?;
+        }
+        Ok(())
     }
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum TransitionType {
     Simple = 0,
     Fade = 1,
@@ -367,8 +652,14 @@ pub enum TransitionType {
     Grow = 4,
     Wave = 5,
     None = 6,
+    Reveal = 7,
+    Dissolve = 8,
+    Melt = 9,
+    Crossfade = 10,
+    Slide = 11,
 }
 
+#[derive(Clone, PartialEq)]
 pub struct Transition {
     pub transition_type: TransitionType,
     pub duration: f32,
@@ -379,10 +670,19 @@ pub struct Transition {
     pub bezier: (f32, f32, f32, f32),
     pub wave: (f32, f32),
     pub invert_y: bool,
+    /// Whether the fade blends in linear light instead of sRGB, which keeps its midpoints from
+    /// looking too dark
+    pub linear: bool,
+    /// One byte per pixel of the image, telling when that pixel switches to the new image: darker
+    /// ones switch first. Only the fade and dissolve transitions use it
+    pub mask: Option<Box<[u8]>>,
 }
 
 impl Transition {
-    pub(super) fn serialize(&self, buf: &mut ImageRequestBuilder) {
+    /// How many bytes a serialized transition takes, not counting its mask
+    pub(super) const SERIALIZED_LEN: usize = 53;
+
+    pub(super) fn serialize(&self, buf: &mut Vec<u8>) {
         let Self {
             transition_type,
             duration,
@@ -393,44 +693,57 @@ impl Transition {
             bezier,
             wave,
             invert_y,
+            linear,
+            mask,
         } = self;
 
-        buf.push_byte(*transition_type as u8);
-        buf.extend(&duration.to_ne_bytes());
-        buf.push_byte(step.get());
-        buf.extend(&fps.to_ne_bytes());
-        buf.extend(&angle.to_ne_bytes());
+        buf.push(*transition_type as u8);
+        buf.extend_from_slice(&duration.to_ne_bytes());
+        buf.push(step.get());
+        buf.extend_from_slice(&fps.to_ne_bytes());
+        buf.extend_from_slice(&angle.to_ne_bytes());
         match pos.x {
             Coord::Pixel(f) => {
-                buf.push_byte(0);
-                buf.extend(&f.to_ne_bytes());
+                buf.push(0);
+                buf.extend_from_slice(&f.to_ne_bytes());
             }
             Coord::Percent(f) => {
-                buf.push_byte(1);
-                buf.extend(&f.to_ne_bytes());
+                buf.push(1);
+                buf.extend_from_slice(&f.to_ne_bytes());
             }
         }
         match pos.y {
             Coord::Pixel(f) => {
-                buf.push_byte(0);
-                buf.extend(&f.to_ne_bytes());
+                buf.push(0);
+                buf.extend_from_slice(&f.to_ne_bytes());
             }
             Coord::Percent(f) => {
-                buf.push_byte(1);
-                buf.extend(&f.to_ne_bytes());
+                buf.push(1);
+                buf.extend_from_slice(&f.to_ne_bytes());
             }
         }
-        buf.extend(&bezier.0.to_ne_bytes());
-        buf.extend(&bezier.1.to_ne_bytes());
-        buf.extend(&bezier.2.to_ne_bytes());
-        buf.extend(&bezier.3.to_ne_bytes());
-        buf.extend(&wave.0.to_ne_bytes());
-        buf.extend(&wave.1.to_ne_bytes());
-        buf.push_byte(*invert_y as u8);
+        buf.extend_from_slice(&bezier.0.to_ne_bytes());
+        buf.extend_from_slice(&bezier.1.to_ne_bytes());
+        buf.extend_from_slice(&bezier.2.to_ne_bytes());
+        buf.extend_from_slice(&bezier.3.to_ne_bytes());
+        buf.extend_from_slice(&wave.0.to_ne_bytes());
+        buf.extend_from_slice(&wave.1.to_ne_bytes());
+        buf.push(*invert_y as u8);
+        buf.push(*linear as u8);
+        if let Some(mask) = mask {
+            buf.push(1);
+            buf.extend_from_slice(&(mask.len() as u32).to_ne_bytes());
+            buf.extend_from_slice(mask);
+        } else {
+            buf.push(0);
+        }
     }
 
-    pub(super) fn deserialize(bytes: &[u8]) -> Self {
-        assert!(bytes.len() > 50);
+    /// Returns the deserialized transition and how many bytes it took
+    pub(super) fn deserialize(bytes: &[u8]) -> Result<(Self, usize), ParseError> {
+        if bytes.len() < Self::SERIALIZED_LEN {
+            return Err(ParseError::Truncated("transition"));
+        }
         let transition_type = match bytes[0] {
             0 => TransitionType::Simple,
             1 => TransitionType::Fade,
@@ -438,10 +751,18 @@ impl Transition {
             3 => TransitionType::Wipe,
             4 => TransitionType::Grow,
             5 => TransitionType::Wave,
+            7 => TransitionType::Reveal,
+            8 => TransitionType::Dissolve,
+            9 => TransitionType::Melt,
+            10 => TransitionType::Crossfade,
+            11 => TransitionType::Slide,
             _ => TransitionType::None,
         };
         let duration = f32::from_ne_bytes(bytes[1..5].try_into().unwrap());
-        let step = NonZeroU8::new(bytes[5]).expect("received step of 0");
+        if Duration::try_from_secs_f32(duration).is_err() {
+            return Err(ParseError::Invalid("transition duration"));
+        }
+        let step = NonZeroU8::new(bytes[5]).ok_or(ParseError::Invalid("transition step"))?;
         let fps = u16::from_ne_bytes(bytes[6..8].try_into().unwrap());
         let angle = f64::from_ne_bytes(bytes[8..16].try_into().unwrap());
         let pos = {
@@ -471,17 +792,77 @@ impl Transition {
         );
 
         let invert_y = bytes[50] != 0;
+        let linear = bytes[51] != 0;
 
-        Self {
-            transition_type,
-            duration,
-            step,
-            fps,
-            angle,
-            pos,
-            bezier,
-            wave,
-            invert_y,
+        let coord = |c: &Coord| match c {
+            Coord::Pixel(f) | Coord::Percent(f) => *f,
+        };
+        let floats = [
+            coord(&pos.x),
+            coord(&pos.y),
+            bezier.0,
+            bezier.1,
+            bezier.2,
+            bezier.3,
+            wave.0,
+            wave.1,
+        ];
+        if !angle.is_finite() || !floats.iter().all(|f| f.is_finite()) {
+            return Err(ParseError::Invalid("transition"));
+        }
+
+        let mut len = Self::SERIALIZED_LEN;
+        let mask = if bytes[52] == 1 {
+            let mask_len = bytes
+                .get(53..57)
+                .ok_or(ParseError::Truncated("transition mask"))?;
+            let mask_len = u32::from_ne_bytes(mask_len.try_into().unwrap()) as usize;
+            len += 4 + mask_len;
+            let mask = bytes
+                .get(57..)
+                .and_then(|mask| mask.get(..mask_len))
+                .ok_or(ParseError::Truncated("transition mask"))?;
+            Some(mask.into())
+        } else {
+            None
+        };
+
+        Ok((
+            Self {
+                transition_type,
+                duration,
+                step,
+                fps,
+                angle,
+                pos,
+                bezier,
+                wave,
+                invert_y,
+                linear,
+                mask,
+            },
+            len,
+        ))
+    }
+}
+
+/// What the outputs listed in a request are matched against
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMatch {
+    /// the output's name, like DP-1, which must be the same
+    #[default]
+    Name = 0,
+    /// the output's description, like "Dell U2720Q", which only has to contain the given text,
+    /// ignoring case
+    Description = 1,
+}
+
+impl OutputMatch {
+    pub(super) fn from_u8(byte: u8) -> Self {
+        match byte {
+            1 => Self::Description,
+            _ => Self::Name,
         }
     }
 }
@@ -489,14 +870,26 @@ impl Transition {
 pub struct ClearSend {
     pub color: [u8; 3],
     pub outputs: Box<[String]>,
+    pub match_by: OutputMatch,
+    /// How to change into the color. Without one, the outputs change instantly
+    pub transition: Option<Transition>,
 }
 
 impl ClearSend {
     pub fn create_request(self) -> Mmap {
+        let mut transition = Vec::new();
+        if let Some(t) = &self.transition {
+            transition.push(1);
+            t.serialize(&mut transition);
+        } else {
+            transition.push(0);
+        }
         // 1 - output length
-        // 3 - color bytes
         // 4 + output.len() - output len + bytes
-        let len = 4 + self.outputs.iter().map(|o| 4 + o.len()).sum::<usize>();
+        // 3 - color bytes
+        // 1 - what the outputs are matched against
+        // transition.len() - whether there is a transition, followed by it
+        let len = 5 + self.outputs.iter().map(|o| 4 + o.len()).sum::<usize>() + transition.len();
         let mut mmap = Mmap::create(len);
         let bytes = mmap.slice_mut();
         bytes[0] = self.outputs.len() as u8; // we assume someone does not have more than
@@ -509,6 +902,8 @@ impl ClearSend {
             i += 4 + len as usize;
         }
         bytes[i..i + 3].copy_from_slice(&self.color);
+        bytes[i + 3] = self.match_by as u8;
+        bytes[i + 4..].copy_from_slice(&transition);
         mmap
     }
 }
@@ -516,6 +911,231 @@ impl ClearSend {
 pub struct ClearReq {
     pub color: [u8; 3],
     pub outputs: Box<[MmappedStr]>,
+    pub match_by: OutputMatch,
+    pub transition: Option<Transition>,
+}
+
+pub struct ScreenshotSend {
+    pub output: String,
+}
+
+impl ScreenshotSend {
+    pub fn create_request(self) -> Mmap {
+        // 4 + output.len() - output len + bytes
+        let len = 4 + self.output.len();
+        let mut mmap = Mmap::create(len);
+        let bytes = mmap.slice_mut();
+        bytes[0..4].copy_from_slice(&(self.output.len() as u32).to_ne_bytes());
+        bytes[4..].copy_from_slice(self.output.as_bytes());
+        mmap
+    }
+}
+
+pub struct ScreenshotReq {
+    pub output: MmappedStr,
+}
+
+pub struct LayerSend {
+    pub layer: Layer,
+    pub outputs: Box<[String]>,
+}
+
+impl LayerSend {
+    pub fn create_request(self) -> Mmap {
+        // 1 - layer
+        // 1 - output length
+        // 4 + output.len() - output len + bytes
+        let len = 2 + self.outputs.iter().map(|o| 4 + o.len()).sum::<usize>();
+        let mut mmap = Mmap::create(len);
+        let bytes = mmap.slice_mut();
+        bytes[0] = self.layer as u8;
+        bytes[1] = self.outputs.len() as u8;
+        let mut i = 2;
+        for output in self.outputs.iter() {
+            let len = output.len() as u32;
+            bytes[i..i + 4].copy_from_slice(&len.to_ne_bytes());
+            bytes[i + 4..i + 4 + len as usize].copy_from_slice(output.as_bytes());
+            i += 4 + len as usize;
+        }
+        mmap
+    }
+}
+
+pub struct LayerReq {
+    pub layer: Layer,
+    pub outputs: Box<[MmappedStr]>,
+}
+
+pub struct WaitIdleSend {
+    pub outputs: Box<[String]>,
+}
+
+impl WaitIdleSend {
+    pub fn create_request(self) -> Mmap {
+        // 1 - output length
+        // 4 + output.len() - output len + bytes
+        let len = 1 + self.outputs.iter().map(|o| 4 + o.len()).sum::<usize>();
+        let mut mmap = Mmap::create(len);
+        let bytes = mmap.slice_mut();
+        bytes[0] = self.outputs.len() as u8;
+        let mut i = 1;
+        for output in self.outputs.iter() {
+            let len = output.len() as u32;
+            bytes[i..i + 4].copy_from_slice(&len.to_ne_bytes());
+            bytes[i + 4..i + 4 + len as usize].copy_from_slice(output.as_bytes());
+            i += 4 + len as usize;
+        }
+        mmap
+    }
+}
+
+/// Which outputs to pause, resume or toggle. An empty list means every output
+pub struct PauseSend {
+    pub outputs: Box<[String]>,
+}
+
+impl PauseSend {
+    pub fn create_request(self) -> Mmap {
+        WaitIdleSend {
+            outputs: self.outputs,
+        }
+        .create_request()
+    }
+}
+
+pub struct PauseReq {
+    pub outputs: Box<[MmappedStr]>,
+}
+
+/// Asks the daemon to answer once none of `outputs` (or none at all, if it's empty) is in the
+/// middle of a transition. Animated images don't count, since they may never end
+pub struct WaitIdleReq {
+    pub outputs: Box<[MmappedStr]>,
+}
+
+/// The contents of an output's canvas, exactly as the daemon last drew them
+pub struct Screenshot {
+    pub dim: (u32, u32),
+    pub format: PixelFormat,
+    pub img: Box<[u8]>,
+}
+
+impl Screenshot {
+    pub(super) fn serialized_size(&self) -> usize {
+        8 // dim
+            + 1 // pixel format
+            + self.img.len()
+    }
+
+    pub(super) fn serialize(&self, buf: &mut [u8]) -> usize {
+        let Self { dim, format, img } = self;
+        buf[0..4].copy_from_slice(&dim.0.to_ne_bytes());
+        buf[4..8].copy_from_slice(&dim.1.to_ne_bytes());
+        buf[8] = *format as u8;
+        buf[9..9 + img.len()].copy_from_slice(img);
+        9 + img.len()
+    }
+
+    pub(super) fn deserialize(bytes: &[u8]) -> Self {
+        assert!(bytes.len() >= 9);
+        let dim = (
+            u32::from_ne_bytes(bytes[0..4].try_into().unwrap()),
+            u32::from_ne_bytes(bytes[4..8].try_into().unwrap()),
+        );
+        let format = match bytes[8] {
+            0 => PixelFormat::Bgr,
+            1 => PixelFormat::Rgb,
+            2 => PixelFormat::Xbgr,
+            4 => PixelFormat::Xbgr2101010,
+            5 => PixelFormat::Xrgb2101010,
+            _ => PixelFormat::Xrgb,
+        };
+        Self {
+            dim,
+            format,
+            img: bytes[9..].into(),
+        }
+    }
+}
+
+/// Something that happened in the daemon, streamed to `swww subscribe` clients
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    OutputAdded(String),
+    OutputRemoved(String),
+    WallpaperChanged { output: String, img: BgImg },
+    TransitionStarted(String),
+    TransitionFinished(String),
+    Paused(String),
+    Resumed(String),
+}
+
+impl Event {
+    pub(super) fn serialized_size(&self) -> usize {
+        1 // kind
+            + match self {
+                Self::WallpaperChanged { output, img } => 4 + output.len() + img.serialized_size(),
+                Self::OutputAdded(name)
+                | Self::OutputRemoved(name)
+                | Self::TransitionStarted(name)
+                | Self::TransitionFinished(name)
+                | Self::Paused(name)
+                | Self::Resumed(name) => 4 + name.len(),
+            }
+    }
+
+    pub(super) fn serialize(&self, buf: &mut [u8]) -> usize {
+        let (kind, name) = match self {
+            Self::OutputAdded(name) => (0, name),
+            Self::OutputRemoved(name) => (1, name),
+            Self::WallpaperChanged { output, .. } => (2, output),
+            Self::TransitionStarted(name) => (3, name),
+            Self::TransitionFinished(name) => (4, name),
+            Self::Paused(name) => (5, name),
+            Self::Resumed(name) => (6, name),
+        };
+        buf[0] = kind;
+        let len = name.len();
+        buf[1..5].copy_from_slice(&(len as u32).to_ne_bytes());
+        buf[5..5 + len].copy_from_slice(name.as_bytes());
+        let i = 5 + len;
+        match self {
+            Self::WallpaperChanged { img, .. } => i + img.serialize(&mut buf[i..]),
+            _ => i,
+        }
+    }
+
+    pub(super) fn deserialize(bytes: &[u8]) -> Self {
+        let name = deserialize_string(&bytes[1..]);
+        match bytes[0] {
+            0 => Self::OutputAdded(name),
+            1 => Self::OutputRemoved(name),
+            2 => Self::WallpaperChanged {
+                img: BgImg::deserialize(&bytes[5 + name.len()..]).0,
+                output: name,
+            },
+            3 => Self::TransitionStarted(name),
+            4 => Self::TransitionFinished(name),
+            5 => Self::Paused(name),
+            _ => Self::Resumed(name),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutputAdded(name) => write!(f, "output-added {name}"),
+            Self::OutputRemoved(name) => write!(f, "output-removed {name}"),
+            Self::WallpaperChanged { output, img } => {
+                write!(f, "wallpaper-changed {output} {img}")
+            }
+            Self::TransitionStarted(name) => write!(f, "transition-started {name}"),
+            Self::TransitionFinished(name) => write!(f, "transition-finished {name}"),
+            Self::Paused(name) => write!(f, "paused {name}"),
+            Self::Resumed(name) => write!(f, "resumed {name}"),
+        }
+    }
 }
 
 pub struct ImgSend {
@@ -523,6 +1143,8 @@ pub struct ImgSend {
     pub dim: (u32, u32),
     pub format: PixelFormat,
     pub img: Box<[u8]>,
+    /// overrides the request's transition for the outputs of this image
+    pub transition: Option<Transition>,
 }
 
 pub struct ImgReq {
@@ -530,50 +1152,79 @@ pub struct ImgReq {
     pub dim: (u32, u32),
     pub format: PixelFormat,
     pub img: MmappedBytes,
+    pub transition: Option<Transition>,
+    /// whether the image was already rotated or flipped with `swww img --transform`, and should
+    /// be drawn as is (see `ImageRequestBuilder::set_transformed`)
+    pub transformed: bool,
 }
 
 impl ImgReq {
-    pub(super) fn deserialize(mmap: &Mmap, bytes: &[u8]) -> (Self, usize) {
+    pub(super) fn deserialize(mmap: &Mmap, bytes: &[u8]) -> Result<(Self, usize), ParseError> {
         let mut i = 0;
-        let path = MmappedStr::new(mmap, &bytes[i..]);
+        let path = MmappedStr::new(mmap, bytes).ok_or(ParseError::Invalid("image path"))?;
         i += 4 + path.str().len();
 
-        let img = MmappedBytes::new(mmap, &bytes[i..]);
+        let img = MmappedBytes::new(mmap, &bytes[i..]).ok_or(ParseError::Truncated("image"))?;
         i += 4 + img.bytes().len();
 
+        let Some(fields) = bytes.get(i..i + 11) else {
+            return Err(ParseError::Truncated("image"));
+        };
         let dim = (
-            u32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap()),
-            u32::from_ne_bytes(bytes[i + 4..i + 8].try_into().unwrap()),
+            u32::from_ne_bytes(fields[0..4].try_into().unwrap()),
+            u32::from_ne_bytes(fields[4..8].try_into().unwrap()),
         );
         i += 8;
 
-        let format = match bytes[i] {
+        let format = match fields[8] {
             0 => PixelFormat::Bgr,
             1 => PixelFormat::Rgb,
             2 => PixelFormat::Xbgr,
+            4 => PixelFormat::Xbgr2101010,
+            5 => PixelFormat::Xrgb2101010,
             _ => PixelFormat::Xrgb,
         };
         i += 1;
 
-        (
+        let transformed = fields[9] == 1;
+        i += 1;
+
+        let transition = if fields[10] == 1 {
+            let (transition, len) = Transition::deserialize(&bytes[i + 1..])?;
+            i += len;
+            Some(transition)
+        } else {
+            None
+        };
+        i += 1;
+
+        Ok((
             Self {
                 path,
                 dim,
                 format,
                 img,
+                transition,
+                transformed,
             },
             i,
-        )
+        ))
     }
 }
 
 pub struct Animation {
     pub animation: Box<[(BitPack, Duration)]>,
+    /// how many times to play the animation before stopping at its last frame. 0 loops forever
+    pub loop_count: u32,
+    /// multiplies the playback speed: frames last their duration divided by this
+    pub speed: f32,
 }
 
 impl Animation {
+    /// Only serializes the frames, since this is also what we write to the cache. The loop count
+    /// and speed are sent separately, in `ImageRequestBuilder::push`
     pub(crate) fn serialize(&self, buf: &mut ImageRequestBuilder) {
-        let Self { animation } = self;
+        let Self { animation, .. } = self;
 
         buf.extend(&(animation.len() as u32).to_ne_bytes());
         for (bitpack, duration) in animation.iter() {
@@ -582,26 +1233,40 @@ impl Animation {
         }
     }
 
-    pub(crate) fn deserialize(mmap: &Mmap, bytes: &[u8]) -> (Self, usize) {
-        let mut i = 0;
-        let animation_len = u32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
-        i += 4;
-        let mut animation = Vec::with_capacity(animation_len);
-        for _ in 0..animation_len {
-            let (anim, offset) = BitPack::deserialize(mmap, &bytes[i..]);
+    /// Fails instead of panicking on malformed data, since the frames may come from the cache
+    pub(crate) fn deserialize(mmap: &Mmap, bytes: &[u8]) -> Result<(Self, usize), String> {
+        let Some(animation_len) = bytes.get(0..4) else {
+            return Err("animation is missing its frame count".to_string());
+        };
+        let animation_len = u32::from_ne_bytes(animation_len.try_into().unwrap()) as usize;
+        if animation_len == 0 {
+            return Err("animation has no frames".to_string());
+        }
+        let mut i = 4;
+        // every frame takes at least 21 bytes, so don't trust the count for the allocation
+        let mut animation = Vec::with_capacity(animation_len.min(bytes.len() / 21));
+        for frame in 0..animation_len {
+            let (anim, offset) = BitPack::deserialize(mmap, &bytes[i..])
+                .map_err(|e| format!("animation frame {frame}: {e}"))?;
             i += offset;
-            let duration =
-                Duration::from_secs_f64(f64::from_ne_bytes(bytes[i..i + 8].try_into().unwrap()));
+            let Some(duration) = bytes.get(i..i + 8) else {
+                return Err(format!("animation frame {frame} is missing its duration"));
+            };
+            let duration = f64::from_ne_bytes(duration.try_into().unwrap());
+            let duration = Duration::try_from_secs_f64(duration)
+                .map_err(|e| format!("animation frame {frame} has duration {duration}: {e}"))?;
             i += 8;
             animation.push((anim, duration));
         }
 
-        (
+        Ok((
             Self {
                 animation: animation.into(),
+                loop_count: 0,
+                speed: 1.0,
             },
             i,
-        )
+        ))
     }
 }
 
@@ -609,7 +1274,21 @@ pub struct ImageReq {
     pub transition: Transition,
     pub imgs: Vec<ImgReq>,
     pub outputs: Vec<Box<[MmappedStr]>>,
-    pub animations: Option<Vec<Animation>>,
+    pub animations: Vec<Option<Animation>>,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlideshowOrder {
+    Sequential = 0,
+    Random = 1,
+}
+
+pub struct SlideshowReq {
+    /// every image in the slideshow, with the outputs it should be displayed at
+    pub image: ImageReq,
+    pub interval: Duration,
+    pub order: SlideshowOrder,
 }
 
 fn deserialize_string(bytes: &[u8]) -> String {
@@ -229,6 +229,10 @@ impl Mmap {
     }
 }
 
+// SAFETY: a `Mmap` owns its mapping and the file behind it, and only hands out slices borrowed from
+// itself, so moving it to another thread is no different from moving a `Vec<u8>`
+unsafe impl Send for Mmap {}
+
 impl Drop for Mmap {
     #[inline]
     fn drop(&mut self) {
@@ -251,15 +255,31 @@ impl<const UTF8: bool> Mmapped<UTF8> {
     const PROT: ProtFlags = ProtFlags::READ;
     const FLAGS: MapFlags = MapFlags::SHARED;
 
+    /// Maps the bytes that follow the length at the start of `bytes`.
+    ///
+    /// Returns `None` if there are fewer bytes than the length says, if they can't be mapped, or, for
+    /// strings, if they aren't valid utf8, since these come from other processes through the socket
     #[must_use]
-    pub(crate) fn new(map: &Mmap, bytes: &[u8]) -> Self {
-        let len = u32::from_ne_bytes(bytes[0..4].try_into().unwrap()) as usize;
-        let bytes = &bytes[4..];
-        Self::new_with_len(map, bytes, len)
+    pub(crate) fn new(map: &Mmap, bytes: &[u8]) -> Option<Self> {
+        let len = u32::from_ne_bytes(bytes.get(0..4)?.try_into().unwrap()) as usize;
+        let bytes = bytes.get(4..)?.get(..len)?;
+        Self::map(map, bytes, len)
     }
 
     #[must_use]
     pub(crate) fn new_with_len(map: &Mmap, bytes: &[u8], len: usize) -> Self {
+        Self::map(map, bytes, len).expect("failed to map bytes from the socket")
+    }
+
+    fn map(map: &Mmap, bytes: &[u8], len: usize) -> Option<Self> {
+        if len == 0 {
+            // mmap can't make empty mappings, and we don't need one anyway
+            return Some(Self {
+                base_ptr: NonNull::dangling(),
+                ptr: NonNull::dangling(),
+                len,
+            });
+        }
         let offset = bytes.as_ptr() as usize - map.ptr.as_ptr() as usize;
         let page_size = rustix::param::page_size();
         let page_offset = offset - offset % page_size;
@@ -273,7 +293,7 @@ impl<const UTF8: bool> Mmapped<UTF8> {
                 &map.fd,
                 page_offset as u64,
             )
-            .unwrap();
+            .ok()?;
             // SAFETY: the function above will never return a null pointer if it succeeds
             // POSIX says that the implementation will never select an address at 0
             NonNull::new_unchecked(ptr)
@@ -281,13 +301,12 @@ impl<const UTF8: bool> Mmapped<UTF8> {
         let ptr =
             unsafe { NonNull::new_unchecked(base_ptr.as_ptr().byte_add(offset - page_offset)) };
 
-        if UTF8 {
-            // try to parse, panicking if we fail
-            let s = unsafe { std::slice::from_raw_parts(ptr.as_ptr().cast(), len) };
-            let _s = std::str::from_utf8(s).expect("received a non utf8 string from socket");
+        let mapped = Self { base_ptr, ptr, len };
+        // if this fails, dropping `mapped` unmaps it again
+        if UTF8 && std::str::from_utf8(mapped.bytes()).is_err() {
+            return None;
         }
-
-        Self { base_ptr, ptr, len }
+        Some(mapped)
     }
 
     #[inline]
@@ -310,9 +329,27 @@ impl<const UTF8: bool> Mmapped<UTF8> {
     }
 }
 
+impl MmappedBytes {
+    /// Copies `bytes` into a mapping of their own, for data we make ourselves instead of receiving
+    /// it from the socket
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut map = Mmap::create(bytes.len());
+        map.slice_mut().copy_from_slice(bytes);
+        Self::new_with_len(&map, map.slice(), bytes.len())
+    }
+}
+
+// SAFETY: every `Mmapped` owns its own read-only mapping, which nothing else ever unmaps or writes
+// to, so it can be moved to another thread just like a `Box<[u8]>`
+unsafe impl<const UTF8: bool> Send for Mmapped<UTF8> {}
+
 impl<const UTF8: bool> Drop for Mmapped<UTF8> {
     fn drop(&mut self) {
         let len = self.len + self.ptr.as_ptr() as usize - self.base_ptr.as_ptr() as usize;
+        if len == 0 {
+            return;
+        }
         if let Err(e) = unsafe { munmap(self.base_ptr.as_ptr(), len) } {
             eprintln!("ERROR WHEN UNMAPPING MEMORY: {e}");
         }
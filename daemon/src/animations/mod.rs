@@ -8,8 +8,11 @@ use std::{
 
 use common::{
     compression::Decompressor,
-    ipc::{self, Animation, BgImg, ImgReq, PixelFormat},
-    mmap::MmappedBytes,
+    ipc::{
+        self, Animation, BgImg, ImgReq, PixelFormat, SlideshowOrder, TransitionProgress,
+        TransitionType,
+    },
+    mmap::{MmappedBytes, MmappedStr},
 };
 
 use crate::{wallpaper::Wallpaper, wayland::ObjectManager};
@@ -17,41 +20,123 @@ use crate::{wallpaper::Wallpaper, wayland::ObjectManager};
 mod transitions;
 use transitions::Effect;
 
+/// The refresh rate we assume for outputs that haven't told us theirs, in mHz
+const FALLBACK_REFRESH: u32 = 60_000;
+
+/// How long each frame of a transition at `fps` lasts. An `fps` of 0 means matching the refresh
+/// rate of `wallpapers`: the slowest one, so that none of them gets frames it can't show
+fn frame_time(fps: u16, wallpapers: &[Rc<RefCell<Wallpaper>>]) -> Duration {
+    if fps != 0 {
+        return Duration::from_nanos(1_000_000_000 / fps as u64);
+    }
+    let refresh = wallpapers
+        .iter()
+        .map(|w| w.borrow().refresh())
+        .filter(|refresh| *refresh != 0)
+        .min()
+        .unwrap_or(FALLBACK_REFRESH);
+    // the refresh rate is in mHz
+    Duration::from_nanos(1_000_000_000_000 / refresh as u64)
+}
+
 pub struct TransitionAnimator {
     pub wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
     fps: Duration,
     effect: Effect,
-    img: MmappedBytes,
-    animation: Option<Animation>,
+    img: Rc<MmappedBytes>,
+    animation: Option<Rc<Animation>>,
     now: Instant,
     over: bool,
+    /// whether we committed any of its frames yet
+    shown: bool,
+    start: Instant,
+    /// how long we expect the transition to take
+    duration: Duration,
 }
 
 impl TransitionAnimator {
+    /// `transition` is the request's transition, used unless `img_req` carries its own. Fails if
+    /// the image doesn't fit the wallpapers
     pub fn new(
-        mut wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
+        wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
         transition: &ipc::Transition,
         pixel_format: PixelFormat,
         img_req: ImgReq,
         animation: Option<Animation>,
-    ) -> Option<Self> {
-        let ImgReq { img, path, dim, .. } = img_req;
-        if wallpapers.is_empty() {
-            return None;
-        }
-        for w in wallpapers.iter_mut() {
-            w.borrow_mut()
-                .set_img_info(BgImg::Img(path.str().to_string()));
+    ) -> Result<Self, String> {
+        let ImgReq {
+            img,
+            path,
+            dim,
+            transition: img_transition,
+            transformed,
+            ..
+        } = img_req;
+        let animator = Self::new_shared(
+            wallpapers,
+            img_transition.as_ref().unwrap_or(transition),
+            pixel_format,
+            path.str(),
+            dim,
+            Rc::new(img),
+            animation.map(Rc::new),
+        )?;
+        for wallpaper in &animator.wallpapers {
+            wallpaper
+                .borrow_mut()
+                .set_transformed(transformed, pixel_format);
         }
+        Ok(animator)
+    }
 
-        let expect = wallpapers[0].borrow().get_dimensions();
+    /// Like `new`, but the image may be used again later, as is the case for slideshows
+    ///
+    /// Effects draw onto the wallpapers' canvas in place, and every canvas we hand out starts as a
+    /// copy of the last frame we drew. So if this interrupts another transition, the new effect
+    /// blends from whatever the old one left on screen
+    pub fn new_shared(
+        mut wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
+        transition: &ipc::Transition,
+        pixel_format: PixelFormat,
+        path: &str,
+        dim: (u32, u32),
+        img: Rc<MmappedBytes>,
+        animation: Option<Rc<Animation>>,
+    ) -> Result<Self, String> {
+        let Some(first) = wallpapers.first() else {
+            return Err("there are no outputs to draw on".to_string());
+        };
+        let expect = first.borrow().get_dimensions();
         if dim != expect {
-            error!("image has wrong dimensions! Expect {expect:?}, actual {dim:?}");
-            return None;
+            return Err(format!(
+                "image has wrong dimensions! Expect {expect:?}, actual {dim:?}"
+            ));
         }
-        let fps = Duration::from_nanos(1_000_000_000 / transition.fps as u64);
-        let effect = Effect::new(transition, pixel_format, dim);
-        Some(Self {
+        let expected_len = dim.0 as usize * dim.1 as usize * pixel_format.channels() as usize;
+        if img.bytes().len() != expected_len {
+            return Err(format!(
+                "image has {} bytes, but its dimensions need {expected_len}",
+                img.bytes().len()
+            ));
+        }
+        for w in wallpapers.iter_mut() {
+            w.borrow_mut().set_img_info(BgImg::Img(path.to_string()));
+        }
+        let fps = frame_time(transition.fps, &wallpapers);
+        let canvas_stride = wallpapers[0].borrow().stride(pixel_format);
+        let effect = Effect::new(transition, pixel_format, dim, canvas_stride);
+        let duration = match transition.transition_type {
+            TransitionType::None => Duration::ZERO,
+            // simple transitions ignore the duration, moving every byte by `step` each frame
+            // instead, so this is how long they take at worst
+            TransitionType::Simple => fps * u8::MAX.div_ceil(transition.step.get()) as u32,
+            // crossfades ignore it too, raising the new image's opacity by `step` each frame
+            TransitionType::Crossfade => fps * 256u32.div_ceil(transition.step.get() as u32),
+            // and so do slides, moving the new image by `step` 256ths of the screen each frame
+            TransitionType::Slide => fps * 256u32.div_ceil(transition.step.get() as u32),
+            _ => Duration::from_secs_f32(transition.duration),
+        };
+        Ok(Self {
             wallpapers,
             effect,
             fps,
@@ -59,15 +144,34 @@ impl TransitionAnimator {
             animation,
             now: Instant::now(),
             over: false,
+            shown: false,
+            start: Instant::now(),
+            duration,
         })
     }
 
+    /// How far along the transition is, or `None` if it's already over
+    pub fn progress(&self) -> Option<TransitionProgress> {
+        if self.over {
+            None
+        } else {
+            Some(TransitionProgress::new(self.duration, self.start.elapsed()))
+        }
+    }
+
     pub fn time_to_draw(&self) -> std::time::Duration {
         self.fps.saturating_sub(self.now.elapsed())
     }
 
+    /// Must be called right after committing one of our frames
     pub fn updt_time(&mut self) {
         self.now = Instant::now();
+        self.shown = true;
+    }
+
+    /// Whether the compositor got any of our frames yet
+    pub fn is_shown(&self) -> bool {
+        self.shown
     }
 
     pub fn frame(&mut self, objman: &mut ObjectManager, pixel_format: PixelFormat) -> bool {
@@ -86,6 +190,14 @@ impl TransitionAnimator {
         }
     }
 
+    /// Draws the image we are transitioning to, skipping whatever frames were left. Just like with
+    /// `frame`, the wallpapers must be committed afterwards
+    pub fn finish(&mut self, objman: &mut ObjectManager, pixel_format: PixelFormat) {
+        self.effect.skip_to_end();
+        self.over = false;
+        self.frame(objman, pixel_format);
+    }
+
     pub fn into_image_animator(self) -> Option<ImageAnimator> {
         let Self {
             wallpapers,
@@ -96,6 +208,7 @@ impl TransitionAnimator {
         animation.map(|animation| ImageAnimator {
             now: Instant::now(),
             wallpapers,
+            speed: animation.speed,
             animation,
             decompressor: Decompressor::new(),
             i: 0,
@@ -106,23 +219,37 @@ impl TransitionAnimator {
 pub struct ImageAnimator {
     now: Instant,
     pub wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
-    animation: Animation,
+    animation: Rc<Animation>,
+    /// starts as the animation's own, but may change while it plays
+    speed: f32,
     decompressor: Decompressor,
     i: usize,
 }
 
 impl ImageAnimator {
     pub fn time_to_draw(&self) -> std::time::Duration {
-        self.animation.animation[self.i % self.animation.animation.len()]
-            .1
+        let duration = self.animation.animation[self.i % self.animation.animation.len()].1;
+        // durations come from other processes, so dividing them must not overflow
+        Duration::try_from_secs_f64(duration.as_secs_f64() / self.speed as f64)
+            .unwrap_or(Duration::MAX)
             .saturating_sub(self.now.elapsed())
     }
 
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
     pub fn updt_time(&mut self) {
         self.now = Instant::now();
     }
 
-    pub fn frame(&mut self, objman: &mut ObjectManager, pixel_format: PixelFormat) {
+    /// Draws the next frame, returning whether the animation is over instead if it already played
+    /// as many times as it should. The last frame it drew stays on screen
+    pub fn frame(&mut self, objman: &mut ObjectManager, pixel_format: PixelFormat) -> bool {
         let Self {
             wallpapers,
             animation,
@@ -131,15 +258,24 @@ impl ImageAnimator {
             ..
         } = self;
 
+        // the last frame of every loop takes us back to the first one, so we skip it in the last
+        // loop to stay on the actual last frame
+        let loop_count = animation.loop_count as usize;
+        if loop_count != 0 && *i + 1 >= loop_count * animation.animation.len() {
+            return true;
+        }
+
         let frame = &animation.animation[*i % animation.animation.len()].0;
 
         let mut j = 0;
         while j < wallpapers.len() {
-            let result = wallpapers[j]
-                .borrow_mut()
-                .canvas_change(objman, pixel_format, |canvas| {
-                    decompressor.decompress(frame, canvas, pixel_format)
-                });
+            let mut wallpaper = wallpapers[j].borrow_mut();
+            let width = wallpaper.get_dimensions().0 as usize;
+            let stride = wallpaper.stride(pixel_format);
+            let result = wallpaper.canvas_change(objman, pixel_format, |canvas| {
+                decompressor.decompress_strided(frame, canvas, pixel_format, width, stride)
+            });
+            drop(wallpaper);
 
             if let Err(e) = result {
                 error!("failed to unpack frame: {e}");
@@ -150,5 +286,127 @@ impl ImageAnimator {
         }
 
         *i += 1;
+        false
+    }
+}
+
+struct SlideshowImg {
+    path: MmappedStr,
+    dim: (u32, u32),
+    img: Rc<MmappedBytes>,
+    animation: Option<Rc<Animation>>,
+}
+
+/// Cycles through a list of images, creating a new `TransitionAnimator` every time the interval
+/// elapses
+pub struct Slideshow {
+    pub wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
+    transition: ipc::Transition,
+    imgs: Vec<SlideshowImg>,
+    interval: Duration,
+    order: SlideshowOrder,
+    current: Option<usize>,
+    now: Instant,
+    /// since when the slideshow is paused, if it is
+    paused: Option<Instant>,
+}
+
+impl Slideshow {
+    pub fn new(
+        wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
+        transition: ipc::Transition,
+        interval: Duration,
+        order: SlideshowOrder,
+    ) -> Self {
+        Self {
+            wallpapers,
+            transition,
+            imgs: Vec::new(),
+            interval,
+            order,
+            current: None,
+            now: Instant::now(),
+            paused: None,
+        }
+    }
+
+    pub fn push(&mut self, img_req: ImgReq, animation: Option<Animation>) {
+        let ImgReq { img, path, dim, .. } = img_req;
+        self.imgs.push(SlideshowImg {
+            path,
+            dim,
+            img: Rc::new(img),
+            animation: animation.map(Rc::new),
+        });
+    }
+
+    /// Postpones the next image change, used when resuming from a pause
+    /// Pauses the slideshow while any of its wallpapers is paused. The time it spends paused does
+    /// not count towards the interval
+    pub fn sync_pause(&mut self) {
+        let paused = crate::wallpaper::any_paused(&self.wallpapers);
+        match self.paused {
+            None if paused => self.paused = Some(Instant::now()),
+            Some(since) if !paused => {
+                self.now += since.elapsed();
+                self.paused = None;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    pub fn time_to_next(&self) -> Duration {
+        if self.current.is_none() {
+            Duration::ZERO
+        } else {
+            self.interval.saturating_sub(self.now.elapsed())
+        }
+    }
+
+    /// Picks the next image and creates the transition to it
+    pub fn next(&mut self, pixel_format: PixelFormat) -> Option<TransitionAnimator> {
+        let len = self.imgs.len();
+        if len == 0 {
+            return None;
+        }
+
+        let next = match (self.order, self.current) {
+            (SlideshowOrder::Sequential, Some(current)) => (current + 1) % len,
+            (SlideshowOrder::Sequential, None) => 0,
+            // never repeat the image currently on screen
+            (SlideshowOrder::Random, Some(current)) if len > 1 => {
+                let next = fastrand::usize(..len - 1);
+                if next >= current {
+                    next + 1
+                } else {
+                    next
+                }
+            }
+            (SlideshowOrder::Random, _) => fastrand::usize(..len),
+        };
+        self.current = Some(next);
+        self.now = Instant::now();
+
+        let img = &self.imgs[next];
+        let animator = TransitionAnimator::new_shared(
+            self.wallpapers.clone(),
+            &self.transition,
+            pixel_format,
+            img.path.str(),
+            img.dim,
+            Rc::clone(&img.img),
+            img.animation.clone(),
+        )
+        .inspect_err(|e| error!("failed to show {} in a slideshow: {e}", img.path.str()))
+        .ok()?;
+        // slideshows can't be transformed by the user
+        for wallpaper in &animator.wallpapers {
+            wallpaper.borrow_mut().set_transformed(false, pixel_format);
+        }
+        Some(animator)
     }
 }
@@ -1,14 +1,15 @@
-use std::{cell::RefCell, rc::Rc, time::Instant};
+use std::{cell::RefCell, rc::Rc, sync::OnceLock, time::Instant};
 
 use crate::{wallpaper::Wallpaper, wayland::ObjectManager};
 use common::ipc::{PixelFormat, Transition, TransitionType};
 
 use keyframe::{
     functions::BezierCurve, keyframes, mint::Vector2, num_traits::Pow, AnimationSequence,
+    EasingFunction,
 };
 
-fn bezier_seq(transition: &Transition, start: f32, end: f32) -> (AnimationSequence<f32>, Instant) {
-    let bezier = BezierCurve::from(
+fn bezier_curve(transition: &Transition) -> BezierCurve {
+    BezierCurve::from(
         Vector2 {
             x: transition.bezier.0,
             y: transition.bezier.1,
@@ -17,7 +18,11 @@ fn bezier_seq(transition: &Transition, start: f32, end: f32) -> (AnimationSequen
             x: transition.bezier.2,
             y: transition.bezier.3,
         },
-    );
+    )
+}
+
+fn bezier_seq(transition: &Transition, start: f32, end: f32) -> (AnimationSequence<f32>, Instant) {
+    let bezier = bezier_curve(transition);
     (
         keyframes![(start, 0.0, bezier), (end, transition.duration, bezier)],
         Instant::now(),
@@ -35,11 +40,39 @@ fn change_byte(step: u8, old: &mut u8, new: &u8) {
     }
 }
 
-struct None;
+/// How many bytes each row takes in the image we are transitioning to and in the canvas we are
+/// drawing on. They differ when the canvas' rows are padded
+#[derive(Clone, Copy)]
+struct Rows {
+    img_stride: usize,
+    canvas_stride: usize,
+}
+
+impl Rows {
+    /// pairs every row of the canvas with the matching row of the image, leaving out the padding
+    fn zip<'a>(
+        self,
+        canvas: &'a mut [u8],
+        img: &'a [u8],
+    ) -> impl Iterator<Item = (&'a mut [u8], &'a [u8])> {
+        let Self {
+            img_stride,
+            canvas_stride,
+        } = self;
+        canvas
+            .chunks_exact_mut(canvas_stride)
+            .zip(img.chunks_exact(img_stride))
+            .map(move |(canvas, img)| (&mut canvas[..img_stride], img))
+    }
+}
+
+struct None {
+    rows: Rows,
+}
 
 impl None {
-    fn new() -> Self {
-        Self
+    fn new(rows: Rows) -> Self {
+        Self { rows }
     }
 
     fn run(
@@ -49,9 +82,14 @@ impl None {
         wallpapers: &mut [Rc<RefCell<Wallpaper>>],
         img: &[u8],
     ) -> bool {
+        let rows = self.rows;
         wallpapers.iter().for_each(|w| {
             w.borrow_mut()
-                .canvas_change(objman, pixel_format, |canvas| canvas.copy_from_slice(img))
+                .canvas_change(objman, pixel_format, |canvas| {
+                    for (canvas, img) in rows.zip(canvas, img) {
+                        canvas.copy_from_slice(img);
+                    }
+                })
         });
         true
     }
@@ -62,25 +100,92 @@ pub enum Effect {
     None(None),
     Simple(Simple),
     Fade(Fade),
+    Crossfade(Crossfade),
+    Slide(Slide),
     Wave(Wave),
     Wipe(Wipe),
     Grow(Grow),
     Outer(Outer),
+    Reveal(Reveal),
+    Dissolve(Dissolve),
+    Melt(Melt),
 }
 
 impl Effect {
-    pub fn new(transition: &Transition, pixel_format: PixelFormat, dimensions: (u32, u32)) -> Self {
+    /// `canvas_stride` is how many bytes each row of the wallpapers' canvas takes, padding included
+    pub fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        canvas_stride: usize,
+    ) -> Self {
+        let rows = Rows {
+            img_stride: dimensions.0 as usize * pixel_format.channels() as usize,
+            canvas_stride,
+        };
+        let mask = transition.mask.as_deref().filter(|mask| {
+            let fits = mask.len() == dimensions.0 as usize * dimensions.1 as usize;
+            if !fits {
+                log::warn!("transition mask does not match the image's size, ignoring it");
+            }
+            fits
+        });
         match transition.transition_type {
-            TransitionType::Simple => Self::Simple(Simple::new(transition.step.get())),
-            TransitionType::Fade => Self::Fade(Fade::new(transition)),
-            TransitionType::Outer => Self::Outer(Outer::new(transition, pixel_format, dimensions)),
-            TransitionType::Wipe => Self::Wipe(Wipe::new(transition, pixel_format, dimensions)),
-            TransitionType::Grow => Self::Grow(Grow::new(transition, pixel_format, dimensions)),
-            TransitionType::Wave => Self::Wave(Wave::new(transition, pixel_format, dimensions)),
-            TransitionType::None => Self::None(None::new()),
+            TransitionType::Simple => Self::Simple(Simple::new(transition.step.get(), rows)),
+            TransitionType::Fade => Self::Fade(Fade::new(transition, pixel_format, mask, rows)),
+            TransitionType::Crossfade => {
+                Self::Crossfade(Crossfade::new(transition, pixel_format, rows))
+            }
+            TransitionType::Slide => {
+                Self::Slide(Slide::new(transition, pixel_format, dimensions, rows))
+            }
+            TransitionType::Outer => {
+                Self::Outer(Outer::new(transition, pixel_format, dimensions, rows))
+            }
+            TransitionType::Wipe => {
+                Self::Wipe(Wipe::new(transition, pixel_format, dimensions, rows))
+            }
+            TransitionType::Grow => {
+                Self::Grow(Grow::new(transition, pixel_format, dimensions, rows))
+            }
+            TransitionType::Wave => {
+                Self::Wave(Wave::new(transition, pixel_format, dimensions, rows))
+            }
+            TransitionType::Reveal => Self::Reveal(Reveal::new(transition, pixel_format, rows)),
+            TransitionType::Dissolve => Self::Dissolve(Dissolve::new(
+                transition,
+                pixel_format,
+                dimensions,
+                mask,
+                rows,
+            )),
+            TransitionType::Melt => {
+                Self::Melt(Melt::new(transition, pixel_format, dimensions, rows))
+            }
+            TransitionType::None => Self::None(None::new(rows)),
         }
     }
 
+    /// Makes the next `execute` draw the image we are transitioning to all at once, skipping
+    /// whatever frames were left
+    pub fn skip_to_end(&mut self) {
+        let rows = match self {
+            Effect::None(effect) => effect.rows,
+            Effect::Simple(effect) => effect.rows,
+            Effect::Fade(effect) => effect.rows,
+            Effect::Crossfade(effect) => effect.rows,
+            Effect::Slide(effect) => effect.rows,
+            Effect::Wave(effect) => effect.rows,
+            Effect::Wipe(effect) => effect.rows,
+            Effect::Grow(effect) => effect.rows,
+            Effect::Outer(effect) => effect.rows,
+            Effect::Reveal(effect) => effect.rows,
+            Effect::Dissolve(effect) => effect.rows,
+            Effect::Melt(effect) => effect.rows,
+        };
+        *self = Effect::None(None::new(rows));
+    }
+
     pub fn execute(
         &mut self,
         objman: &mut ObjectManager,
@@ -92,20 +197,31 @@ impl Effect {
             Effect::None(effect) => effect.run(objman, pixel_format, wallpapers, img),
             Effect::Simple(effect) => effect.run(objman, pixel_format, wallpapers, img),
             Effect::Fade(effect) => effect.run(objman, pixel_format, wallpapers, img),
+            Effect::Crossfade(effect) => effect.run(objman, pixel_format, wallpapers, img),
+            Effect::Slide(effect) => effect.run(objman, pixel_format, wallpapers, img),
             Effect::Wave(effect) => effect.run(objman, pixel_format, wallpapers, img),
             Effect::Wipe(effect) => effect.run(objman, pixel_format, wallpapers, img),
             Effect::Grow(effect) => effect.run(objman, pixel_format, wallpapers, img),
             Effect::Outer(effect) => effect.run(objman, pixel_format, wallpapers, img),
+            Effect::Reveal(effect) => effect.run(objman, pixel_format, wallpapers, img),
+            Effect::Dissolve(effect) => effect.run(objman, pixel_format, wallpapers, img),
+            Effect::Melt(effect) => effect.run(objman, pixel_format, wallpapers, img),
         };
         // we only finish for real if we are doing a None or a Simple transition
         if done {
             *self = match self {
                 Effect::None(_) | Effect::Simple(_) => return true,
-                Effect::Fade(t) => Effect::Simple(Simple::new((t.step / 4 + 4) as u8)),
-                Effect::Wave(t) => Effect::Simple(Simple::new(t.step / 4 + 4)),
-                Effect::Wipe(t) => Effect::Simple(Simple::new(t.step / 4 + 4)),
-                Effect::Grow(t) => Effect::Simple(Simple::new(t.step / 4 + 4)),
-                Effect::Outer(t) => Effect::Simple(Simple::new(t.step / 4 + 4)),
+                Effect::Fade(t) => Effect::Simple(Simple::new((t.step / 4 + 4) as u8, t.rows)),
+                Effect::Wave(t) => Effect::Simple(Simple::new(t.step / 4 + 4, t.rows)),
+                Effect::Wipe(t) => Effect::Simple(Simple::new(t.step / 4 + 4, t.rows)),
+                Effect::Grow(t) => Effect::Simple(Simple::new(t.step / 4 + 4, t.rows)),
+                Effect::Outer(t) => Effect::Simple(Simple::new(t.step / 4 + 4, t.rows)),
+                Effect::Reveal(t) => Effect::Simple(Simple::new(t.step / 4 + 4, t.rows)),
+                // every block (or column) was already copied over, this just confirms we are done
+                Effect::Crossfade(t) => Effect::Simple(Simple::new(u8::MAX, t.rows)),
+                Effect::Slide(t) => Effect::Simple(Simple::new(u8::MAX, t.rows)),
+                Effect::Dissolve(t) => Effect::Simple(Simple::new(u8::MAX, t.rows)),
+                Effect::Melt(t) => Effect::Simple(Simple::new(u8::MAX, t.rows)),
             };
             return false;
         }
@@ -115,11 +231,12 @@ impl Effect {
 
 struct Simple {
     step: u8,
+    rows: Rows,
 }
 
 impl Simple {
-    fn new(step: u8) -> Self {
-        Self { step }
+    fn new(step: u8, rows: Rows) -> Self {
+        Self { step, rows }
     }
     fn run(
         &mut self,
@@ -128,33 +245,123 @@ impl Simple {
         wallpapers: &mut [Rc<RefCell<Wallpaper>>],
         img: &[u8],
     ) -> bool {
-        let step = self.step;
+        let Self { step, rows } = *self;
         let mut done = true;
         for wallpaper in wallpapers.iter() {
             wallpaper
                 .borrow_mut()
                 .canvas_change(objman, pixel_format, |canvas| {
-                    for (old, new) in canvas.iter_mut().zip(img) {
-                        change_byte(step, old, new);
+                    for (canvas, img) in rows.zip(canvas, img) {
+                        for (old, new) in canvas.iter_mut().zip(img) {
+                            change_byte(step, old, new);
+                        }
+                        done = done && canvas == img;
                     }
-                    done = done && canvas == img;
                 });
         }
         done
     }
 }
 
+/// How many of the mask's 256 levels a pixel takes to fade in, when the fade follows a mask
+const MASK_SOFTNESS: u32 = 32;
+
+/// Lookup tables to go from sRGB to linear light and back.
+///
+/// Linear values have 12 bits: going back from fewer than that bands the dark tones, where the
+/// sRGB curve is steepest
+struct LinearLight {
+    to_linear: [u16; 256],
+    to_srgb: [u8; 4096],
+}
+
+impl LinearLight {
+    fn get() -> &'static Self {
+        static LUT: OnceLock<LinearLight> = OnceLock::new();
+        LUT.get_or_init(|| {
+            let mut to_linear = [0; 256];
+            for (i, linear) in to_linear.iter_mut().enumerate() {
+                let c = i as f32 / 255.0;
+                let c = if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                };
+                *linear = (c * 4095.0).round() as u16;
+            }
+            let mut to_srgb = [0; 4096];
+            for (i, srgb) in to_srgb.iter_mut().enumerate() {
+                let c = i as f32 / 4095.0;
+                let c = if c <= 0.0031308 {
+                    c * 12.92
+                } else {
+                    1.055 * c.powf(1.0 / 2.4) - 0.055
+                };
+                *srgb = (c * 255.0).round() as u8;
+            }
+            Self { to_linear, to_srgb }
+        })
+    }
+
+    /// Blends `old` into `new` by `step / 256`, in linear light
+    #[inline(always)]
+    fn blend(&self, old: u8, new: u8, step: u16) -> u8 {
+        let x = self.to_linear[old as usize] as u32 * (256 - step as u32);
+        let y = self.to_linear[new as usize] as u32 * step as u32;
+        self.to_srgb[((x + y) >> 8) as usize]
+    }
+}
+
+/// Blends one pixel of the canvas into the new image by `step / 256`. Only the first three bytes
+/// are colors: the fourth one of 4 channel formats is padding, so it is blended as is
+#[inline(always)]
+fn fade_pixel(old: &mut [u8], new: &[u8], step: u16, linear: Option<&LinearLight>) {
+    let colors = match linear {
+        Some(linear) => {
+            for (old, new) in old.iter_mut().zip(new).take(3) {
+                *old = linear.blend(*old, *new, step);
+            }
+            3
+        }
+        None => 0,
+    };
+    for (old, new) in old.iter_mut().zip(new).skip(colors) {
+        let x = *old as u16 * (256 - step);
+        let y = *new as u16 * step;
+        *old = ((x + y) >> 8) as u8;
+    }
+}
+
 struct Fade {
     start: Instant,
     seq: AnimationSequence<f32>,
     step: u16,
+    channels: usize,
+    /// when set, each pixel only starts fading once `step` goes past its value in here
+    mask: Option<Box<[u8]>>,
+    /// when set, we blend in linear light
+    linear: Option<&'static LinearLight>,
+    rows: Rows,
 }
 
 impl Fade {
-    fn new(transition: &Transition) -> Self {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        mask: Option<&[u8]>,
+        rows: Rows,
+    ) -> Self {
         let (seq, start) = bezier_seq(transition, 0.0, 1.0);
         let step = 0;
-        Self { start, seq, step }
+        Self {
+            start,
+            seq,
+            step,
+            channels: pixel_format.channels() as usize,
+            mask: mask.map(Box::from),
+            linear: transition.linear.then(LinearLight::get),
+            rows,
+        }
     }
     fn run(
         &mut self,
@@ -163,14 +370,36 @@ impl Fade {
         wallpapers: &mut [Rc<RefCell<Wallpaper>>],
         img: &[u8],
     ) -> bool {
+        // with a mask, the fade's front has to go past the brightest pixels by `MASK_SOFTNESS`
+        let front = self.step as u32 * (256 + MASK_SOFTNESS) / 256;
         for wallpaper in wallpapers.iter() {
             wallpaper
                 .borrow_mut()
                 .canvas_change(objman, pixel_format, |canvas| {
-                    for (old, new) in canvas.iter_mut().zip(img) {
-                        let x = *old as u16 * (256 - self.step);
-                        let y = *new as u16 * self.step;
-                        *old = ((x + y) >> 8) as u8;
+                    let rows = self.rows.zip(canvas, img);
+                    let Some(mask) = &self.mask else {
+                        for (canvas, img) in rows {
+                            let pixels = canvas
+                                .chunks_exact_mut(self.channels)
+                                .zip(img.chunks_exact(self.channels));
+                            for (old, new) in pixels {
+                                fade_pixel(old, new, self.step, self.linear);
+                            }
+                        }
+                        return;
+                    };
+
+                    let width = self.rows.img_stride / self.channels;
+                    for ((canvas, img), mask) in rows.zip(mask.chunks_exact(width)) {
+                        let pixels = canvas
+                            .chunks_exact_mut(self.channels)
+                            .zip(img.chunks_exact(self.channels))
+                            .zip(mask);
+                        for ((old, new), mask) in pixels {
+                            let step = (front.saturating_sub(*mask as u32) * 256 / MASK_SOFTNESS)
+                                .min(256) as u16;
+                            fade_pixel(old, new, step, self.linear);
+                        }
                     }
                 });
         }
@@ -180,6 +409,218 @@ impl Fade {
     }
 }
 
+/// Blends what each wallpaper showed when the transition started into the new image, raising the
+/// new image's opacity by a fixed step every frame
+struct Crossfade {
+    step: u16,
+    /// the new image's opacity, out of 256
+    alpha: u16,
+    channels: usize,
+    /// when set, we blend in linear light
+    linear: Option<&'static LinearLight>,
+    /// what each wallpaper showed when the transition started, keyed by their output name
+    old: Vec<(u32, Box<[u8]>)>,
+    rows: Rows,
+}
+
+impl Crossfade {
+    fn new(transition: &Transition, pixel_format: PixelFormat, rows: Rows) -> Self {
+        Self {
+            step: transition.step.get() as u16,
+            alpha: 0,
+            channels: pixel_format.channels() as usize,
+            linear: transition.linear.then(LinearLight::get),
+            old: Vec::new(),
+            rows,
+        }
+    }
+
+    fn run(
+        &mut self,
+        objman: &mut ObjectManager,
+        pixel_format: PixelFormat,
+        wallpapers: &mut [Rc<RefCell<Wallpaper>>],
+        img: &[u8],
+    ) -> bool {
+        self.alpha = (self.alpha + self.step).min(256);
+        let Self {
+            alpha,
+            channels,
+            linear,
+            rows,
+            ..
+        } = *self;
+        for wallpaper in wallpapers.iter() {
+            let mut wallpaper = wallpaper.borrow_mut();
+            let output_name = wallpaper.output_name();
+            let old = &mut self.old;
+            wallpaper.canvas_change(objman, pixel_format, |canvas| {
+                let i = match old.iter().position(|(name, _)| *name == output_name) {
+                    Some(i) => i,
+                    None => {
+                        old.push((output_name, canvas.into()));
+                        old.len() - 1
+                    }
+                };
+                let old = &old[i].1;
+                assert_eq!(
+                    old.len(),
+                    canvas.len(),
+                    "wallpaper changed size mid-transition"
+                );
+                canvas.copy_from_slice(old);
+
+                for (canvas, img) in rows.zip(canvas, img) {
+                    let pixels = canvas
+                        .chunks_exact_mut(channels)
+                        .zip(img.chunks_exact(channels));
+                    for (old, new) in pixels {
+                        fade_pixel(old, new, alpha, linear);
+                    }
+                }
+            });
+        }
+        self.alpha == 256
+    }
+}
+
+/// The edge of the screen the new image comes in from, in a `Slide`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Edge {
+    Right,
+    Top,
+    Left,
+    Bottom,
+}
+
+impl Edge {
+    /// Rounds `angle` to the nearest edge. Like with `Wipe`, 0 degrees goes from right to left,
+    /// and 90 from top to bottom
+    fn from_angle(angle: f64) -> Self {
+        match ((angle.rem_euclid(360.0) + 45.0) / 90.0) as u32 % 4 {
+            0 => Self::Right,
+            1 => Self::Top,
+            2 => Self::Left,
+            _ => Self::Bottom,
+        }
+    }
+}
+
+/// Moves the new image in from one of the screen's edges, pushing what each wallpaper showed when
+/// the transition started out through the opposite one
+struct Slide {
+    step: u16,
+    /// how far the new image has come in, out of 256, before easing
+    progress: u16,
+    bezier: BezierCurve,
+    edge: Edge,
+    height: usize,
+    channels: usize,
+    /// what each wallpaper showed when the transition started, keyed by their output name
+    old: Vec<(u32, Box<[u8]>)>,
+    rows: Rows,
+}
+
+impl Slide {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        rows: Rows,
+    ) -> Self {
+        Self {
+            step: transition.step.get() as u16,
+            progress: 0,
+            bezier: bezier_curve(transition),
+            edge: Edge::from_angle(transition.angle),
+            height: dimensions.1 as usize,
+            channels: pixel_format.channels() as usize,
+            old: Vec::new(),
+            rows,
+        }
+    }
+
+    fn run(
+        &mut self,
+        objman: &mut ObjectManager,
+        pixel_format: PixelFormat,
+        wallpapers: &mut [Rc<RefCell<Wallpaper>>],
+        img: &[u8],
+    ) -> bool {
+        self.progress = (self.progress + self.step).min(256);
+        let Self {
+            edge,
+            height,
+            channels,
+            rows,
+            ..
+        } = *self;
+        let Rows {
+            img_stride,
+            canvas_stride,
+        } = rows;
+        let eased = self.bezier.y(self.progress as f64 / 256.0).clamp(0.0, 1.0);
+        // how much of the new image is on screen: bytes of each row when coming in from the sides,
+        // whole rows when coming in from the top or bottom
+        let shift = match edge {
+            Edge::Right | Edge::Left => {
+                let width = img_stride / channels;
+                ((eased * width as f64).round() as usize).min(width) * channels
+            }
+            Edge::Top | Edge::Bottom => ((eased * height as f64).round() as usize).min(height),
+        };
+
+        for wallpaper in wallpapers.iter() {
+            let mut wallpaper = wallpaper.borrow_mut();
+            let output_name = wallpaper.output_name();
+            let old = &mut self.old;
+            wallpaper.canvas_change(objman, pixel_format, |canvas| {
+                let i = match old.iter().position(|(name, _)| *name == output_name) {
+                    Some(i) => i,
+                    None => {
+                        old.push((output_name, canvas.into()));
+                        old.len() - 1
+                    }
+                };
+                let old = &old[i].1;
+                assert_eq!(
+                    old.len(),
+                    canvas.len(),
+                    "wallpaper changed size mid-transition"
+                );
+                let old_row = |y: usize| &old[y * canvas_stride..][..img_stride];
+                let new_row = |y: usize| &img[y * img_stride..][..img_stride];
+
+                let canvas_rows = canvas.chunks_exact_mut(canvas_stride).take(height);
+                for (y, canvas) in canvas_rows.enumerate() {
+                    let canvas = &mut canvas[..img_stride];
+                    match edge {
+                        Edge::Right => {
+                            let (left, right) = canvas.split_at_mut(img_stride - shift);
+                            left.copy_from_slice(&old_row(y)[shift..]);
+                            right.copy_from_slice(&new_row(y)[..shift]);
+                        }
+                        Edge::Left => {
+                            let (left, right) = canvas.split_at_mut(shift);
+                            left.copy_from_slice(&new_row(y)[img_stride - shift..]);
+                            right.copy_from_slice(&old_row(y)[..img_stride - shift]);
+                        }
+                        Edge::Top if y < shift => {
+                            canvas.copy_from_slice(new_row(height - shift + y))
+                        }
+                        Edge::Top => canvas.copy_from_slice(old_row(y - shift)),
+                        Edge::Bottom if y + shift < height => {
+                            canvas.copy_from_slice(old_row(y + shift))
+                        }
+                        Edge::Bottom => canvas.copy_from_slice(new_row(y + shift - height)),
+                    }
+                }
+            });
+        }
+        self.progress == 256
+    }
+}
+
 struct Wave {
     start: Instant,
     seq: AnimationSequence<f32>,
@@ -187,6 +628,7 @@ struct Wave {
     height: usize,
     center: (u32, u32),
     stride: usize,
+    rows: Rows,
     sin: f64,
     cos: f64,
     scale_x: f64,
@@ -198,7 +640,12 @@ struct Wave {
 }
 
 impl Wave {
-    fn new(transition: &Transition, pixel_format: PixelFormat, dimensions: (u32, u32)) -> Self {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        rows: Rows,
+    ) -> Self {
         let width = dimensions.0;
         let height = dimensions.1;
         let center = (width / 2, height / 2);
@@ -228,6 +675,7 @@ impl Wave {
             height,
             center,
             stride,
+            rows,
             a,
             b,
             sin,
@@ -250,6 +698,7 @@ impl Wave {
             height,
             center,
             stride,
+            rows,
             sin,
             cos,
             scale_x,
@@ -260,6 +709,7 @@ impl Wave {
             step,
             ..
         } = *self;
+        let canvas_stride = rows.canvas_stride;
         // graph: https://www.desmos.com/calculator/wunde042es
         //
         // checks if a pixel is to the left or right of the line
@@ -299,7 +749,8 @@ impl Wave {
                             (x as usize * channels, stride)
                         };
                         for col in col_begin..col_end {
-                            let old = unsafe { canvas.get_unchecked_mut(line * stride + col) };
+                            let old =
+                                unsafe { canvas.get_unchecked_mut(line * canvas_stride + col) };
                             let new = unsafe { img.get_unchecked(line * stride + col) };
                             change_byte(step, old, new);
                         }
@@ -316,8 +767,9 @@ impl Wave {
                         for col in col_begin..col_end {
                             if is_low(col as f64, line as f64, offset) {
                                 let i = line * stride + col * channels;
+                                let canvas_i = line * canvas_stride + col * channels;
                                 for j in 0..channels {
-                                    let old = unsafe { canvas.get_unchecked_mut(i + j) };
+                                    let old = unsafe { canvas.get_unchecked_mut(canvas_i + j) };
                                     let new = unsafe { img.get_unchecked(i + j) };
                                     change_byte(step, old, new);
                                 }
@@ -338,6 +790,7 @@ struct Wipe {
     height: usize,
     center: (u32, u32),
     stride: usize,
+    rows: Rows,
     circle_radius: f64,
     a: f64,
     b: f64,
@@ -345,7 +798,12 @@ struct Wipe {
 }
 
 impl Wipe {
-    fn new(transition: &Transition, pixel_format: PixelFormat, dimensions: (u32, u32)) -> Self {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        rows: Rows,
+    ) -> Self {
         let width = dimensions.0;
         let height = dimensions.1;
         let center = (width / 2, height / 2);
@@ -377,6 +835,7 @@ impl Wipe {
             height,
             center,
             stride,
+            rows,
             circle_radius,
             a,
             b,
@@ -395,12 +854,14 @@ impl Wipe {
             height,
             center,
             stride,
+            rows,
             circle_radius,
             a,
             b,
             step,
             ..
         } = *self;
+        let canvas_stride = rows.canvas_stride;
         let channels = pixel_format.channels() as usize;
         let offset = self.seq.now() as f64;
         self.seq.advance_to(self.start.elapsed().as_secs_f64());
@@ -420,7 +881,8 @@ impl Wipe {
                             (x as usize * channels, stride)
                         };
                         for col in col_begin..col_end {
-                            let old = unsafe { canvas.get_unchecked_mut(line * stride + col) };
+                            let old =
+                                unsafe { canvas.get_unchecked_mut(line * canvas_stride + col) };
                             let new = unsafe { img.get_unchecked(line * stride + col) };
                             change_byte(step, old, new);
                         }
@@ -439,12 +901,18 @@ struct Grow {
     center_x: usize,
     center_y: usize,
     stride: usize,
+    rows: Rows,
     dist_center: f32,
     step: u8,
 }
 
 impl Grow {
-    fn new(transition: &Transition, pixel_format: PixelFormat, dimensions: (u32, u32)) -> Self {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        rows: Rows,
+    ) -> Self {
         let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
         let (center_x, center_y) = transition.pos.to_pixel(dimensions, transition.invert_y);
         let dist_center: f32 = 0.0;
@@ -475,6 +943,7 @@ impl Grow {
             center_x,
             center_y,
             stride,
+            rows,
             dist_center,
             step,
         }
@@ -492,10 +961,12 @@ impl Grow {
             center_x,
             center_y,
             stride,
+            rows,
             dist_center,
             step,
             ..
         } = *self;
+        let canvas_stride = rows.canvas_stride;
         let channels = pixel_format.channels() as usize;
 
         for wallpaper in wallpapers.iter() {
@@ -512,7 +983,8 @@ impl Grow {
                         let col_begin = center_x.saturating_sub(offset) * channels;
                         let col_end = width.min(center_x + offset) * channels;
                         for col in col_begin..col_end {
-                            let old = unsafe { canvas.get_unchecked_mut(line * stride + col) };
+                            let old =
+                                unsafe { canvas.get_unchecked_mut(line * canvas_stride + col) };
                             let new = unsafe { img.get_unchecked(line * stride + col) };
                             change_byte(step, old, new);
                         }
@@ -534,12 +1006,18 @@ struct Outer {
     center_x: usize,
     center_y: usize,
     stride: usize,
+    rows: Rows,
     dist_center: f32,
     step: u8,
 }
 
 impl Outer {
-    fn new(transition: &Transition, pixel_format: PixelFormat, dimensions: (u32, u32)) -> Self {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        rows: Rows,
+    ) -> Self {
         let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
         let (center_x, center_y) = transition.pos.to_pixel(dimensions, transition.invert_y);
         let dist_center = {
@@ -569,6 +1047,7 @@ impl Outer {
             center_x,
             center_y,
             stride,
+            rows,
             dist_center,
         }
     }
@@ -585,10 +1064,12 @@ impl Outer {
             center_x,
             center_y,
             stride,
+            rows,
             dist_center,
             step,
             ..
         } = *self;
+        let canvas_stride = rows.canvas_stride;
         let channels = pixel_format.channels() as usize;
         for wallpaper in wallpapers.iter() {
             wallpaper
@@ -601,12 +1082,14 @@ impl Outer {
                         let col_begin = center_x.saturating_sub(offset) * channels;
                         let col_end = width.min(center_x + offset) * channels;
                         for col in 0..col_begin {
-                            let old = unsafe { canvas.get_unchecked_mut(line * stride + col) };
+                            let old =
+                                unsafe { canvas.get_unchecked_mut(line * canvas_stride + col) };
                             let new = unsafe { img.get_unchecked(line * stride + col) };
                             change_byte(step, old, new);
                         }
                         for col in col_end..stride {
-                            let old = unsafe { canvas.get_unchecked_mut(line * stride + col) };
+                            let old =
+                                unsafe { canvas.get_unchecked_mut(line * canvas_stride + col) };
                             let new = unsafe { img.get_unchecked(line * stride + col) };
                             change_byte(step, old, new);
                         }
@@ -618,3 +1101,328 @@ impl Outer {
         self.start.elapsed().as_secs_f64() > self.seq.duration()
     }
 }
+
+/// Reveals the new image from its brightest pixels to its darkest ones
+struct Reveal {
+    start: Instant,
+    /// pixels whose luminance is at least this much get drawn. Goes from 256 down to 0
+    seq: AnimationSequence<f32>,
+    channels: usize,
+    /// where the red and blue channels are in each pixel
+    red: usize,
+    blue: usize,
+    rows: Rows,
+    step: u8,
+}
+
+impl Reveal {
+    fn new(transition: &Transition, pixel_format: PixelFormat, rows: Rows) -> Self {
+        let (seq, start) = bezier_seq(transition, 256.0, 0.0);
+        let (red, blue) = if pixel_format.must_swap_r_and_b_channels() {
+            (2, 0)
+        } else {
+            (0, 2)
+        };
+        Self {
+            start,
+            seq,
+            channels: pixel_format.channels() as usize,
+            red,
+            blue,
+            rows,
+            step: transition.step.get(),
+        }
+    }
+
+    fn run(
+        &mut self,
+        objman: &mut ObjectManager,
+        pixel_format: PixelFormat,
+        wallpapers: &mut [Rc<RefCell<Wallpaper>>],
+        img: &[u8],
+    ) -> bool {
+        let Self {
+            channels,
+            red,
+            blue,
+            rows,
+            step,
+            ..
+        } = *self;
+        let threshold = self.seq.now() as u32;
+        self.seq.advance_to(self.start.elapsed().as_secs_f64());
+        for wallpaper in wallpapers.iter() {
+            wallpaper
+                .borrow_mut()
+                .canvas_change(objman, pixel_format, |canvas| {
+                    for (canvas, img) in rows.zip(canvas, img) {
+                        let pixels = canvas
+                            .chunks_exact_mut(channels)
+                            .zip(img.chunks_exact(channels));
+                        for (old, new) in pixels {
+                            if luminance(new[red], new[1], new[blue]) >= threshold {
+                                for (old, new) in old.iter_mut().zip(new) {
+                                    change_byte(step, old, new);
+                                }
+                            }
+                        }
+                    }
+                });
+        }
+        self.start.elapsed().as_secs_f64() > self.seq.duration()
+    }
+}
+
+/// Flips square blocks of pixels from the old image to the new one, in a pseudo-random order, or
+/// in the order given by the transition's mask
+///
+/// The order only depends on where each block is, so outputs of the same size dissolve in exactly
+/// the same pattern
+struct Dissolve {
+    start: Instant,
+    /// blocks whose threshold is below this get drawn. Goes from 0 up to 256
+    seq: AnimationSequence<f32>,
+    /// side of each block, in pixels
+    block: usize,
+    channels: usize,
+    /// how many blocks fit in a row, rounding up
+    blocks_per_row: usize,
+    /// the threshold of every block, row by row
+    thresholds: Box<[u8]>,
+    rows: Rows,
+}
+
+impl Dissolve {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        mask: Option<&[u8]>,
+        rows: Rows,
+    ) -> Self {
+        let (seq, start) = bezier_seq(transition, 0.0, 256.0);
+        let block = transition.step.get() as u32;
+        let blocks_per_row = dimensions.0.div_ceil(block);
+        let threshold = |x: u32, y: u32| match mask {
+            // each block goes when its top left pixel would
+            Some(mask) => mask[(y * block * dimensions.0 + x * block) as usize],
+            None => block_threshold(x, y),
+        };
+        let thresholds = (0..dimensions.1.div_ceil(block))
+            .flat_map(|y| (0..blocks_per_row).map(move |x| threshold(x, y)))
+            .collect();
+        Self {
+            start,
+            seq,
+            block: block as usize,
+            channels: pixel_format.channels() as usize,
+            blocks_per_row: blocks_per_row as usize,
+            thresholds,
+            rows,
+        }
+    }
+
+    fn run(
+        &mut self,
+        objman: &mut ObjectManager,
+        pixel_format: PixelFormat,
+        wallpapers: &mut [Rc<RefCell<Wallpaper>>],
+        img: &[u8],
+    ) -> bool {
+        let Self {
+            block,
+            channels,
+            blocks_per_row,
+            rows,
+            ..
+        } = *self;
+        let progress = self.seq.now() as u32;
+        self.seq.advance_to(self.start.elapsed().as_secs_f64());
+        let thresholds = &self.thresholds;
+        for wallpaper in wallpapers.iter() {
+            wallpaper
+                .borrow_mut()
+                .canvas_change(objman, pixel_format, |canvas| {
+                    for (line, (canvas, img)) in rows.zip(canvas, img).enumerate() {
+                        let first = line / block * blocks_per_row;
+                        let blocks = canvas
+                            .chunks_mut(block * channels)
+                            .zip(img.chunks(block * channels))
+                            .zip(&thresholds[first..first + blocks_per_row]);
+                        for ((old, new), threshold) in blocks {
+                            if (*threshold as u32) < progress {
+                                old.copy_from_slice(new);
+                            }
+                        }
+                    }
+                });
+        }
+        self.start.elapsed().as_secs_f64() > self.seq.duration()
+    }
+}
+
+/// How much later than the first column the last one may start falling, as a fraction of the
+/// transition's duration
+const MELT_MAX_DELAY: f32 = 0.5;
+
+/// Slides columns of the old image down the screen, each starting at a slightly different time,
+/// uncovering the new image behind them
+struct Melt {
+    start: Instant,
+    /// goes from 0 up to 1 + `MELT_MAX_DELAY`
+    seq: AnimationSequence<f32>,
+    height: usize,
+    /// width of each column, in pixels
+    column: usize,
+    channels: usize,
+    /// when each column starts falling, from 0 to `MELT_MAX_DELAY`
+    delays: Box<[f32]>,
+    /// what each wallpaper showed when the transition started, keyed by their output name
+    old: Vec<(u32, Box<[u8]>)>,
+    rows: Rows,
+}
+
+impl Melt {
+    fn new(
+        transition: &Transition,
+        pixel_format: PixelFormat,
+        dimensions: (u32, u32),
+        rows: Rows,
+    ) -> Self {
+        let (seq, start) = bezier_seq(transition, 0.0, 1.0 + MELT_MAX_DELAY);
+        let column = transition.step.get() as u32;
+        // like in the original, neighbouring columns start at similar times, so they look like
+        // they are tearing apart rather than being random noise
+        let mut delay: i32 = block_threshold(0, 0) as i32 % 16;
+        let delays = (0..dimensions.0.div_ceil(column))
+            .map(|x| {
+                delay = (delay + block_threshold(x, 1) as i32 % 3 - 1).clamp(0, 15);
+                delay as f32 / 15.0 * MELT_MAX_DELAY
+            })
+            .collect();
+        Self {
+            start,
+            seq,
+            height: dimensions.1 as usize,
+            column: column as usize,
+            channels: pixel_format.channels() as usize,
+            delays,
+            old: Vec::new(),
+            rows,
+        }
+    }
+
+    fn run(
+        &mut self,
+        objman: &mut ObjectManager,
+        pixel_format: PixelFormat,
+        wallpapers: &mut [Rc<RefCell<Wallpaper>>],
+        img: &[u8],
+    ) -> bool {
+        let Self {
+            height,
+            column,
+            channels,
+            rows,
+            ..
+        } = *self;
+        let progress = self.seq.now();
+        self.seq.advance_to(self.start.elapsed().as_secs_f64());
+
+        // how far down each column has fallen, accelerating as it goes
+        let offsets: Vec<usize> = self
+            .delays
+            .iter()
+            .map(|delay| {
+                let t = (progress - delay).max(0.0);
+                (t * t * height as f32).min(height as f32) as usize
+            })
+            .collect();
+
+        for wallpaper in wallpapers.iter() {
+            let mut wallpaper = wallpaper.borrow_mut();
+            let output_name = wallpaper.output_name();
+            let old = &mut self.old;
+            wallpaper.canvas_change(objman, pixel_format, |canvas| {
+                let i = match old.iter().position(|(name, _)| *name == output_name) {
+                    Some(i) => i,
+                    None => {
+                        old.push((output_name, canvas.into()));
+                        old.len() - 1
+                    }
+                };
+                let old = &old[i].1;
+                assert_eq!(
+                    old.len(),
+                    canvas.len(),
+                    "wallpaper changed size mid-transition"
+                );
+
+                let column_len = column * channels;
+                for (x, offset) in offsets.iter().enumerate() {
+                    let col_begin = x * column_len;
+                    let col_end = (col_begin + column_len).min(rows.img_stride);
+                    for line in 0..height {
+                        let canvas_i = line * rows.canvas_stride;
+                        let (src, src_i) = if line < *offset {
+                            (img, line * rows.img_stride)
+                        } else {
+                            (&old[..], (line - offset) * rows.canvas_stride)
+                        };
+                        canvas[canvas_i + col_begin..canvas_i + col_end]
+                            .copy_from_slice(&src[src_i + col_begin..src_i + col_end]);
+                    }
+                }
+            });
+        }
+        self.start.elapsed().as_secs_f64() > self.seq.duration()
+    }
+}
+
+/// Scrambles a block's coordinates into the moment it gets drawn, from 0 to 255
+#[inline(always)]
+fn block_threshold(x: u32, y: u32) -> u8 {
+    let mut h = x.wrapping_mul(0x9e37_79b1) ^ y.wrapping_mul(0x85eb_ca77);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    (h >> 24) as u8
+}
+
+/// Approximates the perceived brightness of a color (ITU-R BT.601), from 0 to 255
+#[inline(always)]
+fn luminance(r: u8, g: u8, b: u8) -> u32 {
+    (r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_light_fade_keeps_midpoints_bright() {
+        let linear = LinearLight::get();
+        let mut pixel = [0, 0, 0, 0];
+        fade_pixel(&mut pixel, &[255, 255, 255, 255], 128, Some(linear));
+        for channel in &pixel[..3] {
+            assert!(channel.abs_diff(188) <= 1, "got {channel}");
+        }
+        // the padding byte is not a color
+        assert_eq!(pixel[3], 127);
+
+        let mut pixel = [0, 0, 0];
+        fade_pixel(&mut pixel, &[255, 255, 255], 128, None);
+        assert_eq!(pixel, [127, 127, 127]);
+    }
+
+    #[test]
+    fn linear_light_round_trips_every_value() {
+        let linear = LinearLight::get();
+        for v in 0..=255 {
+            assert_eq!(linear.blend(v, 0, 0), v);
+            assert_eq!(linear.blend(0, v, 256), v);
+        }
+    }
+}
@@ -1,16 +1,167 @@
-use common::ipc::PixelFormat;
+use common::ipc::{Layer, PixelFormat};
+use log::LevelFilter;
 
 pub struct Cli {
     pub format: Option<PixelFormat>,
-    pub quiet: bool,
+    /// the level of everything `log_filter` doesn't say otherwise about
+    pub log_level: LevelFilter,
+    pub log_filter: LogFilter,
     pub no_cache: bool,
+    pub no_transitions: bool,
+    /// whether to reconnect to the compositor when we lose the connection to it, instead of exiting
+    pub reconnect: bool,
+    pub layer: Layer,
+    pub namespace: String,
+    /// replaces the socket path we would derive from the namespace
+    pub socket: Option<std::path::PathBuf>,
+    pub outputs: OutputFilter,
+    pub cache_dir: Option<std::path::PathBuf>,
+    /// in bytes
+    pub cache_limit: Option<u64>,
+    /// where to append the logs to, besides stderr
+    pub log_file: Option<std::path::PathBuf>,
+}
+
+/// Same as `--log-file`, which overrides it
+const LOG_FILE_ENV: &str = "SWWW_LOG_FILE";
+
+/// The outputs the daemon should manage, as names that may contain `*` and `?` wildcards
+#[derive(Clone, Default)]
+pub struct OutputFilter {
+    patterns: Vec<String>,
+}
+
+impl OutputFilter {
+    pub fn new(list: &str) -> Self {
+        Self {
+            patterns: list
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+
+    /// An empty filter means we manage every output
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.is_empty()
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` matches any sequence of characters (including none)
+/// and `?` matches exactly one
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Per module log levels, given as `module=level` pairs, like `wayland=trace,transitions=debug`.
+/// A level without a module applies to every module the other pairs don't mention
+#[derive(Clone, Default)]
+pub struct LogFilter {
+    default: Option<LevelFilter>,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn new(spec: &str) -> Result<Self, String> {
+        let mut filter = Self::default();
+        for directive in spec.split(',').filter(|s| !s.is_empty()) {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (Some(module), level),
+                None => (None, directive),
+            };
+            let level: LevelFilter = level.parse().map_err(|_| {
+                format!("'{level}' is not a log level. Use one of: off, error, warn, info, debug or trace")
+            })?;
+            match module {
+                Some("") => return Err(format!("'{directive}' is missing a module name")),
+                Some(module) => filter.modules.push((module.to_string(), level)),
+                None => filter.default = Some(level),
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The level enabled for log records whose target is `target`. The most specific module
+    /// matching it wins and, among equally specific ones, the last one given
+    pub fn level(&self, target: &str, default: LevelFilter) -> LevelFilter {
+        let module = target.strip_prefix("swww_daemon::").unwrap_or(target);
+        self.modules
+            .iter()
+            .filter(|(name, _)| module_matches(name, module))
+            // `max_by_key` returns the last of the maximums
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, level)| *level)
+            .or(self.default)
+            .unwrap_or(default)
+    }
+
+    /// The most verbose level any module may log at
+    pub fn max_level(&self, default: LevelFilter) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .chain(Some(self.default.unwrap_or(default)))
+            .max()
+            .unwrap_or(default)
+    }
+}
+
+/// Whether `name` is one of the modules in the path of `module` (like `wayland` or `globals` for
+/// `wayland::globals`), or a sequence of them
+fn module_matches(name: &str, module: &str) -> bool {
+    module.match_indices(name).any(|(i, _)| {
+        let (before, after) = (&module[..i], &module[i + name.len()..]);
+        (before.is_empty() || before.ends_with("::"))
+            && (after.is_empty() || after.starts_with("::"))
+    })
+}
+
+/// `verbosity` is the number of times `-v` was given minus the number of times `-q` was
+fn log_level(verbosity: i32) -> LevelFilter {
+    match verbosity {
+        ..=-2 => LevelFilter::Off,
+        -1 => LevelFilter::Error,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        3.. => LevelFilter::Trace,
+    }
 }
 
 impl Cli {
     pub fn new() -> Self {
-        let mut quiet = false;
+        let mut verbosity = 0;
+        let mut log_filter = LogFilter::default();
         let mut no_cache = false;
+        let mut no_transitions = false;
+        let mut reconnect = false;
         let mut format = None;
+        let mut layer = Layer::Background;
+        let mut namespace = String::new();
+        let mut socket = None;
+        let mut outputs = OutputFilter::default();
+        let mut cache_dir = None;
+        let mut cache_limit = None;
+        let mut log_file = std::env::var_os(LOG_FILE_ENV)
+            .filter(|path| !path.is_empty())
+            .map(std::path::PathBuf::from);
         let mut args = std::env::args();
         args.next(); // skip the first argument
 
@@ -21,19 +172,95 @@ impl Cli {
                     Some("xbgr") => format = Some(PixelFormat::Xbgr),
                     Some("rgb") => format = Some(PixelFormat::Rgb),
                     Some("bgr") => format = Some(PixelFormat::Bgr),
+                    Some("xbgr2101010") => format = Some(PixelFormat::Xbgr2101010),
+                    Some("xrgb2101010") => format = Some(PixelFormat::Xrgb2101010),
                     _ => {
-                        eprintln!("`--format` command line option must be one of: 'xrgb', 'xbgr', 'rgb' or 'bgr'");
+                        eprintln!("`--format` command line option must be one of: 'xrgb', 'xbgr', 'rgb', 'bgr', 'xrgb2101010' or 'xbgr2101010'");
                         std::process::exit(-2);
                     }
                 },
-                "-q" | "--quiet" => quiet = true,
+                "-l" | "--layer" => match args.next().as_deref() {
+                    Some("background") => layer = Layer::Background,
+                    Some("bottom") => layer = Layer::Bottom,
+                    Some("top") => layer = Layer::Top,
+                    Some("overlay") => layer = Layer::Overlay,
+                    _ => {
+                        eprintln!("`--layer` command line option must be one of: 'background', 'bottom', 'top' or 'overlay'");
+                        std::process::exit(-2);
+                    }
+                },
+                "-n" | "--namespace" => match args.next() {
+                    Some(ns) if !ns.contains('/') => namespace = ns,
+                    _ => {
+                        eprintln!(
+                            "`--namespace` command line option must be a name without any '/'"
+                        );
+                        std::process::exit(-2);
+                    }
+                },
+                "--socket" => match args.next() {
+                    Some(path) if !path.is_empty() => socket = Some(path.into()),
+                    _ => {
+                        eprintln!("`--socket` command line option must be a path");
+                        std::process::exit(-2);
+                    }
+                },
+                "-o" | "--outputs" => match args.next() {
+                    Some(list) if !OutputFilter::new(&list).is_empty() => {
+                        outputs = OutputFilter::new(&list)
+                    }
+                    _ => {
+                        eprintln!("`--outputs` command line option must be a comma separated list of output names");
+                        std::process::exit(-2);
+                    }
+                },
+                "--cache-dir" => match args.next() {
+                    Some(dir) if !dir.is_empty() => cache_dir = Some(dir.into()),
+                    _ => {
+                        eprintln!("`--cache-dir` command line option must be a directory");
+                        std::process::exit(-2);
+                    }
+                },
+                "--cache-limit" => {
+                    match args.next().as_deref().and_then(common::cache::parse_size) {
+                        Some(limit) => cache_limit = Some(limit),
+                        None => {
+                            eprintln!("`--cache-limit` command line option must be a size, like '1G' or '500M'");
+                            std::process::exit(-2);
+                        }
+                    }
+                }
+                "--log-file" => match args.next() {
+                    Some(path) if !path.is_empty() => log_file = Some(path.into()),
+                    _ => {
+                        eprintln!("`--log-file` command line option must be a path");
+                        std::process::exit(-2);
+                    }
+                },
+                "--log-filter" => match args.next().as_deref().map(LogFilter::new) {
+                    Some(Ok(filter)) => log_filter = filter,
+                    Some(Err(e)) => {
+                        eprintln!("`--log-filter` command line option is invalid: {e}");
+                        std::process::exit(-2);
+                    }
+                    None => {
+                        eprintln!("`--log-filter` command line option must be a list of module=level pairs");
+                        std::process::exit(-2);
+                    }
+                },
+                "-q" | "--quiet" => verbosity -= 1,
+                "-v" | "--verbose" => verbosity += 1,
+                "-vv" => verbosity += 2,
+                "-vvv" => verbosity += 3,
                 "--no-cache" => no_cache = true,
+                "--no-transitions" => no_transitions = true,
+                "--reconnect" => reconnect = true,
                 "-h" | "--help" => {
                     println!("swww-daemon");
                     println!();
                     println!("Options:");
                     println!();
-                    println!("  -f|--format <xrgb|xbgr|rgb|bgr>");
+                    println!("  -f|--format <xrgb|xbgr|rgb|bgr|xrgb2101010|xbgr2101010>");
                     println!("          force the use of a specific wl_shm format.");
                     println!();
                     println!(
@@ -42,6 +269,62 @@ impl Cli {
                     println!("          Only use this as a workaround when you run into problems.");
                     println!("          Whatever you chose, make sure you compositor actually supports it!");
                     println!("          'xrgb' is the most compatible one.");
+                    println!("          The 2101010 formats are never picked automatically.");
+                    println!();
+                    println!("          The format used is remembered for the next time the daemon starts.");
+                    println!(
+                        "          That is only a hint: this option always overrides it, and it's"
+                    );
+                    println!("          ignored if the compositor stops supporting it.");
+                    println!();
+                    println!("  -l|--layer <background|bottom|top|overlay>");
+                    println!(
+                        "          which layer to display the wallpapers in (default: background)."
+                    );
+                    println!();
+                    println!("  -n|--namespace <namespace>");
+                    println!(
+                        "          run this daemon under a namespace, so that several daemons can"
+                    );
+                    println!(
+                        "          coexist (e.g. one in the background layer and another in the"
+                    );
+                    println!(
+                        "          overlay layer). Control it with `swww --namespace <namespace>`."
+                    );
+                    println!();
+                    println!("  --socket <path>");
+                    println!(
+                        "          listen on this socket instead of the one derived from the namespace"
+                    );
+                    println!(
+                        "          and $XDG_RUNTIME_DIR. Overrides $SWWW_SOCKET. Clients must be"
+                    );
+                    println!("          pointed at it too, with `swww --socket` or $SWWW_SOCKET.");
+                    println!();
+                    println!("  -o|--outputs <output1,output2,...>");
+                    println!(
+                        "          only manage these outputs, ignoring every other one. Names may"
+                    );
+                    println!(
+                        "          use `*` and `?` wildcards (e.g. 'HDMI-*'). Default: all outputs."
+                    );
+                    println!();
+                    println!("  --cache-dir <directory>");
+                    println!(
+                        "          keep the cache in a `swww` subdirectory of this directory instead"
+                    );
+                    println!("          of the default one.");
+                    println!("          Clients must be pointed at it too, with $SWWW_CACHE_DIR.");
+                    println!();
+                    println!("  --cache-limit <size>");
+                    println!(
+                        "          how much space cached animations may take up before the least"
+                    );
+                    println!(
+                        "          recently used ones are deleted, like '500M'. 0 means no limit."
+                    );
+                    println!("          Clients must be given it too, with $SWWW_CACHE_LIMIT. Default: 1G.");
                     println!();
                     println!("  --no-cache");
                     println!(
@@ -49,7 +332,42 @@ impl Cli {
                     );
                     println!("          Useful if you always want to select which image 'swww' loads manually using 'swww img'");
                     println!();
-                    println!("  -q|--quiet    will only log errors");
+                    println!("  --no-transitions");
+                    println!(
+                        "          Change images instantly, ignoring the transition requested by 'swww'."
+                    );
+                    println!("          Animated images still play normally.");
+                    println!();
+                    println!("  --reconnect");
+                    println!(
+                        "          when the compositor goes away (e.g. because it was restarted),"
+                    );
+                    println!("          wait for it to come back and restore the wallpapers, instead of exiting.");
+                    println!();
+                    println!("  --log-file <path>");
+                    println!(
+                        "          also append the logs to this file, with wall-clock timestamps."
+                    );
+                    println!(
+                        "          Once it grows past 8M, it is moved to '<path>.old'. Overrides"
+                    );
+                    println!("          $SWWW_LOG_FILE.");
+                    println!();
+                    println!("  --log-filter <module=level,...>");
+                    println!(
+                        "          log these modules at these levels, regardless of -v and -q. For"
+                    );
+                    println!(
+                        "          example, 'wayland=trace,transitions=debug'. A level on its own"
+                    );
+                    println!("          applies to every other module.");
+                    println!();
+                    println!(
+                        "  -v|--verbose  log more: -v for info, -vv for debug, -vvv for trace"
+                    );
+                    println!(
+                        "  -q|--quiet    log less: -q for errors only, -qq for nothing at all"
+                    );
                     println!("  -h|--help     print help");
                     println!("  -V|--version  print version");
                     std::process::exit(0);
@@ -68,8 +386,88 @@ impl Cli {
 
         Self {
             format,
-            quiet,
+            log_level: log_level(verbosity),
+            log_filter,
             no_cache,
+            no_transitions,
+            reconnect,
+            layer,
+            namespace,
+            socket,
+            outputs,
+            cache_dir,
+            cache_limit,
+            log_file,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_filter_matches_names_and_globs() {
+        let filter = OutputFilter::new("DP-1,HDMI-*,eDP-?");
+        assert!(filter.matches("DP-1"));
+        assert!(!filter.matches("DP-10"));
+        assert!(filter.matches("HDMI-A-1"));
+        assert!(filter.matches("HDMI-"));
+        assert!(filter.matches("eDP-2"));
+        assert!(!filter.matches("eDP-"));
+        assert!(!filter.matches("DP-2"));
+    }
+
+    #[test]
+    fn log_filter_picks_the_most_specific_module() {
+        let filter =
+            LogFilter::new("wayland=debug,wayland::globals=trace,transitions=info").unwrap();
+        let level = |target| filter.level(target, LevelFilter::Warn);
+        assert_eq!(level("swww_daemon::wayland"), LevelFilter::Debug);
+        assert_eq!(level("swww_daemon::wayland::bump_pool"), LevelFilter::Debug);
+        assert_eq!(level("swww_daemon::wayland::globals"), LevelFilter::Trace);
+        assert_eq!(
+            level("swww_daemon::animations::transitions"),
+            LevelFilter::Info
+        );
+        assert_eq!(level("swww_daemon::animations"), LevelFilter::Warn);
+        assert_eq!(level("swww_daemon::wallpaper"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(LevelFilter::Warn), LevelFilter::Trace);
+
+        let filter = LogFilter::new("off,wallpaper=error,wallpaper=DEBUG").unwrap();
+        assert_eq!(
+            filter.level("swww_daemon::wallpaper", LevelFilter::Warn),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            filter.level("swww_daemon", LevelFilter::Warn),
+            LevelFilter::Off
+        );
+        assert_eq!(filter.max_level(LevelFilter::Trace), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn invalid_log_filters_are_rejected() {
+        assert!(LogFilter::new("wayland=loud").is_err());
+        assert!(LogFilter::new("=debug").is_err());
+        assert!(LogFilter::new("wayland").is_err());
+    }
+
+    #[test]
+    fn verbosity_maps_to_log_levels() {
+        assert_eq!(log_level(-2), LevelFilter::Off);
+        assert_eq!(log_level(-1), LevelFilter::Error);
+        assert_eq!(log_level(0), LevelFilter::Warn);
+        assert_eq!(log_level(1), LevelFilter::Info);
+        assert_eq!(log_level(2), LevelFilter::Debug);
+        assert_eq!(log_level(5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn empty_output_filter_matches_everything() {
+        for filter in [OutputFilter::default(), OutputFilter::new(",")] {
+            assert!(filter.is_empty());
+            assert!(filter.matches("DP-1"));
         }
     }
 }
@@ -0,0 +1,153 @@
+//! The file given to `--log-file`, which we append to and rotate once it gets too big
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Once the log reaches this size, it is moved to `<path>.old` (replacing the previous one), and we
+/// start over with an empty file
+const MAX_LEN: u64 = 8 * 1024 * 1024;
+
+pub struct LogFile {
+    path: PathBuf,
+    /// `None` if we failed to reopen it after rotating. We try again with the next line
+    file: Option<File>,
+    len: u64,
+    max_len: u64,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Self::with_max_len(path, MAX_LEN)
+    }
+
+    fn with_max_len(path: &Path, max_len: u64) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Some(file),
+            len,
+            max_len,
+        })
+    }
+
+    /// Appends `line` (which should end in a newline), rotating the file first if it would grow
+    /// past the maximum size.
+    ///
+    /// Errors are ignored on purpose: there is nowhere left to report them, and a full disk is no
+    /// reason to take the wallpapers down with it. The line is simply lost
+    pub fn write_line(&mut self, line: &str) {
+        if self.len > 0 && self.len + line.len() as u64 > self.max_len {
+            self.rotate();
+        }
+        if self.file.is_none() {
+            self.reopen();
+        }
+        if let Some(file) = &mut self.file {
+            if file.write_all(line.as_bytes()).is_ok() {
+                self.len += line.len() as u64;
+            }
+        }
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+        let _ = fs::rename(&self.path, old_path(&self.path));
+        self.len = 0;
+    }
+
+    fn reopen(&mut self) {
+        if let Ok(file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+        {
+            self.len = file.metadata().map(|m| m.len()).unwrap_or(0);
+            self.file = Some(file);
+        }
+    }
+}
+
+/// `path` with `.old` appended to its file name
+fn old_path(path: &Path) -> PathBuf {
+    let mut old = OsString::from(path.as_os_str());
+    old.push(".old");
+    old.into()
+}
+
+/// Formats `time` as an RFC3339 UTC timestamp with millisecond precision, like
+/// `2024-05-01T13:04:59.123Z`
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let millis = since_epoch.subsec_millis();
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Turns the number of days since 1970-01-01 into a (year, month, day) date in the proleptic
+/// Gregorian calendar. This is Howard Hinnant's `civil_from_days`, restricted to dates after the
+/// epoch
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_rfc3339_timestamps() {
+        let at = |secs: u64, millis: u64| UNIX_EPOCH + Duration::from_millis(secs * 1000 + millis);
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(at(951_782_400, 5)), "2000-02-29T00:00:00.005Z");
+        assert_eq!(rfc3339(at(1_714_568_699, 123)), "2024-05-01T13:04:59.123Z");
+        assert_eq!(rfc3339(at(1_735_689_599, 999)), "2024-12-31T23:59:59.999Z");
+    }
+
+    #[test]
+    fn rotates_into_a_single_old_file() {
+        let dir = std::env::temp_dir().join(format!("swww-log-file-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("swww.log");
+
+        let mut log = LogFile::with_max_len(&path, 10).unwrap();
+        log.write_line("first\n");
+        log.write_line("second\n");
+        log.write_line("third\n");
+        drop(log);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(old_path(&path)).unwrap(), "second\n");
+
+        // reopening appends instead of truncating
+        let mut log = LogFile::with_max_len(&path, 10).unwrap();
+        log.write_line("4th\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n4th\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,6 +4,7 @@
 
 mod animations;
 mod cli;
+mod log_file;
 mod wallpaper;
 #[allow(dead_code)]
 mod wayland;
@@ -13,7 +14,8 @@ use rustix::{
     fd::OwnedFd,
 };
 
-use wallpaper::Wallpaper;
+use cli::OutputFilter;
+use wallpaper::{OutputEvent, PendingOutput, Wallpaper};
 use wayland::{
     globals::{self, InitState},
     ObjectId, ObjectManager,
@@ -27,14 +29,22 @@ use std::{
     path::Path,
     rc::Rc,
     sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use animations::{ImageAnimator, TransitionAnimator};
+use animations::{ImageAnimator, Slideshow, TransitionAnimator};
 use common::ipc::{
-    Answer, BgInfo, ImageReq, IpcSocket, PixelFormat, RequestRecv, RequestSend, Scale, Server,
+    Answer, BgInfo, Event, ImageReq, IpcSocket, Layer, LayerReq, OutputMatch, PauseReq,
+    PixelFormat, RequestRecv, RequestSend, Scale, ScreenshotReq, Server, SlideshowReq, Transition,
+    TransitionType, WaitIdleReq,
 };
-use common::mmap::MmappedStr;
+use common::mmap::{MmappedBytes, MmappedStr};
+
+/// For how long we quietly drop events sent to the `wl_output`s of outputs that were removed.
+///
+/// The compositor may have sent them before it processed our release request, which happens a lot
+/// when waking up from suspend
+const REMOVED_OUTPUT_GRACE: Duration = Duration::from_secs(5);
 
 // We need this because this might be set by signals, so we can't keep it in the daemon
 static EXIT: AtomicBool = AtomicBool::new(false);
@@ -51,22 +61,61 @@ extern "C" fn signal_handler(_s: libc::c_int) {
     exit_daemon();
 }
 
+/// A client waiting for the transitions on `wallpapers` to end before we answer it
+struct IdleWaiter {
+    wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
+    stream: IpcSocket<Server>,
+}
+
+/// An image sent to outputs whose transition hadn't shown a single frame yet. It starts once that
+/// frame is out, unless an even newer image replaces it first
+struct PendingImg {
+    transition: TransitionAnimator,
+    /// how many images sent before this one we dropped without ever showing them
+    skipped: usize,
+}
+
 struct Daemon {
     objman: ObjectManager,
     pixel_format: PixelFormat,
     wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
     transition_animators: Vec<TransitionAnimator>,
+    pending_imgs: Vec<PendingImg>,
     image_animators: Vec<ImageAnimator>,
+    slideshows: Vec<Slideshow>,
+    idle_waiters: Vec<IdleWaiter>,
+    /// clients from `swww subscribe`, which get an `Answer::Event` for everything that happens
+    subscribers: Vec<IpcSocket<Server>>,
+    /// events that happened since we last notified the subscribers
+    events: Vec<Event>,
+    /// outputs we can't yet tell whether to manage, because we don't know their names
+    pending_outputs: Vec<PendingOutput>,
+    /// the `wl_output`s of recently removed outputs, and when they were removed
+    removed_outputs: Vec<(ObjectId, Instant)>,
     use_cache: bool,
+    /// whether to swap images instantly, regardless of the requested transition
+    no_transitions: bool,
+    layer: Layer,
+    namespace: String,
+    output_filter: OutputFilter,
     fractional_scale_manager: Option<ObjectId>,
+    output_power_manager: Option<ObjectId>,
     poll_time: PollTime,
 }
 
 impl Daemon {
-    fn new(init_state: InitState, no_cache: bool) -> Self {
+    fn new(
+        init_state: InitState,
+        no_cache: bool,
+        no_transitions: bool,
+        layer: Layer,
+        namespace: String,
+        output_filter: OutputFilter,
+    ) -> Self {
         let InitState {
             output_names,
             fractional_scale,
+            output_power,
             objman,
             pixel_format,
         } = init_state;
@@ -75,6 +124,7 @@ impl Daemon {
             fractional_scale.is_some(),
             objman.fractional_scale_support()
         );
+        assert_eq!(output_power.is_some(), objman.output_power_support());
 
         log::info!("Selected wl_shm format: {pixel_format:?}");
 
@@ -83,51 +133,189 @@ impl Daemon {
             pixel_format,
             wallpapers: Vec::new(),
             transition_animators: Vec::new(),
+            pending_imgs: Vec::new(),
             image_animators: Vec::new(),
+            slideshows: Vec::new(),
+            idle_waiters: Vec::new(),
+            subscribers: Vec::new(),
+            events: Vec::new(),
+            pending_outputs: Vec::new(),
+            removed_outputs: Vec::new(),
             use_cache: !no_cache,
+            no_transitions,
+            layer,
+            namespace,
+            output_filter,
             fractional_scale_manager: fractional_scale.map(|x| x.id()),
+            output_power_manager: output_power.map(|x| x.id()),
             poll_time: PollTime::Never,
         };
 
         for output_name in output_names {
-            daemon.new_output(output_name);
+            daemon.add_output(output_name);
         }
 
         daemon
     }
 
-    fn new_output(&mut self, output_name: u32) {
+    /// Starts managing the `wl_output` global named `output_name`. If we only manage some outputs,
+    /// it stays pending until we know its name
+    fn add_output(&mut self, output_name: u32) {
+        if self.output_filter.is_empty() {
+            let output = wallpaper::bind_output(&mut self.objman, output_name);
+            self.new_output(output_name, output);
+        } else {
+            let pending = PendingOutput::new(&mut self.objman, output_name);
+            self.pending_outputs.push(pending);
+        }
+    }
+
+    fn new_output(&mut self, output_name: u32, output: ObjectId) -> Rc<RefCell<Wallpaper>> {
         let wallpaper = Rc::new(RefCell::new(Wallpaper::new(
             &mut self.objman,
             self.pixel_format,
             self.fractional_scale_manager,
+            self.output_power_manager,
             output_name,
+            output,
+            self.layer,
+            &self.namespace,
         )));
-        self.wallpapers.push(wallpaper);
+        self.wallpapers.push(Rc::clone(&wallpaper));
+        wallpaper
+    }
+
+    fn pending_output(&mut self, output: ObjectId) -> Option<&mut PendingOutput> {
+        self.pending_outputs
+            .iter_mut()
+            .find(|p| p.output() == output)
+    }
+
+    /// Called on the pending output's first `done` event, when we finally know its name
+    fn resolve_pending_output(&mut self, i: usize) {
+        let pending = self.pending_outputs.swap_remove(i);
+        match pending.name() {
+            Some(name) if self.output_filter.matches(name) => {
+                let wallpaper = self.new_output(pending.output_name(), pending.output());
+                let mut wallpaper = wallpaper.borrow_mut();
+                wallpaper.replay(pending.into_events());
+                wallpaper.commit_surface_changes(&mut self.objman, self.use_cache);
+            }
+            name => {
+                info!(
+                    "ignoring output {}: it isn't in `--outputs`",
+                    name.unwrap_or("?")
+                );
+                self.removed_outputs
+                    .push((pending.output(), Instant::now()));
+                pending.release();
+            }
+        }
+    }
+
+    fn remove_wallpaper(&mut self, i: usize) {
+        let w = self.wallpapers.remove(i);
+        let output = w.borrow().output_id();
+        self.removed_outputs.push((output, Instant::now()));
+        self.events.extend(w.borrow().removed_event());
+        self.stop_animations(&[w]);
+    }
+
+    /// Whether `output` belonged to an output that was removed less than `REMOVED_OUTPUT_GRACE`
+    /// ago
+    fn is_recently_removed_output(&mut self, output: ObjectId) -> bool {
+        self.removed_outputs
+            .retain(|(_, removed)| removed.elapsed() < REMOVED_OUTPUT_GRACE);
+        self.removed_outputs.iter().any(|(id, _)| *id == output)
+    }
+
+    /// Finds the wallpaper of the `wl_output` that sent us `event`
+    ///
+    /// Late events for removed outputs are expected, so we only warn about outputs we've never
+    /// seen
+    fn output_wallpaper(
+        &mut self,
+        output: ObjectId,
+        event: &str,
+    ) -> Option<Rc<RefCell<Wallpaper>>> {
+        let wallpaper = self
+            .wallpapers
+            .iter()
+            .find(|w| w.borrow().has_output(output))
+            .cloned();
+        if wallpaper.is_none() {
+            if self.is_recently_removed_output(output) {
+                debug!(
+                    "dropping wl_output.{event} for removed output {}",
+                    output.get()
+                );
+            } else {
+                warn!(
+                    "received wl_output.{event} for unknown output {}",
+                    output.get()
+                );
+            }
+        }
+        wallpaper
     }
 
     fn recv_socket_msg(&mut self, stream: IpcSocket<Server>) {
         let bytes = match stream.recv() {
             Ok(bytes) => bytes,
+            Err(e) if e.is_closed() => {
+                debug!("client disconnected without sending a request");
+                return;
+            }
+            Err(e) if e.is_bad_message() => {
+                warn!("rejecting request: {e}");
+                if let Err(e) = Answer::Err(e.to_string()).send(&stream) {
+                    error!("error sending answer to client: {e}");
+                }
+                return;
+            }
             Err(e) => {
                 error!("FATAL: cannot read socket: {e}. Exiting...");
                 exit_daemon();
                 return;
             }
         };
-        let request = RequestRecv::receive(bytes);
+        let request = match RequestRecv::receive(bytes) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("rejecting malformed request: {e}");
+                if let Err(e) = Answer::Err(format!("malformed request: {e}")).send(&stream) {
+                    error!("error sending answer to client: {e}");
+                }
+                return;
+            }
+        };
         let answer = match request {
             RequestRecv::Clear(clear) => {
-                let wallpapers = self.find_wallpapers_by_names(&clear.outputs);
+                let wallpapers = match clear.match_by {
+                    OutputMatch::Name => self.find_wallpapers_by_names(&clear.outputs),
+                    OutputMatch::Description => {
+                        self.find_wallpapers_by_descriptions(&clear.outputs)
+                    }
+                };
+                let mut failures = self.unknown_outputs(&clear.outputs, clear.match_by);
+                let applied = !wallpapers.is_empty();
                 self.stop_animations(&wallpapers);
-                for wallpaper in &wallpapers {
-                    let mut wallpaper = wallpaper.borrow_mut();
-                    wallpaper.set_img_info(common::ipc::BgImg::Color(clear.color));
-                    wallpaper.clear(&mut self.objman, self.pixel_format, clear.color);
+                if let Some(transition) = clear.transition {
+                    let transition = self.effective_transition(transition);
+                    failures.extend(self.clear_with_transition(
+                        wallpapers,
+                        clear.color,
+                        &transition,
+                    ));
+                } else {
+                    for wallpaper in &wallpapers {
+                        wallpaper
+                            .borrow_mut()
+                            .set_img_info(common::ipc::BgImg::Color(clear.color));
+                    }
+                    self.clear_instantly(&wallpapers, clear.color);
                 }
-                crate::wallpaper::attach_buffers_and_damage_surfaces(&mut self.objman, &wallpapers);
-                crate::wallpaper::commit_wallpapers(&wallpapers);
-                Answer::Ok
+                answer_with(applied, failures)
             }
             RequestRecv::Ping => Answer::Ping(self.wallpapers.iter().all(|w| {
                 w.borrow()
@@ -135,6 +323,7 @@ impl Daemon {
                     .load(std::sync::atomic::Ordering::Acquire)
             })),
             RequestRecv::Kill => {
+                self.slideshows.clear();
                 exit_daemon();
                 Answer::Ok
             }
@@ -145,29 +334,135 @@ impl Daemon {
                 mut outputs,
                 mut animations,
             }) => {
+                let transition = self.effective_transition(transition);
+                let mut applied = false;
+                let mut failures = Vec::new();
                 while !imgs.is_empty() && !outputs.is_empty() {
                     let names = outputs.pop().unwrap();
-                    let img = imgs.pop().unwrap();
-                    let animation = if let Some(ref mut animations) = animations {
-                        animations.pop()
-                    } else {
-                        None
-                    };
+                    let mut img = imgs.pop().unwrap();
+                    img.transition = img.transition.map(|t| self.effective_transition(t));
+                    let animation = animations.pop().flatten();
+                    failures.extend(self.unknown_outputs(&names, OutputMatch::Name));
                     let wallpapers = self.find_wallpapers_by_names(&names);
-                    self.stop_animations(&wallpapers);
-                    if let Some(mut transition) = TransitionAnimator::new(
+                    if wallpapers.is_empty() {
+                        continue;
+                    }
+                    if let Some(animation) = &animation {
+                        if self.change_playback_speed(&wallpapers, img.path.str(), animation.speed)
+                        {
+                            applied = true;
+                            continue;
+                        }
+                    }
+                    let path = img.path.str().to_string();
+                    let transition = match TransitionAnimator::new(
                         wallpapers,
                         &transition,
                         self.pixel_format,
                         img,
                         animation,
                     ) {
-                        transition.frame(&mut self.objman, self.pixel_format);
-                        self.transition_animators.push(transition);
+                        Ok(transition) => transition,
+                        Err(e) => {
+                            // a bad image leaves whatever was there alone
+                            let e = format!("failed to show {path} on {}: {e}", describe(&names));
+                            error!("{e}");
+                            failures.push(e);
+                            continue;
+                        }
+                    };
+                    applied = true;
+                    // restarting a transition before it even showed its first frame only makes it
+                    // stutter, so we wait for that, keeping nothing but the latest image
+                    if self.awaiting_first_frame(&transition.wallpapers) {
+                        self.stop_slideshows(&transition.wallpapers);
+                        let skipped = self.take_pending_imgs(&transition.wallpapers);
+                        self.pending_imgs.push(PendingImg {
+                            transition,
+                            skipped,
+                        });
+                    } else {
+                        self.start_transition(transition);
                     }
                 }
                 self.poll_time = PollTime::Instant;
-                Answer::Ok
+                answer_with(applied, failures)
+            }
+            RequestRecv::Slideshow(slideshow) => {
+                let failures: Vec<_> = slideshow
+                    .image
+                    .outputs
+                    .iter()
+                    .flat_map(|names| self.unknown_outputs(names, OutputMatch::Name))
+                    .collect();
+                let applied = self.start_slideshows(slideshow);
+                answer_with(applied, failures)
+            }
+            RequestRecv::Screenshot(ScreenshotReq { output }) => Answer::Screenshot(
+                self.wallpapers
+                    .iter()
+                    .find(|w| w.borrow().has_name(output.str()))
+                    .and_then(|w| w.borrow_mut().screenshot(self.pixel_format)),
+            ),
+            RequestRecv::SetLayer(LayerReq { layer, outputs }) => {
+                let failures = self.unknown_outputs(&outputs, OutputMatch::Name);
+                let applied = !self.find_wallpapers_by_names(&outputs).is_empty();
+                self.set_layer(layer, &outputs);
+                answer_with(applied, failures)
+            }
+            RequestRecv::WaitIdle(WaitIdleReq { outputs }) => {
+                let wallpapers = self.find_wallpapers_by_names(&outputs);
+                if wallpapers.is_empty() && !outputs.is_empty() {
+                    answer_with(false, self.unknown_outputs(&outputs, OutputMatch::Name))
+                } else if self.in_transition(&wallpapers) {
+                    // we answer once the transitions are over, in `answer_idle_waiters`
+                    self.idle_waiters.push(IdleWaiter { wallpapers, stream });
+                    return;
+                } else {
+                    Answer::Ok
+                }
+            }
+            RequestRecv::Subscribe => {
+                if let Err(e) = Answer::Ok.send(&stream) {
+                    error!("error sending answer to client: {e}");
+                    return;
+                }
+                // a subscriber that stops reading must never block us
+                if let Err(e) = rustix::io::ioctl_fionbio(stream.as_fd(), true) {
+                    error!("failed to make subscriber socket non-blocking: {e}");
+                    return;
+                }
+                self.subscribers.push(stream);
+                return;
+            }
+            RequestRecv::Pause(PauseReq { outputs }) => {
+                let wallpapers = self.find_wallpapers_by_names(&outputs);
+                if wallpapers.is_empty() && !outputs.is_empty() {
+                    answer_with(false, self.unknown_outputs(&outputs, OutputMatch::Name))
+                } else {
+                    self.set_paused(&wallpapers, true);
+                    Answer::Paused(true)
+                }
+            }
+            RequestRecv::Resume(PauseReq { outputs }) => {
+                let wallpapers = self.find_wallpapers_by_names(&outputs);
+                if wallpapers.is_empty() && !outputs.is_empty() {
+                    answer_with(false, self.unknown_outputs(&outputs, OutputMatch::Name))
+                } else {
+                    self.set_paused(&wallpapers, false);
+                    Answer::Paused(false)
+                }
+            }
+            RequestRecv::TogglePause(PauseReq { outputs }) => {
+                let wallpapers = self.find_wallpapers_by_names(&outputs);
+                if wallpapers.is_empty() && !outputs.is_empty() {
+                    answer_with(false, self.unknown_outputs(&outputs, OutputMatch::Name))
+                } else {
+                    // anything still running gets paused, so that all of them end up in the same state
+                    let pause = !wallpapers.iter().all(|w| w.borrow().is_paused());
+                    self.set_paused(&wallpapers, pause);
+                    Answer::Paused(pause)
+                }
             }
         };
         if let Err(e) = answer.send(&stream) {
@@ -175,10 +470,356 @@ impl Daemon {
         }
     }
 
+    fn clear_instantly(&mut self, wallpapers: &[Rc<RefCell<Wallpaper>>], color: [u8; 3]) {
+        for wallpaper in wallpapers {
+            wallpaper
+                .borrow_mut()
+                .clear(&mut self.objman, self.pixel_format, color);
+        }
+        crate::wallpaper::attach_buffers_and_damage_surfaces(&mut self.objman, wallpapers);
+        crate::wallpaper::commit_wallpapers(wallpapers);
+    }
+
+    /// Called once `wallpaper` changed size from `old_dim`. Images get processed again for the new
+    /// size by the cache loader `Wallpaper::commit_surface_changes` spawns, but colors we can redraw
+    /// right away
+    fn surface_resized(&mut self, wallpaper: Rc<RefCell<Wallpaper>>, old_dim: (u32, u32)) {
+        self.stop_animations(std::slice::from_ref(&wallpaper));
+        let img = wallpaper.borrow().get_img_info();
+        match img {
+            common::ipc::BgImg::Color(color) => self.clear_instantly(&[wallpaper], color),
+            // unless some other output still has the old size, the animation cached for it is
+            // dead weight now
+            common::ipc::BgImg::Img(path) => {
+                if !self
+                    .wallpapers
+                    .iter()
+                    .any(|w| w.borrow().get_dimensions() == old_dim)
+                {
+                    if let Err(e) = common::cache::prune_stale(Path::new(&path), old_dim) {
+                        warn!("failed to prune stale cache entries for {path}: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Transitions `wallpapers` into a solid `color`, drawing an image of it for each size they
+    /// come in. Outputs we fail to start a transition on are cleared instantly instead, and the
+    /// reason is returned, so that the client hears about it
+    fn clear_with_transition(
+        &mut self,
+        mut wallpapers: Vec<Rc<RefCell<Wallpaper>>>,
+        color: [u8; 3],
+        transition: &Transition,
+    ) -> Vec<String> {
+        let mut failures = Vec::new();
+        let channels = self.pixel_format.channels() as usize;
+        let mut pixel = [u8::MAX; 4];
+        pixel[..3].copy_from_slice(&color);
+        while let Some(wallpaper) = wallpapers.first() {
+            let dim = wallpaper.borrow().get_dimensions();
+            let (same_dim, rest): (Vec<_>, _) = wallpapers
+                .into_iter()
+                .partition(|w| w.borrow().get_dimensions() == dim);
+            wallpapers = rest;
+
+            let img = pixel[..channels].repeat(dim.0 as usize * dim.1 as usize);
+            match TransitionAnimator::new_shared(
+                same_dim.clone(),
+                transition,
+                self.pixel_format,
+                "",
+                dim,
+                Rc::new(MmappedBytes::from_bytes(&img)),
+                None,
+            ) {
+                Ok(mut animator) => {
+                    // we still want to report the color, not the image we made out of it
+                    for w in &animator.wallpapers {
+                        w.borrow_mut()
+                            .set_img_info(common::ipc::BgImg::Color(color));
+                    }
+                    animator.frame(&mut self.objman, self.pixel_format);
+                    self.transition_animators.push(animator);
+                }
+                Err(e) => {
+                    let e = format!("failed to clear outputs with a transition: {e}");
+                    error!("{e}");
+                    failures.push(e);
+                    for w in &same_dim {
+                        w.borrow_mut()
+                            .set_img_info(common::ipc::BgImg::Color(color));
+                    }
+                    self.clear_instantly(&same_dim, color);
+                }
+            }
+        }
+        self.poll_time = PollTime::Instant;
+        failures
+    }
+
+    /// With `--no-transitions`, every transition becomes `TransitionType::None`. Animated images
+    /// still play, only the change into them is instant
+    fn effective_transition(&self, transition: Transition) -> Transition {
+        if self.no_transitions {
+            Transition {
+                transition_type: TransitionType::None,
+                ..transition
+            }
+        } else {
+            transition
+        }
+    }
+
+    /// If `wallpapers` are already playing the animated image at `path`, at a different speed,
+    /// changes the speed without restarting the animation. Returns whether it did
+    fn change_playback_speed(
+        &mut self,
+        wallpapers: &[Rc<RefCell<Wallpaper>>],
+        path: &str,
+        speed: f32,
+    ) -> bool {
+        if wallpapers.is_empty() || !wallpapers.iter().all(|w| w.borrow().is_displaying(path)) {
+            return false;
+        }
+        let animator = self.image_animators.iter_mut().find(|animator| {
+            animator.wallpapers.len() == wallpapers.len()
+                && animator
+                    .wallpapers
+                    .iter()
+                    .all(|w1| wallpapers.iter().any(|w2| Rc::ptr_eq(w1, w2)))
+        });
+        match animator {
+            Some(animator) if animator.speed() != speed => {
+                info!("changing playback speed of {path} to {speed}");
+                animator.set_speed(speed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether any of `wallpapers` is in the middle of a transition, or about to start one
+    fn in_transition(&self, wallpapers: &[Rc<RefCell<Wallpaper>>]) -> bool {
+        let any_of = |others: &[Rc<RefCell<Wallpaper>>]| {
+            others
+                .iter()
+                .any(|w1| wallpapers.iter().any(|w2| Rc::ptr_eq(w1, w2)))
+        };
+        self.transition_animators
+            .iter()
+            .any(|animator| any_of(&animator.wallpapers))
+            || self
+                .pending_imgs
+                .iter()
+                .any(|pending| any_of(&pending.transition.wallpapers))
+    }
+
+    /// Whether any of `wallpapers` is in a transition that has yet to show its first frame
+    fn awaiting_first_frame(&self, wallpapers: &[Rc<RefCell<Wallpaper>>]) -> bool {
+        self.transition_animators.iter().any(|animator| {
+            // outputs that are turned off won't show it anytime soon
+            !animator.is_shown()
+                && !wallpaper::all_powered_off(&animator.wallpapers)
+                && animator
+                    .wallpapers
+                    .iter()
+                    .any(|w1| wallpapers.iter().any(|w2| Rc::ptr_eq(w1, w2)))
+        })
+    }
+
+    fn start_transition(&mut self, mut transition: TransitionAnimator) {
+        self.stop_animations(&transition.wallpapers);
+        transition.frame(&mut self.objman, self.pixel_format);
+        self.transition_animators.push(transition);
+    }
+
+    /// Starts the pending images whose outputs already showed their transition's first frame
+    fn start_pending_imgs(&mut self) {
+        let (waiting, ready) = std::mem::take(&mut self.pending_imgs)
+            .into_iter()
+            .partition::<Vec<_>, _>(|pending| {
+                self.awaiting_first_frame(&pending.transition.wallpapers)
+            });
+        self.pending_imgs = waiting;
+        if !ready.is_empty() {
+            self.set_poll_time(PollTime::Instant);
+        }
+        for pending in ready {
+            if pending.skipped > 0 {
+                debug!(
+                    "skipped {} image(s) sent before the previous transition could be shown",
+                    pending.skipped,
+                );
+            }
+            self.start_transition(pending.transition);
+        }
+    }
+
+    /// Removes `wallpapers` from the pending images, returning how many images will now never be
+    /// shown
+    fn take_pending_imgs(&mut self, wallpapers: &[Rc<RefCell<Wallpaper>>]) -> usize {
+        for pending in self.pending_imgs.iter_mut() {
+            pending
+                .transition
+                .wallpapers
+                .retain(|w1| !wallpapers.iter().any(|w2| Rc::ptr_eq(w1, w2)));
+        }
+        let mut skipped = 0;
+        self.pending_imgs.retain(|pending| {
+            if pending.transition.wallpapers.is_empty() {
+                skipped += pending.skipped + 1;
+            }
+            !pending.transition.wallpapers.is_empty()
+        });
+        skipped
+    }
+
+    /// Answers every client waiting on wallpapers that are no longer in a transition
+    fn answer_idle_waiters(&mut self) {
+        let mut i = 0;
+        while i < self.idle_waiters.len() {
+            if self.in_transition(&self.idle_waiters[i].wallpapers) {
+                i += 1;
+                continue;
+            }
+            let waiter = self.idle_waiters.swap_remove(i);
+            if let Err(e) = Answer::Ok.send(&waiter.stream) {
+                error!("error sending answer to client: {e}");
+            }
+        }
+    }
+
+    /// Sends every event since the last call to our subscribers, dropping the ones that went away
+    /// or can't keep up
+    fn notify_subscribers(&mut self) {
+        for wallpaper in &self.wallpapers {
+            let in_transition = self.in_transition(std::slice::from_ref(wallpaper));
+            wallpaper
+                .borrow_mut()
+                .take_events(in_transition, &mut self.events);
+        }
+        for event in self.events.drain(..) {
+            self.subscribers.retain(|subscriber| {
+                match Answer::Event(event.clone()).send(subscriber) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        info!("dropping subscriber: {e}");
+                        false
+                    }
+                }
+            });
+        }
+    }
+
+    /// Drops the subscribers whose sockets `poll` flagged, one flag per subscriber. Subscribers
+    /// never write to us, so this only happens when they hang up
+    fn drop_hung_up_subscribers(&mut self, flagged: &[bool]) {
+        let mut flagged = flagged.iter();
+        self.subscribers.retain(|_| {
+            let hung_up = flagged.next().copied().unwrap_or(false);
+            if hung_up {
+                info!("dropping subscriber: it hung up");
+            }
+            !hung_up
+        });
+    }
+
     fn wallpapers_info(&self) -> Box<[BgInfo]> {
         self.wallpapers
             .iter()
-            .map(|wallpaper| wallpaper.borrow().get_bg_info(self.pixel_format))
+            .map(|wallpaper| {
+                let transition = self
+                    .transition_animators
+                    .iter()
+                    .find(|animator| animator.wallpapers.iter().any(|w| Rc::ptr_eq(w, wallpaper)))
+                    .and_then(|animator| animator.progress());
+                let animated = self
+                    .image_animators
+                    .iter()
+                    .any(|animator| animator.wallpapers.iter().any(|w| Rc::ptr_eq(w, wallpaper)));
+                wallpaper
+                    .borrow()
+                    .get_bg_info(self.pixel_format, animated, transition)
+            })
+            .collect()
+    }
+
+    /// Stops (or restarts) the animated images and slideshows of `wallpapers`. Transitions that
+    /// already started still finish
+    fn set_paused(&mut self, wallpapers: &[Rc<RefCell<Wallpaper>>], paused: bool) {
+        let mut changed = 0;
+        for wallpaper in wallpapers {
+            changed += wallpaper.borrow_mut().set_paused(paused) as usize;
+        }
+        if changed == 0 {
+            return;
+        }
+        if paused {
+            info!("pausing animations on {changed} output(s)");
+        } else {
+            info!("resuming animations on {changed} output(s)");
+        }
+        for slideshow in self.slideshows.iter_mut() {
+            slideshow.sync_pause();
+        }
+        self.poll_time = PollTime::Instant;
+    }
+
+    /// Moves the wallpapers of `outputs` (or all of them, if it's empty) to `layer`
+    fn set_layer(&mut self, layer: Layer, outputs: &[MmappedStr]) {
+        // outputs connected from now on should also go in the new layer
+        if outputs.is_empty() {
+            self.layer = layer;
+        }
+
+        let wallpapers: Vec<_> = self
+            .find_wallpapers_by_names(outputs)
+            .into_iter()
+            .filter(|w| w.borrow_mut().set_layer(layer))
+            .collect();
+
+        // the layer change only applies on the next commit, which we use to attach the frame we last
+        // drew again, so the surface shows up in its new layer with the same image. Wallpapers we
+        // haven't drawn anything to yet will only get a buffer when they get an image
+        let drawn: Vec<_> = wallpapers
+            .iter()
+            .filter(|w| w.borrow().has_drawn())
+            .cloned()
+            .collect();
+        for wallpaper in &drawn {
+            // this just takes a free buffer, copying the last frame onto it
+            wallpaper
+                .borrow_mut()
+                .canvas_change(&mut self.objman, self.pixel_format, |_| ());
+        }
+        crate::wallpaper::attach_buffers_and_damage_surfaces(&mut self.objman, &drawn);
+        crate::wallpaper::commit_wallpapers(&wallpapers);
+    }
+
+    /// An error for each of `names` that matches none of our outputs
+    fn unknown_outputs(&self, names: &[MmappedStr], match_by: OutputMatch) -> Vec<String> {
+        names
+            .iter()
+            .filter(|name| {
+                let name = std::slice::from_ref(*name);
+                match match_by {
+                    OutputMatch::Name => self.find_wallpapers_by_names(name).is_empty(),
+                    OutputMatch::Description => {
+                        self.find_wallpapers_by_descriptions(name).is_empty()
+                    }
+                }
+            })
+            .map(|name| match (match_by, name.str().strip_prefix("desc:")) {
+                (OutputMatch::Name, None) => format!("there is no output named {}", name.str()),
+                (OutputMatch::Name, Some(desc)) => {
+                    format!("no output has a description containing '{desc}'")
+                }
+                (OutputMatch::Description, _) => {
+                    format!("no output has a description containing '{}'", name.str())
+                }
+            })
             .collect()
     }
 
@@ -186,7 +827,11 @@ impl Daemon {
         self.wallpapers
             .iter()
             .filter_map(|wallpaper| {
-                if names.is_empty() || names.iter().any(|n| wallpaper.borrow().has_name(n.str())) {
+                let matches = |name: &MmappedStr| match name.str().strip_prefix("desc:") {
+                    Some(desc) => wallpaper.borrow().has_description(desc),
+                    None => wallpaper.borrow().has_name(name.str()),
+                };
+                if names.is_empty() || names.iter().any(matches) {
                     return Some(Rc::clone(wallpaper));
                 }
                 None
@@ -194,12 +839,136 @@ impl Daemon {
             .collect()
     }
 
+    /// Like `find_wallpapers_by_names`, but matching `descs` against the outputs' descriptions.
+    /// Identical monitors usually share a description, so all of them are returned
+    fn find_wallpapers_by_descriptions(&self, descs: &[MmappedStr]) -> Vec<Rc<RefCell<Wallpaper>>> {
+        self.wallpapers
+            .iter()
+            .filter(|wallpaper| {
+                descs.is_empty()
+                    || descs
+                        .iter()
+                        .any(|d| wallpaper.borrow().has_description(d.str()))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns whether any of the requested outputs got a slideshow
+    fn start_slideshows(&mut self, slideshow: SlideshowReq) -> bool {
+        let SlideshowReq {
+            image:
+                ImageReq {
+                    transition,
+                    imgs,
+                    outputs,
+                    animations,
+                },
+            interval,
+            order,
+        } = slideshow;
+
+        // the client sends every image once for every group of outputs with the same dimensions,
+        // so we make one slideshow per group
+        let mut groups: Vec<(Box<[MmappedStr]>, Slideshow)> = Vec::new();
+        for ((img, names), animation) in imgs.into_iter().zip(outputs).zip(animations) {
+            let group = groups.iter_mut().find(|(group_names, _)| {
+                group_names.len() == names.len()
+                    && group_names
+                        .iter()
+                        .zip(names.iter())
+                        .all(|(n1, n2)| n1.str() == n2.str())
+            });
+            match group {
+                Some((_, slideshow)) => slideshow.push(img, animation),
+                None => {
+                    let wallpapers = self.find_wallpapers_by_names(&names);
+                    let transition = img.transition.clone().unwrap_or(transition.clone());
+                    let transition = self.effective_transition(transition);
+                    let mut slideshow = Slideshow::new(wallpapers, transition, interval, order);
+                    slideshow.push(img, animation);
+                    groups.push((names, slideshow));
+                }
+            }
+        }
+
+        let mut started = false;
+        for (_, slideshow) in groups {
+            if slideshow.wallpapers.is_empty() {
+                continue;
+            }
+            self.stop_animations(&slideshow.wallpapers);
+            self.slideshows.push(slideshow);
+            started = true;
+        }
+        self.update_slideshows();
+        // slideshows on paused outputs still show their first image, and only then wait
+        for slideshow in self.slideshows.iter_mut() {
+            slideshow.sync_pause();
+        }
+        started
+    }
+
+    /// Advances every slideshow whose interval has elapsed, and makes sure we wake up in time for
+    /// the next one
+    fn update_slideshows(&mut self) {
+        // the timeout is recalculated every time, since slideshows might have been stopped
+        if let PollTime::Timeout(_) = self.poll_time {
+            self.poll_time = PollTime::Never;
+        }
+
+        for i in 0..self.slideshows.len() {
+            if self.slideshows[i].is_paused() {
+                continue;
+            }
+            if self.slideshows[i].time_to_next().is_zero() {
+                let wallpapers = self.slideshows[i].wallpapers.clone();
+                self.stop_animators(&wallpapers);
+                if let Some(mut transition) = self.slideshows[i].next(self.pixel_format) {
+                    transition.frame(&mut self.objman, self.pixel_format);
+                    self.transition_animators.push(transition);
+                    self.set_poll_time(PollTime::Instant);
+                }
+            }
+
+            let time = self.slideshows[i].time_to_next();
+            let millis = time.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32;
+            self.set_poll_time(PollTime::Timeout(millis));
+        }
+    }
+
+    /// Sets the poll time, unless we are already going to wake up sooner than the new value
+    fn set_poll_time(&mut self, new: PollTime) {
+        self.poll_time = match (self.poll_time, new) {
+            (PollTime::Timeout(old), PollTime::Timeout(new)) => PollTime::Timeout(old.min(new)),
+            (PollTime::Never | PollTime::Timeout(_), new) => new,
+            (PollTime::Short, PollTime::Instant) => PollTime::Instant,
+            (old, _) => old,
+        };
+    }
+
+    /// Draws and commits the last frame of every running transition, so that exiting never leaves
+    /// one frozen halfway through
+    fn finish_transitions(&mut self) {
+        for mut animator in self.transition_animators.drain(..) {
+            animator.finish(&mut self.objman, self.pixel_format);
+            wallpaper::attach_buffers_and_damage_surfaces(&mut self.objman, &animator.wallpapers);
+            wallpaper::commit_wallpapers(&animator.wallpapers);
+        }
+    }
+
     fn draw(&mut self) {
         self.poll_time = PollTime::Never;
 
         let mut i = 0;
         while i < self.transition_animators.len() {
             let animator = &mut self.transition_animators[i];
+            // there is no point in drawing to outputs that are turned off. We don't even set a
+            // poll time: we will draw again once one of them is turned back on
+            if wallpaper::all_powered_off(&animator.wallpapers) {
+                i += 1;
+                continue;
+            }
             if animator
                 .wallpapers
                 .iter()
@@ -232,9 +1001,16 @@ impl Daemon {
             }
             i += 1;
         }
+        self.start_pending_imgs();
+        self.answer_idle_waiters();
 
         self.image_animators.retain(|a| !a.wallpapers.is_empty());
         for animator in &mut self.image_animators {
+            if wallpaper::all_powered_off(&animator.wallpapers)
+                || wallpaper::any_paused(&animator.wallpapers)
+            {
+                continue;
+            }
             if animator
                 .wallpapers
                 .iter()
@@ -256,12 +1032,34 @@ impl Daemon {
                 );
                 wallpaper::commit_wallpapers(&animator.wallpapers);
                 animator.updt_time();
-                animator.frame(&mut self.objman, self.pixel_format);
+                if animator.frame(&mut self.objman, self.pixel_format) {
+                    // the animation left the buffers holding different frames, so we sync them up
+                    // for whatever draws next. The animator itself is dropped in the next `draw`
+                    for wallpaper in animator.wallpapers.drain(..) {
+                        wallpaper.borrow_mut().sync_buffers(self.pixel_format);
+                    }
+                }
             }
         }
     }
 
     fn stop_animations(&mut self, wallpapers: &[Rc<RefCell<Wallpaper>>]) {
+        self.stop_animators(wallpapers);
+        self.stop_slideshows(wallpapers);
+    }
+
+    fn stop_slideshows(&mut self, wallpapers: &[Rc<RefCell<Wallpaper>>]) {
+        for slideshow in self.slideshows.iter_mut() {
+            slideshow
+                .wallpapers
+                .retain(|w1| !wallpapers.iter().any(|w2| w1.borrow().eq(&w2.borrow())));
+        }
+
+        self.slideshows.retain(|s| !s.wallpapers.is_empty());
+    }
+
+    fn stop_animators(&mut self, wallpapers: &[Rc<RefCell<Wallpaper>>]) {
+        self.take_pending_imgs(wallpapers);
         for transition in self.transition_animators.iter_mut() {
             transition
                 .wallpapers
@@ -269,15 +1067,22 @@ impl Daemon {
         }
 
         for animator in self.image_animators.iter_mut() {
-            animator
-                .wallpapers
-                .retain(|w1| !wallpapers.iter().any(|w2| w1.borrow().eq(&w2.borrow())));
+            animator.wallpapers.retain(|w1| {
+                let stop = wallpapers.iter().any(|w2| w1.borrow().eq(&w2.borrow()));
+                if stop {
+                    // the animation might have left its buffers holding different frames, so we
+                    // sync them up before something else starts drawing from them
+                    w1.borrow_mut().sync_buffers(self.pixel_format);
+                }
+                !stop
+            });
         }
 
         self.transition_animators
             .retain(|t| !t.wallpapers.is_empty());
 
         self.image_animators.retain(|a| !a.wallpapers.is_empty());
+        self.answer_idle_waiters();
     }
 }
 
@@ -301,19 +1106,27 @@ impl wayland::interfaces::wl_registry::EvHandler for Daemon {
             if version < 4 {
                 error!("your compositor must support at least version 4 of wl_output");
             } else {
-                self.new_output(name);
+                self.add_output(name);
             }
         }
     }
 
     fn global_remove(&mut self, name: u32) {
         if let Some(i) = self
+            .pending_outputs
+            .iter()
+            .position(|p| p.output_name() == name)
+        {
+            let pending = self.pending_outputs.swap_remove(i);
+            self.removed_outputs
+                .push((pending.output(), Instant::now()));
+            pending.release();
+        } else if let Some(i) = self
             .wallpapers
             .iter()
             .position(|w| w.borrow().has_output_name(name))
         {
-            let w = self.wallpapers.remove(i);
-            self.stop_animations(&[w]);
+            self.remove_wallpaper(i);
         }
     }
 }
@@ -330,8 +1143,8 @@ impl wayland::interfaces::wl_output::EvHandler for Daemon {
     fn geometry(
         &mut self,
         sender_id: ObjectId,
-        _x: i32,
-        _y: i32,
+        x: i32,
+        y: i32,
         _physical_width: i32,
         _physical_height: i32,
         _subpixel: i32,
@@ -339,73 +1152,78 @@ impl wayland::interfaces::wl_output::EvHandler for Daemon {
         _model: &str,
         transform: i32,
     ) {
-        for wallpaper in self.wallpapers.iter() {
+        let valid_transform =
+            transform as u32 <= wayland::interfaces::wl_output::transform::FLIPPED_270;
+        if !valid_transform {
+            error!("received invalid transform value from compositor: {transform}")
+        }
+        if let Some(pending) = self.pending_output(sender_id) {
+            pending.push(OutputEvent::Position(x, y));
+            if valid_transform {
+                pending.push(OutputEvent::Transform(transform as u32));
+            }
+        } else if let Some(wallpaper) = self.output_wallpaper(sender_id, "geometry") {
             let mut wallpaper = wallpaper.borrow_mut();
-            if wallpaper.has_output(sender_id) {
-                if transform as u32 > wayland::interfaces::wl_output::transform::FLIPPED_270 {
-                    error!("received invalid transform value from compositor: {transform}")
-                } else {
-                    wallpaper.set_transform(transform as u32);
-                }
-                break;
+            wallpaper.set_position(x, y);
+            if valid_transform {
+                wallpaper.set_transform(transform as u32);
             }
         }
     }
 
-    fn mode(&mut self, sender_id: ObjectId, _flags: u32, width: i32, height: i32, _refresh: i32) {
-        for wallpaper in self.wallpapers.iter() {
+    fn mode(&mut self, sender_id: ObjectId, _flags: u32, width: i32, height: i32, refresh: i32) {
+        if let Some(pending) = self.pending_output(sender_id) {
+            pending.push(OutputEvent::Mode(width, height, refresh));
+        } else if let Some(wallpaper) = self.output_wallpaper(sender_id, "mode") {
             let mut wallpaper = wallpaper.borrow_mut();
-            if wallpaper.has_output(sender_id) {
-                wallpaper.set_dimensions(width, height);
-                break;
-            }
+            wallpaper.set_dimensions(width, height);
+            wallpaper.set_refresh(refresh);
         }
     }
 
     fn done(&mut self, sender_id: ObjectId) {
-        for wallpaper in self.wallpapers.iter() {
-            if wallpaper.borrow().has_output(sender_id) {
-                if wallpaper
-                    .borrow_mut()
-                    .commit_surface_changes(&mut self.objman, self.use_cache)
-                {
-                    self.stop_animations(&[wallpaper.clone()]);
-                }
-                break;
+        if let Some(i) = self
+            .pending_outputs
+            .iter()
+            .position(|p| p.output() == sender_id)
+        {
+            self.resolve_pending_output(i);
+        } else if let Some(wallpaper) = self.output_wallpaper(sender_id, "done") {
+            let old_dim = wallpaper.borrow().get_dimensions();
+            if wallpaper
+                .borrow_mut()
+                .commit_surface_changes(&mut self.objman, self.use_cache)
+            {
+                self.surface_resized(wallpaper, old_dim);
             }
         }
     }
 
     fn scale(&mut self, sender_id: ObjectId, factor: i32) {
-        for wallpaper in self.wallpapers.iter() {
-            let mut wallpaper = wallpaper.borrow_mut();
-            if wallpaper.has_output(sender_id) {
-                match NonZeroI32::new(factor) {
-                    Some(factor) => wallpaper.set_scale(Scale::Whole(factor)),
-                    None => error!("received scale factor of 0 from compositor"),
-                }
-                break;
-            }
+        let Some(factor) = NonZeroI32::new(factor) else {
+            error!("received scale factor of 0 from compositor");
+            return;
+        };
+        if let Some(pending) = self.pending_output(sender_id) {
+            pending.push(OutputEvent::Scale(Scale::Whole(factor)));
+        } else if let Some(wallpaper) = self.output_wallpaper(sender_id, "scale") {
+            wallpaper.borrow_mut().set_scale(Scale::Whole(factor));
         }
     }
 
     fn name(&mut self, sender_id: ObjectId, name: &str) {
-        for wallpaper in self.wallpapers.iter() {
-            let mut wallpaper = wallpaper.borrow_mut();
-            if wallpaper.has_output(sender_id) {
-                wallpaper.set_name(name.to_string());
-                break;
-            }
+        if let Some(pending) = self.pending_output(sender_id) {
+            pending.push(OutputEvent::Name(name.to_string()));
+        } else if let Some(wallpaper) = self.output_wallpaper(sender_id, "name") {
+            wallpaper.borrow_mut().set_name(name.to_string());
         }
     }
 
     fn description(&mut self, sender_id: ObjectId, description: &str) {
-        for wallpaper in self.wallpapers.iter() {
-            let mut wallpaper = wallpaper.borrow_mut();
-            if wallpaper.has_output(sender_id) {
-                wallpaper.set_desc(description.to_string());
-                break;
-            }
+        if let Some(pending) = self.pending_output(sender_id) {
+            pending.push(OutputEvent::Description(description.to_string()));
+        } else if let Some(wallpaper) = self.output_wallpaper(sender_id, "description") {
+            wallpaper.borrow_mut().set_desc(description.to_string());
         }
     }
 }
@@ -432,21 +1250,36 @@ impl wayland::interfaces::wl_surface::EvHandler for Daemon {
         }
     }
 
-    fn preferred_buffer_transform(&mut self, _sender_id: ObjectId, _transform: u32) {
-        warn!("Received PreferredBufferTransform. We currently ignore those")
+    fn preferred_buffer_transform(&mut self, sender_id: ObjectId, transform: u32) {
+        if transform > wayland::interfaces::wl_output::transform::FLIPPED_270 {
+            error!("received invalid buffer transform value from compositor: {transform}");
+            return;
+        }
+        for wallpaper in self.wallpapers.iter() {
+            let mut wallpaper = wallpaper.borrow_mut();
+            if wallpaper.has_surface(sender_id) {
+                wallpaper.set_preferred_transform(transform, self.pixel_format);
+                break;
+            }
+        }
     }
 }
 
 impl wayland::interfaces::wl_buffer::EvHandler for Daemon {
     fn release(&mut self, sender_id: ObjectId) {
-        for wallpaper in self.wallpapers.iter() {
+        let found = self.wallpapers.iter().any(|wallpaper| {
             let strong_count = Rc::strong_count(wallpaper);
-            if wallpaper
+            wallpaper
                 .borrow_mut()
                 .try_set_buffer_release_flag(sender_id, strong_count)
-            {
-                break;
-            }
+        });
+        if !found {
+            // the buffer belonged to a wallpaper that was already destroyed, or to a pool that
+            // was resized
+            debug!(
+                "dropping wl_buffer.release for unknown buffer {}",
+                sender_id.get()
+            );
         }
     }
 }
@@ -480,8 +1313,51 @@ impl wayland::interfaces::zwlr_layer_surface_v1::EvHandler for Daemon {
             .iter()
             .position(|w| w.borrow().has_layer_surface(sender_id))
         {
-            let w = self.wallpapers.remove(i);
-            self.stop_animations(&[w]);
+            self.remove_wallpaper(i);
+        }
+    }
+}
+
+impl wayland::interfaces::zwlr_output_power_v1::EvHandler for Daemon {
+    fn mode(&mut self, sender_id: ObjectId, mode: u32) {
+        let powered_on = mode != wayland::interfaces::zwlr_output_power_v1::mode::OFF;
+        if let Some(wallpaper) = self
+            .wallpapers
+            .iter()
+            .find(|w| w.borrow().has_output_power(sender_id))
+            .cloned()
+        {
+            let was_powered_on = wallpaper.borrow().is_powered_on();
+            wallpaper.borrow_mut().set_powered_on(powered_on);
+            if powered_on && !was_powered_on {
+                debug!("output {} turned on", sender_id.get());
+                // we might have stopped getting frame callbacks while the output was off, so we
+                // don't wait for one before committing a fresh frame
+                wallpaper.borrow_mut().frame_callback_completed();
+                self.draw();
+            } else if !powered_on && was_powered_on {
+                debug!("output {} turned off", sender_id.get());
+            }
+        }
+    }
+
+    fn failed(&mut self, sender_id: ObjectId) {
+        if let Some(wallpaper) = self
+            .wallpapers
+            .iter()
+            .find(|w| w.borrow().has_output_power(sender_id))
+            .cloned()
+        {
+            warn!(
+                "cannot know whether output {} is turned on. We will assume it always is",
+                sender_id.get()
+            );
+            let was_powered_on = wallpaper.borrow().is_powered_on();
+            wallpaper.borrow_mut().output_power_failed();
+            if !was_powered_on {
+                wallpaper.borrow_mut().frame_callback_completed();
+                self.draw();
+            }
         }
     }
 }
@@ -492,12 +1368,13 @@ impl wayland::interfaces::wp_fractional_scale_v1::EvHandler for Daemon {
             if wallpaper.borrow().has_fractional_scale(sender_id) {
                 match NonZeroI32::new(scale as i32) {
                     Some(factor) => {
+                        let old_dim = wallpaper.borrow().get_dimensions();
                         wallpaper.borrow_mut().set_scale(Scale::Fractional(factor));
                         if wallpaper
                             .borrow_mut()
                             .commit_surface_changes(&mut self.objman, self.use_cache)
                         {
-                            self.stop_animations(&[wallpaper.clone()]);
+                            self.surface_resized(wallpaper.clone(), old_dim);
                         }
                     }
                     None => error!("received scale factor of 0 from compositor"),
@@ -508,99 +1385,283 @@ impl wayland::interfaces::wp_fractional_scale_v1::EvHandler for Daemon {
     }
 }
 
+/// Remembers the pixel format we chose, so that we prefer it the next time we start
+fn store_pixel_format(namespace: &str, pixel_format: PixelFormat) {
+    if let Err(e) = common::cache::store_pixel_format(namespace, pixel_format) {
+        warn!("failed to store the pixel format for next time: {e}");
+    }
+}
+
 fn main() -> Result<(), String> {
     // first, get the command line arguments and make the logger
     let cli = cli::Cli::new();
-    make_logger(cli.quiet);
+    make_logger(
+        cli.log_level,
+        cli.log_filter.clone(),
+        cli.log_file.as_deref(),
+    );
+    // through the environment, the clients we spawn to load the cache use this directory as well.
+    // We are still single threaded here, so nobody else is reading it
+    if let Some(dir) = &cli.cache_dir {
+        std::env::set_var(common::cache::CACHE_DIR_ENV, dir);
+    }
+    if let Some(limit) = cli.cache_limit {
+        std::env::set_var(common::cache::CACHE_LIMIT_ENV, limit.to_string());
+    }
+    if let Some(socket) = &cli.socket {
+        std::env::set_var(common::ipc::SOCKET_ENV, socket);
+    }
 
-    // initialize the wayland connection, getting all the necessary globals
-    let init_state = wayland::globals::init(cli.format);
+    // initialize the wayland connection, getting all the necessary globals. The pixel format we
+    // used last time only matters if `--format` doesn't choose one for us, and a forced one isn't
+    // remembered, so that it doesn't stick around once `--format` is gone
+    let pixel_format_hint = match cli.format {
+        Some(_) => None,
+        None => common::cache::load_pixel_format(&cli.namespace).unwrap_or_else(|e| {
+            warn!("failed to load the pixel format used last time: {e}");
+            None
+        }),
+    };
+    let mut init_state = match wayland::globals::init(cli.format, pixel_format_hint) {
+        Ok(init_state) => init_state,
+        Err(e) => {
+            error!("{e}");
+            return Err(e);
+        }
+    };
+    if cli.format.is_none() {
+        store_pixel_format(&cli.namespace, init_state.pixel_format);
+    }
 
     // create the socket listener and setup the signal handlers
     // this will also return an error if there is an `swww-daemon` instance already
     // running
-    let listener = SocketWrapper::new()?;
+    let listener = SocketWrapper::new(&cli.namespace)?;
     setup_signals();
+    let mut watchdog = Watchdog::from_env();
+
+    let mut layer = cli.layer;
+    let result = loop {
+        // use the initializer to create the Daemon, then drop it to free up the memory
+        let mut daemon = Daemon::new(
+            init_state,
+            cli.no_cache,
+            cli.no_transitions,
+            layer,
+            cli.namespace.clone(),
+            cli.outputs.clone(),
+        );
 
-    // use the initializer to create the Daemon, then drop it to free up the memory
-    let mut daemon = Daemon::new(init_state, cli.no_cache);
+        if let Ok(true) = sd_notify::booted() {
+            // we keep `NOTIFY_SOCKET` around, since the watchdog pings go through it as well
+            if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]) {
+                error!("Error sending status update to systemd: {e}");
+            }
+        }
 
-    if let Ok(true) = sd_notify::booted() {
-        if let Err(e) = sd_notify::notify(true, &[sd_notify::NotifyState::Ready]) {
-            error!("Error sending status update to systemd: {e}");
+        let stopped = run(&mut daemon, &listener, watchdog.as_mut());
+        // `swww layer` may have moved the wallpapers since we started
+        layer = daemon.layer;
+        let pixel_format = daemon.pixel_format;
+        drop(daemon);
+
+        match stopped {
+            Ok(Stopped::Exit) => break Ok(()),
+            Ok(Stopped::LostConnection(e)) if cli.reconnect => {
+                warn!("lost the connection to the compositor: {e}. Reconnecting...");
+                match reconnect(cli.format, pixel_format, watchdog.as_mut()) {
+                    Ok(Some(state)) => {
+                        info!("reconnected to the compositor");
+                        if cli.format.is_none() {
+                            store_pixel_format(&cli.namespace, state.pixel_format);
+                        }
+                        init_state = state;
+                    }
+                    Ok(None) => break Ok(()),
+                    Err(e) => {
+                        error!("{e}");
+                        break Err(e);
+                    }
+                }
+            }
+            Ok(Stopped::LostConnection(e)) => {
+                error!("lost the connection to the compositor: {e}");
+                break Err(format!("lost the connection to the compositor: {e}"));
+            }
+            Err(e) => break Err(e),
         }
-    }
+    };
 
+    drop(listener);
+    info!("Goodbye!");
+    result
+}
+
+/// Why `run` returned
+enum Stopped {
+    /// we were told to exit
+    Exit,
+    /// the compositor went away, or broke the connection
+    LostConnection(String),
+}
+
+/// The main loop, which runs until we are told to exit or lose the connection to the compositor
+///
+/// With a systemd watchdog, we wake up in time to ping it even when there is nothing else to do, so
+/// that only a daemon that is actually stuck gets restarted
+fn run(
+    daemon: &mut Daemon,
+    listener: &SocketWrapper,
+    mut watchdog: Option<&mut Watchdog>,
+) -> Result<Stopped, String> {
     let wayland_fd = wayland::globals::wayland_fd();
-    let mut fds = [
-        PollFd::new(&wayland_fd, PollFlags::IN),
-        PollFd::new(&listener.0, PollFlags::IN),
-    ];
 
-    // main loop
     while !should_daemon_exit() {
-        use wayland::{interfaces::*, wire, WlDynObj};
+        use wayland::wire;
+
+        daemon.update_slideshows();
+
+        // the subscribers are polled too, so that we notice them hanging up even when there is
+        // nothing to send them
+        let mut fds = vec![
+            PollFd::new(&wayland_fd, PollFlags::IN),
+            PollFd::new(&listener.fd, PollFlags::IN),
+        ];
+        fds.extend(
+            daemon
+                .subscribers
+                .iter()
+                .map(|subscriber| PollFd::new(subscriber.as_fd(), PollFlags::IN)),
+        );
 
-        if let Err(e) = poll(&mut fds, daemon.poll_time.into()) {
+        let timeout = daemon
+            .poll_time
+            .capped(watchdog.as_ref().map(|w| w.time_to_ping()));
+        let polled = poll(&mut fds, timeout);
+        if let Some(watchdog) = watchdog.as_mut() {
+            watchdog.ping_if_due();
+        }
+        if let Err(e) = polled {
             match e {
                 rustix::io::Errno::INTR => continue,
                 _ => return Err(format!("failed to poll file descriptors: {e:?}")),
             }
         }
 
-        if !fds[0].revents().is_empty() {
+        let revents: Vec<bool> = fds.iter().map(|fd| !fd.revents().is_empty()).collect();
+        drop(fds);
+        daemon.drop_hung_up_subscribers(&revents[2..]);
+
+        if revents[0] {
             let (msg, payload) = match wire::WireMsg::recv() {
                 Ok((msg, payload)) => (msg, payload),
-                Err(rustix::io::Errno::INTR) => continue,
-                Err(e) => return Err(format!("failed to receive wire message: {e:?}")),
+                Err(e) if is_transient_wayland_error(e) => continue,
+                // this is what happens when the compositor dies, so we let `main` clean up (or
+                // reconnect) instead of unwinding out of here
+                Err(e) => return Ok(Stopped::LostConnection(e.to_string())),
             };
 
-            match msg.sender_id() {
-                globals::WL_DISPLAY => wl_display::event(&mut daemon, msg, payload),
-                globals::WL_REGISTRY => wl_registry::event(&mut daemon, msg, payload),
-                globals::WL_COMPOSITOR => error!("wl_compositor has no events"),
-                globals::WL_SHM => wl_shm::event(&mut daemon, msg, payload),
-                globals::WP_VIEWPORTER => error!("wp_viewporter has no events"),
-                globals::ZWLR_LAYER_SHELL_V1 => error!("zwlr_layer_shell_v1 has no events"),
-                other => {
-                    let obj_id = daemon.objman.get(other);
-                    match obj_id {
-                        Some(WlDynObj::Output) => wl_output::event(&mut daemon, msg, payload),
-                        Some(WlDynObj::Surface) => wl_surface::event(&mut daemon, msg, payload),
-                        Some(WlDynObj::Region) => error!("wl_region has no events"),
-                        Some(WlDynObj::LayerSurface) => {
-                            zwlr_layer_surface_v1::event(&mut daemon, msg, payload)
-                        }
-                        Some(WlDynObj::Buffer) => wl_buffer::event(&mut daemon, msg, payload),
-                        Some(WlDynObj::ShmPool) => error!("wl_shm_pool has no events"),
-                        Some(WlDynObj::Callback) => wl_callback::event(&mut daemon, msg, payload),
-                        Some(WlDynObj::Viewport) => error!("wp_viewport has no events"),
-                        Some(WlDynObj::FractionalScale) => {
-                            wp_fractional_scale_v1::event(&mut daemon, msg, payload)
-                        }
-                        None => error!("Received event for deleted object ({other:?})"),
-                    }
-                }
-            }
+            handle_event(daemon, msg, payload);
         }
 
-        if !fds[1].revents().is_empty() {
-            match rustix::net::accept(&listener.0) {
+        if revents[1] {
+            match rustix::net::accept(&listener.fd) {
                 Ok(stream) => daemon.recv_socket_msg(IpcSocket::new(stream)),
                 Err(rustix::io::Errno::INTR | rustix::io::Errno::WOULDBLOCK) => continue,
                 Err(e) => return Err(format!("failed to accept incoming connection: {e}")),
             }
         }
 
-        if !matches!(daemon.poll_time, PollTime::Never) {
+        if matches!(daemon.poll_time, PollTime::Instant | PollTime::Short) {
             daemon.draw();
         }
+
+        daemon.notify_subscribers();
     }
+    // whether we were killed or got a signal, we leave the transitions' images on screen instead
+    // of whatever frame they were at
+    daemon.finish_transitions();
+    Ok(Stopped::Exit)
+}
 
-    drop(daemon);
-    drop(listener);
-    info!("Goodbye!");
-    Ok(())
+/// Hands an event from the compositor to whatever handles events for the object that sent it
+fn handle_event(
+    daemon: &mut Daemon,
+    msg: wayland::wire::WireMsg,
+    payload: wayland::wire::WaylandPayload,
+) {
+    use wayland::{interfaces::*, WlDynObj};
+
+    match msg.sender_id() {
+        globals::WL_DISPLAY => wl_display::event(daemon, msg, payload),
+        globals::WL_REGISTRY => wl_registry::event(daemon, msg, payload),
+        globals::WL_COMPOSITOR => error!("wl_compositor has no events"),
+        globals::WL_SHM => wl_shm::event(daemon, msg, payload),
+        globals::WP_VIEWPORTER => error!("wp_viewporter has no events"),
+        globals::ZWLR_LAYER_SHELL_V1 => error!("zwlr_layer_shell_v1 has no events"),
+        other => {
+            let obj_id = daemon.objman.get(other);
+            match obj_id {
+                Some(WlDynObj::Output) => wl_output::event(daemon, msg, payload),
+                Some(WlDynObj::Surface) => wl_surface::event(daemon, msg, payload),
+                Some(WlDynObj::Region) => error!("wl_region has no events"),
+                Some(WlDynObj::LayerSurface) => zwlr_layer_surface_v1::event(daemon, msg, payload),
+                Some(WlDynObj::Buffer) => wl_buffer::event(daemon, msg, payload),
+                Some(WlDynObj::ShmPool) => error!("wl_shm_pool has no events"),
+                Some(WlDynObj::Callback) => wl_callback::event(daemon, msg, payload),
+                Some(WlDynObj::Viewport) => error!("wp_viewport has no events"),
+                Some(WlDynObj::FractionalScale) => {
+                    wp_fractional_scale_v1::event(daemon, msg, payload)
+                }
+                Some(WlDynObj::OutputPower) => zwlr_output_power_v1::event(daemon, msg, payload),
+                None if daemon.is_recently_removed_output(other) => {
+                    debug!("dropping event for removed output ({other:?})")
+                }
+                None => error!("Received event for deleted object ({other:?})"),
+            }
+        }
+    }
+}
+
+/// For how long `--reconnect` waits for the compositor to come back before giving up
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait between attempts to reconnect
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Keeps trying to connect to the compositor again, until it works, we time out, or we are told to
+/// exit, in which case this returns `Ok(None)`
+fn reconnect(
+    format: Option<PixelFormat>,
+    pixel_format_hint: PixelFormat,
+    mut watchdog: Option<&mut Watchdog>,
+) -> Result<Option<InitState>, String> {
+    let start = Instant::now();
+    loop {
+        // trying to reconnect is not hanging, so the watchdog shouldn't restart us meanwhile
+        if let Some(watchdog) = watchdog.as_mut() {
+            watchdog.ping_if_due();
+        }
+        if should_daemon_exit() {
+            return Ok(None);
+        }
+        match wayland::globals::reconnect(format, Some(pixel_format_hint)) {
+            Ok(init_state) => return Ok(Some(init_state)),
+            Err(e) if start.elapsed() < RECONNECT_TIMEOUT => debug!("failed to reconnect: {e}"),
+            Err(e) => return Err(format!("gave up reconnecting to the compositor: {e}")),
+        }
+        std::thread::sleep(RECONNECT_INTERVAL);
+    }
+}
+
+/// Whether we may just try again after failing to receive a message from the compositor. Anything
+/// else means the connection is unusable
+fn is_transient_wayland_error(e: rustix::io::Errno) -> bool {
+    use rustix::io::Errno;
+    matches!(
+        e,
+        Errno::INTR | Errno::WOULDBLOCK | Errno::NOBUFS | Errno::NOMEM
+    )
 }
 
 fn setup_signals() {
@@ -628,14 +1689,31 @@ fn setup_signals() {
 }
 
 /// This is a wrapper that makes sure to delete the socket when it is dropped
-struct SocketWrapper(OwnedFd);
+struct SocketWrapper {
+    fd: OwnedFd,
+    path: String,
+    /// whether we bound the socket ourselves. The one systemd passes us through socket activation
+    /// belongs to it, so we must leave its file alone
+    owned: bool,
+}
 impl SocketWrapper {
-    fn new() -> Result<Self, String> {
-        let addr = IpcSocket::<Server>::path();
-        let addr = Path::new(addr);
+    fn new(namespace: &str) -> Result<Self, String> {
+        let path = IpcSocket::<Server>::path(namespace);
+
+        // systemd only ever starts one of us per socket, so there is nothing to check
+        if let Some(socket) = IpcSocket::<Server>::from_systemd().map_err(|e| e.to_string())? {
+            info!("using the socket passed by systemd");
+            return Ok(Self {
+                fd: socket.to_fd(),
+                path,
+                owned: false,
+            });
+        }
+
+        let addr = Path::new(&path);
 
         if addr.exists() {
-            if is_daemon_running()? {
+            if is_daemon_running(namespace)? {
                 return Err(
                     "There is an swww-daemon instance already running on this socket!".to_string(),
                 );
@@ -655,23 +1733,31 @@ impl SocketWrapper {
             None => return Err("couldn't find a valid runtime directory".to_owned()),
         };
 
-        if !runtime_dir.exists() {
+        // a bare file name lives in the current directory
+        if !runtime_dir.as_os_str().is_empty() && !runtime_dir.exists() {
             match fs::create_dir(runtime_dir) {
                 Ok(()) => (),
                 Err(e) => return Err(format!("failed to create runtime dir: {e}")),
             }
         }
 
-        let socket = IpcSocket::server().map_err(|err| err.to_string())?;
+        let socket = IpcSocket::server(namespace).map_err(|err| err.to_string())?;
 
         debug!("Created socket in {:?}", addr);
-        Ok(Self(socket.to_fd()))
+        Ok(Self {
+            fd: socket.to_fd(),
+            path,
+            owned: true,
+        })
     }
 }
 
 impl Drop for SocketWrapper {
     fn drop(&mut self) {
-        let addr = IpcSocket::<Server>::path();
+        if !self.owned {
+            return;
+        }
+        let addr = &self.path;
         if let Err(e) = fs::remove_file(Path::new(addr)) {
             error!("Failed to remove socket at {addr}: {e}");
         }
@@ -679,7 +1765,6 @@ impl Drop for SocketWrapper {
     }
 }
 
-#[repr(i32)]
 #[derive(Clone, Copy)]
 /// We use PollTime as a way of making sure we draw at the right time
 /// when we call `Daemon::draw` before the frame callback returned, we need to *not* draw and
@@ -687,31 +1772,119 @@ impl Drop for SocketWrapper {
 ///
 /// The instant poll time is for when we receive an img request, after we set up the requested
 /// transitions
+///
+/// The timeout poll time is for waking up when the next slideshow image is due. It does not cause
+/// a call to `Daemon::draw` by itself
 enum PollTime {
-    Never = -1,
-    Instant = 0,
-    Short = 1,
+    Never,
+    Instant,
+    Short,
+    Timeout(i32),
 }
 
 impl From<PollTime> for i32 {
     fn from(value: PollTime) -> Self {
-        value as i32
+        match value {
+            PollTime::Never => -1,
+            PollTime::Instant => 0,
+            PollTime::Short => 1,
+            PollTime::Timeout(millis) => millis,
+        }
+    }
+}
+
+impl PollTime {
+    /// The timeout to poll with, so that we wake up after `cap` at the latest, whatever the poll
+    /// time
+    fn capped(self, cap: Option<Duration>) -> i32 {
+        let millis = i32::from(self);
+        let Some(cap) = cap else {
+            return millis;
+        };
+        let cap = cap.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32;
+        if millis < 0 {
+            cap
+        } else {
+            millis.min(cap)
+        }
+    }
+}
+
+/// Pings systemd's watchdog, if the service has one, so that systemd restarts us if we ever hang
+struct Watchdog {
+    /// how often to ping, which is half of how long systemd waits for a ping
+    interval: Duration,
+    last_ping: Instant,
+}
+
+impl Watchdog {
+    /// Reads the watchdog interval systemd set in `WATCHDOG_USEC`, if it set one for us
+    fn from_env() -> Option<Self> {
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(true, &mut usec) || usec == 0 {
+            return None;
+        }
+        let interval = Duration::from_micros(usec) / 2;
+        debug!("pinging the systemd watchdog every {interval:?}");
+        Some(Self {
+            interval,
+            last_ping: Instant::now(),
+        })
+    }
+
+    /// How long until the next ping is due
+    fn time_to_ping(&self) -> Duration {
+        self.interval.saturating_sub(self.last_ping.elapsed())
+    }
+
+    fn ping_if_due(&mut self) {
+        if !self.time_to_ping().is_zero() {
+            return;
+        }
+        if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]) {
+            error!("Error pinging the systemd watchdog: {e}");
+        }
+        self.last_ping = Instant::now();
     }
 }
 
 struct Logger {
     level_filter: LevelFilter,
+    filter: cli::LogFilter,
     start: std::time::Instant,
     is_term: bool,
+    file: Option<std::sync::Mutex<log_file::LogFile>>,
 }
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.level_filter
+        metadata.level() <= self.filter.level(metadata.target(), self.level_filter)
     }
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
+            let msg = record.args();
+
+            if let Some(file) = &self.file {
+                let level = match record.level() {
+                    log::Level::Error => "[ERROR]",
+                    log::Level::Warn => "[WARN] ",
+                    log::Level::Info => "[INFO] ",
+                    log::Level::Debug => "[DEBUG]",
+                    log::Level::Trace => "[TRACE]",
+                };
+                let time = log_file::rfc3339(std::time::SystemTime::now());
+                // a panic while holding the lock must not stop us from logging the panic itself
+                file.lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .write_line(&format!("{time} {level} {msg}\n"));
+
+                // the file already has everything, so only duplicate it for someone watching
+                if !self.is_term {
+                    return;
+                }
+            }
+
             let time = self.start.elapsed().as_millis();
 
             let level = if self.is_term {
@@ -719,18 +1892,19 @@ impl log::Log for Logger {
                     log::Level::Error => "\x1b[31m[ERROR]\x1b[0m",
                     log::Level::Warn => "\x1b[33m[WARN]\x1b[0m ",
                     log::Level::Info => "\x1b[32m[INFO]\x1b[0m ",
-                    log::Level::Debug | log::Level::Trace => "\x1b[36m[DEBUG]\x1b[0m",
+                    log::Level::Debug => "\x1b[36m[DEBUG]\x1b[0m",
+                    log::Level::Trace => "\x1b[35m[TRACE]\x1b[0m",
                 }
             } else {
                 match record.level() {
                     log::Level::Error => "[ERROR]",
                     log::Level::Warn => "[WARN] ",
                     log::Level::Info => "[INFO] ",
-                    log::Level::Debug | log::Level::Trace => "[DEBUG]",
+                    log::Level::Debug => "[DEBUG]",
+                    log::Level::Trace => "[TRACE]",
                 }
             };
 
-            let msg = record.args();
             let _ = std::io::stderr().write_fmt(format_args!("{time:>10}ms {level} {msg}\n"));
         }
     }
@@ -740,24 +1914,35 @@ impl log::Log for Logger {
     }
 }
 
-fn make_logger(quiet: bool) {
-    let level_filter = if quiet {
-        LevelFilter::Error
-    } else {
-        LevelFilter::Debug
+fn make_logger(level_filter: LevelFilter, filter: cli::LogFilter, log_file: Option<&Path>) {
+    let max_level = filter.max_level(level_filter);
+
+    let (file, file_error) = match log_file.map(log_file::LogFile::open) {
+        Some(Ok(file)) => (Some(std::sync::Mutex::new(file)), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
     };
 
     log::set_boxed_logger(Box::new(Logger {
         level_filter,
+        filter,
         start: std::time::Instant::now(),
         is_term: std::io::stderr().is_terminal(),
+        file,
     }))
-    .map(|()| log::set_max_level(level_filter))
+    .map(|()| log::set_max_level(max_level))
     .unwrap();
+
+    if let (Some(path), Some(e)) = (log_file, file_error) {
+        warn!(
+            "failed to open log file {}: {e}. Logging to stderr only",
+            path.display()
+        );
+    }
 }
 
-pub fn is_daemon_running() -> Result<bool, String> {
-    let sock = match IpcSocket::connect() {
+pub fn is_daemon_running(namespace: &str) -> Result<bool, String> {
+    let sock = match IpcSocket::connect(namespace) {
         Ok(s) => s,
         // likely a connection refused; either way, this is a reliable signal there's no surviving
         // daemon.
@@ -772,6 +1957,33 @@ pub fn is_daemon_running() -> Result<bool, String> {
     }
 }
 
+/// The answer to a request that ran into `failures`: an error if none of it could be `applied`,
+/// or a warning for each failure if only some of it could
+fn answer_with(applied: bool, failures: Vec<String>) -> Answer {
+    let mut unique: Vec<String> = Vec::with_capacity(failures.len());
+    for failure in failures {
+        if !unique.contains(&failure) {
+            unique.push(failure);
+        }
+    }
+    if unique.is_empty() {
+        Answer::Ok
+    } else if applied {
+        Answer::Warnings(unique.into())
+    } else {
+        Answer::Err(unique.join("\n"))
+    }
+}
+
+/// How we name the outputs of a request in messages
+fn describe(names: &[MmappedStr]) -> String {
+    if names.is_empty() {
+        "every output".to_string()
+    } else {
+        names.iter().map(|n| n.str()).collect::<Vec<_>>().join(", ")
+    }
+}
+
 /// copy-pasted from the `spin_sleep` crate on crates.io
 ///
 /// This will sleep for an amount of time we can roughly expected the OS to still be precise enough
@@ -787,3 +1999,1399 @@ fn spin_sleep(duration: std::time::Duration) {
         std::thread::yield_now();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compression::Compressor;
+    use common::ipc::{
+        Animation, BgImg, ClearSend, Client, Coord, ImageRequestBuilder, ImgSend, LayerSend,
+        OutputMatch, PauseSend, Position, RawMsg, ScreenshotSend, Transform, WaitIdleSend,
+    };
+    use std::num::NonZeroU8;
+    use wayland::backend::test as test_backend;
+
+    const DIM: (u32, u32) = (8, 8);
+
+    /// Makes a daemon with one wallpaper for every global name in `output_names`, all of them
+    /// already configured, as if the compositor had sent us all of their events
+    fn configured_daemon(output_names: &[u32]) -> Daemon {
+        configured_daemon_with_output_power(output_names, false)
+    }
+
+    /// Same as `configured_daemon`, but optionally pretending the compositor supports
+    /// `zwlr_output_power_manager_v1`
+    fn configured_daemon_with_output_power(output_names: &[u32], output_power: bool) -> Daemon {
+        let mut objman = ObjectManager::new();
+        objman.set_output_power_support(output_power);
+        let init_state = InitState {
+            output_names: output_names.to_vec(),
+            fractional_scale: None,
+            output_power: output_power.then(globals::OutputPowerManager::test_manager),
+            objman,
+            pixel_format: PixelFormat::Xrgb,
+        };
+        let mut daemon = Daemon::new(
+            init_state,
+            true,
+            false,
+            Layer::Background,
+            String::new(),
+            OutputFilter::default(),
+        );
+        for (i, wallpaper) in daemon.wallpapers.iter().enumerate() {
+            let mut wallpaper = wallpaper.borrow_mut();
+            wallpaper.set_name(format!("TEST-{i}"));
+            wallpaper.set_dimensions(DIM.0 as i32, DIM.1 as i32);
+            wallpaper.commit_surface_changes(&mut daemon.objman, false);
+        }
+        daemon
+    }
+
+    /// Sends a white image to every output, with a transition slow enough that it will still be
+    /// running after a few frames
+    fn send_img(daemon: &mut Daemon) {
+        send_color(daemon, 255, TransitionType::Simple, 0.0);
+    }
+
+    /// Sends an image where every byte is `byte` to every output, with a transition of type
+    /// `transition_type` that takes `duration` seconds. Simple transitions move one step per frame
+    fn send_color(daemon: &mut Daemon, byte: u8, transition_type: TransitionType, duration: f32) {
+        send_color_with(daemon, byte, test_transition(transition_type, duration));
+    }
+
+    /// Like `send_color`, but with an arbitrary transition
+    fn send_color_with(daemon: &mut Daemon, byte: u8, transition: Transition) {
+        let mut builder = ImageRequestBuilder::new(transition, "");
+        builder.set_cache_writes(false);
+        push_color(&mut builder, byte);
+        assert!(matches!(
+            send_request(daemon, RequestSend::Img(builder.build())),
+            Answer::Ok
+        ));
+    }
+
+    /// Pushes an image where every byte is `byte`, for every output
+    fn push_color(builder: &mut ImageRequestBuilder, byte: u8) {
+        let len = DIM.0 as usize * DIM.1 as usize * PixelFormat::Xrgb.channels() as usize;
+        builder.push(
+            ImgSend {
+                path: format!("{byte:02x}"),
+                dim: DIM,
+                format: PixelFormat::Xrgb,
+                img: vec![byte; len].into_boxed_slice(),
+                transition: None,
+            },
+            "Lanczos3".to_string(),
+            &[],
+            None,
+        );
+    }
+
+    /// A transition of type `transition_type` that takes `duration` seconds. Simple transitions
+    /// move one step per frame
+    fn test_transition(transition_type: TransitionType, duration: f32) -> Transition {
+        Transition {
+            transition_type,
+            duration,
+            step: NonZeroU8::MIN,
+            fps: u16::MAX,
+            angle: 0.0,
+            pos: Position::new(Coord::Pixel(0.0), Coord::Pixel(0.0)),
+            bezier: (0.0, 0.0, 1.0, 1.0),
+            wave: (0.0, 0.0),
+            invert_y: false,
+            linear: false,
+            mask: None,
+        }
+    }
+
+    /// Sends `request` to the daemon through a socket pair, returning its answer
+    fn send_request(daemon: &mut Daemon, request: RequestSend) -> Answer {
+        send_raw(daemon, request.into())
+    }
+
+    /// Like `send_request`, but lets us send messages no client would
+    fn send_raw(daemon: &mut Daemon, msg: RawMsg) -> Answer {
+        let (client, server) = rustix::net::socketpair(
+            rustix::net::AddressFamily::UNIX,
+            rustix::net::SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let client = IpcSocket::<Client>::new(client);
+        client.send(msg).unwrap();
+        daemon.recv_socket_msg(IpcSocket::new(server));
+        Answer::receive(client.recv().unwrap())
+    }
+
+    fn screenshot(daemon: &mut Daemon, output: &str) -> Option<common::ipc::Screenshot> {
+        let request = ScreenshotSend {
+            output: output.to_string(),
+        };
+        match send_request(daemon, RequestSend::Screenshot(request.create_request())) {
+            Answer::Screenshot(screenshot) => screenshot,
+            _ => panic!("daemon did not answer with a screenshot"),
+        }
+    }
+
+    /// Draws a frame, and then pretends the compositor sent all the frame callbacks
+    fn draw_frame(daemon: &mut Daemon) {
+        daemon.draw();
+        for wallpaper in daemon.wallpapers.iter() {
+            wallpaper.borrow_mut().frame_callback_completed();
+        }
+    }
+
+    /// Handles every event queued with `test_backend::push_event`, like the main loop would
+    fn dispatch_events(daemon: &mut Daemon) {
+        while let Ok((msg, payload)) = wayland::wire::WireMsg::recv() {
+            handle_event(daemon, msg, payload);
+        }
+    }
+
+    #[test]
+    fn interrupted_transition_blends_from_what_is_on_screen() {
+        let mut daemon = configured_daemon(&[140]);
+        send_color(&mut daemon, 255, TransitionType::Grow, 60.0);
+        draw_frame(&mut daemon);
+
+        // pretend the grow got halfway across
+        let stride = daemon.wallpapers[0].borrow().stride(daemon.pixel_format);
+        let half = DIM.0 as usize / 2 * PixelFormat::Xrgb.channels() as usize;
+        daemon.wallpapers[0].borrow_mut().canvas_change(
+            &mut daemon.objman,
+            daemon.pixel_format,
+            |canvas| {
+                for row in canvas.chunks_exact_mut(stride) {
+                    row[..half].fill(255);
+                    row[half..].fill(0);
+                }
+            },
+        );
+        let on_screen = screenshot(&mut daemon, "TEST-0").unwrap().img;
+
+        send_color(&mut daemon, 128, TransitionType::Fade, 60.0);
+        for _ in 0..3 {
+            draw_frame(&mut daemon);
+        }
+        let blended = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        for (before, after) in on_screen.iter().zip(blended.iter()) {
+            assert!(
+                before.abs_diff(*after) <= 2,
+                "the fade jumped from {before} to {after}"
+            );
+        }
+    }
+
+    #[test]
+    fn images_sent_before_a_transition_shows_are_coalesced() {
+        let mut daemon = configured_daemon(&[145]);
+        send_color(&mut daemon, 0x10, TransitionType::Fade, 60.0);
+        for byte in [0x20, 0x30, 0x40] {
+            send_color(&mut daemon, byte, TransitionType::None, 0.0);
+        }
+        assert_eq!(daemon.pending_imgs.len(), 1);
+        assert_eq!(daemon.pending_imgs[0].skipped, 2);
+        assert_eq!(daemon.transition_animators.len(), 1);
+
+        while !daemon.transition_animators.is_empty() || !daemon.pending_imgs.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 0x40));
+
+        // clearing the output drops whatever image was still waiting
+        send_color(&mut daemon, 0x10, TransitionType::Fade, 60.0);
+        send_color(&mut daemon, 0x20, TransitionType::None, 0.0);
+        let clear = ClearSend {
+            color: [0, 0, 0],
+            outputs: Box::new([]),
+            match_by: OutputMatch::Name,
+            transition: None,
+        };
+        send_request(&mut daemon, RequestSend::Clear(clear.create_request()));
+        assert!(daemon.pending_imgs.is_empty());
+    }
+
+    #[test]
+    fn reveal_holds_dark_pixels_back_until_the_end() {
+        let mut daemon = configured_daemon(&[150]);
+        send_color(&mut daemon, 255, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+
+        send_color(&mut daemon, 16, TransitionType::Reveal, 60.0);
+        for _ in 0..3 {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(
+            img.iter().all(|b| *b == 255),
+            "dark pixels were revealed early"
+        );
+
+        send_color(&mut daemon, 16, TransitionType::Reveal, 0.0);
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 16));
+    }
+
+    #[test]
+    fn dissolve_flips_pixels_without_blending() {
+        let mut daemon = configured_daemon(&[210]);
+        send_color(&mut daemon, 255, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+
+        send_color(&mut daemon, 16, TransitionType::Dissolve, 60.0);
+        for _ in 0..3 {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(
+            img.iter().all(|b| *b == 255 || *b == 16),
+            "dissolve blended the old and new images"
+        );
+
+        send_color(&mut daemon, 16, TransitionType::Dissolve, 0.0);
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 16));
+    }
+
+    #[test]
+    fn melt_slides_the_old_image_down() {
+        let mut daemon = configured_daemon(&[220]);
+        send_color(&mut daemon, 255, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+
+        send_color(&mut daemon, 16, TransitionType::Melt, 60.0);
+        for _ in 0..3 {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(
+            img.iter().all(|b| *b == 255),
+            "columns started falling too early"
+        );
+
+        send_color(&mut daemon, 16, TransitionType::Melt, 0.0);
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 16));
+    }
+
+    #[test]
+    fn crossfade_blends_linearly_between_the_two_images() {
+        let mut daemon = configured_daemon(&[264]);
+        send_color(&mut daemon, 255, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+
+        // with a step of 1, the new image's opacity is the number of frames drawn, out of 256. The
+        // first one is drawn as soon as the request arrives
+        send_color(&mut daemon, 0, TransitionType::Crossfade, 60.0);
+        let mut drawn = 1;
+        for frames in [64, 128] {
+            while drawn < frames {
+                draw_frame(&mut daemon);
+                drawn += 1;
+            }
+            let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+            let expected = (255 * (256 - frames) / 256) as u8;
+            assert!(
+                img.iter().all(|b| *b == expected),
+                "expected {expected} after {frames} frames, got {}",
+                img[0]
+            );
+        }
+
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn auto_fps_follows_the_slowest_refresh_rate() {
+        let mut daemon = configured_daemon(&[266, 267]);
+        // absurdly slow refresh rates, so the test doesn't depend on how fast it runs
+        for (wallpaper, refresh) in daemon.wallpapers.iter().zip([1000, 500]) {
+            let mut wallpaper = wallpaper.borrow_mut();
+            wallpaper.set_refresh(refresh);
+            wallpaper.commit_surface_changes(&mut daemon.objman, false);
+        }
+
+        let clear = ClearSend {
+            color: [0, 0, 0],
+            outputs: Box::new([]),
+            match_by: OutputMatch::Name,
+            transition: Some(Transition {
+                fps: 0,
+                ..test_transition(TransitionType::Fade, 60.0)
+            }),
+        };
+        send_request(&mut daemon, RequestSend::Clear(clear.create_request()));
+        assert_eq!(daemon.transition_animators.len(), 1);
+        let time = daemon.transition_animators[0].time_to_draw();
+        assert!(
+            time > Duration::from_millis(1500) && time <= Duration::from_secs(2),
+            "drawing every {time:?}"
+        );
+    }
+
+    #[test]
+    fn slide_pushes_the_old_image_out() {
+        let channels = PixelFormat::Xrgb.channels() as usize;
+        let width = DIM.0 as usize;
+        // with a step of 64, the new image comes in a quarter of the way each frame, and the first
+        // frame is drawn as soon as the request arrives
+        // (angle, whether it slides vertically, whether it comes in from the top or left)
+        for (angle, vertical, from_start) in [
+            (0.0, false, false),
+            (90.0, true, true),
+            (180.0, false, true),
+            (270.0, true, false),
+        ] {
+            let mut daemon = configured_daemon(&[285]);
+            send_color(&mut daemon, 255, TransitionType::None, 0.0);
+            draw_frame(&mut daemon);
+            // the old image is a gradient along the direction of the slide, so we can tell how
+            // far it moved
+            let stride = daemon.wallpapers[0].borrow().stride(daemon.pixel_format);
+            daemon.wallpapers[0].borrow_mut().canvas_change(
+                &mut daemon.objman,
+                daemon.pixel_format,
+                |canvas| {
+                    for (y, row) in canvas.chunks_exact_mut(stride).enumerate() {
+                        for (x, pixel) in row[..width * channels]
+                            .chunks_exact_mut(channels)
+                            .enumerate()
+                        {
+                            pixel.fill(10 + 10 * if vertical { y } else { x } as u8);
+                        }
+                    }
+                },
+            );
+
+            let mut transition = test_transition(TransitionType::Slide, 0.0);
+            transition.step = NonZeroU8::new(64).unwrap();
+            transition.angle = angle;
+            send_color_with(&mut daemon, 0, transition);
+            let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+            for (i, pixel) in img.chunks_exact(channels).enumerate() {
+                let (x, y) = (i % width, i / width);
+                let pos = if vertical { y } else { x };
+                // the old image moves two pixels away from the edge the new one comes in from
+                let expected = match from_start {
+                    true if pos < 2 => 0,
+                    true => 10 + 10 * (pos - 2) as u8,
+                    false if pos >= 6 => 0,
+                    false => 10 + 10 * (pos + 2) as u8,
+                };
+                assert_eq!(pixel[0], expected, "at {x},{y} for angle {angle}");
+            }
+
+            while !daemon.transition_animators.is_empty() {
+                draw_frame(&mut daemon);
+            }
+            let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+            assert!(img.iter().all(|b| *b == 0));
+        }
+    }
+
+    #[test]
+    fn clear_with_a_transition_fades_into_the_color() {
+        let mut daemon = configured_daemon(&[230]);
+        send_color(&mut daemon, 255, TransitionType::None, 0.0);
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+
+        let color = [16, 32, 64];
+        let clear = ClearSend {
+            color,
+            outputs: Box::new([]),
+            match_by: OutputMatch::Name,
+            transition: Some(test_transition(TransitionType::Fade, 60.0)),
+        };
+        assert!(matches!(
+            send_request(&mut daemon, RequestSend::Clear(clear.create_request())),
+            Answer::Ok
+        ));
+        assert_eq!(daemon.transition_animators.len(), 1);
+        assert!(daemon.wallpapers_info()[0].img == BgImg::Color(color));
+        draw_frame(&mut daemon);
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.chunks_exact(4).all(|pixel| pixel[0] > color[0]));
+
+        let clear = ClearSend {
+            color,
+            outputs: Box::new([]),
+            match_by: OutputMatch::Name,
+            transition: Some(test_transition(TransitionType::Fade, 0.0)),
+        };
+        send_request(&mut daemon, RequestSend::Clear(clear.create_request()));
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.chunks_exact(4).all(|pixel| pixel[..3] == color));
+        assert!(daemon.wallpapers_info()[0].img == BgImg::Color(color));
+    }
+
+    #[test]
+    fn no_transitions_changes_images_instantly() {
+        let mut daemon = configured_daemon(&[160]);
+        daemon.no_transitions = true;
+        send_color(&mut daemon, 200, TransitionType::Fade, 60.0);
+        draw_frame(&mut daemon);
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 200));
+    }
+
+    #[test]
+    fn wait_idle_is_answered_once_the_transition_ends() {
+        let mut daemon = configured_daemon(&[170, 171]);
+        send_color(&mut daemon, 255, TransitionType::Simple, 0.0);
+        draw_frame(&mut daemon);
+        assert!(!daemon.transition_animators.is_empty());
+
+        let (client, server) = rustix::net::socketpair(
+            rustix::net::AddressFamily::UNIX,
+            rustix::net::SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let client = IpcSocket::<Client>::new(client);
+        let request = WaitIdleSend {
+            outputs: Box::new(["TEST-1".to_string()]),
+        };
+        RequestSend::WaitIdle(request.create_request())
+            .send(&client)
+            .unwrap();
+        daemon.recv_socket_msg(IpcSocket::new(server));
+        assert_eq!(
+            daemon.idle_waiters.len(),
+            1,
+            "answered in the middle of a transition"
+        );
+
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        assert!(daemon.idle_waiters.is_empty());
+        assert!(matches!(
+            Answer::receive(client.recv().unwrap()),
+            Answer::Ok
+        ));
+
+        // with nothing going on, we should be answered right away
+        let request = WaitIdleSend {
+            outputs: Box::new([]),
+        };
+        assert!(matches!(
+            send_request(&mut daemon, RequestSend::WaitIdle(request.create_request())),
+            Answer::Ok
+        ));
+    }
+
+    /// Sends an animation going through frames where every byte is 255, 128 and 64, each lasting
+    /// `frame_duration`, to every output
+    fn send_animation(daemon: &mut Daemon, frame_duration: Duration, loop_count: u32, speed: f32) {
+        send_animation_to(daemon, &[], "animation", frame_duration, loop_count, speed);
+    }
+
+    /// Like `send_animation`, but only to `outputs`, and under its own `path`
+    fn send_animation_to(
+        daemon: &mut Daemon,
+        outputs: &[String],
+        path: &str,
+        frame_duration: Duration,
+        loop_count: u32,
+        speed: f32,
+    ) {
+        let pixels = DIM.0 as usize * DIM.1 as usize;
+        let frames = [255, 128, 64].map(|byte| vec![byte; pixels * 3]);
+        let mut compressor = Compressor::new();
+        let animation = (0..frames.len())
+            .map(|i| {
+                let next = &frames[(i + 1) % frames.len()];
+                let bitpack = compressor.compress(&frames[i], next, PixelFormat::Xrgb);
+                (bitpack.unwrap(), frame_duration)
+            })
+            .collect();
+
+        let transition = Transition {
+            transition_type: TransitionType::None,
+            duration: 0.0,
+            step: NonZeroU8::MAX,
+            fps: u16::MAX,
+            angle: 0.0,
+            pos: Position::new(Coord::Pixel(0.0), Coord::Pixel(0.0)),
+            bezier: (0.0, 0.0, 1.0, 1.0),
+            wave: (0.0, 0.0),
+            invert_y: false,
+            linear: false,
+            mask: None,
+        };
+        let mut builder = ImageRequestBuilder::new(transition, "");
+        builder.set_cache_writes(false);
+        builder.set_animation_cache_writes(false);
+        builder.push(
+            ImgSend {
+                path: path.to_string(),
+                dim: DIM,
+                format: PixelFormat::Xrgb,
+                img: vec![255; pixels * 4].into_boxed_slice(),
+                transition: None,
+            },
+            "Lanczos3".to_string(),
+            outputs,
+            Some(Animation {
+                animation,
+                loop_count,
+                speed,
+            }),
+        );
+        send_request(daemon, RequestSend::Img(builder.build()));
+    }
+
+    #[test]
+    fn animations_stop_at_their_last_frame_after_their_loop_count() {
+        let mut daemon = configured_daemon(&[180]);
+        send_animation(&mut daemon, Duration::ZERO, 1, 1.0);
+        for _ in 0..10 {
+            draw_frame(&mut daemon);
+        }
+        assert!(daemon.image_animators.is_empty());
+        assert!(!daemon.wallpapers_info()[0].animated);
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.chunks_exact(4).all(|pixel| pixel[..3] == [64; 3]));
+    }
+
+    #[test]
+    fn different_animations_do_not_wait_on_each_other() {
+        let mut daemon = configured_daemon(&[266, 267]);
+        for (i, path) in ["first", "second"].into_iter().enumerate() {
+            let output = format!("TEST-{i}");
+            send_animation_to(&mut daemon, &[output], path, Duration::ZERO, 0, 1.0);
+        }
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        assert_eq!(daemon.image_animators.len(), 2);
+
+        // the second output never gets its frame callbacks, as if it were stalled
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            daemon.draw();
+            daemon.wallpapers[0].borrow_mut().frame_callback_completed();
+            seen.push(screenshot(&mut daemon, "TEST-0").unwrap().img[0]);
+        }
+        assert!(
+            [255, 128, 64].iter().all(|byte| seen.contains(byte)),
+            "the first animation got stuck behind the second: {seen:?}"
+        );
+
+        // and once it goes away, the first one still plays
+        daemon.remove_wallpaper(1);
+        assert_eq!(daemon.image_animators.len(), 1);
+        let before = screenshot(&mut daemon, "TEST-0").unwrap().img[0];
+        draw_frame(&mut daemon);
+        assert_ne!(screenshot(&mut daemon, "TEST-0").unwrap().img[0], before);
+    }
+
+    #[test]
+    fn sending_the_same_animation_at_another_speed_keeps_it_playing() {
+        let mut daemon = configured_daemon(&[200]);
+        let frame_duration = Duration::from_secs(10);
+        send_animation(&mut daemon, frame_duration, 0, 1.0);
+        draw_frame(&mut daemon);
+        assert_eq!(daemon.image_animators.len(), 1);
+        assert!(daemon.image_animators[0].time_to_draw() > frame_duration / 2);
+
+        send_animation(&mut daemon, frame_duration, 0, 4.0);
+        // a new transition would have stopped the animation
+        assert!(daemon.transition_animators.is_empty());
+        assert_eq!(daemon.image_animators.len(), 1);
+        assert!(daemon.image_animators[0].time_to_draw() <= frame_duration / 4);
+    }
+
+    #[test]
+    fn malformed_requests_are_answered_with_an_error() {
+        let mut daemon = configured_daemon(&[268]);
+        send_color(&mut daemon, 0x40, TransitionType::None, 0.0);
+
+        let garbage = RawMsg::from_raw_parts(3, &[0xff; 64]).unwrap();
+        assert!(matches!(send_raw(&mut daemon, garbage), Answer::Err(_)));
+        let empty = RawMsg::from_raw_parts(2, &[]).unwrap();
+        assert!(matches!(send_raw(&mut daemon, empty), Answer::Err(_)));
+
+        // an image too small for the dimensions it claims to have
+        let mut builder = ImageRequestBuilder::new(test_transition(TransitionType::None, 0.0), "");
+        builder.set_cache_writes(false);
+        builder.push(
+            ImgSend {
+                path: "short".to_string(),
+                dim: DIM,
+                format: PixelFormat::Xrgb,
+                img: vec![0; 3].into_boxed_slice(),
+                transition: None,
+            },
+            "Lanczos3".to_string(),
+            &[],
+            None,
+        );
+        assert!(matches!(
+            send_request(&mut daemon, RequestSend::Img(builder.build())),
+            Answer::Err(_)
+        ));
+        draw_frame(&mut daemon);
+
+        // the daemon is still up, and still showing what it was showing before
+        assert!(matches!(
+            send_request(&mut daemon, RequestSend::Ping),
+            Answer::Ping(_)
+        ));
+        let img = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img.iter().all(|b| *b == 0x40));
+    }
+
+    #[test]
+    fn failures_are_reported_to_the_client() {
+        let mut daemon = configured_daemon(&[272, 273]);
+        let img = |outputs: &[&str], dim: (u32, u32)| {
+            let len = dim.0 as usize * dim.1 as usize * PixelFormat::Xrgb.channels() as usize;
+            let mut builder =
+                ImageRequestBuilder::new(test_transition(TransitionType::None, 0.0), "");
+            builder.set_cache_writes(false);
+            builder.push(
+                ImgSend {
+                    path: "img".to_string(),
+                    dim,
+                    format: PixelFormat::Xrgb,
+                    img: vec![0x80; len].into_boxed_slice(),
+                    transition: None,
+                },
+                "Lanczos3".to_string(),
+                &outputs.iter().map(|o| o.to_string()).collect::<Vec<_>>(),
+                None,
+            );
+            RequestSend::Img(builder.build())
+        };
+
+        // some of it went through
+        match send_request(&mut daemon, img(&["TEST-0", "NOPE"], DIM)) {
+            Answer::Warnings(warnings) => {
+                assert_eq!(warnings[..], ["there is no output named NOPE".to_string()]);
+            }
+            _ => panic!("daemon did not warn about the missing output"),
+        }
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let img0 = screenshot(&mut daemon, "TEST-0").unwrap().img;
+        assert!(img0.iter().all(|b| *b == 0x80));
+
+        // none of it did
+        assert!(matches!(
+            send_request(&mut daemon, img(&["NOPE"], DIM)),
+            Answer::Err(e) if e == "there is no output named NOPE"
+        ));
+        assert!(matches!(
+            send_request(&mut daemon, img(&["TEST-1"], (DIM.0 + 1, DIM.1))),
+            Answer::Err(e) if e.contains("wrong dimensions")
+        ));
+        assert!(daemon.transition_animators.is_empty());
+        let request = PauseSend {
+            outputs: Box::new(["NOPE".to_string()]),
+        };
+        assert!(matches!(
+            send_request(&mut daemon, RequestSend::Pause(request.create_request())),
+            Answer::Err(_)
+        ));
+        let clear = ClearSend {
+            color: [0, 0, 0],
+            outputs: Box::new(["nope".to_string()]),
+            match_by: OutputMatch::Description,
+            transition: None,
+        };
+        assert!(matches!(
+            send_request(&mut daemon, RequestSend::Clear(clear.create_request())),
+            Answer::Err(e) if e == "no output has a description containing 'nope'"
+        ));
+    }
+
+    fn send_pause(daemon: &mut Daemon, outputs: &[&str], pause: Option<bool>) -> bool {
+        let request = PauseSend {
+            outputs: outputs.iter().map(|o| o.to_string()).collect(),
+        }
+        .create_request();
+        let request = match pause {
+            Some(true) => RequestSend::Pause(request),
+            Some(false) => RequestSend::Resume(request),
+            None => RequestSend::TogglePause(request),
+        };
+        match send_request(daemon, request) {
+            Answer::Paused(paused) => paused,
+            _ => panic!("daemon did not answer with whether it paused"),
+        }
+    }
+
+    #[test]
+    fn resized_outputs_redraw_their_color_at_the_new_size() {
+        use wayland::interfaces::wl_output::EvHandler;
+
+        let mut daemon = configured_daemon(&[265]);
+        let color = [16, 32, 64];
+        let clear = ClearSend {
+            color,
+            outputs: Box::new([]),
+            match_by: OutputMatch::Name,
+            transition: None,
+        };
+        send_request(&mut daemon, RequestSend::Clear(clear.create_request()));
+
+        let output = daemon.wallpapers[0].borrow().output_id();
+        daemon.wallpapers[0]
+            .borrow_mut()
+            .set_dimensions(DIM.0 as i32 * 2, DIM.1 as i32);
+        daemon.done(output);
+
+        let screenshot = screenshot(&mut daemon, "TEST-0").unwrap();
+        assert_eq!(screenshot.dim, (DIM.0 * 2, DIM.1));
+        assert!(screenshot
+            .img
+            .chunks_exact(4)
+            .all(|pixel| pixel[..3] == color));
+    }
+
+    #[test]
+    fn rotating_an_output_swaps_its_dimensions() {
+        use wayland::interfaces::wl_output::transform;
+
+        let mut daemon = configured_daemon(&[263]);
+        let mut wallpaper = daemon.wallpapers[0].borrow_mut();
+        wallpaper.set_dimensions(16, 8);
+        assert!(wallpaper.commit_surface_changes(&mut daemon.objman, false));
+
+        for (wl_transform, expected, dim) in [
+            (transform::_90, Transform::Rotate90, (8, 16)),
+            (transform::FLIPPED_270, Transform::Flipped270, (8, 16)),
+            (transform::_180, Transform::Rotate180, (16, 8)),
+            (transform::FLIPPED_90, Transform::Flipped90, (8, 16)),
+        ] {
+            wallpaper.set_transform(wl_transform);
+            wallpaper.commit_surface_changes(&mut daemon.objman, false);
+            let info = wallpaper.get_bg_info(PixelFormat::Xrgb, false, None);
+            assert_eq!(info.transform, expected);
+            assert_eq!(info.dim, dim);
+            // nothing changed, so there is nothing to reconfigure
+            assert!(!wallpaper.commit_surface_changes(&mut daemon.objman, false));
+        }
+    }
+
+    #[test]
+    fn clear_can_match_outputs_by_description() {
+        let mut daemon = configured_daemon(&[260, 261, 262]);
+        let descs = [
+            "Dell Inc. DELL U2720Q",
+            "dell inc. dell u2720q",
+            "BenQ GW2480",
+        ];
+        for (wallpaper, desc) in daemon.wallpapers.iter().zip(descs) {
+            let mut wallpaper = wallpaper.borrow_mut();
+            wallpaper.set_desc(desc.to_string());
+            wallpaper.commit_surface_changes(&mut daemon.objman, false);
+        }
+        let clear = |daemon: &mut Daemon, color: [u8; 3], outputs: &[&str], match_by| {
+            let clear = ClearSend {
+                color,
+                outputs: outputs.iter().map(|o| o.to_string()).collect(),
+                match_by,
+                transition: None,
+            };
+            send_request(daemon, RequestSend::Clear(clear.create_request()));
+        };
+        let colors = |daemon: &Daemon| -> Vec<BgImg> {
+            daemon
+                .wallpapers_info()
+                .iter()
+                .map(|i| i.img.clone())
+                .collect()
+        };
+
+        clear(&mut daemon, [0, 0, 0], &[], OutputMatch::Name);
+        // both identical monitors match, whatever the case
+        clear(
+            &mut daemon,
+            [1, 1, 1],
+            &["U2720q"],
+            OutputMatch::Description,
+        );
+        assert!(
+            colors(&daemon)
+                == [
+                    BgImg::Color([1, 1, 1]),
+                    BgImg::Color([1, 1, 1]),
+                    BgImg::Color([0, 0, 0])
+                ]
+        );
+        // names are not descriptions
+        clear(
+            &mut daemon,
+            [2, 2, 2],
+            &["TEST-2"],
+            OutputMatch::Description,
+        );
+        assert!(colors(&daemon)[2] == BgImg::Color([0, 0, 0]));
+        assert_eq!(daemon.wallpapers_info()[2].desc, "BenQ GW2480");
+
+        // names with a `desc:` prefix are matched against descriptions too
+        clear(
+            &mut daemon,
+            [3, 3, 3],
+            &["TEST-0", "desc:benq"],
+            OutputMatch::Name,
+        );
+        assert!(
+            colors(&daemon)
+                == [
+                    BgImg::Color([3, 3, 3]),
+                    BgImg::Color([1, 1, 1]),
+                    BgImg::Color([3, 3, 3])
+                ]
+        );
+    }
+
+    #[test]
+    fn pausing_only_affects_the_requested_outputs() {
+        let mut daemon = configured_daemon(&[250, 251]);
+        let paused = |daemon: &Daemon| -> Vec<bool> {
+            daemon.wallpapers_info().iter().map(|i| i.paused).collect()
+        };
+
+        assert!(send_pause(&mut daemon, &["TEST-0"], Some(true)));
+        assert!(send_pause(&mut daemon, &["TEST-0"], Some(true)));
+        assert_eq!(paused(&daemon), [true, false]);
+
+        // toggling pauses everything as long as something is still running
+        assert!(send_pause(&mut daemon, &[], None));
+        assert_eq!(paused(&daemon), [true, true]);
+        assert!(!send_pause(&mut daemon, &[], None));
+        assert_eq!(paused(&daemon), [false, false]);
+
+        // an animation shared with a paused output stays frozen until it is resumed
+        send_pause(&mut daemon, &["TEST-1"], Some(true));
+        send_animation(&mut daemon, Duration::ZERO, 1, 1.0);
+        for _ in 0..10 {
+            draw_frame(&mut daemon);
+        }
+        assert_eq!(daemon.image_animators.len(), 1);
+        assert!(!send_pause(&mut daemon, &["TEST-1"], Some(false)));
+        for _ in 0..10 {
+            draw_frame(&mut daemon);
+        }
+        assert!(daemon.image_animators.is_empty());
+    }
+
+    #[test]
+    fn set_layer_only_moves_the_requested_outputs_and_keeps_their_image() {
+        let mut daemon = configured_daemon(&[130, 131]);
+        send_img(&mut daemon);
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        let before = screenshot(&mut daemon, "TEST-1").unwrap().img;
+
+        let request = LayerSend {
+            layer: Layer::Overlay,
+            outputs: Box::new(["TEST-1".to_string()]),
+        };
+        let answer = send_request(&mut daemon, RequestSend::SetLayer(request.create_request()));
+        assert!(matches!(answer, Answer::Ok));
+        assert_eq!(daemon.wallpapers[0].borrow().layer(), Layer::Background);
+        assert_eq!(daemon.wallpapers[1].borrow().layer(), Layer::Overlay);
+        assert_eq!(screenshot(&mut daemon, "TEST-1").unwrap().img, before);
+        // only changing every output changes where new ones go
+        assert_eq!(daemon.layer, Layer::Background);
+
+        let request = LayerSend {
+            layer: Layer::Top,
+            outputs: Box::new([]),
+        };
+        send_request(&mut daemon, RequestSend::SetLayer(request.create_request()));
+        assert!(daemon
+            .wallpapers
+            .iter()
+            .all(|w| w.borrow().layer() == Layer::Top));
+        assert_eq!(daemon.layer, Layer::Top);
+    }
+
+    #[test]
+    fn removing_output_mid_transition_keeps_animating_the_others() {
+        let mut daemon = configured_daemon(&[10, 11]);
+        send_img(&mut daemon);
+        draw_frame(&mut daemon);
+        assert_eq!(daemon.transition_animators.len(), 1);
+        assert_eq!(daemon.transition_animators[0].wallpapers.len(), 2);
+
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 10);
+        assert_eq!(daemon.wallpapers.len(), 1);
+        assert_eq!(daemon.transition_animators.len(), 1);
+        assert_eq!(daemon.transition_animators[0].wallpapers.len(), 1);
+        assert!(daemon.transition_animators[0].wallpapers[0]
+            .borrow()
+            .has_output_name(11));
+
+        // the remaining output must still be drawable
+        draw_frame(&mut daemon);
+        assert_eq!(daemon.transition_animators.len(), 1);
+    }
+
+    #[test]
+    fn removing_every_output_mid_transition_drops_the_transition() {
+        let mut daemon = configured_daemon(&[20, 21]);
+        send_img(&mut daemon);
+        draw_frame(&mut daemon);
+
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 20);
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 21);
+        assert!(daemon.wallpapers.is_empty());
+        assert!(daemon.transition_animators.is_empty());
+
+        // drawing with nothing left must not panic
+        draw_frame(&mut daemon);
+    }
+
+    #[test]
+    fn query_reports_transition_progress() {
+        let mut daemon = configured_daemon(&[110]);
+        let transition = |daemon: &mut Daemon| match send_request(daemon, RequestSend::Query) {
+            Answer::Info(info) => info[0].transition,
+            _ => panic!("daemon did not answer with info"),
+        };
+        assert!(transition(&mut daemon).is_none());
+
+        send_img(&mut daemon);
+        let progress = transition(&mut daemon).unwrap();
+        // send_img uses a simple transition with step 1, at u16::MAX fps
+        let expected = Duration::from_nanos(1_000_000_000 / u16::MAX as u64) * 255;
+        // durations go through the socket in whole milliseconds
+        assert_eq!(
+            progress.duration,
+            Duration::from_millis(expected.as_millis() as u64)
+        );
+        assert!(progress.remaining <= progress.duration);
+
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        assert!(transition(&mut daemon).is_none());
+    }
+
+    #[test]
+    fn outputs_outside_the_filter_are_released() {
+        use wayland::interfaces::{wl_output, wl_registry};
+
+        let init_state = InitState {
+            output_names: vec![120, 121],
+            fractional_scale: None,
+            output_power: None,
+            objman: ObjectManager::new(),
+            pixel_format: PixelFormat::Xrgb,
+        };
+        let mut daemon = Daemon::new(
+            init_state,
+            true,
+            false,
+            Layer::Background,
+            String::new(),
+            OutputFilter::new("DP-*"),
+        );
+        // we can't know which outputs to manage before they tell us their names
+        assert!(daemon.wallpapers.is_empty());
+        let outputs: Vec<_> = daemon.pending_outputs.iter().map(|p| p.output()).collect();
+        assert_eq!(outputs.len(), 2);
+
+        for (&output, name) in outputs.iter().zip(["DP-1", "HDMI-A-1"]) {
+            wl_output::EvHandler::mode(&mut daemon, output, 0, DIM.0 as i32, DIM.1 as i32, 0);
+            wl_output::EvHandler::name(&mut daemon, output, name);
+            wl_output::EvHandler::done(&mut daemon, output);
+        }
+        assert!(daemon.pending_outputs.is_empty());
+        assert_eq!(daemon.wallpapers.len(), 1);
+        assert!(daemon.wallpapers[0].borrow().has_name("DP-1"));
+        assert_eq!(daemon.wallpapers[0].borrow().get_dimensions(), DIM);
+        assert_eq!(daemon.wallpapers_info().len(), 1);
+        // the ignored output's late events must not be mistaken for an unknown output's
+        assert!(daemon.is_recently_removed_output(outputs[1]));
+
+        // hotplugged outputs go through the same filter
+        wl_registry::EvHandler::global(&mut daemon, 122, "wl_output", 4);
+        assert_eq!(daemon.pending_outputs.len(), 1);
+        wl_registry::EvHandler::global_remove(&mut daemon, 122);
+        assert!(daemon.pending_outputs.is_empty());
+        assert_eq!(daemon.wallpapers.len(), 1);
+    }
+
+    #[test]
+    fn query_reports_output_position_and_refresh_rate() {
+        use wayland::interfaces::wl_output;
+
+        let mut daemon = configured_daemon(&[190]);
+        let output = daemon.wallpapers[0].borrow().output_id();
+        wl_output::EvHandler::geometry(&mut daemon, output, 1920, -40, 0, 0, 0, "", "", 0);
+        wl_output::EvHandler::mode(&mut daemon, output, 1, DIM.0 as i32, DIM.1 as i32, 59951);
+        wl_output::EvHandler::done(&mut daemon, output);
+        let info = &daemon.wallpapers_info()[0];
+        assert_eq!(info.position, (1920, -40));
+        assert_eq!(info.refresh, 59951);
+
+        // a new mode, as after a resolution change, updates the refresh rate
+        wl_output::EvHandler::mode(&mut daemon, output, 1, DIM.0 as i32, DIM.1 as i32, 144000);
+        wl_output::EvHandler::done(&mut daemon, output);
+        assert_eq!(daemon.wallpapers_info()[0].refresh, 144000);
+    }
+
+    #[test]
+    fn removing_an_unknown_output_does_nothing() {
+        let mut daemon = configured_daemon(&[30]);
+        send_img(&mut daemon);
+
+        // a global that isn't one of our outputs
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 31);
+        assert_eq!(daemon.wallpapers.len(), 1);
+        assert_eq!(daemon.transition_animators.len(), 1);
+
+        // an output we already removed
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 30);
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 30);
+        assert!(daemon.wallpapers.is_empty());
+        assert!(daemon.transition_animators.is_empty());
+    }
+
+    #[test]
+    fn global_remove_mid_transition_releases_the_output() {
+        let mut daemon = configured_daemon(&[50, 51]);
+        send_img(&mut daemon);
+        draw_frame(&mut daemon);
+        let removed = daemon
+            .wallpapers
+            .iter()
+            .find(|w| w.borrow().has_output_name(50))
+            .unwrap()
+            .borrow()
+            .output_id();
+        test_backend::take_requests();
+
+        // wl_registry.global_remove
+        test_backend::push_event(globals::WL_REGISTRY, 1, &[50]);
+        dispatch_events(&mut daemon);
+        assert_eq!(daemon.wallpapers.len(), 1);
+        assert_eq!(daemon.transition_animators[0].wallpapers.len(), 1);
+        // wl_output.release
+        assert!(test_backend::take_requests()
+            .iter()
+            .any(|request| request.object == removed && request.op == 0));
+
+        // wl_output.done, which the compositor sent before it got our release
+        test_backend::push_event(removed, 2, &[]);
+        dispatch_events(&mut daemon);
+
+        // wl_surface.commit, for the output that is left
+        draw_frame(&mut daemon);
+        let remaining = daemon.wallpapers[0].borrow();
+        assert!(test_backend::take_requests()
+            .iter()
+            .any(|request| request.op == 6 && remaining.has_surface(request.object)));
+    }
+
+    #[test]
+    fn screenshot_samples_the_frame_being_animated() {
+        let mut daemon = configured_daemon(&[40]);
+        send_img(&mut daemon);
+        draw_frame(&mut daemon);
+
+        let first = screenshot(&mut daemon, "TEST-0").unwrap();
+        assert_eq!(first.dim, DIM);
+        assert_eq!(first.format, PixelFormat::Xrgb);
+        assert_eq!(
+            first.img.len(),
+            DIM.0 as usize * DIM.1 as usize * PixelFormat::Xrgb.channels() as usize
+        );
+
+        // we are still in the middle of the transition, so the next frame must be different
+        draw_frame(&mut daemon);
+        let second = screenshot(&mut daemon, "TEST-0").unwrap();
+        assert_ne!(first.img, second.img);
+    }
+
+    #[test]
+    fn exiting_mid_transition_commits_its_last_frame() {
+        let mut daemon = configured_daemon(&[60]);
+        send_color(&mut daemon, 0, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+        draw_frame(&mut daemon);
+        send_color(&mut daemon, 255, TransitionType::Wipe, 60.0);
+        draw_frame(&mut daemon);
+        draw_frame(&mut daemon);
+        assert_eq!(daemon.transition_animators.len(), 1);
+        let halfway = screenshot(&mut daemon, "TEST-0").unwrap();
+        assert!(halfway.img.iter().any(|&byte| byte != 255));
+        test_backend::take_requests();
+
+        daemon.finish_transitions();
+        assert!(daemon.transition_animators.is_empty());
+        let last = screenshot(&mut daemon, "TEST-0").unwrap();
+        assert!(last.img.iter().all(|&byte| byte == 255));
+        // wl_surface.commit
+        let wallpaper = daemon.wallpapers[0].borrow();
+        assert!(test_backend::take_requests()
+            .iter()
+            .any(|request| request.op == 6 && wallpaper.has_surface(request.object)));
+    }
+
+    #[test]
+    fn draws_in_the_preferred_buffer_transform_unless_the_image_was_transformed() {
+        use wayland::interfaces::wl_output::transform;
+
+        let mut daemon = configured_daemon(&[80]);
+        let surface = daemon.wallpapers[0].borrow().surface();
+        let buffer_transforms = || -> Vec<Vec<u32>> {
+            // wl_surface.set_buffer_transform
+            test_backend::take_requests()
+                .into_iter()
+                .filter(|request| request.object == surface && request.op == 7)
+                .map(|request| request.args)
+                .collect()
+        };
+
+        // wl_surface.preferred_buffer_transform
+        test_backend::push_event(surface, 3, &[transform::_90]);
+        dispatch_events(&mut daemon);
+        send_color(&mut daemon, 255, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+        draw_frame(&mut daemon);
+        // the compositor hears about it once, along with the first buffer in that transform
+        assert_eq!(buffer_transforms(), [[transform::_90]]);
+
+        let mut builder = ImageRequestBuilder::new(test_transition(TransitionType::None, 0.0), "");
+        builder.set_cache_writes(false);
+        builder.set_transformed(true);
+        push_color(&mut builder, 128);
+        send_request(&mut daemon, RequestSend::Img(builder.build()));
+        draw_frame(&mut daemon);
+        assert_eq!(buffer_transforms(), [[transform::NORMAL]]);
+        // the screenshot is upright either way
+        let screenshot = screenshot(&mut daemon, "TEST-0").unwrap();
+        assert!(screenshot.img.iter().all(|&byte| byte == 128));
+
+        // images that weren't transformed go back to what the compositor prefers
+        send_color(&mut daemon, 0, TransitionType::None, 0.0);
+        draw_frame(&mut daemon);
+        assert_eq!(buffer_transforms(), [[transform::_90]]);
+    }
+
+    #[test]
+    fn screenshot_of_unknown_output_is_none() {
+        let mut daemon = configured_daemon(&[50]);
+        assert!(screenshot(&mut daemon, "TEST-0").is_some());
+        assert!(screenshot(&mut daemon, "DOES-NOT-EXIST").is_none());
+    }
+
+    #[test]
+    fn late_events_for_removed_output_are_dropped() {
+        use wayland::interfaces::{wl_buffer, wl_output};
+
+        let mut daemon = configured_daemon(&[60, 61]);
+        send_img(&mut daemon);
+        let output = daemon.wallpapers[0].borrow().output_id();
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 60);
+
+        // the compositor sent these before it processed our release request
+        wl_output::EvHandler::geometry(&mut daemon, output, 0, 0, 0, 0, 0, "", "", 0);
+        wl_output::EvHandler::mode(&mut daemon, output, 0, 16, 16, 60);
+        wl_output::EvHandler::scale(&mut daemon, output, 2);
+        wl_output::EvHandler::name(&mut daemon, output, "TEST-0");
+        wl_output::EvHandler::description(&mut daemon, output, "removed");
+        wl_output::EvHandler::done(&mut daemon, output);
+        wl_buffer::EvHandler::release(&mut daemon, output);
+
+        assert!(daemon.is_recently_removed_output(output));
+        assert_eq!(daemon.wallpapers.len(), 1);
+        assert!(daemon.wallpapers[0].borrow().has_name("TEST-1"));
+        assert_eq!(daemon.wallpapers[0].borrow().get_dimensions(), DIM);
+
+        // the daemon must keep working for the remaining output
+        draw_frame(&mut daemon);
+        assert!(screenshot(&mut daemon, "TEST-1").is_some());
+    }
+
+    #[test]
+    fn watchdog_caps_the_poll_timeout() {
+        let cap = Some(Duration::from_micros(2500));
+        assert_eq!(PollTime::Never.capped(None), -1);
+        assert_eq!(PollTime::Never.capped(cap), 3);
+        assert_eq!(PollTime::Timeout(10_000).capped(cap), 3);
+        assert_eq!(PollTime::Timeout(1).capped(cap), 1);
+        assert_eq!(PollTime::Instant.capped(cap), 0);
+        assert_eq!(PollTime::Short.capped(Some(Duration::ZERO)), 0);
+    }
+
+    #[test]
+    fn removed_outputs_are_forgotten_after_the_grace_period() {
+        let mut daemon = configured_daemon(&[70]);
+        let output = daemon.wallpapers[0].borrow().output_id();
+        wayland::interfaces::wl_registry::EvHandler::global_remove(&mut daemon, 70);
+        assert!(daemon.is_recently_removed_output(output));
+
+        daemon.removed_outputs[0].1 -= REMOVED_OUTPUT_GRACE;
+        assert!(!daemon.is_recently_removed_output(output));
+        assert!(daemon.removed_outputs.is_empty());
+    }
+
+    #[test]
+    fn powered_off_outputs_are_not_animated() {
+        use wayland::interfaces::zwlr_output_power_v1::{mode, EvHandler};
+
+        let mut daemon = configured_daemon_with_output_power(&[90], true);
+        send_img(&mut daemon);
+        draw_frame(&mut daemon);
+        let output_power = daemon.wallpapers[0].borrow().output_power().unwrap();
+
+        EvHandler::mode(&mut daemon, output_power, mode::OFF);
+        let before = screenshot(&mut daemon, "TEST-0").unwrap();
+        draw_frame(&mut daemon);
+        draw_frame(&mut daemon);
+        assert!(matches!(daemon.poll_time, PollTime::Never));
+        assert_eq!(screenshot(&mut daemon, "TEST-0").unwrap().img, before.img);
+
+        // turning it back on must draw a new frame right away
+        EvHandler::mode(&mut daemon, output_power, mode::ON);
+        assert_ne!(screenshot(&mut daemon, "TEST-0").unwrap().img, before.img);
+    }
+
+    #[test]
+    fn failed_output_power_counts_as_powered_on() {
+        use wayland::interfaces::zwlr_output_power_v1::{mode, EvHandler};
+
+        let mut daemon = configured_daemon_with_output_power(&[100], true);
+        let output_power = daemon.wallpapers[0].borrow().output_power().unwrap();
+        EvHandler::mode(&mut daemon, output_power, mode::OFF);
+        assert!(!daemon.wallpapers[0].borrow().is_powered_on());
+
+        EvHandler::failed(&mut daemon, output_power);
+        assert!(daemon.wallpapers[0].borrow().is_powered_on());
+        assert!(daemon.wallpapers[0].borrow().output_power().is_none());
+    }
+
+    #[test]
+    fn subscribers_get_events_until_they_hang_up() {
+        let mut daemon = configured_daemon(&[240]);
+        let (client, server) = rustix::net::socketpair(
+            rustix::net::AddressFamily::UNIX,
+            rustix::net::SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let client = IpcSocket::<Client>::new(client);
+        RequestSend::Subscribe.send(&client).unwrap();
+        daemon.recv_socket_msg(IpcSocket::new(server));
+        assert!(matches!(
+            Answer::receive(client.recv().unwrap()),
+            Answer::Ok
+        ));
+
+        send_color(&mut daemon, 0xff, TransitionType::None, 0.0);
+        daemon.notify_subscribers();
+        let next_event = || match Answer::receive(client.recv().unwrap()) {
+            Answer::Event(event) => event,
+            _ => panic!("daemon sent something other than an event"),
+        };
+        assert_eq!(next_event(), Event::OutputAdded("TEST-0".to_string()));
+        assert_eq!(
+            next_event(),
+            Event::WallpaperChanged {
+                output: "TEST-0".to_string(),
+                img: BgImg::Img("ff".to_string()),
+            }
+        );
+        assert_eq!(next_event(), Event::TransitionStarted("TEST-0".to_string()));
+        while !daemon.transition_animators.is_empty() {
+            draw_frame(&mut daemon);
+        }
+        daemon.notify_subscribers();
+        assert_eq!(
+            next_event(),
+            Event::TransitionFinished("TEST-0".to_string())
+        );
+
+        send_pause(&mut daemon, &["TEST-0"], Some(true));
+        daemon.notify_subscribers();
+        assert_eq!(next_event(), Event::Paused("TEST-0".to_string()));
+        // there is nothing to tell about an output paused and resumed in between notifications
+        send_pause(&mut daemon, &["TEST-0"], Some(false));
+        send_pause(&mut daemon, &["TEST-0"], Some(true));
+        daemon.notify_subscribers();
+        send_pause(&mut daemon, &["TEST-0"], Some(false));
+        daemon.notify_subscribers();
+        assert_eq!(next_event(), Event::Resumed("TEST-0".to_string()));
+
+        daemon.remove_wallpaper(0);
+        daemon.notify_subscribers();
+        assert_eq!(next_event(), Event::OutputRemoved("TEST-0".to_string()));
+
+        drop(client);
+        daemon.events.push(Event::OutputAdded("TEST-1".to_string()));
+        daemon.notify_subscribers();
+        assert!(daemon.subscribers.is_empty());
+    }
+
+    #[test]
+    fn subscribers_that_hang_up_are_dropped_without_sending_them_anything() {
+        let mut daemon = configured_daemon(&[241]);
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let (client, server) = rustix::net::socketpair(
+                rustix::net::AddressFamily::UNIX,
+                rustix::net::SocketType::STREAM,
+                rustix::net::SocketFlags::CLOEXEC,
+                None,
+            )
+            .unwrap();
+            let client = IpcSocket::<Client>::new(client);
+            RequestSend::Subscribe.send(&client).unwrap();
+            daemon.recv_socket_msg(IpcSocket::new(server));
+            clients.push(client);
+        }
+        assert_eq!(daemon.subscribers.len(), 3);
+
+        drop(clients.remove(1));
+        let mut fds: Vec<_> = daemon
+            .subscribers
+            .iter()
+            .map(|subscriber| PollFd::new(subscriber.as_fd(), PollFlags::IN))
+            .collect();
+        poll(&mut fds, 0).unwrap();
+        let flagged: Vec<bool> = fds.iter().map(|fd| !fd.revents().is_empty()).collect();
+        drop(fds);
+        assert_eq!(flagged, [false, true, false]);
+
+        daemon.drop_hung_up_subscribers(&flagged);
+        assert_eq!(daemon.subscribers.len(), 2);
+        // the ones left are the ones still listening
+        daemon.events.push(Event::OutputAdded("TEST-1".to_string()));
+        daemon.notify_subscribers();
+        assert_eq!(daemon.subscribers.len(), 2);
+    }
+}
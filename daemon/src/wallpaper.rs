@@ -1,4 +1,6 @@
-use common::ipc::{BgImg, BgInfo, PixelFormat, Scale};
+use common::ipc::{
+    BgImg, BgInfo, Event, Layer, PixelFormat, Scale, Screenshot, Transform, TransitionProgress,
+};
 use log::{debug, error, warn};
 
 use std::{cell::RefCell, num::NonZeroI32, rc::Rc, sync::atomic::AtomicBool};
@@ -6,7 +8,8 @@ use std::{cell::RefCell, num::NonZeroI32, rc::Rc, sync::atomic::AtomicBool};
 use crate::wayland::{
     bump_pool::BumpPool,
     interfaces::{
-        wl_output, wl_surface, wp_fractional_scale_v1, wp_viewport, zwlr_layer_surface_v1,
+        wl_output, wl_surface, wp_fractional_scale_v1, wp_viewport, zwlr_layer_shell_v1,
+        zwlr_layer_surface_v1, zwlr_output_power_v1,
     },
     ObjectId, ObjectManager, WlDynObj,
 };
@@ -42,6 +45,10 @@ struct WallpaperInner {
     height: NonZeroI32,
     scale_factor: Scale,
     transform: u32,
+    /// where the output is in the compositor's global space
+    position: (i32, i32),
+    /// in mHz, or 0 if the compositor doesn't know
+    refresh: u32,
 }
 
 impl Default for WallpaperInner {
@@ -53,6 +60,8 @@ impl Default for WallpaperInner {
             height: unsafe { NonZeroI32::new_unchecked(4) },
             scale_factor: Scale::Whole(unsafe { NonZeroI32::new_unchecked(1) }),
             transform: wl_output::transform::NORMAL,
+            position: (0, 0),
+            refresh: 0,
         }
     }
 }
@@ -64,7 +73,16 @@ pub(super) struct Wallpaper {
     wp_viewport: ObjectId,
     #[allow(unused)]
     wp_fractional: Option<ObjectId>,
+    /// only present if the compositor supports `zwlr_output_power_manager_v1`
+    output_power: Option<ObjectId>,
+    /// whether the output is turned on. We assume it is until the compositor tells us otherwise
+    powered_on: bool,
+    /// whether `swww pause` froze this output's animated image and slideshow
+    paused: bool,
     layer_surface: ObjectId,
+    layer: Layer,
+    /// the namespace of the daemon this wallpaper belongs to
+    namespace: String,
 
     inner: WallpaperInner,
     inner_staging: WallpaperInner,
@@ -73,9 +91,95 @@ pub(super) struct Wallpaper {
 
     frame_callback_handler: FrameCallbackHandler,
     img: BgImg,
+    /// whether `img` changed since we last told our subscribers about it
+    img_changed: bool,
+    /// whether our subscribers know about this output. We only tell them once we know its name
+    announced: bool,
+    /// whether we last told our subscribers this output was paused
+    announced_paused: bool,
+    /// whether we last told our subscribers this output was in a transition
+    announced_transition: bool,
+    /// the transform the compositor would like our buffers in, from
+    /// `wl_surface::preferred_buffer_transform`
+    preferred_transform: u32,
+    /// whether the image we draw was already rotated or flipped with `swww img --transform`, in
+    /// which case we leave our buffers upright
+    transformed: bool,
+    /// the transform our buffers are in, if we haven't told the compositor about it yet
+    pending_buffer_transform: Option<u32>,
     pool: BumpPool,
 }
 
+/// Something the compositor told us about an output before we created its wallpaper
+pub(crate) enum OutputEvent {
+    Transform(u32),
+    Position(i32, i32),
+    /// width, height and refresh rate
+    Mode(i32, i32, i32),
+    Scale(Scale),
+    Name(String),
+    Description(String),
+}
+
+/// An output whose name we don't know yet, so we can't tell whether we should manage it. We hold
+/// on to its events until the first `done`, to replay them on its wallpaper if we create one
+pub(crate) struct PendingOutput {
+    output: ObjectId,
+    output_name: u32,
+    name: Option<String>,
+    events: Vec<OutputEvent>,
+}
+
+impl PendingOutput {
+    pub(crate) fn new(objman: &mut ObjectManager, output_name: u32) -> Self {
+        debug!("New pending output: {output_name}");
+        Self {
+            output: bind_output(objman, output_name),
+            output_name,
+            name: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub(crate) fn output(&self) -> ObjectId {
+        self.output
+    }
+
+    pub(crate) fn output_name(&self) -> u32 {
+        self.output_name
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(crate) fn push(&mut self, event: OutputEvent) {
+        if let OutputEvent::Name(name) = &event {
+            self.name = Some(name.clone());
+        }
+        self.events.push(event);
+    }
+
+    pub(crate) fn into_events(self) -> Vec<OutputEvent> {
+        self.events
+    }
+
+    /// Releases the `wl_output`, for outputs we've decided not to manage
+    pub(crate) fn release(self) {
+        if let Err(e) = wl_output::req::release(self.output) {
+            error!("error releasing wl_output: {e:?}");
+        }
+    }
+}
+
+/// Binds the `wl_output` global named `output_name`
+pub(crate) fn bind_output(objman: &mut ObjectManager, output_name: u32) -> ObjectId {
+    use crate::wayland::{self, interfaces::wl_registry};
+    let output = objman.create(wayland::WlDynObj::Output);
+    wl_registry::req::bind(output_name, output, "wl_output", 4).unwrap();
+    output
+}
+
 impl std::cmp::PartialEq for Wallpaper {
     fn eq(&self, other: &Self) -> bool {
         self.output_name == other.output_name
@@ -83,15 +187,19 @@ impl std::cmp::PartialEq for Wallpaper {
 }
 
 impl Wallpaper {
+    /// `output` must already be bound to the `wl_output` global named `output_name`
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         objman: &mut ObjectManager,
         pixel_format: PixelFormat,
         fractional_scale_manager: Option<ObjectId>,
+        output_power_manager: Option<ObjectId>,
         output_name: u32,
+        output: ObjectId,
+        layer: Layer,
+        namespace: &str,
     ) -> Self {
         use crate::wayland::{self, interfaces::*};
-        let output = objman.create(wayland::WlDynObj::Output);
-        wl_registry::req::bind(output_name, output, "wl_output", 4).unwrap();
 
         let wl_surface = objman.create(wayland::WlDynObj::Surface);
         wl_compositor::req::create_surface(wl_surface).unwrap();
@@ -107,8 +215,8 @@ impl Wallpaper {
             layer_surface,
             wl_surface,
             Some(output),
-            zwlr_layer_shell_v1::layer::BACKGROUND,
-            "swww-daemon",
+            layer_shell_layer(layer),
+            &layer_surface_namespace(namespace),
         )
         .unwrap();
 
@@ -126,6 +234,13 @@ impl Wallpaper {
             None
         };
 
+        let output_power = output_power_manager.map(|power_man| {
+            let output_power = objman.create(wayland::WlDynObj::OutputPower);
+            zwlr_output_power_manager_v1::req::get_output_power(power_man, output_power, output)
+                .unwrap();
+            output_power
+        });
+
         let inner = WallpaperInner::default();
         let inner_staging = WallpaperInner::default();
 
@@ -153,26 +268,78 @@ impl Wallpaper {
             wl_surface,
             wp_viewport,
             wp_fractional,
+            output_power,
+            powered_on: true,
+            paused: false,
             layer_surface,
+            layer,
+            namespace: namespace.to_string(),
             inner,
             inner_staging,
             configured: AtomicBool::new(false),
             frame_callback_handler,
             img: BgImg::Color([0, 0, 0]),
+            img_changed: false,
+            announced: false,
+            announced_paused: false,
+            announced_transition: false,
+            preferred_transform: wl_output::transform::NORMAL,
+            transformed: false,
+            pending_buffer_transform: None,
             pool,
         }
     }
 
-    pub fn get_bg_info(&self, pixel_format: PixelFormat) -> BgInfo {
+    pub fn get_bg_info(
+        &self,
+        pixel_format: PixelFormat,
+        animated: bool,
+        transition: Option<TransitionProgress>,
+    ) -> BgInfo {
         BgInfo {
             name: self.inner.name.clone().unwrap_or("?".to_string()),
+            desc: self.inner.desc.clone().unwrap_or_default(),
             dim: (
                 self.inner.width.get() as u32,
                 self.inner.height.get() as u32,
             ),
             scale_factor: self.inner.scale_factor,
+            transform: match self.inner.transform {
+                wl_output::transform::_90 => Transform::Rotate90,
+                wl_output::transform::_180 => Transform::Rotate180,
+                wl_output::transform::_270 => Transform::Rotate270,
+                wl_output::transform::FLIPPED => Transform::Flipped,
+                wl_output::transform::FLIPPED_90 => Transform::Flipped90,
+                wl_output::transform::FLIPPED_180 => Transform::Flipped180,
+                wl_output::transform::FLIPPED_270 => Transform::Flipped270,
+                _ => Transform::Normal,
+            },
+            position: self.inner.position,
+            refresh: self.inner.refresh,
             img: self.img.clone(),
             pixel_format,
+            paused: self.paused,
+            animated,
+            layer: self.layer,
+            namespace: layer_surface_namespace(&self.namespace),
+            transition,
+        }
+    }
+
+    /// Applies everything the compositor told us while the output was still pending
+    pub(crate) fn replay(&mut self, events: Vec<OutputEvent>) {
+        for event in events {
+            match event {
+                OutputEvent::Transform(transform) => self.set_transform(transform),
+                OutputEvent::Position(x, y) => self.set_position(x, y),
+                OutputEvent::Mode(width, height, refresh) => {
+                    self.set_dimensions(width, height);
+                    self.set_refresh(refresh);
+                }
+                OutputEvent::Scale(scale) => self.set_scale(scale),
+                OutputEvent::Name(name) => self.set_name(name),
+                OutputEvent::Description(desc) => self.set_desc(desc),
+            }
         }
     }
 
@@ -215,6 +382,50 @@ impl Wallpaper {
         self.inner_staging.transform = transform;
     }
 
+    /// Draws in `transform` from now on, unless the image was already transformed by the user
+    pub(super) fn set_preferred_transform(&mut self, transform: u32, pixel_format: PixelFormat) {
+        debug!(
+            "output {:?} - preferred buffer transform: {transform}",
+            self.inner.name
+        );
+        self.preferred_transform = transform;
+        self.update_buffer_transform(pixel_format);
+    }
+
+    /// Whether the image we are about to draw was already rotated or flipped with
+    /// `swww img --transform`, and so should be drawn as is
+    pub(super) fn set_transformed(&mut self, transformed: bool, pixel_format: PixelFormat) {
+        self.transformed = transformed;
+        self.update_buffer_transform(pixel_format);
+    }
+
+    fn update_buffer_transform(&mut self, pixel_format: PixelFormat) {
+        let transform = if self.transformed {
+            wl_output::transform::NORMAL
+        } else {
+            self.preferred_transform
+        };
+        if transform != self.pool.transform() {
+            self.pool.set_transform(transform, pixel_format);
+            // the compositor must only hear about it along with a buffer in the new transform
+            self.pending_buffer_transform = Some(transform);
+        }
+    }
+
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        self.inner_staging.position = (x, y);
+    }
+
+    /// in mHz, or 0 if the compositor hasn't told us
+    pub(super) fn refresh(&self) -> u32 {
+        self.inner.refresh
+    }
+
+    /// `refresh` is in mHz, as the compositor sends it
+    pub fn set_refresh(&mut self, refresh: i32) {
+        self.inner_staging.refresh = refresh.max(0) as u32;
+    }
+
     pub fn set_scale(&mut self, scale: Scale) {
         let staging = &mut self.inner_staging;
         if staging.scale_factor == scale {
@@ -253,33 +464,42 @@ impl Wallpaper {
         let inner = &mut self.inner;
         let staging = &self.inner_staging;
 
-        if (inner.name != staging.name && use_cache)
-            || (self.img.is_set()
-                && (inner.scale_factor != staging.scale_factor
-                    || inner.width != staging.width
-                    || inner.height != staging.height))
-        {
+        // the mode is given before the transform, but the surface is laid out after it. The
+        // compositor rotates what we draw along with the output, so we don't have to
+        let (width, height) = if matches!(
+            staging.transform,
+            transform::_90 | transform::_270 | transform::FLIPPED_90 | transform::FLIPPED_270
+        ) {
+            (staging.height, staging.width)
+        } else {
+            (staging.width, staging.height)
+        };
+
+        // an image processed for the old size would look blurry or misaligned, so we have it
+        // processed again from the original file. Colors are redrawn by the daemon itself
+        let resized = self.img.is_set()
+            && (inner.scale_factor != staging.scale_factor
+                || inner.width != width
+                || inner.height != height);
+        if (inner.name != staging.name && use_cache) || resized {
             let name = staging.name.clone().unwrap_or("".to_string());
+            let namespace = self.namespace.clone();
             std::thread::Builder::new()
                 .name("cache loader".to_string())
                 .stack_size(1 << 14)
                 .spawn(move || {
-                    if let Err(e) = common::cache::load(&name) {
+                    let result = if resized {
+                        common::cache::reload(&name, &namespace)
+                    } else {
+                        common::cache::load(&name, &namespace)
+                    };
+                    if let Err(e) = result {
                         warn!("failed to load cache: {e}");
                     }
                 })
                 .unwrap(); // builder only fails if `name` contains null bytes
         }
 
-        let (width, height) = if matches!(
-            staging.transform,
-            transform::_90 | transform::_270 | transform::FLIPPED_90 | transform::FLIPPED_270
-        ) {
-            (staging.height, staging.width)
-        } else {
-            (staging.width, staging.height)
-        };
-
         if staging.scale_factor != inner.scale_factor || staging.transform != inner.transform {
             match staging.scale_factor {
                 Scale::Whole(i) => {
@@ -297,6 +517,8 @@ impl Wallpaper {
 
         inner.scale_factor = staging.scale_factor;
         inner.transform = staging.transform;
+        inner.position = staging.position;
+        inner.refresh = staging.refresh;
         inner.name.clone_from(&staging.name);
         inner.desc.clone_from(&staging.desc);
         if (inner.width, inner.height) == (width, height) {
@@ -332,10 +554,74 @@ impl Wallpaper {
         }
     }
 
+    /// Whether the output's description contains `desc`, ignoring case
+    pub(super) fn has_description(&self, desc: &str) -> bool {
+        match self.inner.desc.as_ref() {
+            Some(d) => d.to_lowercase().contains(&desc.to_lowercase()),
+            None => false,
+        }
+    }
+
     pub(super) fn has_output(&self, output: ObjectId) -> bool {
         self.output == output
     }
 
+    pub(super) fn output_id(&self) -> ObjectId {
+        self.output
+    }
+
+    pub(super) fn has_output_power(&self, output_power: ObjectId) -> bool {
+        self.output_power == Some(output_power)
+    }
+
+    #[cfg(test)]
+    pub(super) fn output_power(&self) -> Option<ObjectId> {
+        self.output_power
+    }
+
+    pub(super) fn is_powered_on(&self) -> bool {
+        self.powered_on
+    }
+
+    pub(super) fn set_powered_on(&mut self, powered_on: bool) {
+        self.powered_on = powered_on;
+    }
+
+    pub(super) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Returns whether this changed anything
+    pub(super) fn set_paused(&mut self, paused: bool) -> bool {
+        if self.paused == paused {
+            return false;
+        }
+        debug!(
+            "output {:?} - {}",
+            self.inner.name,
+            if paused { "pausing" } else { "resuming" }
+        );
+        self.paused = paused;
+        true
+    }
+
+    /// Destroys our `zwlr_output_power_v1`, after the compositor told us it is no longer valid
+    ///
+    /// From here on, we treat the output as always on, like we do for compositors that don't
+    /// support output power management
+    pub(super) fn output_power_failed(&mut self) {
+        if let Some(output_power) = self.output_power.take() {
+            if let Err(e) = zwlr_output_power_v1::req::destroy(output_power) {
+                error!("error destroying zwlr_output_power_v1: {e:?}");
+            }
+        }
+        self.powered_on = true;
+    }
+
+    pub(super) fn output_name(&self) -> u32 {
+        self.output_name
+    }
+
     pub(super) fn has_output_name(&self, name: u32) -> bool {
         self.output_name == name
     }
@@ -344,6 +630,11 @@ impl Wallpaper {
         self.wl_surface == wl_surface
     }
 
+    #[cfg(test)]
+    pub(super) fn surface(&self) -> ObjectId {
+        self.wl_surface
+    }
+
     pub(super) fn has_layer_surface(&self, layer_surface: ObjectId) -> bool {
         self.layer_surface == layer_surface
     }
@@ -357,6 +648,14 @@ impl Wallpaper {
             .set_buffer_release_flag(buffer, rc_strong_count != 1)
     }
 
+    pub(super) fn sync_buffers(&mut self, pixel_format: PixelFormat) {
+        self.pool.sync_buffers(pixel_format);
+    }
+
+    pub(super) fn has_drawn(&self) -> bool {
+        self.pool.has_drawn()
+    }
+
     pub fn is_draw_ready(&self) -> bool {
         self.frame_callback_handler.done
     }
@@ -377,6 +676,12 @@ impl Wallpaper {
         (dim.0 as u32, dim.1 as u32)
     }
 
+    /// how many bytes each row of the canvas takes, which may be more than the image's width
+    /// because of padding
+    pub(super) fn stride(&self, pixel_format: PixelFormat) -> usize {
+        self.pool.stride(pixel_format)
+    }
+
     pub(super) fn canvas_change<F, T>(
         &mut self,
         objman: &mut ObjectManager,
@@ -389,6 +694,16 @@ impl Wallpaper {
         f(self.pool.get_drawable(objman, pixel_format))
     }
 
+    /// Returns the last frame we've drawn, which is what is currently on screen
+    pub(super) fn screenshot(&mut self, pixel_format: PixelFormat) -> Option<Screenshot> {
+        let dim = self.pool.dimensions();
+        self.pool.last_drawn(pixel_format).map(|img| Screenshot {
+            dim,
+            format: pixel_format,
+            img,
+        })
+    }
+
     pub(super) fn frame_callback_completed(&mut self) {
         self.frame_callback_handler.done = true;
     }
@@ -399,16 +714,108 @@ impl Wallpaper {
         pixel_format: PixelFormat,
         color: [u8; 3],
     ) {
+        let stride = self.stride(pixel_format);
+        let row_len = self.pool.dimensions().0 as usize * pixel_format.channels() as usize;
         self.canvas_change(objman, pixel_format, |canvas| {
-            for pixel in canvas.chunks_exact_mut(pixel_format.channels().into()) {
-                pixel[0..3].copy_from_slice(&color);
+            for row in canvas.chunks_exact_mut(stride) {
+                for pixel in row[..row_len].chunks_exact_mut(pixel_format.channels().into()) {
+                    pixel[0..3].copy_from_slice(&color);
+                }
             }
         })
     }
 
+    /// Moves the layer surface to `layer`. This only takes effect on the surface's next commit
+    ///
+    /// Returns whether the layer actually changed
+    pub(super) fn set_layer(&mut self, layer: Layer) -> bool {
+        if self.layer == layer {
+            return false;
+        }
+        debug!("output {:?} - moving to layer: {layer}", self.inner.name);
+        zwlr_layer_surface_v1::req::set_layer(self.layer_surface, layer_shell_layer(layer))
+            .unwrap();
+        self.layer = layer;
+        true
+    }
+
+    #[cfg(test)]
+    pub(super) fn layer(&self) -> Layer {
+        self.layer
+    }
+
+    pub(super) fn is_displaying(&self, path: &str) -> bool {
+        matches!(&self.img, BgImg::Img(img) if img == path)
+    }
+
+    pub(super) fn get_img_info(&self) -> BgImg {
+        self.img.clone()
+    }
+
     pub(super) fn set_img_info(&mut self, img_info: BgImg) {
         debug!("output {:?} - drawing: {}", self.inner.name, img_info);
         self.img = img_info;
+        self.img_changed = true;
+    }
+
+    /// Pushes everything that happened to this output since the last call into `events`.
+    /// `in_transition` is whether the output is currently in a transition, which only the daemon
+    /// knows
+    pub(super) fn take_events(&mut self, in_transition: bool, events: &mut Vec<Event>) {
+        let Some(name) = self.inner.name.as_ref() else {
+            return;
+        };
+        if !self.announced {
+            self.announced = true;
+            events.push(Event::OutputAdded(name.clone()));
+        }
+        if std::mem::take(&mut self.img_changed) {
+            events.push(Event::WallpaperChanged {
+                output: name.clone(),
+                img: self.img.clone(),
+            });
+        }
+        // pausing and resuming between two calls is no change at all
+        if self.announced_paused != self.paused {
+            self.announced_paused = self.paused;
+            events.push(if self.paused {
+                Event::Paused(name.clone())
+            } else {
+                Event::Resumed(name.clone())
+            });
+        }
+        if self.announced_transition != in_transition {
+            self.announced_transition = in_transition;
+            events.push(if in_transition {
+                Event::TransitionStarted(name.clone())
+            } else {
+                Event::TransitionFinished(name.clone())
+            });
+        }
+    }
+
+    /// The event telling our subscribers this output is gone, if they ever heard of it
+    pub(super) fn removed_event(&self) -> Option<Event> {
+        self.announced
+            .then(|| Event::OutputRemoved(self.inner.name.clone().unwrap_or_default()))
+    }
+}
+
+fn layer_shell_layer(layer: Layer) -> u32 {
+    match layer {
+        Layer::Background => zwlr_layer_shell_v1::layer::BACKGROUND,
+        Layer::Bottom => zwlr_layer_shell_v1::layer::BOTTOM,
+        Layer::Top => zwlr_layer_shell_v1::layer::TOP,
+        Layer::Overlay => zwlr_layer_shell_v1::layer::OVERLAY,
+    }
+}
+
+/// The namespace we give to the layer surfaces, so that compositors can tell daemons apart
+fn layer_surface_namespace(namespace: &str) -> String {
+    if namespace.is_empty() {
+        "swww-daemon".to_string()
+    } else {
+        format!("swww-daemon-{namespace}")
     }
 }
 
@@ -441,11 +848,12 @@ pub(crate) fn attach_buffers_and_damage_surfaces(
             let mut wallpaper = wallpaper.borrow_mut();
             let mut msg = MSG;
 
+            if let Some(transform) = wallpaper.pending_buffer_transform.take() {
+                wl_surface::req::set_buffer_transform(wallpaper.wl_surface, transform as i32)
+                    .unwrap();
+            }
             let buf = wallpaper.pool.get_commitable_buffer();
-            let inner = &wallpaper.inner;
-            let (width, height) = inner
-                .scale_factor
-                .mul_dim(inner.width.get(), inner.height.get());
+            let (width, height) = wallpaper.pool.buffer_dimensions();
 
             // attach
             msg[0..4].copy_from_slice(&wallpaper.wl_surface.get().to_ne_bytes());
@@ -453,8 +861,8 @@ pub(crate) fn attach_buffers_and_damage_surfaces(
 
             //damage buffer
             msg[20..24].copy_from_slice(&wallpaper.wl_surface.get().to_ne_bytes());
-            msg[36..40].copy_from_slice(&width.to_ne_bytes());
-            msg[40..44].copy_from_slice(&height.to_ne_bytes());
+            msg[36..40].copy_from_slice(&(width as i32).to_ne_bytes());
+            msg[40..44].copy_from_slice(&(height as i32).to_ne_bytes());
 
             // frame callback
             let callback = objman.create(WlDynObj::Callback);
@@ -468,6 +876,17 @@ pub(crate) fn attach_buffers_and_damage_surfaces(
 }
 
 /// commits multiple wallpapers at once with a single message through the socket
+/// Whether all of `wallpapers` are on outputs that are turned off
+pub(crate) fn all_powered_off(wallpapers: &[Rc<RefCell<Wallpaper>>]) -> bool {
+    !wallpapers.is_empty() && wallpapers.iter().all(|w| !w.borrow().is_powered_on())
+}
+
+/// Whether any of `wallpapers` is paused. Animations shared between outputs can only stop for all
+/// of them at once
+pub(crate) fn any_paused(wallpapers: &[Rc<RefCell<Wallpaper>>]) -> bool {
+    wallpapers.iter().any(|w| w.borrow().is_paused())
+}
+
 pub(crate) fn commit_wallpapers(wallpapers: &[Rc<RefCell<Wallpaper>>]) {
     // Note this is little-endian specific
     #[rustfmt::skip]
@@ -499,9 +918,17 @@ impl Drop for Wallpaper {
                 error!("error destroying wp_fractional_scale_v1: {e:?}");
             }
         }
+        if let Some(output_power) = self.output_power {
+            if let Err(e) = zwlr_output_power_v1::req::destroy(output_power) {
+                error!("error destroying zwlr_output_power_v1: {e:?}");
+            }
+        }
         if let Err(e) = zwlr_layer_surface_v1::req::destroy(self.layer_surface) {
             error!("error destroying zwlr_layer_surface_v1: {e:?}");
         }
+        if let Err(e) = wl_output::req::release(self.output) {
+            error!("error releasing wl_output: {e:?}");
+        }
 
         debug!(
             "Destroyed output {} - {}",
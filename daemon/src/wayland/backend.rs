@@ -0,0 +1,121 @@
+//! Everything the daemon needs from its connection to the compositor: sending requests and
+//! receiving events
+//!
+//! Both go through the `Backend` trait. The daemon talks to `Compositor`, while tests get
+//! `test::TestBackend`, which keeps everything in memory. That way, tests can drive the daemon's
+//! drawing and event handling without a running compositor, check the requests it sent, and feed
+//! it whatever events they want.
+
+use rustix::{fd::BorrowedFd, io, net};
+
+use super::{
+    globals::wayland_fd,
+    wire::{WaylandPayload, WireMsg},
+};
+
+pub trait Backend {
+    /// Sends an already encoded request, along with the file descriptors it carries
+    fn send(msg: &[u8], fds: &[BorrowedFd]) -> io::Result<()>;
+
+    /// Receives the next event
+    ///
+    /// Fails with `Errno::PIPE` if the other end closed the connection
+    fn recv() -> io::Result<(WireMsg, WaylandPayload)>;
+}
+
+/// The backend every request and event goes through
+#[cfg(not(test))]
+pub type Active = Compositor;
+
+/// The backend every request and event goes through
+#[cfg(test)]
+pub type Active = test::TestBackend;
+
+/// The connection to the compositor, in `globals::wayland_fd`
+pub struct Compositor;
+
+impl Backend for Compositor {
+    fn send(msg: &[u8], fds: &[BorrowedFd]) -> io::Result<()> {
+        let iov = io::IoSlice::new(msg);
+        let mut control_buf = [0u8; rustix::cmsg_space!(ScmRights(1))];
+        let mut control = net::SendAncillaryBuffer::new(&mut control_buf);
+        let msg = net::SendAncillaryMessage::ScmRights(fds);
+        control.push(msg);
+        net::sendmsg(wayland_fd(), &[iov], &mut control, net::SendFlags::NOSIGNAL).map(|_| ())
+    }
+
+    fn recv() -> io::Result<(WireMsg, WaylandPayload)> {
+        WireMsg::recv_from(wayland_fd())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::wayland::ObjectId;
+    use std::{cell::RefCell, collections::VecDeque};
+
+    /// A request the daemon sent, as the compositor would have seen it
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Request {
+        pub object: ObjectId,
+        pub op: u16,
+        pub args: Vec<u32>,
+        /// how many file descriptors came with it
+        pub fds: usize,
+    }
+
+    thread_local! {
+        // every test runs in its own thread, so they can't see each other's messages
+        static REQUESTS: RefCell<Vec<Request>> = const { RefCell::new(Vec::new()) };
+        static EVENTS: RefCell<VecDeque<(ObjectId, u16, Vec<u32>)>> =
+            const { RefCell::new(VecDeque::new()) };
+    }
+
+    /// Records every request, and hands out the events queued with `push_event`
+    pub struct TestBackend;
+
+    impl Backend for TestBackend {
+        /// `msg` may hold several requests back to back, or none at all
+        fn send(msg: &[u8], fds: &[BorrowedFd]) -> io::Result<()> {
+            let words: Vec<u32> = msg
+                .chunks_exact(4)
+                .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+                .collect();
+            let mut rest = words.as_slice();
+            let mut fds = fds.len();
+            while !rest.is_empty() {
+                let len = (rest[1] >> 16) as usize / 4;
+                let request = Request {
+                    object: ObjectId::new(rest[0].try_into().expect("request with a null object")),
+                    op: (rest[1] & 0xFFFF) as u16,
+                    args: rest[2..len].to_vec(),
+                    // the file descriptors go along with the first request
+                    fds: std::mem::take(&mut fds),
+                };
+                REQUESTS.with_borrow_mut(|requests| requests.push(request));
+                rest = &rest[len..];
+            }
+            Ok(())
+        }
+
+        /// Fails with `Errno::WOULDBLOCK` if there are no events queued, just like a non-blocking
+        /// socket with nothing to read would
+        fn recv() -> io::Result<(WireMsg, WaylandPayload)> {
+            EVENTS
+                .with_borrow_mut(|events| events.pop_front())
+                .map(|(sender_id, op, args)| WireMsg::from_parts(sender_id, op, args))
+                .ok_or(io::Errno::WOULDBLOCK)
+        }
+    }
+
+    /// Takes every request this thread sent so far
+    pub fn take_requests() -> Vec<Request> {
+        REQUESTS.take()
+    }
+
+    /// Queues an event from `object`, which this thread will get from its next `WireMsg::recv`
+    pub fn push_event(object: ObjectId, op: u16, args: &[u32]) {
+        EVENTS.with_borrow_mut(|events| events.push_back((object, op, args.to_vec())));
+    }
+}
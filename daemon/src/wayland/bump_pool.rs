@@ -1,6 +1,6 @@
 use common::{ipc::PixelFormat, mmap::Mmap};
 
-use super::{ObjectId, ObjectManager};
+use super::{interfaces::wl_output::transform, ObjectId, ObjectManager};
 
 #[derive(Debug)]
 struct Buffer {
@@ -60,9 +60,19 @@ pub(crate) struct BumpPool {
     pool_id: ObjectId,
     mmap: Mmap,
     buffers: Vec<Buffer>,
+    /// the size of the canvas we draw on. Our buffers have their width and height swapped if
+    /// `transform` rotates by 90 or 270 degrees
     width: i32,
     height: i32,
+    /// the `wl_output::transform` our buffers are in
+    transform: u32,
     last_used_buffer: usize,
+    /// With 10 bit formats or a transform, everything is drawn here, with 8 bits per channel and
+    /// upright, and only packed and transformed into the actual buffer right before it is
+    /// committed
+    shadow: Vec<u8>,
+    /// the format the shadow must be packed as, if we drew to it since the last commit
+    pending_pack: Option<PixelFormat>,
 }
 
 impl BumpPool {
@@ -73,7 +83,7 @@ impl BumpPool {
         objman: &mut ObjectManager,
         pixel_format: PixelFormat,
     ) -> Self {
-        let len = width as usize * height as usize * pixel_format.channels() as usize;
+        let len = stride(width, pixel_format) * height as usize;
         let mmap = Mmap::create(len);
         let pool_id = objman.create(super::WlDynObj::ShmPool);
         super::interfaces::wl_shm::req::create_pool(pool_id, &mmap.fd(), len as i32)
@@ -86,7 +96,10 @@ impl BumpPool {
             buffers,
             width,
             height,
+            transform: transform::NORMAL,
             last_used_buffer: 0,
+            shadow: Vec::new(),
+            pending_pack: None,
         }
     }
 
@@ -113,8 +126,23 @@ impl BumpPool {
         }
     }
 
+    /// how many bytes each row of the canvas takes, including padding
+    pub(crate) const fn stride(&self, pixel_format: PixelFormat) -> usize {
+        stride(self.width, pixel_format)
+    }
+
+    const fn canvas_len(&self, pixel_format: PixelFormat) -> usize {
+        self.stride(pixel_format) * self.height as usize
+    }
+
+    /// Whether we draw to the shadow instead of straight into our buffers
+    fn is_shadowed(&self, pixel_format: PixelFormat) -> bool {
+        pixel_format.is_10_bit() || self.transform != transform::NORMAL
+    }
+
     const fn buffer_len(&self, pixel_format: PixelFormat) -> usize {
-        self.width as usize * self.height as usize * pixel_format.channels() as usize
+        let (width, height) = self.buffer_dimensions();
+        stride(width as i32, pixel_format) * height as usize
     }
 
     const fn buffer_offset(&self, buffer_index: usize, pixel_format: PixelFormat) -> usize {
@@ -143,13 +171,14 @@ impl BumpPool {
         }
 
         let new_buffer_index = self.buffers.len();
+        let (buffer_width, buffer_height) = self.buffer_dimensions();
         self.buffers.push(Buffer::new(
             objman,
             self.pool_id,
             self.buffer_offset(new_buffer_index, pixel_format) as i32,
-            self.width,
-            self.height,
-            self.width * pixel_format.channels() as i32,
+            buffer_width as i32,
+            buffer_height as i32,
+            stride(buffer_width as i32, pixel_format) as i32,
             super::globals::wl_shm_format(pixel_format),
         ));
 
@@ -182,10 +211,22 @@ impl BumpPool {
         };
         buf.unset_released();
 
+        if self.is_shadowed(pixel_format) {
+            // the shadow always holds the last frame, so there is nothing to copy over
+            self.last_used_buffer = i;
+            self.shadow.resize(self.canvas_len(pixel_format), 0);
+            self.pending_pack = Some(pixel_format);
+            return &mut self.shadow;
+        }
         let len = self.buffer_len(pixel_format);
         let offset = self.buffer_offset(i, pixel_format);
 
-        if self.last_used_buffer != i {
+        if !self.shadow.is_empty() {
+            // we just stopped transforming what we draw, so the last frame is still in the shadow
+            self.mmap.slice_mut()[offset..offset + len].copy_from_slice(&self.shadow);
+            self.shadow = Vec::new();
+            self.last_used_buffer = i;
+        } else if self.last_used_buffer != i {
             let last_offset = self.buffer_offset(self.last_used_buffer, pixel_format);
             self.mmap
                 .slice_mut()
@@ -196,8 +237,104 @@ impl BumpPool {
         &mut self.mmap.slice_mut()[offset..offset + len]
     }
 
-    /// gets the last buffer we've drawn to
-    pub(crate) fn get_commitable_buffer(&self) -> ObjectId {
+    /// Copies the last buffer we've drawn to onto every other buffer the compositor has already
+    /// released, so that they all hold the same frame.
+    ///
+    /// We use this when interrupting an animation, to guarantee whatever is drawn next starts from
+    /// the exact frame the animation stopped at, regardless of which buffer it ends up using
+    pub(crate) fn sync_buffers(&mut self, pixel_format: PixelFormat) {
+        // if there are no buffers, the shared memory might not even be mapped. Buffers we draw to
+        // through the shadow are written in full every time, so their contents never matter
+        if self.buffers.is_empty() || self.is_shadowed(pixel_format) {
+            return;
+        }
+
+        let len = self.buffer_len(pixel_format);
+        let last_offset = self.buffer_offset(self.last_used_buffer, pixel_format);
+        for i in 0..self.buffers.len() {
+            if i != self.last_used_buffer && self.buffers[i].is_released() {
+                let offset = self.buffer_offset(i, pixel_format);
+                self.mmap
+                    .slice_mut()
+                    .copy_within(last_offset..last_offset + len, offset);
+            }
+        }
+    }
+
+    /// Copies out the contents of the last buffer we've drawn to, without any row padding
+    ///
+    /// Returns `None` if the shared memory cannot hold a buffer with the current dimensions, which
+    /// happens when we've been resized and haven't drawn anything since
+    pub(crate) fn last_drawn(&mut self, pixel_format: PixelFormat) -> Option<Box<[u8]>> {
+        let len = self.canvas_len(pixel_format);
+        let row_len = self.width as usize * pixel_format.channels() as usize;
+        // the shadow also holds the last frame right after we stop transforming what we draw
+        if self.is_shadowed(pixel_format) || !self.shadow.is_empty() {
+            return (self.shadow.len() == len).then(|| {
+                self.shadow
+                    .chunks_exact(self.stride(pixel_format))
+                    .flat_map(|row| &row[..row_len])
+                    .copied()
+                    .collect()
+            });
+        }
+        let offset = self.buffer_offset(self.last_used_buffer, pixel_format);
+        if offset + len > self.mmap.len() {
+            return None;
+        }
+
+        // when we aren't animating, the shared memory might have been unmapped, but the file
+        // descriptor still holds the last frame
+        self.mmap.ensure_mapped();
+        let frame = self.mmap.slice()[offset..offset + len]
+            .chunks_exact(self.stride(pixel_format))
+            .flat_map(|row| &row[..row_len])
+            .copied()
+            .collect();
+        if self.buffers.is_empty() {
+            self.mmap.unmap();
+        }
+        Some(frame)
+    }
+
+    /// Whether we've drawn anything since we were last resized
+    pub(crate) fn has_drawn(&self) -> bool {
+        !self.buffers.is_empty()
+    }
+
+    /// the dimensions of the canvas we give out
+    pub(crate) const fn dimensions(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    /// the dimensions of our buffers, as the compositor sees them
+    pub(crate) const fn buffer_dimensions(&self) -> (u32, u32) {
+        if swaps_dimensions(self.transform) {
+            (self.height as u32, self.width as u32)
+        } else {
+            (self.width as u32, self.height as u32)
+        }
+    }
+
+    /// the `wl_output::transform` our buffers are in
+    pub(crate) const fn transform(&self) -> u32 {
+        self.transform
+    }
+
+    /// gets the last buffer we've drawn to, packing and transforming it first if we drew it
+    /// through the shadow
+    pub(crate) fn get_commitable_buffer(&mut self) -> ObjectId {
+        if let Some(pixel_format) = self.pending_pack.take() {
+            let len = self.buffer_len(pixel_format);
+            let offset = self.buffer_offset(self.last_used_buffer, pixel_format);
+            let dst = &mut self.mmap.slice_mut()[offset..offset + len];
+            if self.transform == transform::NORMAL {
+                pack_2101010(&self.shadow, dst);
+            } else {
+                let canvas = (self.width as usize, self.height as usize);
+                copy_transformed(&self.shadow, dst, canvas, self.transform, pixel_format);
+            }
+        }
         self.buffers[self.last_used_buffer].object_id
     }
 
@@ -205,13 +342,112 @@ impl BumpPool {
     pub(crate) fn resize(&mut self, width: i32, height: i32) {
         self.width = width;
         self.height = height;
+        self.shadow.clear();
+        self.drop_buffers();
+    }
+
+    /// Makes our buffers hold what we draw rotated and flipped by `transform`, a
+    /// `wl_output::transform`. What is already on the canvas stays there
+    pub(crate) fn set_transform(&mut self, transform: u32, pixel_format: PixelFormat) {
+        if self.transform == transform {
+            return;
+        }
+        if !self.is_shadowed(pixel_format) {
+            // the canvas is laid out just like the buffers, so the last one holds it. It moves to
+            // the shadow, where we draw from now on, or from which the next buffer is filled
+            let len = self.buffer_len(pixel_format);
+            let offset = self.buffer_offset(self.last_used_buffer, pixel_format);
+            if offset + len <= self.mmap.len() {
+                self.mmap.ensure_mapped();
+                self.shadow = self.mmap.slice()[offset..offset + len].to_vec();
+                if self.buffers.is_empty() {
+                    self.mmap.unmap();
+                }
+            }
+        }
+        self.transform = transform;
+        self.drop_buffers();
+    }
+
+    /// Destroys all our buffers, for when they can't hold what we draw anymore
+    fn drop_buffers(&mut self) {
         self.last_used_buffer = 0;
+        self.pending_pack = None;
         for buffer in self.buffers.drain(..) {
             buffer.destroy();
         }
     }
 }
 
+/// Whether buffers in `transform`, a `wl_output::transform`, are taller than wide when what they
+/// show is wider than tall
+pub(crate) const fn swaps_dimensions(transform: u32) -> bool {
+    matches!(
+        transform,
+        transform::_90 | transform::_270 | transform::FLIPPED_90 | transform::FLIPPED_270
+    )
+}
+
+/// Copies the `canvas` sized `src` into `dst`, flipped around its vertical axis and then rotated
+/// counter-clockwise as `transform` says, just like the compositor expects of buffers we tell it
+/// are in that transform. 10 bit formats are packed along the way
+fn copy_transformed(
+    src: &[u8],
+    dst: &mut [u8],
+    canvas: (usize, usize),
+    transform: u32,
+    pixel_format: PixelFormat,
+) {
+    let (width, height) = canvas;
+    let (dst_width, dst_height) = if swaps_dimensions(transform) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let flipped = transform >= transform::FLIPPED;
+    let channels = pixel_format.channels() as usize;
+    let src_stride = stride(width as i32, pixel_format);
+    let dst_stride = stride(dst_width as i32, pixel_format);
+    for y in 0..dst_height {
+        let row = &mut dst[y * dst_stride..y * dst_stride + dst_width * channels];
+        for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+            // where the pixel was in the flipped canvas, before rotating it
+            let (src_x, src_y) = match transform {
+                transform::_90 | transform::FLIPPED_90 => (width - 1 - y, x),
+                transform::_180 | transform::FLIPPED_180 => (width - 1 - x, height - 1 - y),
+                transform::_270 | transform::FLIPPED_270 => (y, height - 1 - x),
+                _ => (x, y),
+            };
+            let src_x = if flipped { width - 1 - src_x } else { src_x };
+            let begin = src_y * src_stride + src_x * channels;
+            let src = &src[begin..begin + channels];
+            if pixel_format.is_10_bit() {
+                pack_2101010(src, pixel);
+            } else {
+                pixel.copy_from_slice(src);
+            }
+        }
+    }
+}
+
+/// Packs 8 bit `Xbgr` or `Xrgb` pixels into `Xbgr2101010` or `Xrgb2101010` ones, respectively.
+/// Both keep the channels in the same order, starting from the least significant bits
+fn pack_2101010(src: &[u8], dst: &mut [u8]) {
+    // repeating the top bits in the bottom ones maps 255 to 1023, so white stays white
+    let expand = |v: u8| (v as u32) << 2 | (v as u32) >> 6;
+    for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        let word = expand(src[0]) | expand(src[1]) << 10 | expand(src[2]) << 20 | 0b11 << 30;
+        dst.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+/// Some compositors require the rows of wl_shm buffers to be 4-byte aligned, and will display our
+/// wallpaper slanted otherwise. Since 4 channel formats are always aligned, this only ever pads
+/// rows of 3 channel formats
+const fn stride(width: i32, pixel_format: PixelFormat) -> usize {
+    (width as usize * pixel_format.channels() as usize).next_multiple_of(4)
+}
+
 impl Drop for BumpPool {
     fn drop(&mut self) {
         for buffer in self.buffers.drain(..) {
@@ -222,3 +458,188 @@ impl Drop for BumpPool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(pool: &BumpPool, i: usize, pixel_format: PixelFormat) -> &[u8] {
+        let len = pool.buffer_len(pixel_format);
+        let offset = pool.buffer_offset(i, pixel_format);
+        &pool.mmap.slice()[offset..offset + len]
+    }
+
+    #[test]
+    fn sync_buffers_makes_all_released_buffers_equal() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Xrgb;
+        let mut pool = BumpPool::new(4, 4, &mut objman, pixel_format);
+
+        // first animation frame, which the compositor holds on to
+        pool.get_drawable(&mut objman, pixel_format).fill(1);
+        let first = pool.get_commitable_buffer();
+        // the second frame must go to a new buffer, where we apply a diff
+        pool.get_drawable(&mut objman, pixel_format)[..8].fill(2);
+        assert_eq!(pool.buffers.len(), 2);
+
+        // the compositor releases the first buffer, which is now lagging behind
+        assert!(pool.set_buffer_release_flag(first, true));
+        assert_ne!(
+            buffer(&pool, 0, pixel_format),
+            buffer(&pool, 1, pixel_format)
+        );
+
+        // the animation is interrupted here
+        pool.sync_buffers(pixel_format);
+        assert_eq!(
+            buffer(&pool, 0, pixel_format),
+            buffer(&pool, 1, pixel_format)
+        );
+        assert_eq!(
+            &buffer(&pool, 0, pixel_format)[..9],
+            &[2, 2, 2, 2, 2, 2, 2, 2, 1]
+        );
+    }
+
+    #[test]
+    fn sync_buffers_does_not_touch_buffers_held_by_the_compositor() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Xrgb;
+        let mut pool = BumpPool::new(4, 4, &mut objman, pixel_format);
+
+        pool.get_drawable(&mut objman, pixel_format).fill(1);
+        pool.get_drawable(&mut objman, pixel_format).fill(2);
+
+        pool.sync_buffers(pixel_format);
+        assert!(buffer(&pool, 0, pixel_format).iter().all(|&b| b == 1));
+        assert!(buffer(&pool, 1, pixel_format).iter().all(|&b| b == 2));
+    }
+
+    #[test]
+    fn last_drawn_survives_unmapping() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Xrgb;
+        let mut pool = BumpPool::new(4, 4, &mut objman, pixel_format);
+
+        pool.get_drawable(&mut objman, pixel_format).fill(1);
+        let first = pool.get_commitable_buffer();
+        pool.get_drawable(&mut objman, pixel_format).fill(2);
+        let second = pool.get_commitable_buffer();
+        assert!(pool
+            .last_drawn(pixel_format)
+            .unwrap()
+            .iter()
+            .all(|&b| b == 2));
+
+        // the animation ends, so we destroy all buffers and unmap the memory
+        assert!(pool.set_buffer_release_flag(first, false));
+        assert!(pool.set_buffer_release_flag(second, false));
+        assert!(pool.buffers.is_empty());
+        assert!(pool
+            .last_drawn(pixel_format)
+            .unwrap()
+            .iter()
+            .all(|&b| b == 2));
+
+        // we can't know what a resized pool will look like before drawing to it
+        pool.resize(8, 8);
+        assert!(pool.last_drawn(pixel_format).is_none());
+    }
+
+    #[test]
+    fn ten_bit_buffers_are_packed_on_commit() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Xrgb2101010;
+        let mut pool = BumpPool::new(2, 1, &mut objman, pixel_format);
+        assert_eq!(pool.stride(pixel_format), 2 * 4);
+
+        // blue, green, red and padding, like in xrgb
+        let pixels = [255, 128, 0, 0, 1, 2, 3, 4];
+        pool.get_drawable(&mut objman, pixel_format)
+            .copy_from_slice(&pixels);
+        pool.get_commitable_buffer();
+        let expected: Vec<u8> = [
+            1023 | 514 << 10 | 0b11 << 30,
+            4 | 8 << 10 | 12 << 20 | 0b11 << 30,
+        ]
+        .iter()
+        .flat_map(|word: &u32| word.to_le_bytes())
+        .collect();
+        assert_eq!(buffer(&pool, 0, pixel_format), expected);
+        assert_eq!(*pool.last_drawn(pixel_format).unwrap(), pixels);
+
+        // the next frame goes to another buffer, but starts from the same 8 bit pixels
+        let canvas = pool.get_drawable(&mut objman, pixel_format);
+        assert_eq!(canvas, pixels);
+        canvas[0] = 0;
+        pool.get_commitable_buffer();
+        assert_eq!(pool.buffers.len(), 2);
+        let first = (514u32 << 10 | 0b11 << 30).to_le_bytes();
+        assert_eq!(buffer(&pool, 1, pixel_format)[..4], first);
+        assert_eq!(buffer(&pool, 1, pixel_format)[4..], expected[4..]);
+    }
+
+    #[test]
+    fn transformed_buffers_are_rotated_on_commit() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Rgb;
+        let mut pool = BumpPool::new(3, 2, &mut objman, pixel_format);
+        pool.set_transform(transform::_90, pixel_format);
+
+        // every pixel holds its index, and rows are padded from 9 to 12 bytes
+        let canvas = pool.get_drawable(&mut objman, pixel_format);
+        for (y, row) in canvas.chunks_exact_mut(12).enumerate() {
+            for (x, pixel) in row[..9].chunks_exact_mut(3).enumerate() {
+                pixel.fill((y * 3 + x) as u8);
+            }
+        }
+        pool.get_commitable_buffer();
+
+        // rotated counter-clockwise, with rows padded from 6 to 8 bytes
+        assert_eq!(pool.buffer_dimensions(), (2, 3));
+        let pixels: Vec<u8> = buffer(&pool, 0, pixel_format)
+            .chunks_exact(8)
+            .flat_map(|row| row[..6].iter().step_by(3))
+            .copied()
+            .collect();
+        assert_eq!(pixels, [2, 5, 1, 4, 0, 3]);
+        let upright: Vec<u8> = (0..6).flat_map(|i| [i; 3]).collect();
+        assert_eq!(*pool.last_drawn(pixel_format).unwrap(), upright);
+
+        // going back keeps what we drew
+        pool.set_transform(transform::NORMAL, pixel_format);
+        assert_eq!(pool.buffer_dimensions(), (3, 2));
+        assert_eq!(*pool.last_drawn(pixel_format).unwrap(), upright);
+        pool.get_drawable(&mut objman, pixel_format);
+        assert_eq!(*pool.last_drawn(pixel_format).unwrap(), upright);
+    }
+
+    #[test]
+    fn rows_are_padded_to_four_bytes() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Rgb;
+        let mut pool = BumpPool::new(1366, 2, &mut objman, pixel_format);
+        assert_eq!(pool.stride(pixel_format), 4100);
+        assert_eq!(pool.get_drawable(&mut objman, pixel_format).len(), 4100 * 2);
+
+        assert_eq!(pool.stride(PixelFormat::Xrgb), 1366 * 4);
+        pool.resize(1368, 2);
+        assert_eq!(pool.stride(pixel_format), 1368 * 3);
+    }
+
+    #[test]
+    fn last_drawn_skips_row_padding() {
+        let mut objman = ObjectManager::new();
+        let pixel_format = PixelFormat::Rgb;
+        let mut pool = BumpPool::new(1366, 2, &mut objman, pixel_format);
+
+        let canvas = pool.get_drawable(&mut objman, pixel_format);
+        for row in canvas.chunks_exact_mut(4100) {
+            row[..1366 * 3].fill(1);
+            row[1366 * 3..].fill(2);
+        }
+        let frame = pool.last_drawn(pixel_format).unwrap();
+        assert_eq!(frame.len(), 1366 * 3 * 2);
+        assert!(frame.iter().all(|&b| b == 1));
+    }
+}
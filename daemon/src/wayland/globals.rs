@@ -6,7 +6,10 @@
 //! mutated anywhere but in here.
 //!
 //! Second, the `pub init(..)` function only executes once. We ensure that using an atomic boolean.
-//! This means we will only be mutating these variables inside that function, once.
+//! This means we will only be mutating these variables inside that function, once. The one
+//! exception is `reconnect(..)`, which replaces the connection after the compositor went away. It
+//! is only ever called from the main thread (the only one talking to the compositor), once
+//! everything made for the old connection has been dropped.
 //!
 //! In order to be safe, then, all we have to do is make sure we call `init(..)` as early as
 //! possible in the code, and everything will be fine. If we ever fail to that, we have a failsafe
@@ -18,10 +21,15 @@ use rustix::{
 };
 
 use common::ipc::PixelFormat;
-use log::{debug, error};
+use log::{debug, error, info};
 
 use super::{ObjectId, ObjectManager};
-use std::{num::NonZeroU32, path::PathBuf, sync::atomic::AtomicBool};
+use std::{
+    ffi::OsStr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
 
 // all of these objects must always exist for `swww-daemon` to work correctly, so we turn them into
 // global constants
@@ -67,35 +75,73 @@ pub fn wl_shm_format(pixel_format: PixelFormat) -> u32 {
         PixelFormat::Xbgr => super::interfaces::wl_shm::format::XBGR8888,
         PixelFormat::Rgb => super::interfaces::wl_shm::format::RGB888,
         PixelFormat::Bgr => super::interfaces::wl_shm::format::BGR888,
+        PixelFormat::Xbgr2101010 => super::interfaces::wl_shm::format::XBGR2101010,
+        PixelFormat::Xrgb2101010 => super::interfaces::wl_shm::format::XRGB2101010,
     }
 }
 
 /// Note that this function assumes the logger has already been set up
-pub fn init(pixel_format: Option<PixelFormat>) -> InitState {
+/// `pixel_format_hint` is the pixel format we used last time. We prefer it over our usual choice,
+/// as long as the compositor supports it. `pixel_format` (from the command line) overrides both.
+pub fn init(
+    pixel_format: Option<PixelFormat>,
+    pixel_format_hint: Option<PixelFormat>,
+) -> Result<InitState, String> {
     if INITIALIZED.load(std::sync::atomic::Ordering::Relaxed) {
         panic!("trying to run initialization code twice");
     }
 
+    let fd = connect()?;
     unsafe {
-        WAYLAND_FD = connect();
+        WAYLAND_FD = fd;
     }
-    let mut initializer = Initializer::new(pixel_format);
 
     // the only globals that can break catastrophically are WAYLAND_FD and OBJECT_MANAGER, that we
     // have just initialized above. So this is safe
     INITIALIZED.store(true, std::sync::atomic::Ordering::SeqCst);
 
+    bind_globals(pixel_format, pixel_format_hint)
+}
+
+/// Connects to the compositor again after losing the connection to the previous one, going
+/// through the same initialization as `init`
+///
+/// The ids of everything made for the old connection mean nothing to the new one, so all of it
+/// must have been dropped already. Fails if the compositor isn't back yet, or goes away again
+/// before we are done.
+pub fn reconnect(
+    pixel_format: Option<PixelFormat>,
+    pixel_format_hint: Option<PixelFormat>,
+) -> Result<InitState, String> {
+    debug_assert!(INITIALIZED.load(std::sync::atomic::Ordering::Relaxed));
+    let fd = connect()?;
+    // this closes the old connection
+    unsafe {
+        WAYLAND_FD = fd;
+    }
+    bind_globals(pixel_format, pixel_format_hint)
+}
+
+/// Finds and binds the globals we need through the freshly connected `WAYLAND_FD`
+fn bind_globals(
+    pixel_format: Option<PixelFormat>,
+    pixel_format_hint: Option<PixelFormat>,
+) -> Result<InitState, String> {
+    let lost = |e| format!("lost the connection to the compositor during initialization: {e}");
+    let mut initializer = Initializer::new(pixel_format, pixel_format_hint);
+
     // these functions already require for the wayland file descriptor and the object manager to
     // have been initialized, which we just did above
-    super::interfaces::wl_display::req::get_registry().unwrap();
-    super::interfaces::wl_display::req::sync(ObjectId::new(NonZeroU32::new(3).unwrap())).unwrap();
+    super::interfaces::wl_display::req::get_registry().map_err(lost)?;
+    super::interfaces::wl_display::req::sync(ObjectId::new(NonZeroU32::new(3).unwrap()))
+        .map_err(lost)?;
 
     const IDS: [ObjectId; 4] = [WL_COMPOSITOR, WL_SHM, WP_VIEWPORTER, ZWLR_LAYER_SHELL_V1];
 
     // this loop will process and store all advertised wayland globals, storing their global name
     // in the Initializer struct
     while !initializer.should_exit {
-        let (msg, payload) = super::wire::WireMsg::recv().unwrap();
+        let (msg, payload) = super::wire::WireMsg::recv().map_err(lost)?;
         if msg.sender_id().get() == 3 {
             super::interfaces::wl_callback::event(&mut initializer, msg, payload);
         } else if msg.sender_id() == WL_DISPLAY {
@@ -122,7 +168,7 @@ pub fn init(pixel_format: Option<PixelFormat>) -> InitState {
         let id = IDS[i];
         let interface = REQUIRED_GLOBALS[i];
         let version = VERSIONS[i];
-        super::interfaces::wl_registry::req::bind(name, id, interface, version).unwrap();
+        super::interfaces::wl_registry::req::bind(name, id, interface, version).map_err(lost)?;
     }
 
     // bind fractional scale, if it is supported
@@ -133,16 +179,27 @@ pub fn init(pixel_format: Option<PixelFormat>) -> InitState {
             "wp_fractional_scale_manager_v1",
             1,
         )
-        .unwrap();
+        .map_err(lost)?;
+    }
+
+    // bind output power management, if it is supported
+    if let Some(output_power_manager) = initializer.output_power() {
+        super::interfaces::wl_registry::req::bind(
+            output_power_manager.name.get(),
+            output_power_manager.id,
+            "zwlr_output_power_manager_v1",
+            1,
+        )
+        .map_err(lost)?;
     }
 
     let callback_id = initializer.callback_id();
-    super::interfaces::wl_display::req::sync(callback_id).unwrap();
+    super::interfaces::wl_display::req::sync(callback_id).map_err(lost)?;
     initializer.should_exit = false;
     // this loop will go through all the advertised wl_shm format, selecting one for the
     // PIXEL_FORMAT global, if `--format <..>` wasn't passed as a command line argument
     while !initializer.should_exit {
-        let (msg, payload) = super::wire::WireMsg::recv().unwrap();
+        let (msg, payload) = super::wire::WireMsg::recv().map_err(lost)?;
         match msg.sender_id() {
             // in case there are errors
             WL_DISPLAY => super::interfaces::wl_display::event(&mut initializer, msg, payload),
@@ -158,53 +215,112 @@ pub fn init(pixel_format: Option<PixelFormat>) -> InitState {
         }
     }
 
-    initializer.into_init_state()
+    Ok(initializer.into_init_state())
 }
 
 /// mostly copy-pasted from `wayland-client.rs`
-fn connect() -> OwnedFd {
+fn connect() -> Result<OwnedFd, String> {
     if let Ok(txt) = std::env::var("WAYLAND_SOCKET") {
-        // We should connect to the provided WAYLAND_SOCKET
+        // We should connect to the provided WAYLAND_SOCKET. Like libwayland, we take it out of the
+        // environment, so that neither the clients we spawn nor a later reconnection try to use
+        // it again
+        std::env::remove_var("WAYLAND_SOCKET");
         let fd = txt
             .parse::<i32>()
-            .expect("invalid fd in WAYLAND_SOCKET env var");
+            .map_err(|_| format!("WAYLAND_SOCKET is not a file descriptor: {txt:?}"))?;
         let fd = unsafe { OwnedFd::from_raw_fd(fd) };
 
-        let socket_addr =
-            rustix::net::getsockname(&fd).expect("failed to get wayland socket address");
+        let socket_addr = rustix::net::getsockname(&fd)
+            .map_err(|e| format!("WAYLAND_SOCKET ({txt}) is not a usable socket: {e}"))?;
         if let SocketAddrAny::Unix(_) = socket_addr {
-            fd
+            Ok(fd)
         } else {
-            panic!("socket address {:?} is not a unix socket", socket_addr);
+            Err(format!(
+                "WAYLAND_SOCKET ({txt}) is not a unix socket, but {socket_addr:?}"
+            ))
         }
     } else {
-        let socket_name: PathBuf = std::env::var_os("WAYLAND_DISPLAY")
-            .unwrap_or_else(|| {
-                log::warn!("WAYLAND_DISPLAY is not set! Defaulting to wayland-0");
-                std::ffi::OsString::from("wayland-0")
-            })
-            .into();
-
-        let socket_path = if socket_name.is_absolute() {
-            socket_name
-        } else {
-            let mut socket_path: PathBuf = std::env::var_os("XDG_RUNTIME_DIR")
-                .unwrap_or_else(|| {
-                    log::warn!("XDG_RUNTIME_DIR is not set! Defaulting to /run/user/UID");
-                    let uid = rustix::process::getuid();
-                    std::ffi::OsString::from(format!("/run/user/{}", uid.as_raw()))
-                })
-                .into();
-
-            socket_path.push(socket_name);
-            socket_path
-        };
+        let display = std::env::var_os("WAYLAND_DISPLAY");
+        if display.is_none() {
+            log::warn!("WAYLAND_DISPLAY is not set! Defaulting to wayland-0");
+        }
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR");
+        if runtime_dir.is_none() {
+            log::warn!("XDG_RUNTIME_DIR is not set! Defaulting to /run/user/UID");
+        }
+        let socket_path = wayland_socket_path(
+            display.as_deref(),
+            runtime_dir.as_deref(),
+            rustix::process::getuid().as_raw(),
+        );
 
         match std::os::unix::net::UnixStream::connect(&socket_path) {
-            Ok(stream) => stream.into(),
-            Err(e) => panic!("failed to connect to wayland socket at {socket_path:?}: {e}"),
+            Ok(stream) => Ok(stream.into()),
+            Err(e) => Err(connect_error(
+                &socket_path,
+                display.as_deref(),
+                socket_path.exists(),
+                &e,
+            )),
+        }
+    }
+}
+
+/// Where the compositor's socket should be. `WAYLAND_DISPLAY` is either an absolute path, or a
+/// name in `XDG_RUNTIME_DIR`
+fn wayland_socket_path(display: Option<&OsStr>, runtime_dir: Option<&OsStr>, uid: u32) -> PathBuf {
+    let socket_name = Path::new(display.unwrap_or(OsStr::new("wayland-0")));
+    if socket_name.is_absolute() {
+        return socket_name.to_path_buf();
+    }
+    let mut socket_path = match runtime_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(format!("/run/user/{uid}")),
+    };
+    socket_path.push(socket_name);
+    socket_path
+}
+
+/// Explains why we couldn't connect to the compositor at `socket_path`, with hints about what is
+/// most likely wrong with `WAYLAND_DISPLAY`
+fn connect_error(
+    socket_path: &Path,
+    display: Option<&OsStr>,
+    exists: bool,
+    error: &std::io::Error,
+) -> String {
+    let mut msg = format!("failed to connect to the wayland socket at {socket_path:?}: {error}");
+    match display {
+        None => msg.push_str(
+            ". WAYLAND_DISPLAY is not set, so we tried the default, wayland-0. Make sure \
+             swww-daemon runs inside your wayland session, or set WAYLAND_DISPLAY to the name of \
+             the compositor's socket",
+        ),
+        Some(display) => {
+            let display = display.to_string_lossy();
+            if Path::new(display.as_ref()).is_absolute() {
+                msg.push_str(&format!(
+                    ". WAYLAND_DISPLAY ({display}) is an absolute path"
+                ));
+            } else {
+                msg.push_str(&format!(
+                    ". WAYLAND_DISPLAY ({display}) is relative to XDG_RUNTIME_DIR"
+                ));
+            }
+            if exists {
+                msg.push_str(", and the file exists, but the compositor is not listening on it");
+            } else {
+                msg.push_str(", and there is no such file");
+                if display.parse::<u32>().is_ok() {
+                    msg.push_str(&format!(
+                        ". WAYLAND_DISPLAY must be the name of the socket, like wayland-{display}, \
+                         not just a number"
+                    ));
+                }
+            }
         }
     }
+    msg
 }
 
 #[derive(Clone)]
@@ -219,6 +335,30 @@ impl FractionalScaleManager {
     }
 }
 
+/// Lets us know when outputs are turned off, so we can stop animating them
+#[derive(Clone)]
+pub struct OutputPowerManager {
+    id: ObjectId,
+    name: NonZeroU32,
+}
+
+impl OutputPowerManager {
+    pub fn id(&self) -> ObjectId {
+        self.id
+    }
+}
+
+#[cfg(test)]
+impl OutputPowerManager {
+    /// The manager `init` would bind if the compositor doesn't support fractional scaling
+    pub fn test_manager() -> Self {
+        Self {
+            id: ObjectId(unsafe { NonZeroU32::new_unchecked(7) }),
+            name: NonZeroU32::MIN,
+        }
+    }
+}
+
 /// Helper struct to do all the initialization in this file
 struct Initializer {
     objman: ObjectManager,
@@ -226,7 +366,11 @@ struct Initializer {
     global_names: [u32; REQUIRED_GLOBALS.len()],
     output_names: Vec<u32>,
     fractional_scale: Option<FractionalScaleManager>,
+    /// the global name of `zwlr_output_power_manager_v1`. Its id depends on whether we also have
+    /// fractional scaling, so we only make its `OutputPowerManager` once we've seen every global
+    output_power_name: Option<NonZeroU32>,
     forced_shm_format: bool,
+    pixel_format_hint: Option<PixelFormat>,
     should_exit: bool,
 }
 
@@ -234,34 +378,56 @@ struct Initializer {
 pub struct InitState {
     pub output_names: Vec<u32>,
     pub fractional_scale: Option<FractionalScaleManager>,
+    pub output_power: Option<OutputPowerManager>,
     pub objman: ObjectManager,
     pub pixel_format: PixelFormat,
 }
 
 impl Initializer {
-    fn new(cli_format: Option<PixelFormat>) -> Self {
+    fn new(cli_format: Option<PixelFormat>, pixel_format_hint: Option<PixelFormat>) -> Self {
         Self {
             objman: ObjectManager::new(),
             global_names: [0; REQUIRED_GLOBALS.len()],
             output_names: Vec::new(),
             fractional_scale: None,
+            output_power_name: None,
             forced_shm_format: cli_format.is_some(),
+            pixel_format_hint,
             should_exit: false,
             pixel_format: cli_format.unwrap_or(PixelFormat::Xrgb),
         }
     }
 
+    /// the optional globals come right after the required ones, in this order:
+    ///   * `wp_fractional_scale_manager_v1`
+    ///   * `zwlr_output_power_manager_v1`
+    ///
+    /// followed by the callback we use for the second roundtrip
     fn callback_id(&self) -> ObjectId {
-        if self.fractional_scale.is_some() {
-            ObjectId(unsafe { NonZeroU32::new_unchecked(8) })
-        } else {
-            ObjectId(unsafe { NonZeroU32::new_unchecked(7) })
-        }
+        let id =
+            7 + self.fractional_scale.is_some() as u32 + self.output_power_name.is_some() as u32;
+        ObjectId(unsafe { NonZeroU32::new_unchecked(id) })
+    }
+
+    fn output_power(&self) -> Option<OutputPowerManager> {
+        let id = 7 + self.fractional_scale.is_some() as u32;
+        self.output_power_name.map(|name| OutputPowerManager {
+            id: ObjectId(unsafe { NonZeroU32::new_unchecked(id) }),
+            name,
+        })
     }
 
     fn into_init_state(self) -> InitState {
         debug!("Initialization Over");
+        // we can't read back what the compositor shows, so we can't check this ourselves
+        if self.pixel_format.channels() == 3 && !self.forced_shm_format {
+            info!(
+                "if your wallpapers look wrong, run the daemon with `--format xrgb` to force \
+                 a 4 channel format"
+            );
+        }
         InitState {
+            output_power: self.output_power(),
             output_names: self.output_names,
             fractional_scale: self.fractional_scale,
             objman: self.objman,
@@ -287,8 +453,7 @@ impl super::interfaces::wl_display::HasObjman for Initializer {
 impl super::interfaces::wl_display::EvHandler for Initializer {
     fn delete_id(&mut self, id: u32) {
         if id == 3 // initial callback for the roundtrip
-            || self.fractional_scale.is_none() && id == 7
-            || self.fractional_scale.is_some() && id == 8
+            || id == self.callback_id().get()
         {
             self.should_exit = true;
         } else {
@@ -320,6 +485,10 @@ impl super::interfaces::wl_registry::EvHandler for Initializer {
                 });
                 self.objman.set_fractional_scale_support(true);
             }
+            "zwlr_output_power_manager_v1" => {
+                self.output_power_name = Some(name.try_into().unwrap());
+                self.objman.set_output_power_support(true);
+            }
             "wl_output" => {
                 if version < 4 {
                     error!("wl_output implementation must have at least version 4 for swww-daemon")
@@ -351,29 +520,103 @@ impl super::interfaces::wl_registry::EvHandler for Initializer {
 
 impl super::interfaces::wl_shm::EvHandler for Initializer {
     fn format(&mut self, format: u32) {
-        match format {
+        let advertised = match format {
             super::interfaces::wl_shm::format::XRGB8888 => {
                 debug!("available shm format: Xrbg");
+                Some(PixelFormat::Xrgb)
             }
             super::interfaces::wl_shm::format::XBGR8888 => {
                 debug!("available shm format: Xbgr");
                 if !self.forced_shm_format && self.pixel_format == PixelFormat::Xrgb {
                     self.pixel_format = PixelFormat::Xbgr;
                 }
+                Some(PixelFormat::Xbgr)
             }
             super::interfaces::wl_shm::format::RGB888 => {
                 debug!("available shm format: Rbg");
                 if !self.forced_shm_format && self.pixel_format != PixelFormat::Bgr {
                     self.pixel_format = PixelFormat::Rgb
                 }
+                Some(PixelFormat::Rgb)
             }
             super::interfaces::wl_shm::format::BGR888 => {
                 debug!("available shm format: Bgr");
                 if !self.forced_shm_format {
                     self.pixel_format = PixelFormat::Bgr
                 }
+                Some(PixelFormat::Bgr)
+            }
+            // never picked on their own, since they don't look any better with 8 bit images
+            super::interfaces::wl_shm::format::XBGR2101010 => {
+                debug!("available shm format: Xbgr2101010");
+                Some(PixelFormat::Xbgr2101010)
+            }
+            super::interfaces::wl_shm::format::XRGB2101010 => {
+                debug!("available shm format: Xrgb2101010");
+                Some(PixelFormat::Xrgb2101010)
+            }
+            _ => None,
+        };
+
+        if let Some(advertised) = advertised {
+            if !self.forced_shm_format && Some(advertised) == self.pixel_format_hint {
+                debug!("using shm format from last run: {advertised:?}");
+                self.pixel_format = advertised;
+                self.forced_shm_format = true;
             }
-            _ => (),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wayland_socket_paths() {
+        let path = |display: Option<&str>, runtime_dir: Option<&str>| {
+            wayland_socket_path(display.map(OsStr::new), runtime_dir.map(OsStr::new), 1000)
+        };
+        assert_eq!(
+            path(Some("wayland-1"), Some("/run/user/1000")),
+            Path::new("/run/user/1000/wayland-1")
+        );
+        assert_eq!(
+            path(None, Some("/tmp/runtime")),
+            Path::new("/tmp/runtime/wayland-0")
+        );
+        assert_eq!(
+            path(Some("wayland-1"), None),
+            Path::new("/run/user/1000/wayland-1")
+        );
+        // absolute paths ignore XDG_RUNTIME_DIR
+        assert_eq!(
+            path(Some("/tmp/compositor.sock"), Some("/run/user/1000")),
+            Path::new("/tmp/compositor.sock")
+        );
+    }
+
+    #[test]
+    fn connect_errors_explain_what_went_wrong() {
+        let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let explain = |display: Option<&str>, exists| {
+            let path = wayland_socket_path(display.map(OsStr::new), Some(OsStr::new("/run")), 0);
+            connect_error(&path, display.map(OsStr::new), exists, &error)
+        };
+
+        let msg = explain(None, false);
+        assert!(msg.contains("\"/run/wayland-0\""), "{msg}");
+        assert!(msg.contains("WAYLAND_DISPLAY is not set"), "{msg}");
+
+        let msg = explain(Some("1"), false);
+        assert!(msg.contains("\"/run/1\""), "{msg}");
+        assert!(msg.contains("relative to XDG_RUNTIME_DIR"), "{msg}");
+        assert!(msg.contains("no such file"), "{msg}");
+        assert!(msg.contains("like wayland-1"), "{msg}");
+
+        let msg = explain(Some("/tmp/wayland-1"), true);
+        assert!(msg.contains("absolute path"), "{msg}");
+        assert!(msg.contains("the file exists"), "{msg}");
+        assert!(!msg.contains("just a number"), "{msg}");
+    }
+}
@@ -52,6 +52,7 @@ pub mod wl_display {
                     Some(super::super::WlDynObj::Callback) => "wl_callback",
                     Some(super::super::WlDynObj::Viewport) => "wl_viewport",
                     Some(super::super::WlDynObj::FractionalScale) => "wp_fractional_scale_v1",
+                    Some(super::super::WlDynObj::OutputPower) => "zwlr_output_power_v1",
                     None => "???",
                 },
             };
@@ -2270,3 +2271,104 @@ pub mod zwlr_layer_surface_v1 {
         pub const RIGHT: u32 = 8u32;
     }
 }
+///manager to create per-output power management
+///
+///This interface is a manager that allows creating per-output power
+///management mode controls.
+pub mod zwlr_output_power_manager_v1 {
+    use super::*;
+
+    ///Events for this interface
+    pub mod ev {}
+    ///Requests for this interface
+    pub mod req {
+        use super::*;
+        ///get a power management for an output
+        ///
+        ///Create an output power management mode control that can be used to
+        ///adjust the power management mode for a given output.
+        pub fn get_output_power(
+            sender_id: ObjectId,
+            id: ObjectId,
+            output: ObjectId,
+        ) -> rustix::io::Result<()> {
+            let mut wire_msg_builder = WireMsgBuilder::new(sender_id, 0);
+            wire_msg_builder.add_new_specified_id(id);
+            wire_msg_builder.add_object(Some(output));
+            wire_msg_builder.send()
+        }
+        ///destroy the manager
+        ///
+        ///All objects created by the manager will still remain valid, until their
+        ///appropriate destroy request has been called.
+        ///
+        ///THIS IS A DESTRUCTOR
+        pub fn destroy(sender_id: ObjectId) -> rustix::io::Result<()> {
+            let wire_msg_builder = WireMsgBuilder::new(sender_id, 1);
+            wire_msg_builder.send()
+        }
+    }
+}
+///adjust power management mode for an output
+///
+///This object offers requests to set the power management mode of
+///an output.
+pub mod zwlr_output_power_v1 {
+    use super::*;
+
+    pub trait EvHandler {
+        ///Report a power management mode change
+        ///
+        ///Report the power management mode change of an output.
+        ///
+        ///The mode event is sent after an output changed its power
+        ///management mode. The reason can be a client using set_mode or the
+        ///compositor deciding to change an output's mode.
+        ///This event is also sent immediately when the object is created
+        ///so the client is informed about the current power management mode.
+        fn mode(&mut self, sender_id: ObjectId, mode: u32);
+        ///object no longer valid
+        ///
+        ///This event indicates that the output power management mode control
+        ///is no longer valid. This can happen for a number of reasons,
+        ///including:
+        ///- The output doesn't support power management
+        ///- Another client already has exclusive power management mode control
+        ///  for this output
+        ///- The output disappeared
+        ///
+        ///Upon receiving this event, the client should destroy this object.
+        fn failed(&mut self, sender_id: ObjectId);
+    }
+
+    pub fn event<T: EvHandler>(state: &mut T, mut wire_msg: WireMsg, payload: WaylandPayload) {
+        match wire_msg.op() {
+            0 => {
+                let mode = wire_msg.next_u32(&payload);
+                state.mode(wire_msg.sender_id(), mode);
+            }
+            1 => state.failed(wire_msg.sender_id()),
+            e => log::error!("unrecognized event opcode: {e} for interface zwlr_output_power_v1"),
+        }
+    }
+
+    ///Requests for this interface
+    pub mod req {
+        use super::*;
+        ///destroy this power management
+        ///
+        ///Destroys the output power management mode control object.
+        ///
+        ///THIS IS A DESTRUCTOR
+        pub fn destroy(sender_id: ObjectId) -> rustix::io::Result<()> {
+            let wire_msg_builder = WireMsgBuilder::new(sender_id, 1);
+            wire_msg_builder.send()
+        }
+    }
+    pub mod mode {
+        ///Output is turned off.
+        pub const OFF: u32 = 0u32;
+        ///Output is turned on, no power saving
+        pub const ON: u32 = 1u32;
+    }
+}
@@ -21,6 +21,7 @@
 //! now completely independent from them.
 use std::num::NonZeroU32;
 
+pub mod backend;
 pub mod bump_pool;
 pub mod globals;
 pub mod interfaces;
@@ -57,6 +58,7 @@ pub enum WlDynObj {
     Callback,
     Viewport,
     FractionalScale,
+    OutputPower,
 }
 
 /// Object Manager for creating, removing, and maintaining Wayland Objects
@@ -68,6 +70,7 @@ pub struct ObjectManager {
     /// the next id we ought to generate
     next: u32,
     fractional_scale_support: bool,
+    output_power_support: bool,
 }
 
 impl ObjectManager {
@@ -79,9 +82,15 @@ impl ObjectManager {
             objects: Vec::new(),
             next: 0,
             fractional_scale_support: false,
+            output_power_support: false,
         }
     }
 
+    /// The first id we may generate, which comes after the ids of all the globals we've bound
+    const fn offset(&self) -> u32 {
+        Self::BASE_OFFSET + self.fractional_scale_support as u32 + self.output_power_support as u32
+    }
+
     /// get the type of the wayland object from its id
     ///
     /// Returns
@@ -89,7 +98,7 @@ impl ObjectManager {
     ///   * 'None' if the object was already deleted
     #[must_use]
     pub fn get(&self, object_id: ObjectId) -> Option<WlDynObj> {
-        let offset = Self::BASE_OFFSET + self.fractional_scale_support as u32;
+        let offset = self.offset();
         let pos = object_id.get() - offset;
         self.objects[pos as usize]
     }
@@ -97,8 +106,8 @@ impl ObjectManager {
     /// creates a new Id to use in requests
     #[must_use]
     pub fn create(&mut self, object: WlDynObj) -> ObjectId {
-        let offset = Self::BASE_OFFSET + self.fractional_scale_support as u32;
-        if self.next as usize == self.objects.len() {
+        let offset = self.offset();
+        let id = if self.next as usize == self.objects.len() {
             self.next += 1;
             self.objects.push(Some(object));
             ObjectId::new(unsafe { NonZeroU32::new(self.next + offset - 1).unwrap_unchecked() })
@@ -116,7 +125,9 @@ impl ObjectManager {
             }
 
             ObjectId::new(unsafe { NonZeroU32::new(i as u32 + offset).unwrap_unchecked() })
-        }
+        };
+        log::trace!("created {object:?} with id {}", id.get());
+        id
     }
 
     /// removes the wayland object.
@@ -124,9 +135,12 @@ impl ObjectManager {
     /// Removing the same element twice currently works just fine and does not panic,
     /// but that may change in the future
     pub fn remove(&mut self, object_id: ObjectId) {
-        let offset = Self::BASE_OFFSET + self.fractional_scale_support as u32;
+        let offset = self.offset();
         let pos = object_id.get() - offset;
-        self.objects[pos as usize] = None;
+        match self.objects[pos as usize].take() {
+            Some(object) => log::trace!("removed {object:?} with id {}", object_id.get()),
+            None => log::trace!("removed id {} again", object_id.get()),
+        }
         if pos < self.next {
             self.next = pos;
         }
@@ -139,6 +153,14 @@ impl ObjectManager {
     pub fn fractional_scale_support(&self) -> bool {
         self.fractional_scale_support
     }
+
+    pub fn set_output_power_support(&mut self, output_power_support: bool) {
+        self.output_power_support = output_power_support;
+    }
+
+    pub fn output_power_support(&self) -> bool {
+        self.output_power_support
+    }
 }
 
 #[cfg(test)]
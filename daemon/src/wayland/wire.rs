@@ -11,7 +11,10 @@ use rustix::{
 };
 use std::num::NonZeroU32;
 
-use super::{globals::wayland_fd, ObjectId};
+use super::{
+    backend::{Active, Backend},
+    ObjectId,
+};
 
 #[derive(Debug, Clone)]
 pub struct WaylandPayload(Box<[u32]>);
@@ -48,7 +51,14 @@ impl WaylandPayload {
 }
 
 impl WireMsg {
+    /// Receives the next message from the compositor
+    ///
+    /// Fails with `Errno::PIPE` if the compositor closed the connection
     pub fn recv() -> rustix::io::Result<(Self, WaylandPayload)> {
+        Active::recv()
+    }
+
+    pub(super) fn recv_from(fd: BorrowedFd) -> rustix::io::Result<(Self, WaylandPayload)> {
         let fds = Vec::new();
 
         let mut header_buf = [0u32; 2];
@@ -58,12 +68,11 @@ impl WireMsg {
         let mut control = net::RecvAncillaryBuffer::new(i32_slice_to_u8_mut(&mut ancillary_buf));
 
         let iov = io::IoSliceMut::new(u32_slice_to_u8_mut(&mut header_buf));
-        net::recvmsg(
-            wayland_fd(),
-            &mut [iov],
-            &mut control,
-            net::RecvFlags::empty(),
-        )?;
+        let received = net::recvmsg(fd, &mut [iov], &mut control, net::RecvFlags::empty())?;
+        // an empty read means the other end hung up, and there will never be a header to read
+        if received.bytes == 0 {
+            return Err(io::Errno::PIPE);
+        }
 
         let sender_id = ObjectId(
             NonZeroU32::new(header_buf[0])
@@ -79,12 +88,7 @@ impl WireMsg {
             // be left in an inconsistent state (a message without a header)
             rustix::io::retry_on_intr(|| {
                 let iov = io::IoSliceMut::new(u32_slice_to_u8_mut(&mut payload));
-                net::recvmsg(
-                    wayland_fd(),
-                    &mut [iov],
-                    &mut control,
-                    net::RecvFlags::WAITALL,
-                )
+                net::recvmsg(fd, &mut [iov], &mut control, net::RecvFlags::WAITALL)
             })?;
         }
 
@@ -99,6 +103,24 @@ impl WireMsg {
         ))
     }
 
+    /// A message as if `sender_id` had sent it, with `args` as its payload
+    #[cfg(test)]
+    pub(super) fn from_parts(
+        sender_id: ObjectId,
+        op: u16,
+        args: Vec<u32>,
+    ) -> (Self, WaylandPayload) {
+        (
+            Self {
+                sender_id,
+                op,
+                fds: Box::new([]),
+                cur: 0,
+            },
+            WaylandPayload(args.into_boxed_slice()),
+        )
+    }
+
     #[must_use]
     pub fn into_fds(self) -> Box<[OwnedFd]> {
         self.fds
@@ -255,12 +277,7 @@ impl WireMsgBuilder {
 
 /// try to send a raw message through the wayland socket. We do no input validation whatsoever
 pub unsafe fn send_unchecked(msg: &[u8], fds: &[BorrowedFd]) -> rustix::io::Result<()> {
-    let iov = io::IoSlice::new(msg);
-    let mut control_buf = [0u8; rustix::cmsg_space!(ScmRights(1))];
-    let mut control = net::SendAncillaryBuffer::new(&mut control_buf);
-    let msg = net::SendAncillaryMessage::ScmRights(fds);
-    control.push(msg);
-    net::sendmsg(wayland_fd(), &[iov], &mut control, net::SendFlags::NOSIGNAL).map(|_| ())
+    Active::send(msg, fds)
 }
 
 impl WlSlice<'_> {
@@ -417,6 +434,33 @@ fn i32_slice_to_u8_mut(src: &mut [i32]) -> &mut [u8] {
 mod tests {
     use super::*;
 
+    #[test]
+    fn recv_fails_once_the_compositor_hangs_up() {
+        use rustix::fd::AsFd;
+        use rustix::net::{socketpair, AddressFamily, SocketFlags, SocketType};
+
+        let (daemon_end, compositor_end) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        // object 3, opcode 1, with a single argument
+        let msg = [3u32, (12 << 16) | 1, 42];
+        rustix::io::write(&compositor_end, u32_slice_to_u8(&msg)).unwrap();
+        drop(compositor_end);
+
+        let (msg, payload) = WireMsg::recv_from(daemon_end.as_fd()).unwrap();
+        assert_eq!(msg.sender_id().get(), 3);
+        assert_eq!(msg.op(), 1);
+        assert_eq!(payload.get(), [42]);
+        assert_eq!(
+            WireMsg::recv_from(daemon_end.as_fd()).unwrap_err(),
+            io::Errno::PIPE
+        );
+    }
+
     #[test]
     fn fixed_creation() {
         assert_eq!(WlFixed::from(-1), WlFixed::from(0xFFFFFFFFu32));
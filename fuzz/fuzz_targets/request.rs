@@ -0,0 +1,21 @@
+//! Feeds arbitrary bytes to the daemon's request parser, as if some program had written them to
+//! the socket. The first byte selects the request code, and the rest is the shared memory
+//!
+//! Run it with `cargo +nightly fuzz run request` from the repository's root
+#![no_main]
+
+use common::ipc::{RawMsg, RequestRecv};
+use libfuzzer_sys::fuzz_target;
+
+/// Every request code the daemon answers to
+const CODES: [u64; 13] = [0, 1, 2, 3, 4, 9, 10, 11, 12, 15, 17, 18, 19];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&code, bytes)) = data.split_first() else {
+        return;
+    };
+    let code = CODES[code as usize % CODES.len()];
+    if let Some(msg) = RawMsg::from_raw_parts(code, bytes) {
+        let _ = RequestRecv::receive(msg);
+    }
+});